@@ -0,0 +1,116 @@
+//! Store performance at the scale of a long-running base station: 10k nodes and 1M messages.
+//!
+//! Run with `cargo bench`. Filling the store takes a while before the first measurement.
+
+use std::hint::black_box;
+
+use chrono::{DateTime, Local};
+use criterion::{Criterion, criterion_group, criterion_main};
+use edda::store::{SqliteStore, Store};
+use edda::types::{Message, NodeNum};
+use meshtastic::protobufs::{NodeInfo, User};
+
+const NODES: NodeNum = 10_000;
+const MESSAGES: u32 = 1_000_000;
+/// Start of the simulated history, in seconds since the epoch.
+const START: i64 = 1_700_000_000;
+
+fn node(num: NodeNum, last_heard: u32) -> NodeInfo {
+    NodeInfo {
+        num,
+        last_heard,
+        user: Some(User {
+            id: format!("!{num:08x}"),
+            long_name: format!("Node {num}"),
+            short_name: format!("{:04}", num % 10_000),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn message(index: u32) -> Message {
+    Message {
+        outgoing: index.is_multiple_of(3),
+        ts: DateTime::from_timestamp(START + i64::from(index), 0)
+            .unwrap()
+            .with_timezone(&Local),
+        channel: index % 4,
+        text: format!("message number {index} with some typical length"),
+        snr: (!index.is_multiple_of(3)).then_some(f32::from((index % 20) as u8) - 5.0),
+        packet_id: (!index.is_multiple_of(3)).then_some(index),
+        estimated_time: false,
+        note: None,
+        reply_id: None,
+    }
+}
+
+fn store_with_nodes() -> SqliteStore {
+    let mut store = SqliteStore::open_in_memory().unwrap();
+    for num in 0..NODES {
+        store.upsert_node(&node(num, 0)).unwrap();
+    }
+    store
+}
+
+fn store_with_messages() -> SqliteStore {
+    let mut store = store_with_nodes();
+    for index in 0..MESSAGES {
+        store
+            .append_message(index % NODES, &message(index))
+            .unwrap();
+    }
+    store
+}
+
+fn nodes(c: &mut Criterion) {
+    let mut store = store_with_nodes();
+    let mut heard = 0;
+    c.bench_function("upsert_node", |b| {
+        b.iter(|| {
+            heard += 1;
+            store.upsert_node(&node(heard % NODES, heard)).unwrap();
+        })
+    });
+    c.bench_function("get_nodes", |b| {
+        b.iter(|| black_box(store.get_nodes().unwrap()))
+    });
+}
+
+fn messages(c: &mut Criterion) {
+    let mut store = store_with_messages();
+    let mut index = MESSAGES;
+    c.bench_function("append_message", |b| {
+        b.iter(|| {
+            index += 1;
+            store
+                .append_message(index % NODES, &message(index))
+                .unwrap();
+        })
+    });
+    let mut seed = 0;
+    c.bench_function("get_conversation_stats", |b| {
+        b.iter(|| {
+            seed += 1;
+            black_box(store.get_conversation_stats(spread(seed)).unwrap())
+        })
+    });
+    c.bench_function("get_read_state", |b| {
+        b.iter(|| black_box(store.get_read_state().unwrap()))
+    });
+
+    let mut group = c.benchmark_group("full history");
+    group.sample_size(10);
+    group.bench_function("get_messages", |b| {
+        b.iter(|| black_box(store.get_messages().unwrap()))
+    });
+    group.finish();
+}
+
+/// A node spread over the whole range, so caching a single conversation does not help.
+fn spread(seed: u32) -> NodeNum {
+    seed.wrapping_mul(2_654_435_761) % NODES
+}
+
+criterion_group!(benches, nodes, messages);
+criterion_main!(benches);
@@ -0,0 +1,64 @@
+//! Keys for remote administration on firmware that requires public-key (PKC) admin messages.
+//!
+//! A device accepts admin messages from the holders of up to three public keys listed in its
+//! security config. edda keeps its own keypair in the store so this machine can be one of them.
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use meshtastic::protobufs::config::SecurityConfig;
+use rand::RngCore;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// The firmware only has room for this many admin keys.
+pub const MAX_ADMIN_KEYS: usize = 3;
+const KEY_BYTES: usize = 32;
+
+#[derive(Clone)]
+pub struct AdminKeypair {
+    pub public: Vec<u8>,
+    pub private: Vec<u8>,
+}
+
+impl AdminKeypair {
+    /// A new Curve25519 keypair, as used by Meshtastic for PKC.
+    pub fn generate() -> Self {
+        let mut bytes = [0; KEY_BYTES];
+        rand::rng().fill_bytes(&mut bytes);
+        let secret = StaticSecret::from(bytes);
+        Self {
+            public: PublicKey::from(&secret).as_bytes().to_vec(),
+            private: secret.to_bytes().to_vec(),
+        }
+    }
+}
+
+/// Keys are shown and entered in base64, as in the official apps.
+pub fn format_key(key: &[u8]) -> String {
+    STANDARD.encode(key)
+}
+
+pub fn parse_key(text: &str) -> Option<Vec<u8>> {
+    STANDARD
+        .decode(text.trim())
+        .ok()
+        .filter(|key| key.len() == KEY_BYTES)
+}
+
+/// The security config with `key` added to the admin keys, or `None` if there is no room.
+pub fn with_admin_key(config: &SecurityConfig, key: Vec<u8>) -> Option<SecurityConfig> {
+    let mut config = config.clone();
+    if !config.admin_key.contains(&key) {
+        if config.admin_key.len() >= MAX_ADMIN_KEYS {
+            return None;
+        }
+        config.admin_key.push(key);
+    }
+    Some(config)
+}
+
+pub fn without_admin_key(config: &SecurityConfig, index: usize) -> SecurityConfig {
+    let mut config = config.clone();
+    if index < config.admin_key.len() {
+        config.admin_key.remove(index);
+    }
+    config
+}
@@ -0,0 +1,54 @@
+//! A plain text stream of new messages and alerts for terminal screen reader users.
+//!
+//! The TUI redraws regions in place, which screen readers can't follow. Each event is appended
+//! to a file as one spoken-style sentence without symbols or columns, so it can be followed
+//! linearly, e.g. with `tail -f` in another pane.
+
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use chrono::Local;
+
+pub struct Announcer {
+    path: PathBuf,
+}
+
+impl Announcer {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn received(&self, from: &str, broadcast: bool, channel: u32, text: &str) {
+        let to = if broadcast {
+            format!("to everyone on channel {channel}")
+        } else {
+            "to you".to_string()
+        };
+        self.announce(&format!("Message from {from} {to}: {text}"));
+    }
+
+    pub fn sent(&self, to: &str, text: &str) {
+        self.announce(&format!("You sent to {to}: {text}"));
+    }
+
+    pub fn alert(&self, text: &str) {
+        self.announce(&format!("Alert: {text}"));
+    }
+
+    fn announce(&self, sentence: &str) {
+        if let Err(e) = self.append(sentence) {
+            log::error!("Failed to write announcement: {}", e);
+        }
+    }
+
+    fn append(&self, sentence: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}. {}", Local::now().format("%H:%M"), sentence)
+    }
+}
@@ -0,0 +1,157 @@
+//! Gateway forwarding the positions of licensed operators' nodes to APRS-IS.
+//!
+//! Forwarding is strictly opt-in: a node's position is only sent if the node is listed in the
+//! `[aprs.nodes]` configuration and its owner has marked it as licensed.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Write},
+    net::TcpStream,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+use meshtastic::protobufs::{NodeInfo, Position};
+
+use crate::config::AprsConfig;
+use crate::export::DEGREES_PER_UNIT;
+use crate::types::{NodeNum, format_node_num, parse_node_num};
+
+/// APRS-IS asks that fixed stations are not reported more often than this.
+const MIN_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const FEET_PER_METRE: f64 = 3.28084;
+
+pub struct AprsGateway {
+    callsigns: HashMap<NodeNum, String>,
+    licensed: HashSet<NodeNum>,
+    last_sent: HashMap<NodeNum, Instant>,
+    sender: mpsc::Sender<String>,
+}
+
+impl AprsGateway {
+    pub fn spawn(config: AprsConfig) -> Self {
+        let mut callsigns = HashMap::new();
+        for (node, callsign) in &config.nodes {
+            match parse_node_num(node) {
+                Some(num) => {
+                    callsigns.insert(num, callsign.to_uppercase());
+                }
+                None => log::warn!("Ignoring invalid node ID {} in APRS config", node),
+            }
+        }
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || upload(config, receiver));
+        Self {
+            callsigns,
+            licensed: HashSet::new(),
+            last_sent: HashMap::new(),
+            sender,
+        }
+    }
+
+    /// Track whether a node's owner has declared themselves a licensed operator.
+    pub fn node_info(&mut self, node: &NodeInfo) {
+        if node.user.as_ref().is_some_and(|u| u.is_licensed) {
+            self.licensed.insert(node.num);
+        } else {
+            self.licensed.remove(&node.num);
+        }
+    }
+
+    pub fn position(&mut self, num: NodeNum, position: &Position) {
+        let Some(callsign) = self.callsigns.get(&num) else {
+            return;
+        };
+        if !self.licensed.contains(&num) {
+            log::debug!(
+                "Not forwarding {} to APRS-IS: not licensed",
+                format_node_num(num)
+            );
+            return;
+        }
+        if self
+            .last_sent
+            .get(&num)
+            .is_some_and(|sent| sent.elapsed() < MIN_INTERVAL)
+        {
+            return;
+        }
+        let Some(packet) = position_packet(callsign, position) else {
+            return;
+        };
+        self.last_sent.insert(num, Instant::now());
+        // The uploader only stops if this handle is dropped.
+        let _ = self.sender.send(packet);
+    }
+}
+
+/// Format an uncompressed APRS position report, e.g.
+/// `N0CALL-7>APRS,TCPIP*:!4916.45N/12311.12W-/A=000100 Meshtastic`.
+fn position_packet(callsign: &str, position: &Position) -> Option<String> {
+    let latitude = f64::from(position.latitude_i?) * DEGREES_PER_UNIT;
+    let longitude = f64::from(position.longitude_i?) * DEGREES_PER_UNIT;
+    let altitude = position
+        .altitude
+        .map(|a| format!("/A={:06}", (f64::from(a) * FEET_PER_METRE).round() as i64))
+        .unwrap_or_default();
+    Some(format!(
+        "{}>APRS,TCPIP*:!{}{}/{}{}-{} Meshtastic",
+        callsign,
+        degrees_minutes(latitude.abs(), 2),
+        if latitude < 0.0 { 'S' } else { 'N' },
+        degrees_minutes(longitude.abs(), 3),
+        if longitude < 0.0 { 'W' } else { 'E' },
+        altitude
+    ))
+}
+
+/// `DDMM.mm` with the degrees zero-padded to `width` digits.
+fn degrees_minutes(degrees: f64, width: usize) -> String {
+    let hundredths = (degrees * 6000.0).round() as u64;
+    format!(
+        "{:0width$}{:02}.{:02}",
+        hundredths / 6000,
+        hundredths % 6000 / 100,
+        hundredths % 100
+    )
+}
+
+/// Send packets to APRS-IS, reconnecting whenever the connection has dropped.
+fn upload(config: AprsConfig, receiver: Receiver<String>) {
+    let mut connection: Option<TcpStream> = None;
+    for packet in receiver {
+        if connection.is_none() {
+            match connect(&config) {
+                Ok(stream) => connection = Some(stream),
+                Err(e) => {
+                    log::error!("Failed to connect to APRS-IS at {}: {}", config.server, e);
+                    continue;
+                }
+            }
+        }
+        if let Some(stream) = &mut connection
+            && let Err(e) = write!(stream, "{}\r\n", packet)
+        {
+            log::error!("Failed to send to APRS-IS: {}", e);
+            connection = None;
+        }
+    }
+}
+
+fn connect(config: &AprsConfig) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(&config.server)?;
+    write!(
+        stream,
+        "user {} pass {} vers edda {}\r\n",
+        config.callsign,
+        config.passcode,
+        env!("CARGO_PKG_VERSION")
+    )?;
+    // The server sends a banner and periodic keepalives we have no use for, but they must be
+    // read or the server will eventually consider us a slow client.
+    let mut reader = stream.try_clone()?;
+    thread::spawn(move || io::copy(&mut reader, &mut io::sink()));
+    log::info!("Connected to APRS-IS at {}", config.server);
+    Ok(stream)
+}
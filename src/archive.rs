@@ -0,0 +1,322 @@
+//! Scheduled exports in daemon mode, for archival pipelines: every night the messages stored
+//! since the last export, and the telemetry of the nodes heard since, are written as JSON or CSV
+//! to a directory, uploaded to an S3-compatible bucket, or both.
+//!
+//! Each run produces `messages-<date>.<ext>` and `telemetry-<date>.<ext>`. The end of the period
+//! covered is kept in the store, so a run missed while edda was down is caught up by the next.
+
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+
+use chrono::{DateTime, Days, Local, NaiveTime, Utc};
+use color_eyre::eyre::{Result, eyre};
+use meshtastic::protobufs::NodeInfo;
+use ring::{digest, hmac};
+use serde_json::{Value, json};
+
+use crate::config::{ArchiveConfig, ArchiveFormat, S3Config};
+use crate::store::{SqliteStore, Store};
+use crate::types::{Message, NodeNum, format_node_num};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Export on a background thread with its own connection to the store at `path`.
+pub fn spawn(path: PathBuf, config: ArchiveConfig) {
+    if config.directory.is_none() && config.s3.is_none() {
+        log::warn!("No directory or s3 bucket configured in [archive], nothing will be exported");
+        return;
+    }
+    thread::spawn(move || {
+        let mut store = match SqliteStore::open(&path) {
+            Ok(store) => store,
+            Err(e) => {
+                log::error!("Archive task failed to open store: {}", e);
+                return;
+            }
+        };
+        loop {
+            let now = Local::now();
+            let next = next_run(config.at, now);
+            log::info!("Next archive export at {}", next.format("%Y-%m-%d %H:%M"));
+            if let Ok(wait) = (next - now).to_std() {
+                thread::sleep(wait);
+            }
+            if let Err(e) = export(&mut store, &config, Local::now()) {
+                log::error!("Failed to export archive: {}", e);
+            }
+        }
+    });
+}
+
+/// The first time after `now` that is `at` local time.
+fn next_run(at: NaiveTime, now: DateTime<Local>) -> DateTime<Local> {
+    let today = now.date_naive();
+    [today, today + Days::new(1), today + Days::new(2)]
+        .into_iter()
+        .filter_map(|day| day.and_time(at).and_local_timezone(Local).earliest())
+        .find(|time| *time > now)
+        .unwrap_or(now + chrono::Duration::days(1))
+}
+
+/// Export everything since the last export, up to `now`.
+pub fn export(store: &mut SqliteStore, config: &ArchiveConfig, now: DateTime<Local>) -> Result<()> {
+    let since = store
+        .get_last_archive()?
+        .unwrap_or(now.timestamp() - SECONDS_PER_DAY);
+    let messages = store.get_messages_since(since)?;
+    let nodes: Vec<NodeInfo> = store
+        .get_nodes()?
+        .into_iter()
+        .filter(|node| i64::from(node.last_heard) > since)
+        .collect();
+
+    let date = now.format("%Y-%m-%d");
+    let extension = config.format.extension();
+    let files = [
+        (
+            format!("messages-{date}.{extension}"),
+            messages_export(&messages, config.format),
+        ),
+        (
+            format!("telemetry-{date}.{extension}"),
+            telemetry_export(&nodes, config.format),
+        ),
+    ];
+    for (name, contents) in &files {
+        if let Some(directory) = &config.directory {
+            fs::create_dir_all(directory)?;
+            fs::write(directory.join(name), contents)?;
+        }
+        if let Some(s3) = &config.s3 {
+            put_object(s3, name, contents.as_bytes(), Utc::now())?;
+        }
+    }
+    log::info!(
+        "Exported {} messages and {} nodes to the archive",
+        messages.len(),
+        nodes.len()
+    );
+    store.record_archive(now.timestamp())
+}
+
+fn messages_export(messages: &[(NodeNum, Message)], format: ArchiveFormat) -> String {
+    let header = ["time", "node", "outgoing", "channel", "snr", "text", "note"];
+    let rows = messages.iter().map(|(node, message)| {
+        [
+            json!(message.ts.timestamp()),
+            json!(format_node_num(*node)),
+            json!(message.outgoing),
+            json!(message.channel),
+            json!(message.snr),
+            json!(message.text),
+            json!(message.note),
+        ]
+    });
+    table(&header, rows, format)
+}
+
+fn telemetry_export(nodes: &[NodeInfo], format: ArchiveFormat) -> String {
+    let header = [
+        "node",
+        "long_name",
+        "last_heard",
+        "snr",
+        "hops",
+        "battery_level",
+        "voltage",
+        "channel_utilization",
+        "air_util_tx",
+        "latitude",
+        "longitude",
+        "altitude",
+    ];
+    let rows = nodes.iter().map(|node| {
+        let metrics = node.device_metrics.unwrap_or_default();
+        let position = node.position.unwrap_or_default();
+        let degrees = |value: Option<i32>| value.map(|v| f64::from(v) * 1e-7);
+        [
+            json!(format_node_num(node.num)),
+            json!(node.user.as_ref().map(|user| &user.long_name)),
+            json!(node.last_heard),
+            json!(node.snr),
+            json!(node.hops_away),
+            json!(metrics.battery_level),
+            json!(metrics.voltage),
+            json!(metrics.channel_utilization),
+            json!(metrics.air_util_tx),
+            json!(degrees(position.latitude_i)),
+            json!(degrees(position.longitude_i)),
+            json!(position.altitude),
+        ]
+    });
+    table(&header, rows, format)
+}
+
+/// Rows as a JSON array of objects, or as CSV with a header line.
+fn table<const N: usize>(
+    header: &[&str; N],
+    rows: impl Iterator<Item = [Value; N]>,
+    format: ArchiveFormat,
+) -> String {
+    match format {
+        ArchiveFormat::Json => {
+            let objects: Vec<Value> = rows
+                .map(|row| {
+                    Value::Object(
+                        header
+                            .iter()
+                            .map(|name| name.to_string())
+                            .zip(row)
+                            .collect(),
+                    )
+                })
+                .collect();
+            Value::Array(objects).to_string()
+        }
+        ArchiveFormat::Csv => {
+            let mut csv = header.join(",");
+            csv.push('\n');
+            for row in rows {
+                let fields: Vec<String> = row.iter().map(csv_field).collect();
+                csv.push_str(&fields.join(","));
+                csv.push('\n');
+            }
+            csv
+        }
+    }
+}
+
+fn csv_field(value: &Value) -> String {
+    let text = match value {
+        Value::Null => return String::new(),
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+/// Upload an object, signed with AWS Signature Version 4.
+fn put_object(s3: &S3Config, name: &str, body: &[u8], now: DateTime<Utc>) -> Result<()> {
+    let (url, authorization, date, payload_hash) = sign_put(s3, name, body, now)?;
+    ureq::put(&url)
+        .header("Authorization", &authorization)
+        .header("x-amz-date", &date)
+        .header("x-amz-content-sha256", &payload_hash)
+        .send(body)?;
+    Ok(())
+}
+
+/// The URL and the `Authorization`, `x-amz-date` and `x-amz-content-sha256` headers for
+/// uploading `body` as `name`.
+fn sign_put(
+    s3: &S3Config,
+    name: &str,
+    body: &[u8],
+    now: DateTime<Utc>,
+) -> Result<(String, String, String, String)> {
+    let endpoint = s3.endpoint.trim_end_matches('/');
+    let host = endpoint
+        .strip_prefix("https://")
+        .or_else(|| endpoint.strip_prefix("http://"))
+        .ok_or_else(|| {
+            eyre!(
+                "S3 endpoint {} must start with http:// or https://",
+                endpoint
+            )
+        })?;
+    let path = format!("/{}/{}{}", s3.bucket, s3.prefix, name);
+    let path = uri_encode(&path);
+
+    let date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let day = now.format("%Y%m%d").to_string();
+    let payload_hash = hex(digest::digest(&digest::SHA256, body).as_ref());
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "PUT\n{path}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{date}\n\n\
+         {signed_headers}\n{payload_hash}"
+    );
+    let scope = format!("{day}/{}/s3/aws4_request", s3.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{date}\n{scope}\n{}",
+        hex(digest::digest(&digest::SHA256, canonical_request.as_bytes()).as_ref())
+    );
+
+    let key = [day.as_str(), &s3.region, "s3", "aws4_request"]
+        .iter()
+        .fold(
+            format!("AWS4{}", s3.secret_key).into_bytes(),
+            |key, part| hmac_sha256(&key, part.as_bytes()),
+        );
+    let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, \
+         Signature={signature}",
+        s3.access_key
+    );
+    Ok((
+        format!("{endpoint}{path}"),
+        authorization,
+        date,
+        payload_hash,
+    ))
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    hmac::sign(&key, data).as_ref().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Percent-encode a path the way S3 signs it: everything but unreserved characters and `/`.
+fn uri_encode(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn upload_is_signed_like_the_aws_sdk() {
+        let s3 = S3Config {
+            endpoint: "http://minio.lan:9000".to_string(),
+            bucket: "archive".to_string(),
+            region: "us-east-1".to_string(),
+            prefix: "edda/".to_string(),
+            access_key: "AKIDEXAMPLE".to_string(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+        };
+        let now = Utc.with_ymd_and_hms(2026, 10, 17, 2, 0, 0).unwrap();
+        let (url, authorization, date, _) =
+            sign_put(&s3, "messages-2026-10-17.json", b"[]", now).unwrap();
+        assert_eq!(
+            url,
+            "http://minio.lan:9000/archive/edda/messages-2026-10-17.json"
+        );
+        assert_eq!(date, "20261017T020000Z");
+        // As computed by botocore for the same request.
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20261017/us-east-1/s3/aws4_request, \
+             SignedHeaders=host;x-amz-content-sha256;x-amz-date, \
+             Signature=7349086199d3f259683136f01a29b1109095d40b440fc9ef18bd7a5b37a8fbee"
+        );
+    }
+}
@@ -0,0 +1,69 @@
+//! Replies for stations left unattended in daemon mode, so whoever sends them a direct message
+//! knows it was recorded even though nobody is there to answer.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local};
+
+use crate::types::NodeNum;
+
+/// Each sender gets at most one reply in this long.
+const REPLY_INTERVAL_SECONDS: i64 = 60 * 60;
+
+pub struct AutoReply {
+    /// Reply text, with `{time}` standing for when the message was logged.
+    template: String,
+    /// When each sender was last replied to.
+    replied: HashMap<NodeNum, DateTime<Local>>,
+}
+
+impl AutoReply {
+    pub fn new(template: String) -> Self {
+        Self {
+            template,
+            replied: HashMap::new(),
+        }
+    }
+
+    /// The reply to a direct message from `from` logged at `time`, unless they had one within
+    /// the last hour.
+    pub fn reply(&mut self, from: NodeNum, time: DateTime<Local>) -> Option<String> {
+        if let Some(last) = self.replied.get(&from)
+            && (time - *last).num_seconds() < REPLY_INTERVAL_SECONDS
+        {
+            return None;
+        }
+        self.replied.insert(from, time);
+        Some(
+            self.template
+                .replace("{time}", &time.format("%Y-%m-%d %H:%M").to_string()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn each_sender_gets_one_reply_an_hour() {
+        let mut auto_reply = AutoReply::new("Unattended, logged at {time}".to_string());
+        let time = Local.with_ymd_and_hms(2026, 10, 17, 9, 30, 0).unwrap();
+        assert_eq!(
+            auto_reply.reply(1, time).as_deref(),
+            Some("Unattended, logged at 2026-10-17 09:30")
+        );
+        assert_eq!(
+            auto_reply.reply(1, time + chrono::Duration::minutes(59)),
+            None
+        );
+        assert!(auto_reply.reply(2, time).is_some());
+        assert!(
+            auto_reply
+                .reply(1, time + chrono::Duration::hours(1))
+                .is_some()
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! Battery gauges and trends for the node list.
+//!
+//! The trend comes from the levels a node's telemetry reported over the last few hours: a
+//! least-squares slope, so one noisy sample does not flip the arrow. A solar node whose arrow
+//! points down in the middle of the day is one that needs a visit.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::types::NodeNum;
+
+/// Levels reported within this many seconds of a node's latest one decide its trend.
+pub const TREND_WINDOW: i64 = 6 * 60 * 60;
+/// Samples spanning fewer seconds than this are too close together to show a trend.
+const MIN_SPAN: i64 = 30 * 60;
+/// Levels changing more slowly than this, in percent per hour, are steady.
+const STEADY_PER_HOUR: f64 = 1.0;
+/// Levels above 100 mean the node is externally powered.
+const MAX_LEVEL: u32 = 100;
+/// Width of the gauge in cells.
+const GAUGE_CELLS: u32 = 4;
+/// Partial blocks, by eighths of a cell filled.
+const EIGHTHS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trend {
+    Charging,
+    Discharging,
+    Steady,
+}
+
+impl Trend {
+    pub fn arrow(self) -> char {
+        match self {
+            Trend::Charging => '↑',
+            Trend::Discharging => '↓',
+            Trend::Steady => '→',
+        }
+    }
+}
+
+/// Recent battery levels of every node, oldest first.
+#[derive(Debug, Default)]
+pub struct BatteryHistory {
+    samples: HashMap<NodeNum, VecDeque<(i64, u32)>>,
+}
+
+impl BatteryHistory {
+    /// Note a level a node reported at `time` (seconds since the epoch). Samples that fell out
+    /// of the window are forgotten, and running on external power starts the history afresh.
+    pub fn record(&mut self, node: NodeNum, time: i64, level: u32) {
+        if level > MAX_LEVEL {
+            self.samples.remove(&node);
+            return;
+        }
+        let samples = self.samples.entry(node).or_default();
+        samples.push_back((time, level));
+        while samples
+            .front()
+            .is_some_and(|&(t, _)| t < time - TREND_WINDOW)
+        {
+            samples.pop_front();
+        }
+    }
+
+    /// Which way a node's battery is going, if enough of its levels were reported to tell.
+    pub fn trend(&self, node: NodeNum) -> Option<Trend> {
+        let samples = self.samples.get(&node)?;
+        let (&(first, _), &(last, _)) = (samples.front()?, samples.back()?);
+        if last - first < MIN_SPAN {
+            return None;
+        }
+        let count = samples.len() as f64;
+        let mean_time = samples
+            .iter()
+            .map(|&(t, _)| (t - first) as f64)
+            .sum::<f64>()
+            / count;
+        let mean_level = samples.iter().map(|&(_, l)| f64::from(l)).sum::<f64>() / count;
+        let (covariance, variance) =
+            samples
+                .iter()
+                .fold((0.0, 0.0), |(covariance, variance), &(t, l)| {
+                    let dt = (t - first) as f64 - mean_time;
+                    (
+                        covariance + dt * (f64::from(l) - mean_level),
+                        variance + dt * dt,
+                    )
+                });
+        let per_hour = covariance / variance * 3600.0;
+        Some(if per_hour >= STEADY_PER_HOUR {
+            Trend::Charging
+        } else if per_hour <= -STEADY_PER_HOUR {
+            Trend::Discharging
+        } else {
+            Trend::Steady
+        })
+    }
+}
+
+impl FromIterator<(NodeNum, i64, u32)> for BatteryHistory {
+    /// A history from stored levels in chronological order.
+    fn from_iter<I: IntoIterator<Item = (NodeNum, i64, u32)>>(levels: I) -> Self {
+        let mut history = Self::default();
+        for (node, time, level) in levels {
+            history.record(node, time, level);
+        }
+        history
+    }
+}
+
+/// A bar of `GAUGE_CELLS` cells filled in proportion to a level from 0 to 100.
+pub fn gauge(level: u32) -> String {
+    let eighths = level.min(MAX_LEVEL) * GAUGE_CELLS * 8 / MAX_LEVEL;
+    (0..GAUGE_CELLS)
+        .map(|cell| match eighths.saturating_sub(cell * 8) {
+            0 => ' ',
+            filled if filled >= 8 => '█',
+            filled => EIGHTHS[filled as usize],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODE: NodeNum = 0xa1b2_c3d4;
+    const HOUR: i64 = 60 * 60;
+
+    fn history(levels: &[(i64, u32)]) -> BatteryHistory {
+        levels
+            .iter()
+            .map(|&(time, level)| (NODE, time, level))
+            .collect()
+    }
+
+    #[test]
+    fn trend_follows_the_slope_of_recent_levels() {
+        let charging = history(&[(0, 40), (HOUR, 43), (2 * HOUR, 41), (3 * HOUR, 48)]);
+        assert_eq!(charging.trend(NODE), Some(Trend::Charging));
+        let discharging = history(&[(0, 80), (HOUR, 78), (2 * HOUR, 75)]);
+        assert_eq!(discharging.trend(NODE), Some(Trend::Discharging));
+        let steady = history(&[(0, 60), (HOUR, 61), (2 * HOUR, 60)]);
+        assert_eq!(steady.trend(NODE), Some(Trend::Steady));
+    }
+
+    #[test]
+    fn no_trend_without_enough_history() {
+        assert_eq!(history(&[]).trend(NODE), None);
+        assert_eq!(history(&[(0, 50)]).trend(NODE), None);
+        assert_eq!(history(&[(0, 50), (MIN_SPAN - 1, 90)]).trend(NODE), None);
+        // External power starts over.
+        assert_eq!(history(&[(0, 50), (HOUR, 101)]).trend(NODE), None);
+    }
+
+    #[test]
+    fn levels_older_than_the_window_are_forgotten() {
+        let history = history(&[(0, 10), (HOUR, 90), (HOUR + TREND_WINDOW, 90)]);
+        assert_eq!(history.trend(NODE), Some(Trend::Steady));
+    }
+
+    #[test]
+    fn gauge_fills_in_eighths() {
+        assert_eq!(gauge(0), "    ");
+        assert_eq!(gauge(50), "██  ");
+        assert_eq!(gauge(60), "██▍ ");
+        assert_eq!(gauge(100), "████");
+        assert_eq!(gauge(101), "████");
+    }
+}
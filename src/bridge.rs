@@ -0,0 +1,183 @@
+//! Bridge mode: stay connected to two radios on different meshes and relay chosen channels
+//! between them, making edda a cross-mesh gateway.
+//!
+//! Only channel broadcasts are relayed, prefixed with the sender's short name. A message never
+//! goes back the way it came: texts relayed recently are remembered and an arriving copy of one
+//! is dropped, which also stops two bridges between the same meshes from ping-ponging. Each
+//! direction is rate limited, so a busy mesh cannot flood the other.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::Result;
+use tokio::sync::mpsc;
+
+use crate::config::{BridgeConfig, Config};
+use crate::mesh;
+use crate::types::{BROADCAST, MeshEvent, NodeNum, PACKET_BYTE_LIMIT, UiEvent, format_node_num};
+
+/// How long a relayed text is remembered to recognise it coming back.
+const ECHO_WINDOW: Duration = Duration::from_secs(10 * 60);
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    A,
+    B,
+}
+
+impl Side {
+    fn index(self) -> usize {
+        match self {
+            Side::A => 0,
+            Side::B => 1,
+        }
+    }
+}
+
+pub struct Bridge {
+    config: BridgeConfig,
+    /// Short names of the nodes on each side.
+    names: [HashMap<NodeNum, String>; 2],
+    /// Texts we relayed, oldest first.
+    relayed: VecDeque<(Instant, String)>,
+    /// When we relayed to each side within the rate window.
+    sent: [VecDeque<Instant>; 2],
+}
+
+impl Bridge {
+    pub fn new(config: BridgeConfig) -> Self {
+        Self {
+            config,
+            names: Default::default(),
+            relayed: VecDeque::new(),
+            sent: Default::default(),
+        }
+    }
+
+    /// Handle an event from one radio, returning the channel and text to send on the other.
+    pub fn handle(&mut self, from: Side, event: MeshEvent) -> Option<(u32, String)> {
+        match event {
+            MeshEvent::MyNode(node) | MeshEvent::NodeAvailable(node) => {
+                if let Some(user) = node.user {
+                    self.names[from.index()].insert(node.num, user.short_name);
+                }
+                None
+            }
+            MeshEvent::Message {
+                node_id,
+                to,
+                channel,
+                message,
+                ..
+            } if to.id() == BROADCAST => self.relay(from, node_id.id(), channel, message),
+            _ => None,
+        }
+    }
+
+    fn relay(
+        &mut self,
+        from: Side,
+        node: NodeNum,
+        channel: u32,
+        text: String,
+    ) -> Option<(u32, String)> {
+        let now = Instant::now();
+        while self
+            .relayed
+            .front()
+            .is_some_and(|(time, _)| now - *time > ECHO_WINDOW)
+        {
+            self.relayed.pop_front();
+        }
+        if self.relayed.iter().any(|(_, relayed)| *relayed == text) {
+            log::debug!("Not relaying a message that was relayed before: {}", text);
+            return None;
+        }
+        let target = self.config.channels.iter().find_map(|bridged| match from {
+            Side::A if bridged.a == channel => Some(bridged.b),
+            Side::B if bridged.b == channel => Some(bridged.a),
+            _ => None,
+        })?;
+
+        let to = match from {
+            Side::A => Side::B,
+            Side::B => Side::A,
+        };
+        let sent = &mut self.sent[to.index()];
+        while sent.front().is_some_and(|time| now - *time > RATE_WINDOW) {
+            sent.pop_front();
+        }
+        if sent.len() >= self.config.max_per_minute as usize {
+            log::warn!(
+                "Bridge rate limit reached, dropping message on channel {}",
+                channel
+            );
+            return None;
+        }
+        sent.push_back(now);
+
+        let name = self.names[from.index()]
+            .get(&node)
+            .cloned()
+            .unwrap_or_else(|| format_node_num(node));
+        let mut relayed = format!("[{name}] {text}");
+        while relayed.len() > PACKET_BYTE_LIMIT {
+            relayed.pop();
+        }
+        self.relayed.push_back((now, relayed.clone()));
+        Some((target, relayed))
+    }
+}
+
+/// Connect to both radios and relay between them, each connection being made again whenever it
+/// fails.
+pub fn run(config: Config, port_a: String, port_b: String) -> Result<()> {
+    if config.bridge.channels.is_empty() {
+        log::warn!("No channels configured in [bridge], nothing will be relayed");
+    }
+    let (transmitter_a, mut receiver_a) =
+        mesh::spawn(config.clone(), mesh::Transport::parse(&port_a));
+    let (transmitter_b, mut receiver_b) =
+        mesh::spawn(config.clone(), mesh::Transport::parse(&port_b));
+    let mut bridge = Bridge::new(config.bridge);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async move {
+        log::info!("Bridge running");
+        loop {
+            let (relay, transmitter) = tokio::select! {
+                event = receiver_a.recv() => match event {
+                    Some(event) => (bridge.handle(Side::A, event), &transmitter_b),
+                    None => break,
+                },
+                event = receiver_b.recv() => match event {
+                    Some(event) => (bridge.handle(Side::B, event), &transmitter_a),
+                    None => break,
+                },
+            };
+            if let Some((channel, text)) = relay {
+                send(transmitter, channel, text).await;
+            }
+        }
+        log::warn!("A Meshtastic thread stopped, exiting bridge");
+        Ok(())
+    })
+}
+
+async fn send(transmitter: &mpsc::Sender<UiEvent>, channel: u32, text: String) {
+    log::info!("Relaying to channel {}: {}", channel, text);
+    let event = UiEvent::Message {
+        node_id: BROADCAST.into(),
+        message: text,
+        channel,
+        reply_id: None,
+        emoji: false,
+        outbox: None,
+    };
+    if let Err(e) = transmitter.send(event).await {
+        log::error!("Failed to relay message: {}", e);
+    }
+}
@@ -0,0 +1,272 @@
+//! One config change applied to several administered nodes, one node after the other, with the
+//! outcome for each.
+//!
+//! Each node first gets a request for the setting about to change, whose answer carries the
+//! session passkey admin changes need. The change is then sent with that passkey, and counts as
+//! applied once the node acknowledges it.
+
+use std::time::{Duration, Instant};
+
+use meshtastic::protobufs::{Channel, Config, admin_message, config, config::PositionConfig};
+
+use crate::types::{NodeNum, UiEvent};
+
+/// Give up on a node that has not answered a step in this long.
+const STEP_TIMEOUT: Duration = Duration::from_secs(90);
+
+#[derive(Debug, Clone)]
+pub enum Change {
+    /// Copy one of our own device's channels, e.g. after giving it a new key.
+    Channel(Channel),
+    /// How often nodes broadcast their position, in seconds.
+    PositionInterval(u32),
+}
+
+impl Change {
+    pub fn label(&self) -> String {
+        match self {
+            Change::Channel(channel) => {
+                let name = channel
+                    .settings
+                    .as_ref()
+                    .map(|settings| settings.name.as_str())
+                    .unwrap_or_default();
+                format!("channel {} {name} from this device", channel.index)
+            }
+            Change::PositionInterval(seconds) => {
+                format!("position broadcast every {seconds} s")
+            }
+        }
+    }
+
+    /// The request for the current setting, whose answer opens the session.
+    fn read_request(&self) -> admin_message::PayloadVariant {
+        match self {
+            // Channel requests count from 1.
+            Change::Channel(channel) => {
+                admin_message::PayloadVariant::GetChannelRequest(channel.index as u32 + 1)
+            }
+            Change::PositionInterval(_) => admin_message::PayloadVariant::GetConfigRequest(
+                admin_message::ConfigType::PositionConfig as i32,
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeState {
+    Pending,
+    /// Asked for the current setting; has the passkey and position config as they arrive.
+    Reading {
+        since: Instant,
+        passkey: Option<Vec<u8>>,
+        position: Option<PositionConfig>,
+    },
+    /// Sent the change, waiting for the node to acknowledge it.
+    Applying {
+        since: Instant,
+    },
+    Done,
+    Failed(&'static str),
+}
+
+impl NodeState {
+    pub fn label(&self) -> &'static str {
+        match self {
+            NodeState::Pending => "waiting its turn",
+            NodeState::Reading { .. } => "opening a session",
+            NodeState::Applying { .. } => "applying",
+            NodeState::Done => "done",
+            NodeState::Failed(reason) => reason,
+        }
+    }
+}
+
+pub struct BulkAdmin {
+    pub change: Change,
+    pub nodes: Vec<(NodeNum, NodeState)>,
+}
+
+impl BulkAdmin {
+    pub fn new(change: Change, nodes: impl IntoIterator<Item = NodeNum>) -> Self {
+        Self {
+            change,
+            nodes: nodes
+                .into_iter()
+                .map(|node| (node, NodeState::Pending))
+                .collect(),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.nodes
+            .iter()
+            .all(|(_, state)| matches!(state, NodeState::Done | NodeState::Failed(_)))
+    }
+
+    /// The request to send next, starting the next pending node if none is in progress.
+    pub fn start_next(&mut self) -> Option<UiEvent> {
+        if self.nodes.iter().any(|(_, state)| {
+            matches!(
+                state,
+                NodeState::Reading { .. } | NodeState::Applying { .. }
+            )
+        }) {
+            return None;
+        }
+        let (node, state) = self
+            .nodes
+            .iter_mut()
+            .find(|(_, state)| *state == NodeState::Pending)?;
+        *state = NodeState::Reading {
+            since: Instant::now(),
+            passkey: None,
+            position: None,
+        };
+        Some(UiEvent::RemoteAdmin {
+            node: *node,
+            payload: Box::new(self.change.read_request()),
+            passkey: Vec::new(),
+        })
+    }
+
+    /// A node opened an admin session; returns the change to send if it can go now.
+    pub fn session(&mut self, from: NodeNum, key: Vec<u8>) -> Option<UiEvent> {
+        if let NodeState::Reading { passkey, .. } = self.state_mut(from)? {
+            *passkey = Some(key);
+        }
+        self.apply(from)
+    }
+
+    /// A node answered with its config; returns the change to send if it can go now.
+    pub fn config(&mut self, from: NodeNum, answer: Config) -> Option<UiEvent> {
+        if let NodeState::Reading { position, .. } = self.state_mut(from)?
+            && let Some(config::PayloadVariant::Position(current)) = answer.payload_variant
+        {
+            *position = Some(current);
+        }
+        self.apply(from)
+    }
+
+    /// The outcome of a packet sent to a node; returns the next request to send, if any.
+    pub fn delivery(&mut self, to: NodeNum, delivered: bool) -> Option<UiEvent> {
+        let state = self.state_mut(to)?;
+        match state {
+            NodeState::Reading { .. } if !delivered => *state = NodeState::Failed("unreachable"),
+            NodeState::Applying { .. } if delivered => *state = NodeState::Done,
+            NodeState::Applying { .. } => *state = NodeState::Failed("change not acknowledged"),
+            _ => return None,
+        }
+        self.start_next()
+    }
+
+    /// Give up on a node that stopped answering; returns the next request to send, if any.
+    pub fn check_timeout(&mut self) -> Option<UiEvent> {
+        let (_, state) = self.nodes.iter_mut().find(|(_, state)| match state {
+            NodeState::Reading { since, .. } | NodeState::Applying { since } => {
+                since.elapsed() > STEP_TIMEOUT
+            }
+            _ => false,
+        })?;
+        *state = match state {
+            NodeState::Reading { .. } => {
+                NodeState::Failed("no answer; is our node's key one of its admin keys?")
+            }
+            _ => NodeState::Failed("change not acknowledged"),
+        };
+        self.start_next()
+    }
+
+    fn state_mut(&mut self, node: NodeNum) -> Option<&mut NodeState> {
+        self.nodes
+            .iter_mut()
+            .find(|(num, _)| *num == node)
+            .map(|(_, state)| state)
+    }
+
+    /// Send the change once the session, and for a position change the current config, are in.
+    fn apply(&mut self, node: NodeNum) -> Option<UiEvent> {
+        let change = self.change.clone();
+        let state = self.state_mut(node)?;
+        let NodeState::Reading {
+            passkey: Some(passkey),
+            position,
+            ..
+        } = state
+        else {
+            return None;
+        };
+        let payload = match change {
+            Change::Channel(channel) => admin_message::PayloadVariant::SetChannel(channel),
+            Change::PositionInterval(seconds) => {
+                // Keep the node's other position settings as they are.
+                let current = (*position)?;
+                admin_message::PayloadVariant::SetConfig(Config {
+                    payload_variant: Some(config::PayloadVariant::Position(PositionConfig {
+                        position_broadcast_secs: seconds,
+                        ..current
+                    })),
+                })
+            }
+        };
+        let passkey = passkey.clone();
+        *state = NodeState::Applying {
+            since: Instant::now(),
+        };
+        Some(UiEvent::RemoteAdmin {
+            node,
+            payload: Box::new(payload),
+            passkey,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nodes_are_changed_one_after_the_other() {
+        let mut bulk = BulkAdmin::new(Change::PositionInterval(900), [1, 2]);
+        assert!(matches!(
+            bulk.start_next(),
+            Some(UiEvent::RemoteAdmin { node: 1, .. })
+        ));
+        // Only one node at a time.
+        assert!(bulk.start_next().is_none());
+
+        assert!(bulk.session(1, vec![7]).is_none());
+        let position = Config {
+            payload_variant: Some(config::PayloadVariant::Position(PositionConfig {
+                gps_update_interval: 120,
+                ..PositionConfig::default()
+            })),
+        };
+        let Some(UiEvent::RemoteAdmin {
+            node: 1,
+            payload,
+            passkey,
+        }) = bulk.config(1, position)
+        else {
+            panic!("expected the change to be sent");
+        };
+        assert_eq!(passkey, vec![7]);
+        let admin_message::PayloadVariant::SetConfig(Config {
+            payload_variant: Some(config::PayloadVariant::Position(sent)),
+        }) = *payload
+        else {
+            panic!("expected a position config");
+        };
+        assert_eq!(sent.position_broadcast_secs, 900);
+        assert_eq!(sent.gps_update_interval, 120);
+
+        assert!(matches!(
+            bulk.delivery(1, true),
+            Some(UiEvent::RemoteAdmin { node: 2, .. })
+        ));
+        assert!(bulk.delivery(2, false).is_none());
+        assert!(bulk.is_finished());
+        assert_eq!(bulk.nodes[0].1, NodeState::Done);
+        assert_eq!(bulk.nodes[1].1, NodeState::Failed("unreachable"));
+    }
+}
@@ -0,0 +1,45 @@
+//! Holding back broadcasts while the channel is busy, going by the channel utilization our own
+//! node and its direct neighbors report, so routine chatter doesn't crowd out traffic that
+//! matters. Direct messages and urgent forms are never held.
+
+use std::time::{Duration, Instant};
+
+/// A reading older than this says nothing about the channel now.
+const READING_MAX_AGE: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Default)]
+pub struct BusyChannel {
+    /// Channel utilization, in percent, above which broadcasts are held. Off when unset.
+    pub threshold: Option<f32>,
+    /// The latest utilization reported, with when it arrived.
+    reading: Option<(Instant, f32)>,
+}
+
+impl BusyChannel {
+    pub fn new(threshold: Option<f32>) -> Self {
+        Self {
+            threshold,
+            reading: None,
+        }
+    }
+
+    pub fn report(&mut self, utilization: f32) {
+        self.reading = Some((Instant::now(), utilization));
+    }
+
+    /// Whether broadcasts should wait: deferral is on and the latest recent reading is above
+    /// the threshold.
+    pub fn is_busy(&self) -> bool {
+        match (self.threshold, self.reading) {
+            (Some(threshold), Some((at, utilization))) => {
+                at.elapsed() < READING_MAX_AGE && utilization > threshold
+            }
+            _ => false,
+        }
+    }
+
+    /// The latest utilization reported, in percent.
+    pub fn utilization(&self) -> Option<f32> {
+        self.reading.map(|(_, utilization)| utilization)
+    }
+}
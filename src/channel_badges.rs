@@ -0,0 +1,58 @@
+//! Configured labels and colours for channel indices, marking which channel a message or node
+//! was heard on.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use ratatui::prelude::*;
+
+use crate::config::ChannelStyle;
+
+struct ChannelBadge {
+    label: String,
+    color: Color,
+}
+
+/// Badges of the channels that have a style configured; other channels go unmarked.
+#[derive(Default)]
+pub struct ChannelBadges(HashMap<u32, ChannelBadge>);
+
+impl ChannelBadges {
+    pub fn new(styles: &[ChannelStyle]) -> Self {
+        let badges = styles
+            .iter()
+            .map(|style| {
+                let color = match style.color.as_deref().map(Color::from_str) {
+                    Some(Ok(color)) => color,
+                    Some(Err(_)) => {
+                        log::error!(
+                            "Failed to parse colour {:?} of channel {}",
+                            style.color.as_deref().unwrap_or_default(),
+                            style.index
+                        );
+                        Color::Reset
+                    }
+                    None => Color::Reset,
+                };
+                let badge = ChannelBadge {
+                    label: style
+                        .label
+                        .clone()
+                        .unwrap_or_else(|| format!("ch{}", style.index)),
+                    color,
+                };
+                (style.index, badge)
+            })
+            .collect();
+        Self(badges)
+    }
+
+    /// A `[label] ` span in the channel's colour, if it has a style.
+    pub fn span(&self, channel: u32) -> Option<Span<'static>> {
+        let badge = self.0.get(&channel)?;
+        Some(Span::styled(
+            format!("[{}] ", badge.label),
+            Style::default().fg(badge.color),
+        ))
+    }
+}
@@ -0,0 +1,88 @@
+//! Channel key hygiene: generating fresh PSKs and sharing the resulting channel URL.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Local;
+use meshtastic::Message as _;
+use meshtastic::protobufs::{Channel, ChannelSet, channel::Role, config::LoRaConfig};
+use qrcode::{QrCode, render::unicode::Dense1x2};
+use rand::RngCore;
+
+/// AES-256, the strongest key the firmware accepts.
+const PSK_BYTES: usize = 32;
+const URL_PREFIX: &str = "https://meshtastic.org/e/#";
+
+pub fn generate_psk() -> Vec<u8> {
+    let mut psk = vec![0; PSK_BYTES];
+    rand::rng().fill_bytes(&mut psk);
+    psk
+}
+
+/// A copy of the channel with a freshly generated PSK.
+pub fn with_new_psk(channel: &Channel) -> Channel {
+    let mut channel = channel.clone();
+    channel.settings.get_or_insert_default().psk = generate_psk();
+    channel
+}
+
+/// Whether the channel is encrypted with a key of its own rather than the well-known default.
+pub fn has_private_psk(channel: &Channel) -> bool {
+    channel.settings.as_ref().is_some_and(|s| s.psk.len() > 1)
+}
+
+pub fn is_enabled(channel: &Channel) -> bool {
+    Role::try_from(channel.role).is_ok_and(|r| r != Role::Disabled)
+}
+
+pub fn channel_name(channel: &Channel) -> String {
+    match channel.settings.as_ref().map(|s| s.name.as_str()) {
+        Some("") | None if channel.role == Role::Primary as i32 => "Primary".to_string(),
+        Some("") | None => format!("Channel {}", channel.index),
+        Some(name) => name.to_string(),
+    }
+}
+
+/// The URL other clients import channels from, covering every enabled channel.
+pub fn channel_url<'a>(
+    channels: impl IntoIterator<Item = &'a Channel>,
+    lora_config: Option<&LoRaConfig>,
+) -> String {
+    let set = ChannelSet {
+        settings: channels
+            .into_iter()
+            .filter(|c| is_enabled(c))
+            .filter_map(|c| c.settings.clone())
+            .collect(),
+        lora_config: lora_config.cloned(),
+    };
+    format!(
+        "{URL_PREFIX}{}",
+        URL_SAFE_NO_PAD.encode(set.encode_to_vec())
+    )
+}
+
+/// Write the channel URL and a scannable QR code of it to a timestamped text file in `dir`. With
+/// `ascii` the code is drawn with `#` instead of Unicode half blocks.
+pub fn export_url(url: &str, ascii: bool, dir: &Path) -> io::Result<PathBuf> {
+    let code = QrCode::new(url).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let qr = if ascii {
+        code.render::<char>()
+            .dark_color('#')
+            .light_color(' ')
+            .module_dimensions(2, 1)
+            .quiet_zone(true)
+            .build()
+    } else {
+        code.render::<Dense1x2>().quiet_zone(true).build()
+    };
+    let path = dir.join(format!(
+        "channels_{}.txt",
+        Local::now().format("%Y%m%d_%H%M%S")
+    ));
+    fs::write(&path, format!("{url}\n\n{qr}\n"))?;
+    Ok(path)
+}
@@ -0,0 +1,326 @@
+//! The `:` command line at the bottom of the screen, for what does not deserve a key of its own,
+//! like in vim. Up and Down go through the commands entered before, Tab completes command names
+//! and their arguments, and a command that fails leaves its error on the line.
+//!
+//! - `:connect <port>` drops the connection and connects to another device;
+//! - `:export json|map|survey|traffic` writes what the `j`, `g`, `h` and `t` keys do;
+//! - `:filter <text>` filters the node list, as typing in its search box does;
+//! - `:radio <name>` makes another of the configured radios the active one;
+//! - `:replay <from> [<to>]` plays back the messages and positions stored in a time window,
+//!   from and to times today (`09:00`) or on some day (`2026-10-17T09:00`), to now if no end is
+//!   given; `:replay 6h` plays back the last six hours;
+//! - `:theme color|plain|ascii` switches how the screen is drawn.
+
+use chrono::{DateTime, Duration, Local, NaiveDateTime, NaiveTime};
+use color_eyre::eyre::{Result, bail, eyre};
+
+use crate::mesh::{self, Transport};
+use crate::smart_views;
+
+/// Command names, with the arguments completed for them.
+const COMMANDS: [(&str, &[&str]); 6] = [
+    ("connect", &[]),
+    ("export", &["json", "map", "survey", "traffic"]),
+    ("filter", &[]),
+    ("radio", &[]),
+    ("replay", &[]),
+    ("theme", &["ascii", "color", "plain"]),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Connect(Transport),
+    Export(Export),
+    Filter(String),
+    Radio(String),
+    Replay {
+        from: DateTime<Local>,
+        to: DateTime<Local>,
+    },
+    Theme(Theme),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Export {
+    Json,
+    Map,
+    Survey,
+    Traffic,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Theme {
+    Color,
+    Plain,
+    Ascii,
+}
+
+#[derive(Default)]
+pub struct CommandLine {
+    pub text: String,
+    /// Commands entered, oldest first.
+    history: Vec<String>,
+    /// Position in the history while going through it.
+    browsing: Option<usize>,
+    /// Why the last command failed, shown until the line changes.
+    pub error: Option<String>,
+    /// Names of the configured radios, if there are several.
+    pub radios: Vec<String>,
+}
+
+impl CommandLine {
+    /// Start a new, empty line.
+    pub fn open(&mut self) {
+        self.text.clear();
+        self.browsing = None;
+        self.error = None;
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.text.push(c);
+        self.error = None;
+    }
+
+    pub fn pop(&mut self) {
+        self.text.pop();
+        self.error = None;
+    }
+
+    /// Go to the command entered before the one shown.
+    pub fn previous(&mut self) {
+        let index = match self.browsing {
+            Some(0) => return,
+            Some(index) => index - 1,
+            None if self.history.is_empty() => return,
+            None => self.history.len() - 1,
+        };
+        self.browsing = Some(index);
+        self.text = self.history[index].clone();
+        self.error = None;
+    }
+
+    /// Go to the command entered after the one shown, or back to an empty line.
+    pub fn next(&mut self) {
+        let Some(index) = self.browsing else {
+            return;
+        };
+        if index + 1 < self.history.len() {
+            self.browsing = Some(index + 1);
+            self.text = self.history[index + 1].clone();
+        } else {
+            self.browsing = None;
+            self.text.clear();
+        }
+        self.error = None;
+    }
+
+    /// Complete the command name or argument being typed, as far as it is unambiguous.
+    pub fn complete(&mut self) {
+        let (done, partial) = match self.text.rsplit_once(' ') {
+            Some((done, partial)) => (Some(done), partial),
+            None => (None, self.text.as_str()),
+        };
+        let options: Vec<String> = match done {
+            None => COMMANDS.iter().map(|(name, _)| name.to_string()).collect(),
+            Some("connect") => mesh::available_ports()
+                .into_iter()
+                .map(|port| port.name)
+                .collect(),
+            Some("radio") => self.radios.clone(),
+            Some(name) => COMMANDS
+                .iter()
+                .find(|(command, _)| *command == name)
+                .map(|(_, arguments)| arguments.iter().map(|a| a.to_string()).collect())
+                .unwrap_or_default(),
+        };
+        let matching: Vec<&String> = options
+            .iter()
+            .filter(|option| option.starts_with(partial))
+            .collect();
+        let completed = match matching[..] {
+            [] => return,
+            [only] => format!("{only} "),
+            _ => common_prefix(&matching),
+        };
+        self.text = match done {
+            Some(done) => format!("{done} {completed}"),
+            None => completed,
+        };
+        self.error = None;
+    }
+
+    /// The command on the line, which goes into the history whether it parses or not.
+    pub fn submit(&mut self) -> Result<Command> {
+        let line = self.text.trim().to_string();
+        self.browsing = None;
+        if !line.is_empty() && self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        match parse(&line)? {
+            Command::Radio(name) if !self.radios.contains(&name) => match &self.radios[..] {
+                [] => bail!("There is only one radio"),
+                radios => bail!("The radios are {}", radios.join(", ")),
+            },
+            command => Ok(command),
+        }
+    }
+}
+
+fn common_prefix(options: &[&String]) -> String {
+    let first = options[0];
+    let length = options[1..].iter().fold(first.len(), |length, option| {
+        first
+            .chars()
+            .zip(option.chars())
+            .take_while(|(a, b)| a == b)
+            .count()
+            .min(length)
+    });
+    first.chars().take(length).collect()
+}
+
+pub fn parse(line: &str) -> Result<Command> {
+    let (name, argument) = line
+        .split_once(' ')
+        .map_or((line, ""), |(name, argument)| (name, argument.trim()));
+    Ok(match (name, argument) {
+        ("", _) => bail!("Type a command: connect, export, filter, radio, replay or theme"),
+        ("connect", "") => bail!("Connect to which port?"),
+        ("connect", port) => Command::Connect(Transport::parse(port)),
+        ("export", "json") => Command::Export(Export::Json),
+        ("export", "map") => Command::Export(Export::Map),
+        ("export", "survey") => Command::Export(Export::Survey),
+        ("export", "traffic") => Command::Export(Export::Traffic),
+        ("export", _) => bail!("Export json, map, survey or traffic"),
+        ("filter", text) => Command::Filter(text.to_string()),
+        ("radio", "") => bail!("Switch to which radio?"),
+        ("radio", name) => Command::Radio(name.to_string()),
+        ("replay", "") => bail!("Replay from when? A time, or a duration such as 6h"),
+        ("replay", window) => replay(window, Local::now())?,
+        ("theme", "color") => Command::Theme(Theme::Color),
+        ("theme", "plain") => Command::Theme(Theme::Plain),
+        ("theme", "ascii") => Command::Theme(Theme::Ascii),
+        ("theme", _) => bail!("The themes are color, plain and ascii"),
+        (other, _) => bail!("Unknown command {other}"),
+    })
+}
+
+/// A replay of the window given as `<from> [<to>]` or as a duration up to `now`.
+fn replay(window: &str, now: DateTime<Local>) -> Result<Command> {
+    let (from, to) = match window.split_once(' ') {
+        Some((from, to)) => (moment(from, now)?, moment(to.trim(), now)?),
+        None => match moment(window, now) {
+            Ok(from) => (from, now),
+            Err(e) => match smart_views::seconds(&[window]) {
+                Ok(seconds) => (now - Duration::seconds(seconds), now),
+                Err(_) => return Err(e),
+            },
+        },
+    };
+    if from >= to {
+        bail!("The replay must end after it starts");
+    }
+    Ok(Command::Replay { from, to })
+}
+
+/// A time today such as `09:00`, or on a day such as `2026-10-17T09:00`.
+fn moment(text: &str, now: DateTime<Local>) -> Result<DateTime<Local>> {
+    let naive = match NaiveTime::parse_from_str(text, "%H:%M") {
+        Ok(time) => now.date_naive().and_time(time),
+        Err(_) => NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M")
+            .map_err(|_| eyre!("{text:?} is not a time such as 09:00 or 2026-10-17T09:00"))?,
+    };
+    naive
+        .and_local_timezone(Local)
+        .earliest()
+        .ok_or_else(|| eyre!("{text} does not exist here"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(text: &str) -> CommandLine {
+        let mut line = CommandLine::default();
+        text.chars().for_each(|c| line.push(c));
+        line
+    }
+
+    #[test]
+    fn commands_are_parsed() {
+        assert_eq!(
+            parse("connect tcp://radio.local").unwrap(),
+            Command::Connect(Transport::Tcp("radio.local:4403".to_string()))
+        );
+        assert_eq!(parse("export  map").unwrap(), Command::Export(Export::Map));
+        assert_eq!(
+            parse("filter base camp").unwrap(),
+            Command::Filter("base camp".to_string())
+        );
+        assert_eq!(parse("filter").unwrap(), Command::Filter(String::new()));
+        assert!(parse("export pdf").is_err());
+        assert!(parse("connect").is_err());
+        assert!(parse("launch").is_err());
+
+        let now = Local::now();
+        match replay("6h", now).unwrap() {
+            Command::Replay { from, to } => assert_eq!(to - from, Duration::hours(6)),
+            command => panic!("{command:?}"),
+        }
+        let day = "2026-10-17T";
+        match replay(&format!("{day}09:00 {day}12:30"), now).unwrap() {
+            Command::Replay { from, to } => assert_eq!(to - from, Duration::minutes(210)),
+            command => panic!("{command:?}"),
+        }
+        assert!(replay(&format!("{day}12:30 {day}09:00"), now).is_err());
+        assert!(replay("yesterday", now).is_err());
+
+        // Only configured radios can be switched to.
+        let mut line = typed("radio garage");
+        assert!(line.submit().is_err());
+        line.radios = vec!["base".to_string(), "garage".to_string()];
+        assert_eq!(line.submit().unwrap(), Command::Radio("garage".to_string()));
+    }
+
+    #[test]
+    fn names_and_arguments_complete() {
+        let mut line = typed("th");
+        line.complete();
+        assert_eq!(line.text, "theme ");
+        line.push('p');
+        line.complete();
+        assert_eq!(line.text, "theme plain ");
+
+        // Only as far as the options agree.
+        let mut line = typed("export ");
+        line.complete();
+        assert_eq!(line.text, "export ");
+        let mut line = typed("e");
+        line.complete();
+        assert_eq!(line.text, "export ");
+        let mut line = typed("x");
+        line.complete();
+        assert_eq!(line.text, "x");
+    }
+
+    #[test]
+    fn history_goes_both_ways() {
+        let mut line = CommandLine::default();
+        for command in ["theme plain", "export map", "export map"] {
+            line.open();
+            command.chars().for_each(|c| line.push(c));
+            line.submit().unwrap();
+        }
+        line.open();
+        line.previous();
+        assert_eq!(line.text, "export map");
+        line.previous();
+        assert_eq!(line.text, "theme plain");
+        line.previous();
+        assert_eq!(line.text, "theme plain");
+        line.next();
+        assert_eq!(line.text, "export map");
+        line.next();
+        assert_eq!(line.text, "");
+    }
+}
@@ -0,0 +1,59 @@
+//! Deflate-compressed text for messages too long for one packet, sent on the private port so
+//! only other edda clients see them; everyone else would get binary noise on the text port.
+//!
+//! Structured traffic like ICS-213 forms repeats itself enough to shrink by a third or more.
+
+use miniz_oxide::{deflate, inflate};
+
+use crate::types::PACKET_BYTE_LIMIT;
+
+/// Marks a compressed text payload, so it is told apart from thumbnails on the same port.
+const MAGIC: &[u8] = b"EZ";
+
+/// Longest text accepted for compression. Most text this long will not fit even compressed,
+/// and decompressing is refused beyond it.
+pub const COMPRESSED_TEXT_LIMIT: usize = 1000;
+
+/// The payload carrying `text` compressed, if that makes it fit in a packet.
+pub fn compress(text: &str) -> Option<Vec<u8>> {
+    if text.len() > COMPRESSED_TEXT_LIMIT {
+        return None;
+    }
+    let mut payload = MAGIC.to_vec();
+    payload.extend(deflate::compress_to_vec(text.as_bytes(), 10));
+    (payload.len() <= PACKET_BYTE_LIMIT).then_some(payload)
+}
+
+/// The text carried by a compressed payload.
+pub fn decompress(payload: &[u8]) -> Option<String> {
+    let data = payload.strip_prefix(MAGIC)?;
+    let text = inflate::decompress_to_vec_with_limit(data, COMPRESSED_TEXT_LIMIT).ok()?;
+    String::from_utf8(text).ok()
+}
+
+/// Whether `text` can be sent: in one packet as it is, or compressed if that is allowed.
+pub fn fits(text: &str, compress_long: bool) -> bool {
+    text.len() <= PACKET_BYTE_LIMIT || (compress_long && compress(text).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_text_round_trips() {
+        let text = "Net check-in: all stations report status. ".repeat(12);
+        assert!(text.len() > PACKET_BYTE_LIMIT);
+        let payload = compress(&text).unwrap();
+        assert!(payload.len() <= PACKET_BYTE_LIMIT);
+        assert_eq!(decompress(&payload).as_deref(), Some(text.as_str()));
+        assert!(fits(&text, true));
+        assert!(!fits(&text, false));
+    }
+
+    #[test]
+    fn other_payloads_are_not_text() {
+        assert_eq!(decompress(b"ET\x00\x01"), None);
+        assert_eq!(decompress(b"EZ not deflate"), None);
+    }
+}
@@ -0,0 +1,592 @@
+//! User configuration, read from `config.toml` in the configuration directory (see `dirs`), or
+//! from an `edda.toml` there or in the working directory for setups predating it.
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::ErrorKind,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::NaiveTime;
+use color_eyre::eyre::{Result, WrapErr, bail};
+use serde::{Deserialize, Deserializer, de};
+
+use crate::dirs;
+
+const CONFIG_FILE: &str = "config.toml";
+/// What the configuration file was called before, in the configuration or working directory.
+const LEGACY_CONFIG_FILE: &str = "edda.toml";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Serial port of the device, or `tcp://host[:port]` or a unix socket for `meshtasticd`,
+    /// unless one is given on the command line.
+    pub port: Option<String>,
+    /// Radios to connect to at once, e.g. a base station and a portable one, used instead of
+    /// `port` unless a port is given on the command line. The first is the active one at start.
+    pub radios: Vec<RadioConfig>,
+    /// SQLite database holding nodes seen so far. This and the other paths below are relative to
+    /// the data directory.
+    pub database: PathBuf,
+    /// Directory for daily session logs. Session logging is disabled when unset.
+    pub session_log_dir: Option<PathBuf>,
+    /// Plain text file new messages and alerts are appended to as sentences, for following
+    /// with a screen reader. Disabled when unset.
+    pub announce_file: Option<PathBuf>,
+    /// GeoJSON file kept up to date with node positions while edda runs.
+    pub geojson_feed: Option<PathBuf>,
+    /// CSV file to record coverage survey samples to. Survey mode is off when unset.
+    pub survey_file: Option<PathBuf>,
+    /// Log of numbered formal messages, which keeps their numbering across restarts.
+    pub traffic_log: PathBuf,
+    /// Directory exports are written to, instead of the downloads folder.
+    pub export_dir: Option<PathBuf>,
+    pub retention: Retention,
+    /// HTTP API served in daemon mode. Disabled when unset.
+    pub api: Option<ApiConfig>,
+    /// Unix socket scripts and other frontends can drive the TUI through (see `control`).
+    /// Disabled when unset.
+    pub control_socket: Option<PathBuf>,
+    /// Phone push notification services to forward messages to.
+    pub push: Vec<PushTarget>,
+    /// Remind to rotate channel PSKs this many days after they were last changed.
+    pub psk_rotation_days: Option<u32>,
+    /// Hold broadcasts while the channel utilization we hear reported is above this percentage,
+    /// and send them once it drops. Nothing is held when unset.
+    pub defer_broadcasts_above: Option<f32>,
+    /// Gateway forwarding positions to APRS-IS. Disabled when unset.
+    pub aprs: Option<AprsConfig>,
+    /// Monitor default-key channels across a region through an MQTT server. Disabled when unset.
+    pub monitor: Option<MonitorConfig>,
+    /// Nightly exports of new messages and telemetry in daemon mode. Disabled when unset.
+    pub archive: Option<ArchiveConfig>,
+    /// Reply to direct messages in daemon mode with this text, at most once an hour per sender,
+    /// for unattended stations. `{time}` is replaced by when the message was logged.
+    pub auto_reply: Option<String>,
+    /// Compress messages too long for one packet, for other edda clients to decompress. Such
+    /// messages are unreadable to other Meshtastic apps.
+    pub compress_long_messages: bool,
+    pub watchdog: WatchdogConfig,
+    /// Channels relayed between two radios in bridge mode.
+    pub bridge: BridgeConfig,
+    pub ui: UiConfig,
+    pub experimental: ExperimentalConfig,
+    /// Saved profile to apply to the device on connecting.
+    pub profile: Option<String>,
+    /// The file this configuration was read from, watched for changes while edda runs.
+    #[serde(skip)]
+    pub file: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RadioConfig {
+    /// What to call the radio, e.g. `base`, to switch to it with `:radio base`.
+    pub name: String,
+    /// Its port, taking the same forms as `port`.
+    pub port: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiConfig {
+    pub listen: SocketAddr,
+    /// Clients must send `Authorization: Bearer <token>`.
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PushService {
+    Ntfy,
+    Gotify,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PushTarget {
+    pub service: PushService,
+    /// The topic URL for ntfy, or the server URL for Gotify.
+    pub url: String,
+    /// Access token for ntfy, or application token for Gotify.
+    pub token: Option<String>,
+    /// Only push messages containing one of these words. Empty pushes every message.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AprsConfig {
+    #[serde(default = "default_aprs_server")]
+    pub server: String,
+    /// Callsign the gateway logs in with.
+    pub callsign: String,
+    pub passcode: u16,
+    /// Nodes whose positions may be forwarded, keyed by node ID, with the callsign to report
+    /// them as. Nodes not listed here are never forwarded.
+    pub nodes: HashMap<String, String>,
+}
+
+fn default_aprs_server() -> String {
+    "rotate.aprs2.net:14580".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MonitorConfig {
+    /// MQTT server as `host:port`.
+    #[serde(default = "default_monitor_server")]
+    pub server: String,
+    #[serde(default = "default_monitor_username")]
+    pub username: String,
+    #[serde(default = "default_monitor_password")]
+    pub password: String,
+    /// Topics to subscribe to, e.g. `msh/US/2/e/LongFast/#` for one region's `LongFast`.
+    #[serde(default = "default_monitor_topics")]
+    pub topics: Vec<String>,
+}
+
+/// The public server the Meshtastic apps upload to, with its published credentials.
+fn default_monitor_server() -> String {
+    "mqtt.meshtastic.org:1883".to_string()
+}
+
+fn default_monitor_username() -> String {
+    "meshdev".to_string()
+}
+
+fn default_monitor_password() -> String {
+    "large4cats".to_string()
+}
+
+fn default_monitor_topics() -> Vec<String> {
+    vec!["msh/+/2/e/LongFast/#".to_string()]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArchiveConfig {
+    /// Local time of day to export at, e.g. `"02:00"`.
+    #[serde(default = "default_archive_time", deserialize_with = "time_of_day")]
+    pub at: NaiveTime,
+    #[serde(default)]
+    pub format: ArchiveFormat,
+    /// Directory to write the exports to.
+    pub directory: Option<PathBuf>,
+    /// Bucket to upload the exports to.
+    pub s3: Option<S3Config>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+impl ArchiveFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Json => "json",
+            ArchiveFormat::Csv => "csv",
+        }
+    }
+}
+
+/// An S3-compatible bucket, addressed by path (`<endpoint>/<bucket>/<key>`), which MinIO, Garage
+/// and the like expect.
+#[derive(Debug, Clone, Deserialize)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-central-1.amazonaws.com` or `http://minio.lan:9000`.
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    /// Prepended to the names of the exports, e.g. `edda/`.
+    #[serde(default)]
+    pub prefix: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+fn default_archive_time() -> NaiveTime {
+    NaiveTime::from_hms_opt(2, 0, 0).unwrap_or_default()
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn time_of_day<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let time = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&time, "%H:%M")
+        .map_err(|_| de::Error::custom(format!("invalid time of day {time}, expected HH:MM")))
+}
+
+/// When to alert about the connected device.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    /// Alert when nothing at all has come from the device for this long.
+    pub silence_minutes: u32,
+    /// Alert when the battery falls by this many percentage points within an hour.
+    pub battery_drop_percent: u32,
+    /// Warn when the device reports channel utilization above this percentage for
+    /// `channel_utilization_minutes` on end.
+    pub channel_utilization_percent: f32,
+    pub channel_utilization_minutes: u32,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            silence_minutes: 60,
+            battery_drop_percent: 20,
+            // The firmware itself starts holding back its own traffic at 25 and 40 percent.
+            channel_utilization_percent: 40.0,
+            channel_utilization_minutes: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UiConfig {
+    /// Cap on screen redraws, independent of how fast mesh events are handled. Lower it for
+    /// slow terminals or SSH links.
+    pub max_redraws_per_second: u32,
+    /// How often, in milliseconds, the screen is redrawn anyway to keep times current, and
+    /// timeouts are checked.
+    pub tick_rate_ms: u64,
+    /// Draw without colour or Unicode line art, and redraw rarely, for high-latency sessions.
+    pub plain: bool,
+    /// Use ASCII instead of Unicode line art, blocks and braille. Guessed from the locale and
+    /// `TERM` when unset.
+    pub ascii: Option<bool>,
+    /// Share of the screen width taken by the node list, in percent.
+    pub node_list_width: u16,
+    /// Columns of the node list, in order.
+    pub node_columns: Vec<NodeColumn>,
+    /// Labels and colours marking what arrived on each channel.
+    pub channels: Vec<ChannelStyle>,
+    /// React to direct messages once they have been read with their conversation open, as a read
+    /// receipt. Off unless set, as each receipt is a packet on air.
+    pub read_receipts: bool,
+    /// The reaction sent as a read receipt.
+    pub read_receipt_emoji: String,
+    /// Saved node filters offered above the node list.
+    pub smart_views: Vec<SmartViewConfig>,
+    /// Show conversations threaded, replies nested under the messages they answer, rather than
+    /// in the order they arrived. `t` in the conversation switches.
+    pub threaded: bool,
+    pub colors: ColorsConfig,
+    /// Keys for the actions taken with nothing focused, by action name (see `keymap`).
+    pub keys: HashMap<String, String>,
+}
+
+/// Colour names such as `magenta` or `light-blue`, or `#rrggbb`; the defaults when unset.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ColorsConfig {
+    /// Borders of the focused pane and of popups; yellow by default.
+    pub accent: Option<String>,
+    /// Background of the selected row in lists and tables; dark grey by default.
+    pub selection: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmartViewConfig {
+    pub name: String,
+    /// Conditions a node must all meet, e.g. `battery < 30% and heard in last 2 h`.
+    pub query: String,
+}
+
+/// Features still being tried out, which may change or go away.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ExperimentalConfig {
+    /// Send and receive tiny image thumbnails to and from other edda users (`i`).
+    pub thumbnails: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChannelStyle {
+    pub index: u32,
+    /// Short label shown in front of messages; `ch<index>` when unset.
+    pub label: Option<String>,
+    /// A colour name such as `magenta` or `light-blue`, or `#rrggbb`.
+    pub color: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeColumn {
+    /// Short name, with the unread count after the last column.
+    Name,
+    /// SNR the node was last heard at.
+    Snr,
+    Hops,
+    /// Time since the node was last heard.
+    LastHeard,
+    Battery,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        Self {
+            max_redraws_per_second: 20,
+            tick_rate_ms: 250,
+            plain: false,
+            ascii: None,
+            node_list_width: 30,
+            node_columns: vec![NodeColumn::Name],
+            channels: Vec::new(),
+            read_receipts: false,
+            read_receipt_emoji: "👀".to_string(),
+            smart_views: Vec::new(),
+            threaded: false,
+            colors: ColorsConfig::default(),
+            keys: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BridgeConfig {
+    /// Channels to relay, each as a pair of channel indices on the first and second radio.
+    /// Nothing is relayed when empty.
+    pub channels: Vec<BridgedChannel>,
+    /// Most messages relayed per minute in each direction; the rest are dropped.
+    pub max_per_minute: u32,
+}
+
+impl Default for BridgeConfig {
+    fn default() -> Self {
+        Self {
+            channels: Vec::new(),
+            max_per_minute: 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BridgedChannel {
+    pub a: u32,
+    pub b: u32,
+}
+
+/// How long to keep each kind of history. Anything unset, or set to 0, is kept forever; messages
+/// and telemetry are kept for 90 and 30 days unless configured otherwise.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Retention {
+    pub messages_days: Option<u32>,
+    /// Battery levels and other telemetry nodes reported.
+    pub telemetry_days: Option<u32>,
+    pub positions_days: Option<u32>,
+    /// Metadata of packets heard, used for activity statistics.
+    pub packets_days: Option<u32>,
+    /// Nodes not heard from for this long. Their messages and notes are kept.
+    pub nodes_days: Option<u32>,
+    /// Also remove nodes past `nodes_days` from the device's node database when connecting.
+    /// Favorites are never removed.
+    pub remove_from_device: bool,
+}
+
+impl Retention {
+    /// How long each kind of history is kept, in days, leaving out what is kept forever.
+    fn limits(&self) -> impl Iterator<Item = u32> {
+        [
+            self.messages_days,
+            self.telemetry_days,
+            self.positions_days,
+            self.packets_days,
+            self.nodes_days,
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|&days| days > 0)
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.limits().next().is_none()
+    }
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            messages_days: Some(90),
+            telemetry_days: Some(30),
+            positions_days: None,
+            packets_days: None,
+            nodes_days: None,
+            remove_from_device: false,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: None,
+            radios: Vec::new(),
+            database: PathBuf::from("edda.db"),
+            session_log_dir: None,
+            announce_file: None,
+            geojson_feed: None,
+            survey_file: None,
+            traffic_log: PathBuf::from("traffic.tsv"),
+            export_dir: None,
+            retention: Retention::default(),
+            api: None,
+            control_socket: None,
+            push: Vec::new(),
+            psk_rotation_days: None,
+            defer_broadcasts_above: None,
+            aprs: None,
+            monitor: None,
+            archive: None,
+            auto_reply: None,
+            compress_long_messages: false,
+            watchdog: WatchdogConfig::default(),
+            bridge: BridgeConfig::default(),
+            ui: UiConfig::default(),
+            experimental: ExperimentalConfig::default(),
+            profile: None,
+            file: PathBuf::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration file, falling back to defaults if it does not exist, with the
+    /// `EDDA_*` environment variables layered over it.
+    pub fn load() -> Result<Self> {
+        Self::load_from(&Self::path())
+    }
+
+    /// Like `load`, from a file given rather than the usual one.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let mut config: Self = match fs::read_to_string(path) {
+            Ok(contents) => {
+                log::info!("Using the configuration in {}", path.display());
+                toml::from_str(&contents)
+                    .wrap_err_with(|| format!("Failed to parse {}", path.display()))?
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                log::info!(
+                    "No configuration found at {}, using defaults",
+                    path.display()
+                );
+                Self::default()
+            }
+            Err(e) => {
+                return Err(e).wrap_err_with(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        // A configuration left in the working directory keeps its files there too.
+        if path == Path::new(LEGACY_CONFIG_FILE) {
+            config.resolve_paths(Path::new("."));
+        } else {
+            config.resolve_paths(&dirs::data_dir());
+        }
+        config.apply_env();
+        if config
+            .api
+            .as_ref()
+            .is_some_and(|api| api.token.trim().is_empty())
+        {
+            bail!("{}: the [api] token must not be empty", path.display());
+        }
+        config.file = path.to_path_buf();
+        Ok(config)
+    }
+
+    /// Where the configuration file is: `config.toml` in the configuration directory, unless
+    /// there is only an `edda.toml`, there or in the working directory.
+    pub fn path() -> PathBuf {
+        let dir = dirs::config_dir();
+        let path = dir.join(CONFIG_FILE);
+        if path.exists() {
+            return path;
+        }
+        let legacy = dir.join(LEGACY_CONFIG_FILE);
+        if legacy.exists() {
+            log::info!(
+                "Using {}, consider renaming it to {}",
+                legacy.display(),
+                path.display()
+            );
+            return legacy;
+        }
+        if Path::new(LEGACY_CONFIG_FILE).exists() {
+            log::info!(
+                "Using {LEGACY_CONFIG_FILE} from the working directory, consider moving it to {}",
+                path.display()
+            );
+            return PathBuf::from(LEGACY_CONFIG_FILE);
+        }
+        path
+    }
+
+    /// Make the relative paths in the configuration relative to `base`.
+    fn resolve_paths(&mut self, base: &Path) {
+        // Earlier versions kept these in the working directory; keep using them there rather
+        // than starting afresh.
+        for path in [&mut self.database, &mut self.traffic_log] {
+            let resolved = base.join(&*path);
+            if !resolved.exists() && path.is_relative() && path.exists() {
+                log::warn!(
+                    "Using {} from the working directory, as {} does not exist; move it there \
+                     to keep it wherever edda is started",
+                    path.display(),
+                    resolved.display()
+                );
+            } else {
+                *path = resolved;
+            }
+        }
+        for path in [
+            self.session_log_dir.as_mut(),
+            self.announce_file.as_mut(),
+            self.geojson_feed.as_mut(),
+            self.survey_file.as_mut(),
+            self.control_socket.as_mut(),
+            self.export_dir.as_mut(),
+            self.archive.as_mut().and_then(|a| a.directory.as_mut()),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = base.join(&*path);
+        }
+    }
+
+    /// Where exports go: the configured directory, or else the downloads folder.
+    pub fn export_dir(&self) -> PathBuf {
+        self.export_dir.clone().unwrap_or_else(dirs::export_dir)
+    }
+
+    /// Override settings from environment variables, for deployments where editing the file is
+    /// awkward, e.g. containers. `EDDA_LOG_LEVEL` is read when setting up logging.
+    fn apply_env(&mut self) {
+        if let Ok(port) = env::var("EDDA_PORT") {
+            self.port = Some(port);
+        }
+        if let Ok(database) = env::var("EDDA_DB") {
+            self.database = PathBuf::from(database);
+        }
+        if let Ok(profile) = env::var("EDDA_PROFILE") {
+            self.profile = Some(profile);
+        }
+    }
+
+    /// When the configuration file at `path` was last changed, if it exists.
+    pub fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+}
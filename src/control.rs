@@ -0,0 +1,425 @@
+//! A local control socket, so scripts and other frontends can drive a running edda through the
+//! same outbox, store and radio connection as the TUI. Enabled with `control_socket`.
+//!
+//! Each line sent is a JSON-RPC 2.0 request and gets a response line back:
+//! - `send_text` with `{"to": "!a1b2c3d4", "text": "..."}`: send a text message, returning the
+//!   `outbox` ID its `sent` and `delivery` events carry
+//! - `list_nodes`: known nodes, keyed by node ID
+//! - `get_conversation` with `{"node": "!a1b2c3d4"}`: the messages exchanged with a node
+//! - `subscribe`: from then on, an `event` notification for every node, position, message,
+//!   metrics report, connection change and delivery heard; nodes, positions and messages are
+//!   shaped as in the daemon's `/events`
+//!
+//! Nodes are given by ID or by number. The socket is only accessible to its owner.
+
+use std::{fs, io, os::unix::fs::PermissionsExt, path::PathBuf, thread};
+
+use meshtastic::protobufs::NodeInfo;
+use serde_json::{Map, Value, json};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{broadcast, mpsc, oneshot},
+};
+
+use crate::export;
+use crate::types::{
+    ConnectionStatus, MeshEvent, Message, NodeNum, PACKET_BYTE_LIMIT, format_node_num,
+    parse_node_num,
+};
+
+/// JSON-RPC error codes.
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// What a client asked of edda.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    SendText { to: NodeNum, text: String },
+    ListNodes,
+    GetConversation(NodeNum),
+}
+
+impl Call {
+    fn parse(method: &str, params: &Value) -> Result<Self, (i64, String)> {
+        match method {
+            "send_text" => {
+                let to = node_param(params, "to")?;
+                let text = params.get("text").and_then(Value::as_str).unwrap_or("");
+                if text.is_empty() || text.len() > PACKET_BYTE_LIMIT {
+                    return Err((
+                        INVALID_PARAMS,
+                        format!("Text must be 1 to {} bytes", PACKET_BYTE_LIMIT),
+                    ));
+                }
+                Ok(Call::SendText {
+                    to,
+                    text: text.to_string(),
+                })
+            }
+            "list_nodes" => Ok(Call::ListNodes),
+            "get_conversation" => Ok(Call::GetConversation(node_param(params, "node")?)),
+            _ => Err((METHOD_NOT_FOUND, format!("No method called {method}"))),
+        }
+    }
+}
+
+/// A node given by ID (`!a1b2c3d4`) or number.
+fn node_param(params: &Value, name: &str) -> Result<NodeNum, (i64, String)> {
+    let node = match params.get(name) {
+        Some(Value::String(node)) => parse_node_num(node),
+        Some(Value::Number(node)) => node.as_u64().and_then(|n| NodeNum::try_from(n).ok()),
+        _ => None,
+    };
+    node.ok_or_else(|| (INVALID_PARAMS, format!("Invalid {name}")))
+}
+
+/// A call waiting for the UI to answer it.
+pub struct Request {
+    pub call: Call,
+    reply: oneshot::Sender<Value>,
+}
+
+impl Request {
+    pub fn reply(self, result: Value) {
+        // The client may have gone away meanwhile.
+        let _ = self.reply.send(result);
+    }
+}
+
+/// Handle to the control socket thread.
+pub struct ControlSocket {
+    requests: mpsc::Receiver<Request>,
+    events: broadcast::Sender<Value>,
+}
+
+impl ControlSocket {
+    /// Listen at `path` on a background thread, replacing a socket left behind by an earlier
+    /// run. Failures are logged, not fatal.
+    pub fn spawn(path: PathBuf) -> Self {
+        let (requests_tx, requests) = mpsc::channel(100);
+        let (events, _) = broadcast::channel(100);
+        let subscribers = events.clone();
+        thread::spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    log::error!("Failed to start control socket runtime: {}", e);
+                    return;
+                }
+            };
+            if let Err(e) = runtime.block_on(serve(&path, requests_tx, subscribers)) {
+                log::error!("Control socket {} stopped: {}", path.display(), e);
+            }
+        });
+        Self { requests, events }
+    }
+
+    /// The next call waiting to be answered, if any.
+    pub fn try_recv(&mut self) -> Option<Request> {
+        self.requests.try_recv().ok()
+    }
+
+    /// Pass `event` on to subscribed clients.
+    pub fn event(&self, event: &MeshEvent) {
+        if let Some(event) = event_json(event) {
+            // Having no subscribers is not an error.
+            let _ = self.events.send(event);
+        }
+    }
+}
+
+/// Nodes keyed by node ID, in meshtastic-python's shape, for `list_nodes`.
+pub fn nodes_json<'a>(nodes: impl IntoIterator<Item = &'a NodeInfo>) -> Value {
+    let nodes: Map<String, Value> = nodes
+        .into_iter()
+        .map(|node| (format_node_num(node.num), export::python_node(node)))
+        .collect();
+    Value::Object(nodes)
+}
+
+/// Messages of a conversation for `get_conversation`.
+pub fn conversation_json(messages: &[Message]) -> Value {
+    messages
+        .iter()
+        .map(|message| {
+            json!({
+                "outgoing": message.outgoing,
+                "time": message.ts.to_rfc3339(),
+                "channel": message.channel,
+                "text": message.text,
+                "note": message.note,
+            })
+        })
+        .collect()
+}
+
+fn event_json(event: &MeshEvent) -> Option<Value> {
+    let event = match event {
+        MeshEvent::NodeAvailable(node) => {
+            json!({ "type": "node", "node": export::python_node(node) })
+        }
+        MeshEvent::Position { node_id, position } => json!({
+            "type": "position",
+            "from": format_node_num(node_id.id()),
+            "latitudeI": position.latitude_i,
+            "longitudeI": position.longitude_i,
+            "altitude": position.altitude,
+        }),
+        MeshEvent::Message {
+            node_id,
+            to,
+            channel,
+            message,
+            snr,
+            ..
+        } => json!({
+            "type": "message",
+            "from": format_node_num(node_id.id()),
+            "to": format_node_num(to.id()),
+            "channel": channel,
+            "text": message,
+            "rxSnr": snr,
+        }),
+        MeshEvent::DeviceMetrics { from, metrics } => json!({
+            "type": "metrics",
+            "from": format_node_num(*from),
+            "batteryLevel": metrics.battery_level,
+            "voltage": metrics.voltage,
+            "channelUtilization": metrics.channel_utilization,
+            "airUtilTx": metrics.air_util_tx,
+        }),
+        MeshEvent::ConnectionStatus(ConnectionStatus::Connected) => {
+            json!({ "type": "connection", "status": "connected" })
+        }
+        MeshEvent::ConnectionStatus(ConnectionStatus::Reconnecting { attempt, after }) => json!({
+            "type": "connection",
+            "status": "reconnecting",
+            "attempt": attempt,
+            "after": after.as_secs(),
+        }),
+        MeshEvent::Sent { outbox, packet_id } => json!({
+            "type": "sent",
+            "outbox": outbox,
+            "packetId": packet_id,
+        }),
+        MeshEvent::Delivery {
+            to,
+            delivered,
+            outbox,
+            ..
+        } => json!({
+            "type": "delivery",
+            "to": format_node_num(*to),
+            "delivered": delivered,
+            "outbox": outbox,
+        }),
+        _ => return None,
+    };
+    Some(event)
+}
+
+async fn serve(
+    path: &PathBuf,
+    requests: mpsc::Sender<Request>,
+    events: broadcast::Sender<Value>,
+) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    log::info!("Serving the control socket at {}", path.display());
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(client(stream, requests.clone(), events.clone()));
+    }
+}
+
+enum Incoming {
+    Line(io::Result<Option<String>>),
+    Event(Value),
+}
+
+async fn client(
+    stream: UnixStream,
+    requests: mpsc::Sender<Request>,
+    events: broadcast::Sender<Value>,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut subscription = None;
+    loop {
+        let incoming = tokio::select! {
+            line = lines.next_line() => Incoming::Line(line),
+            event = next_event(&mut subscription) => Incoming::Event(event),
+        };
+        let out = match incoming {
+            Incoming::Line(Ok(Some(line))) if line.trim().is_empty() => continue,
+            Incoming::Line(Ok(Some(line))) => {
+                answer(&line, &requests, &events, &mut subscription).await
+            }
+            Incoming::Line(Ok(None)) => return,
+            Incoming::Line(Err(e)) => {
+                log::warn!("Failed to read from a control client: {}", e);
+                return;
+            }
+            Incoming::Event(event) => {
+                json!({ "jsonrpc": "2.0", "method": "event", "params": event })
+            }
+        };
+        let mut out = out.to_string();
+        out.push('\n');
+        if writer.write_all(out.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// The next event for a subscribed client. Never returns for others.
+async fn next_event(subscription: &mut Option<broadcast::Receiver<Value>>) -> Value {
+    if let Some(events) = subscription {
+        loop {
+            match events.recv().await {
+                Ok(event) => return event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    log::warn!("A control client missed {} events", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+    std::future::pending().await
+}
+
+/// The response to a request line.
+async fn answer(
+    line: &str,
+    requests: &mpsc::Sender<Request>,
+    events: &broadcast::Sender<Value>,
+    subscription: &mut Option<broadcast::Receiver<Value>>,
+) -> Value {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return error(Value::Null, PARSE_ERROR, e.to_string()),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    if method == "subscribe" {
+        *subscription = Some(events.subscribe());
+        return result(id, json!(true));
+    }
+    let call = match Call::parse(method, &params) {
+        Ok(call) => call,
+        Err((code, message)) => return error(id, code, message),
+    };
+    let (reply, answered) = oneshot::channel();
+    if requests.send(Request { call, reply }).await.is_err() {
+        return error(id, SERVER_ERROR, "edda is quitting".to_string());
+    }
+    match answered.await {
+        Ok(answer) => result(id, answer),
+        Err(_) => error(id, SERVER_ERROR, "edda is quitting".to_string()),
+    }
+}
+
+fn result(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+fn error(id: Value, code: i64, message: String) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process, time::Duration};
+
+    use tokio::io::Lines;
+    use tokio::net::unix::OwnedReadHalf;
+
+    use super::*;
+
+    #[test]
+    fn calls_are_checked() {
+        assert_eq!(
+            Call::parse("send_text", &json!({ "to": "!a1b2c3d4", "text": "hi" })),
+            Ok(Call::SendText {
+                to: 0xa1b2c3d4,
+                text: "hi".to_string()
+            })
+        );
+        assert_eq!(
+            Call::parse("get_conversation", &json!({ "node": 42 })),
+            Ok(Call::GetConversation(42))
+        );
+        let code = |method, params| Call::parse(method, &params).unwrap_err().0;
+        assert_eq!(
+            code("send_text", json!({ "to": "!a1b2", "text": "" })),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            code("get_conversation", json!({ "node": "nobody" })),
+            INVALID_PARAMS
+        );
+        assert_eq!(code("reboot", Value::Null), METHOD_NOT_FOUND);
+    }
+
+    async fn response(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> Value {
+        let line = lines.next_line().await.unwrap().unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_are_answered_and_events_streamed() {
+        let path = env::temp_dir().join(format!("edda-control-{}.sock", process::id()));
+        let mut control = ControlSocket::spawn(path.clone());
+        let stream = loop {
+            match UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        writer
+            .write_all(b"{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"list_nodes\"}\n")
+            .await
+            .unwrap();
+        let request = loop {
+            match control.try_recv() {
+                Some(request) => break request,
+                None => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        assert_eq!(request.call, Call::ListNodes);
+        request.reply(json!({}));
+        assert_eq!(
+            response(&mut lines).await,
+            json!({ "jsonrpc": "2.0", "id": 1, "result": {} })
+        );
+
+        writer
+            .write_all(b"{\"jsonrpc\": \"2.0\", \"id\": 2, \"method\": \"subscribe\"}\n")
+            .await
+            .unwrap();
+        assert_eq!(response(&mut lines).await["result"], json!(true));
+        control.event(&MeshEvent::Sent {
+            outbox: 7,
+            packet_id: Some(42),
+        });
+        assert_eq!(
+            response(&mut lines).await["params"],
+            json!({ "type": "sent", "outbox": 7, "packetId": 42 })
+        );
+        let _ = fs::remove_file(path);
+    }
+}
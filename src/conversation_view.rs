@@ -0,0 +1,277 @@
+//! The rendered lines of a conversation, cached between frames.
+//!
+//! Formatting and wrapping every message on each 250 ms frame grows with the history, so the
+//! layout is kept and rebuilt only when the conversation, its length or the pane width changes.
+//!
+//! Threaded, replies are indented under the messages they answer (see `threads`), two columns a
+//! level up to `MAX_DEPTH`.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use ratatui::prelude::*;
+use unicode_width::UnicodeWidthChar;
+
+use crate::channel_badges::ChannelBadges;
+use crate::forms::{self, Ics213};
+use crate::outbox::Acknowledgement;
+use crate::structured::Structured;
+use crate::threads;
+use crate::thumbnail::Thumbnail;
+use crate::types::{Message, NodeNum};
+
+/// Width of the `HH:MM:SS> ` prefix; continuation lines are indented to match. An estimated
+/// time is marked with `~` in place of the `>`.
+const INDENT: usize = 10;
+/// Replies deeper than this are indented no further.
+const MAX_DEPTH: usize = 6;
+
+#[derive(Default)]
+pub struct ConversationView {
+    /// Conversation, message count, pane width and whether threaded the lines were laid out for.
+    key: Option<(Option<NodeNum>, usize, u16, bool)>,
+    lines: Vec<Line<'static>>,
+    /// The lines of each message, none for replies hidden in a collapsed thread.
+    ranges: Vec<Option<Range<usize>>>,
+    /// Indices of the messages shown, in the order shown.
+    order: Vec<usize>,
+}
+
+impl ConversationView {
+    /// Lay out a conversation wrapped to `width` columns, unless it already is, threaded if
+    /// `collapsed` is given with the packet IDs of the messages whose replies are hidden.
+    /// Outgoing messages are marked with their acknowledgements, keyed by timestamp in
+    /// microseconds.
+    pub fn layout(
+        &mut self,
+        node: Option<NodeNum>,
+        messages: &[Message],
+        acks: &HashMap<i64, Acknowledgement>,
+        badges: &ChannelBadges,
+        width: u16,
+        collapsed: Option<&HashSet<u32>>,
+    ) {
+        let key = (node, messages.len(), width, collapsed.is_some());
+        if self.key == Some(key) {
+            return;
+        }
+        let entries = match collapsed {
+            Some(collapsed) => threads::order(messages, collapsed),
+            None => (0..messages.len())
+                .map(|index| threads::Entry {
+                    index,
+                    depth: 0,
+                    hidden: 0,
+                })
+                .collect(),
+        };
+        self.lines.clear();
+        self.ranges = vec![None; messages.len()];
+        self.order.clear();
+        for entry in entries {
+            let message = &messages[entry.index];
+            let start = self.lines.len();
+            let ack = message
+                .outgoing
+                .then(|| acks.get(&message.ts.timestamp_micros()))
+                .flatten();
+            let mut lines = message_lines(message, ack, badges);
+            if entry.hidden > 0 {
+                lines.push(Line::styled(
+                    format!("{}+{} more in thread", " ".repeat(INDENT), entry.hidden),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+            let indent = 2 * entry.depth.min(MAX_DEPTH);
+            for line in lines {
+                for mut line in wrap(line, usize::from(width).saturating_sub(indent)) {
+                    if indent > 0 {
+                        line.spans.insert(0, Span::raw(" ".repeat(indent)));
+                    }
+                    self.lines.push(line);
+                }
+            }
+            self.ranges[entry.index] = Some(start..self.lines.len());
+            self.order.push(entry.index);
+        }
+        self.key = Some(key);
+    }
+
+    /// Force the next layout, for changes other than new messages.
+    pub fn invalidate(&mut self) {
+        self.key = None;
+    }
+
+    pub fn lines(&self) -> &[Line<'static>] {
+        &self.lines
+    }
+
+    /// The lines of a message, as of the last layout.
+    pub fn message_range(&self, index: usize) -> Option<Range<usize>> {
+        self.ranges.get(index).cloned().flatten()
+    }
+
+    /// Indices of the messages shown, in the order shown, as of the last layout.
+    pub fn order(&self) -> &[usize] {
+        &self.order
+    }
+}
+
+/// A conversation as a transcript, for reading back rather than following: every message with
+/// its date and who sent it, `name` or `me`, unwrapped and without acknowledgements.
+pub fn transcript(name: &str, messages: &[Message], badges: &ChannelBadges) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    for message in messages {
+        let mut message_lines = message_lines(message, None, badges);
+        let who = if message.outgoing { "me" } else { name };
+        message_lines[0].spans[0] =
+            Span::raw(format!("{} {who}", message.ts.format("%Y-%m-%d %H:%M:%S")));
+        lines.extend(message_lines);
+    }
+    lines
+}
+
+/// The lines of a message, followed by its local note if it has one.
+fn message_lines(
+    message: &Message,
+    ack: Option<&Acknowledgement>,
+    badges: &ChannelBadges,
+) -> Vec<Line<'static>> {
+    let mut lines = message_body(message, ack, badges);
+    if let Some(note) = &message.note {
+        lines.push(Line::styled(
+            format!("{}NOTE: {note}", " ".repeat(INDENT)),
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::ITALIC),
+        ));
+    }
+    lines
+}
+
+fn message_body(
+    message: &Message,
+    ack: Option<&Acknowledgement>,
+    badges: &ChannelBadges,
+) -> Vec<Line<'static>> {
+    let mut spans = Vec::new();
+    spans.push(Span::raw(message.ts.format("%H:%M:%S").to_string()));
+    let colour = if message.outgoing {
+        Color::Yellow
+    } else {
+        Color::Blue
+    };
+    let separator = if message.estimated_time { "~ " } else { "> " };
+    spans.push(Span::styled(separator, Style::default().fg(colour)));
+    spans.extend(badges.span(message.channel));
+    if let Some(structured) = Structured::from_text(&message.text) {
+        spans.push(Span::styled(
+            format!("{} {}", structured.label(), structured.title()),
+            Style::default().add_modifier(Modifier::BOLD),
+        ));
+        spans.extend(ack_spans(ack));
+        let indent = " ".repeat(INDENT);
+        let mut lines = vec![Line::from(spans)];
+        lines.extend(
+            structured
+                .lines()
+                .into_iter()
+                .map(|line| Line::from(format!("{indent}{line}"))),
+        );
+        return lines;
+    }
+    if let Some(thumbnail) = Thumbnail::from_text(&message.text) {
+        spans.push(Span::styled(
+            format!("THUMBNAIL {}x{}", thumbnail.width, thumbnail.height),
+            Style::default().add_modifier(Modifier::BOLD),
+        ));
+        spans.extend(ack_spans(ack));
+        let indent = " ".repeat(INDENT);
+        let mut lines = vec![Line::from(spans)];
+        lines.extend(
+            thumbnail
+                .render()
+                .into_iter()
+                .map(|row| Line::from(format!("{indent}{row}"))),
+        );
+        return lines;
+    }
+    match Ics213::parse(&message.text) {
+        Some(form) => {
+            spans.push(Span::styled(
+                format!("ICS-213 [{}]", form.precedence.label()),
+                Style::default().add_modifier(Modifier::BOLD),
+            ));
+            spans.extend(ack_spans(ack));
+            let indent = " ".repeat(INDENT);
+            vec![
+                Line::from(spans),
+                Line::from(format!("{indent}NO.: {}", form.number)),
+                Line::from(format!("{indent}TO: {}", form.to)),
+                Line::from(format!("{indent}FROM: {}", form.from)),
+                Line::from(format!("{indent}SUBJECT: {}", form.subject)),
+                Line::from(format!("{indent}{}", form.body)),
+            ]
+        }
+        None => {
+            match forms::parse_receipt(&message.text) {
+                Some(number) => spans.push(Span::styled(
+                    format!("RECEIPT for #{number}"),
+                    Style::default().add_modifier(Modifier::ITALIC),
+                )),
+                None => spans.push(Span::raw(message.text.clone())),
+            }
+            spans.extend(ack_spans(ack));
+            vec![Line::from(spans)]
+        }
+    }
+}
+
+/// Marks for a message the mesh relayed and for one its recipient acknowledged; a message can
+/// have either, or both.
+fn ack_spans(ack: Option<&Acknowledgement>) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    if let Some(ack) = ack {
+        if ack.relayed {
+            spans.push(Span::styled(" [relayed]", Style::default().fg(Color::Cyan)));
+        }
+        if ack.delivered {
+            spans.push(Span::styled(
+                " [delivered]",
+                Style::default().fg(Color::Green),
+            ));
+        }
+    }
+    spans
+}
+
+/// Break a line into lines at most `width` columns wide, indenting the continuations.
+pub fn wrap(line: Line<'static>, width: usize) -> Vec<Line<'static>> {
+    if width <= INDENT || line.width() <= width {
+        return vec![line];
+    }
+    let mut lines = Vec::new();
+    let mut spans = Vec::new();
+    let mut used = 0;
+    for span in line.spans {
+        let mut chunk = String::new();
+        for c in span.content.chars() {
+            let c_width = c.width().unwrap_or(0);
+            if used + c_width > width && used > INDENT {
+                if !chunk.is_empty() {
+                    spans.push(Span::styled(std::mem::take(&mut chunk), span.style));
+                }
+                lines.push(Line::from(std::mem::take(&mut spans)));
+                spans.push(Span::raw(" ".repeat(INDENT)));
+                used = INDENT;
+            }
+            chunk.push(c);
+            used += c_width;
+        }
+        if !chunk.is_empty() {
+            spans.push(Span::styled(chunk, span.style));
+        }
+    }
+    lines.push(Line::from(spans));
+    lines
+}
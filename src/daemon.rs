@@ -0,0 +1,712 @@
+//! Headless mode: keep the radio connection and store running without a terminal, optionally
+//! serving a small authenticated HTTP API so other tools can use edda as their mesh gateway.
+//!
+//! API (all routes require `Authorization: Bearer <token>`):
+//! - `GET /nodes`: known nodes, keyed by node ID
+//! - `GET /messages?node=!a1b2c3d4&since=<RFC 3339>&limit=500`: stored messages, oldest first,
+//!   optionally for one node. At most `limit` are returned (500 unless given, 5000 at most) from
+//!   those after `since`; the time of the last one is the `since` of the next page
+//! - `POST /messages` with `{"to": "!a1b2c3d4", "text": "..."}`: send a text message; answers
+//!   once the radio took it, or with 503 if it did not within 30 seconds
+//! - `GET /read`: per node, the time of the newest message read by any client
+//! - `POST /read` with `{"node": "!a1b2c3d4", "time": "<RFC 3339>"}`: mark a conversation read
+//! - `GET /events`: server-sent events for every node, position and message received, and for
+//!   watchdog alerts about the device
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicI64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Json, Router,
+    extract::{Query, Request, State},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::get,
+};
+use chrono::{DateTime, Local};
+use color_eyre::eyre::{Result, bail};
+use meshtastic::types::NodeId;
+use ring::digest;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use subtle::ConstantTimeEq;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_stream::{Stream, StreamExt, wrappers::BroadcastStream};
+
+use crate::aprs::AprsGateway;
+use crate::auto_reply::AutoReply;
+use crate::config::{ApiConfig, Config};
+#[cfg(all(feature = "dbus", target_os = "linux"))]
+use crate::dbus::DesktopBus;
+use crate::device_time;
+use crate::export;
+use crate::health::HopTracker;
+use crate::mesh;
+use crate::message_actions;
+use crate::notify::NotifyOverrides;
+use crate::profiles::Profile;
+use crate::push::Pusher;
+use crate::session_log::SessionLog;
+use crate::store::{SqliteStore, Store};
+use crate::types::{
+    BROADCAST, ConnectionStatus, MeshEvent, Message, NodeNum, PACKET_BYTE_LIMIT, UiEvent,
+    format_node_num, parse_node_num,
+};
+use crate::watchdog::Watchdog;
+use crate::write_behind::WriteBehind;
+
+/// How often to check whether the device has gone silent.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(60);
+/// How long the radio may take to accept a message handed to the Meshtastic thread. While the
+/// device is away the thread drops what it is given, so a message not taken by then never will be.
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A message handed to the Meshtastic thread, stored and logged once the radio takes it.
+struct PendingSend {
+    to: NodeNum,
+    text: String,
+    queued: Instant,
+    /// Told when the radio takes it, for an API request waiting to answer.
+    taken: Option<oneshot::Sender<()>>,
+}
+
+struct Daemon {
+    /// Writes go through the writer thread, so bursts of packets never hold up the API.
+    store: Mutex<WriteBehind<SqliteStore>>,
+    transmitter: mpsc::Sender<UiEvent>,
+    /// Messages handed to the Meshtastic thread, by the outbox ID they were tagged with.
+    sending: Mutex<HashMap<i64, PendingSend>>,
+    next_outbox: AtomicI64,
+    events: broadcast::Sender<Value>,
+    session_log: Option<SessionLog>,
+    push: Option<Pusher>,
+    aprs: Option<Mutex<AprsGateway>>,
+    hop_tracker: Mutex<HopTracker>,
+    watchdog: Mutex<Watchdog>,
+    my_node: Mutex<Option<NodeNum>>,
+    auto_reply: Option<Mutex<AutoReply>>,
+    token: Option<String>,
+    #[cfg(all(feature = "dbus", target_os = "linux"))]
+    desktop_bus: DesktopBus,
+}
+
+pub fn run(
+    config: Config,
+    store: SqliteStore,
+    port: String,
+    profile: Option<Profile>,
+) -> Result<()> {
+    let (transmitter, mut receiver) = mesh::spawn(config.clone(), mesh::Transport::parse(&port));
+    if let Some(profile) = profile {
+        mesh::apply_on_connect(&transmitter, profile);
+    }
+    let (events, _) = broadcast::channel(100);
+    #[cfg(all(feature = "dbus", target_os = "linux"))]
+    let (desktop_bus, mut desktop_requests) = DesktopBus::spawn();
+    let daemon = Arc::new(Daemon {
+        store: Mutex::new(WriteBehind::new(store)),
+        transmitter,
+        sending: Mutex::new(HashMap::new()),
+        next_outbox: AtomicI64::new(1),
+        events,
+        session_log: config.session_log_dir.clone().map(SessionLog::new),
+        push: Pusher::spawn(config.push.clone()),
+        aprs: config
+            .aprs
+            .clone()
+            .map(|aprs| Mutex::new(AprsGateway::spawn(aprs))),
+        hop_tracker: Mutex::new(HopTracker::default()),
+        watchdog: Mutex::new(Watchdog::new(config.watchdog.clone())),
+        my_node: Mutex::new(None),
+        auto_reply: config
+            .auto_reply
+            .clone()
+            .map(|text| Mutex::new(AutoReply::new(text))),
+        token: config.api.as_ref().map(|api| api.token.clone()),
+        #[cfg(all(feature = "dbus", target_os = "linux"))]
+        desktop_bus,
+    });
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async move {
+        if let Some(api) = &config.api {
+            serve(api, daemon.clone()).await?;
+        }
+        #[cfg(all(feature = "dbus", target_os = "linux"))]
+        {
+            let daemon = daemon.clone();
+            tokio::spawn(async move {
+                while let Some((to, text)) = desktop_requests.recv().await {
+                    if let Err(e) = daemon.send(to, text).await {
+                        log::error!("Failed to send message from D-Bus: {}", e);
+                    }
+                }
+            });
+        }
+        log::info!("Daemon running");
+        let mut watchdog_interval = tokio::time::interval(WATCHDOG_INTERVAL);
+        loop {
+            tokio::select! {
+                event = receiver.recv() => match event {
+                    Some(event) => daemon.handle_event(event),
+                    None => break,
+                },
+                _ = watchdog_interval.tick() => {
+                    daemon.forget_unsent();
+                    let alert = daemon.watchdog.lock().unwrap().check();
+                    if let Some(alert) = alert {
+                        daemon.alert(alert);
+                    }
+                }
+            }
+        }
+        log::warn!("Meshtastic thread stopped, exiting daemon");
+        Ok(())
+    })
+}
+
+fn text_message(to: NodeNum, text: &str, outbox: i64) -> UiEvent {
+    UiEvent::Message {
+        node_id: NodeId::new(to),
+        message: text.to_string(),
+        channel: 0,
+        reply_id: None,
+        emoji: false,
+        outbox: Some(outbox),
+    }
+}
+
+async fn serve(api: &ApiConfig, daemon: Arc<Daemon>) -> Result<()> {
+    let app = Router::new()
+        .route("/nodes", get(get_nodes))
+        .route("/messages", get(get_messages).post(post_message))
+        .route("/read", get(get_read_state).post(post_read_state))
+        .route("/events", get(get_events))
+        .layer(middleware::from_fn_with_state(daemon.clone(), authenticate))
+        .with_state(daemon);
+    let listener = tokio::net::TcpListener::bind(api.listen).await?;
+    log::info!("Serving API on {}", api.listen);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            log::error!("API server stopped: {}", e);
+        }
+    });
+    Ok(())
+}
+
+impl Daemon {
+    /// Hand a text message to the Meshtastic thread and wait for the radio to take it, when it
+    /// is stored and logged. Fails if the radio does not take it in time.
+    async fn send(&self, to: NodeNum, text: String) -> Result<()> {
+        let (taken, answer) = oneshot::channel();
+        let outbox = self.queue_send(to, text.clone(), Some(taken));
+        if let Err(e) = self.transmitter.send(text_message(to, &text, outbox)).await {
+            self.sending.lock().unwrap().remove(&outbox);
+            return Err(e.into());
+        }
+        if let Ok(Ok(())) = tokio::time::timeout(SEND_TIMEOUT, answer).await {
+            return Ok(());
+        }
+        self.sending.lock().unwrap().remove(&outbox);
+        bail!("The radio did not take the message; is the device connected?")
+    }
+
+    /// Note a message about to be handed to the Meshtastic thread, returning the outbox ID to tag
+    /// it with.
+    fn queue_send(&self, to: NodeNum, text: String, taken: Option<oneshot::Sender<()>>) -> i64 {
+        let outbox = self.next_outbox.fetch_add(1, Ordering::Relaxed);
+        let pending = PendingSend {
+            to,
+            text,
+            queued: Instant::now(),
+            taken,
+        };
+        self.sending.lock().unwrap().insert(outbox, pending);
+        outbox
+    }
+
+    /// Give up on messages the radio never took.
+    fn forget_unsent(&self) {
+        self.sending.lock().unwrap().retain(|_, pending| {
+            let waiting = pending.queued.elapsed() < SEND_TIMEOUT;
+            if !waiting {
+                log::warn!("Message to {} was never sent", format_node_num(pending.to));
+            }
+            waiting
+        });
+    }
+
+    /// Answer a direct message with the auto-reply, if it is on and the sender had none lately.
+    fn auto_reply(&self, to: NodeNum, received: DateTime<Local>) {
+        let Some(text) = self
+            .auto_reply
+            .as_ref()
+            .and_then(|auto_reply| auto_reply.lock().unwrap().reply(to, received))
+        else {
+            return;
+        };
+        log::info!("Auto-replying to {}", format_node_num(to));
+        let outbox = self.queue_send(to, text.clone(), None);
+        if let Err(e) = self.transmitter.try_send(text_message(to, &text, outbox)) {
+            log::error!("Failed to send auto-reply: {}", e);
+            self.sending.lock().unwrap().remove(&outbox);
+        }
+    }
+
+    /// Log and store a message the radio took.
+    fn record_sent(
+        &self,
+        store: &mut dyn Store,
+        to: NodeNum,
+        text: String,
+        packet_id: Option<u32>,
+    ) -> Result<()> {
+        if let Some(session_log) = &self.session_log {
+            session_log.record("ME", &format_node_num(to), 0, &text);
+        }
+        let message = Message {
+            outgoing: true,
+            ts: Local::now(),
+            channel: 0,
+            text,
+            snr: None,
+            packet_id,
+            estimated_time: false,
+            note: None,
+            reply_id: None,
+        };
+        store.append_message(to, &message)?;
+        Ok(())
+    }
+
+    /// Log a watchdog alert and pass it on to push targets and event subscribers.
+    fn alert(&self, alert: String) {
+        log::warn!("{}", alert);
+        if let Some(push) = &self.push {
+            push.alert(&alert);
+        }
+        let _ = self.events.send(json!({ "type": "alert", "text": alert }));
+    }
+
+    fn handle_event(&self, event: MeshEvent) {
+        let heard = self.watchdog.lock().unwrap().heard();
+        if let Some(alert) = heard {
+            self.alert(alert);
+        }
+        let mut store = self.store.lock().unwrap();
+        let published = match event {
+            MeshEvent::MyNode(node) => {
+                *self.my_node.lock().unwrap() = Some(node.num);
+                if let Some(aprs) = &self.aprs {
+                    aprs.lock().unwrap().node_info(&node);
+                }
+                log::info!("Connected to node {}", format_node_num(node.num));
+                None
+            }
+            MeshEvent::DeviceConfig(_)
+            | MeshEvent::LoraConfig(_)
+            | MeshEvent::SecurityConfig(_)
+            | MeshEvent::Channel(_)
+            | MeshEvent::Relayed { .. }
+            | MeshEvent::AdminSession { .. }
+            | MeshEvent::Delivery { .. }
+            | MeshEvent::DeviceLog(_)
+            | MeshEvent::PrivateData { .. }
+            | MeshEvent::Monitored(_)
+            | MeshEvent::Metadata { .. }
+            | MeshEvent::RemoteConfig { .. }
+            | MeshEvent::Traceroute { .. }
+            | MeshEvent::Radio { .. } => None,
+            MeshEvent::Sent { outbox, packet_id } => {
+                let pending = self.sending.lock().unwrap().remove(&outbox);
+                if let Some(pending) = pending {
+                    if let Err(e) =
+                        self.record_sent(&mut *store, pending.to, pending.text, packet_id)
+                    {
+                        log::error!("Failed to store sent message: {}", e);
+                    }
+                    if let Some(taken) = pending.taken {
+                        let _ = taken.send(());
+                    }
+                }
+                None
+            }
+            MeshEvent::Rebooted => {
+                let alert = self.watchdog.lock().unwrap().rebooted();
+                self.alert(alert);
+                None
+            }
+            MeshEvent::Error(error) if error.is_fatal() => {
+                self.alert(format!("{}. {}", error, error.guidance()));
+                None
+            }
+            MeshEvent::Error(error) => {
+                log::warn!("{}", error);
+                None
+            }
+            MeshEvent::ConnectionStatus(ConnectionStatus::Connected) => {
+                log::info!("Connected to the device");
+                None
+            }
+            MeshEvent::ConnectionStatus(ConnectionStatus::Reconnecting { attempt, after }) => {
+                log::info!(
+                    "Reconnecting to the device in {} s (attempt {})",
+                    after.as_secs(),
+                    attempt
+                );
+                None
+            }
+            MeshEvent::DeviceMetrics { from, metrics } => {
+                let alert = metrics
+                    .battery_level
+                    .filter(|_| *self.my_node.lock().unwrap() == Some(from))
+                    .and_then(|level| self.watchdog.lock().unwrap().battery(level));
+                if let Some(alert) = alert {
+                    self.alert(alert);
+                }
+                let alert = metrics
+                    .channel_utilization
+                    .filter(|_| *self.my_node.lock().unwrap() == Some(from))
+                    .and_then(|utilization| {
+                        self.watchdog
+                            .lock()
+                            .unwrap()
+                            .channel_utilization(utilization)
+                    });
+                if let Some(alert) = alert {
+                    if let Some(session_log) = &self.session_log {
+                        session_log.annotate(&alert);
+                    }
+                    self.alert(alert);
+                }
+                if let Some(level) = metrics.battery_level
+                    && let Err(e) = store.record_battery(from, level)
+                {
+                    log::error!("Failed to store battery level of {}: {}", from, e);
+                }
+                None
+            }
+            MeshEvent::NodeAvailable(node) => {
+                if let Some(aprs) = &self.aprs {
+                    aprs.lock().unwrap().node_info(&node);
+                }
+                if let Err(e) = store.upsert_node(&node) {
+                    log::error!("Failed to store node {}: {}", node.num, e);
+                }
+                if let Some(user) = &node.user
+                    && let Err(e) = store.record_user(node.num, user, i64::from(node.last_heard))
+                {
+                    log::error!("Failed to store contact {}: {}", node.num, e);
+                }
+                Some(json!({ "type": "node", "node": export::python_node(&node) }))
+            }
+            MeshEvent::User { from, user } => {
+                if let Err(e) = store.record_user(from, &user, Local::now().timestamp()) {
+                    log::error!("Failed to store contact {}: {}", from, e);
+                }
+                None
+            }
+            MeshEvent::Position { node_id, position } => {
+                if let Some(aprs) = &self.aprs {
+                    aprs.lock().unwrap().position(node_id.id(), &position);
+                }
+                if let Err(e) = store.record_position(node_id.id(), &position) {
+                    log::error!("Failed to store position of {}: {}", node_id, e);
+                }
+                Some(json!({
+                    "type": "position",
+                    "from": format_node_num(node_id.id()),
+                    "latitudeI": position.latitude_i,
+                    "longitudeI": position.longitude_i,
+                    "altitude": position.altitude,
+                }))
+            }
+            MeshEvent::Waypoint { from, waypoint } => {
+                if let Err(e) = store.save_waypoint(from, &waypoint) {
+                    log::error!("Failed to store waypoint from {}: {}", from, e);
+                }
+                None
+            }
+            MeshEvent::PacketHeard {
+                from,
+                to,
+                port,
+                hops,
+                airtime_ms,
+                ..
+            } => {
+                if let Some(hops) = hops
+                    && let Some(anomaly) = self.hop_tracker.lock().unwrap().record(from, hops)
+                {
+                    log::warn!("{}", anomaly);
+                }
+                if let Err(e) = store.record_packet(from, to, port, airtime_ms) {
+                    log::error!("Failed to store packet metadata: {}", e);
+                }
+                None
+            }
+            MeshEvent::Message {
+                node_id,
+                to,
+                channel,
+                message,
+                snr,
+                id,
+                packet,
+            } => {
+                if let Some(session_log) = &self.session_log {
+                    let to = if to == BROADCAST { "ALL" } else { "ME" };
+                    session_log.record(&format_node_num(node_id.id()), to, channel, &message);
+                }
+                // Read on every message so changes made from a TUI sharing the store apply.
+                let notify = store
+                    .get_notify_overrides()
+                    .unwrap_or_else(|e| {
+                        log::error!("Failed to load notification overrides: {}", e);
+                        NotifyOverrides::default()
+                    })
+                    .mode(node_id.id(), channel);
+                if let Some(push) = &self.push {
+                    push.message(&format_node_num(node_id.id()), &message, notify);
+                }
+                #[cfg(all(feature = "dbus", target_os = "linux"))]
+                self.desktop_bus.message_received(
+                    node_id.id(),
+                    format_node_num(node_id.id()),
+                    channel,
+                    &message,
+                    notify,
+                );
+                let (ts, estimated_time) = device_time::received_at(packet.rx_time);
+                let stored = Message {
+                    outgoing: false,
+                    ts,
+                    channel,
+                    text: message,
+                    snr: Some(snr),
+                    packet_id: Some(id),
+                    estimated_time,
+                    note: None,
+                    reply_id: None,
+                };
+                if let Err(e) = store.append_message(node_id.id(), &stored) {
+                    log::error!("Failed to store message: {}", e);
+                }
+                if to != BROADCAST && !message_actions::is_reaction(&packet) {
+                    self.auto_reply(node_id.id(), ts);
+                }
+                Some(json!({
+                    "type": "message",
+                    "from": format_node_num(node_id.id()),
+                    "to": format_node_num(to.id()),
+                    "channel": channel,
+                    "text": stored.text,
+                    "rxSnr": snr,
+                }))
+            }
+        };
+        if let Some(event) = published {
+            // Having no subscribers is not an error.
+            let _ = self.events.send(event);
+        }
+    }
+}
+
+async fn authenticate(State(daemon): State<Arc<Daemon>>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .zip(daemon.token.as_deref())
+        .is_some_and(|(given, expected)| token_matches(expected, given));
+    if authorized {
+        next.run(request).await
+    } else {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+/// Whether a bearer token is the configured one. Both are hashed first, so the comparison takes
+/// as long whatever their lengths and wherever they differ.
+fn token_matches(expected: &str, given: &str) -> bool {
+    let [expected, given] =
+        [expected, given].map(|token| digest::digest(&digest::SHA256, token.as_bytes()));
+    expected.as_ref().ct_eq(given.as_ref()).into()
+}
+
+/// Messages `GET /messages` returns unless asked for fewer, and at most.
+const MESSAGES_LIMIT: usize = 500;
+const MAX_MESSAGES_LIMIT: usize = 5000;
+
+fn internal_error(e: impl std::fmt::Display) -> (StatusCode, String) {
+    log::error!("API request failed: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// Use the store on a blocking thread, so waiting for its lock or for the database never holds
+/// up the other requests.
+async fn with_store<T: Send + 'static>(
+    daemon: &Arc<Daemon>,
+    work: impl FnOnce(&mut WriteBehind<SqliteStore>) -> Result<T> + Send + 'static,
+) -> Result<T, (StatusCode, String)> {
+    let daemon = daemon.clone();
+    tokio::task::spawn_blocking(move || work(&mut daemon.store.lock().unwrap()))
+        .await
+        .map_err(internal_error)?
+        .map_err(internal_error)
+}
+
+async fn get_nodes(State(daemon): State<Arc<Daemon>>) -> Result<Json<Value>, (StatusCode, String)> {
+    let nodes = with_store(&daemon, |store| store.get_nodes()).await?;
+    let nodes: Map<String, Value> = nodes
+        .iter()
+        .map(|node| (format_node_num(node.num), export::python_node(node)))
+        .collect();
+    Ok(Json(Value::Object(nodes)))
+}
+
+#[derive(Deserialize)]
+struct MessagesQuery {
+    node: Option<String>,
+    since: Option<String>,
+    limit: Option<usize>,
+}
+
+async fn get_messages(
+    State(daemon): State<Arc<Daemon>>,
+    Query(query): Query<MessagesQuery>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let node = match query.node.as_deref().map(parse_node_num) {
+        Some(None) => return Err((StatusCode::BAD_REQUEST, "Invalid node".to_string())),
+        Some(node) => node,
+        None => None,
+    };
+    let since = match query.since.as_deref().map(DateTime::parse_from_rfc3339) {
+        Some(Ok(since)) => since.timestamp(),
+        Some(Err(_)) => return Err((StatusCode::BAD_REQUEST, "Invalid since".to_string())),
+        None => i64::MIN,
+    };
+    let limit = query
+        .limit
+        .unwrap_or(MESSAGES_LIMIT)
+        .min(MAX_MESSAGES_LIMIT);
+    let messages = with_store(&daemon, move |store| {
+        store.get_messages_page(node, since, limit)
+    })
+    .await?;
+    let messages: Vec<Value> = messages
+        .into_iter()
+        .map(|(num, message)| {
+            json!({
+                "node": format_node_num(num),
+                "outgoing": message.outgoing,
+                "time": message.ts.to_rfc3339(),
+                "channel": message.channel,
+                "text": message.text,
+                "note": message.note,
+            })
+        })
+        .collect();
+    Ok(Json(Value::Array(messages)))
+}
+
+#[derive(Deserialize)]
+struct SendRequest {
+    to: String,
+    text: String,
+}
+
+async fn post_message(
+    State(daemon): State<Arc<Daemon>>,
+    Json(request): Json<SendRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let Some(to) = parse_node_num(&request.to) else {
+        return Err((StatusCode::BAD_REQUEST, "Invalid destination".to_string()));
+    };
+    if request.text.is_empty() || request.text.len() > PACKET_BYTE_LIMIT {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Text must be 1 to {} bytes", PACKET_BYTE_LIMIT),
+        ));
+    }
+
+    // Accepted: the radio took it, but the destination may not have it yet.
+    daemon.send(to, request.text).await.map_err(|e| {
+        log::warn!("Message to {} not sent: {}", request.to, e);
+        (StatusCode::SERVICE_UNAVAILABLE, e.to_string())
+    })?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn get_read_state(
+    State(daemon): State<Arc<Daemon>>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let read_state = with_store(&daemon, |store| store.get_read_state()).await?;
+    let read_state: Map<String, Value> = read_state
+        .into_iter()
+        .map(|(num, time)| {
+            let time = DateTime::from_timestamp(time, 0).unwrap_or_default();
+            (format_node_num(num), json!(time.to_rfc3339()))
+        })
+        .collect();
+    Ok(Json(Value::Object(read_state)))
+}
+
+#[derive(Deserialize)]
+struct ReadRequest {
+    node: String,
+    time: String,
+}
+
+async fn post_read_state(
+    State(daemon): State<Arc<Daemon>>,
+    Json(request): Json<ReadRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let Some(node) = parse_node_num(&request.node) else {
+        return Err((StatusCode::BAD_REQUEST, "Invalid node".to_string()));
+    };
+    let Ok(time) = DateTime::parse_from_rfc3339(&request.time) else {
+        return Err((StatusCode::BAD_REQUEST, "Invalid time".to_string()));
+    };
+    with_store(&daemon, move |store| {
+        store.mark_read(node, time.timestamp())
+    })
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_events(
+    State(daemon): State<Arc<Daemon>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(daemon.events.subscribe())
+        .filter_map(|event| event.ok())
+        .map(|event| Ok(Event::default().data(event.to_string())));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_configured_token_is_accepted() {
+        assert!(token_matches("s3cret", "s3cret"));
+        assert!(!token_matches("s3cret", "s3cre"));
+        assert!(!token_matches("s3cret", "s3cret "));
+        assert!(!token_matches("s3cret", ""));
+    }
+}
@@ -0,0 +1,179 @@
+//! Desktop integration over the D-Bus session bus, on Linux builds with the `dbus` feature.
+//!
+//! edda owns `org.edda.Edda` and serves the `org.edda.Mesh1` interface at `/org/edda/Mesh`:
+//! - `Send(to: s, text: s)`: send a text message to a node ID such as `!a1b2c3d4`
+//! - `MessageReceived(from: s, channel: u, text: s)`: signal emitted for every incoming message
+//!
+//! Incoming messages also raise a desktop notification through `org.freedesktop.Notifications`,
+//! unless their node or channel is muted.
+
+use std::{collections::HashMap, thread};
+
+use tokio::sync::mpsc;
+use zbus::{
+    connection, fdo, interface, object_server::SignalEmitter, proxy, proxy::CacheProperties,
+    zvariant::Value,
+};
+
+use crate::notify::NotifyMode;
+use crate::types::{NodeNum, PACKET_BYTE_LIMIT, format_node_num, parse_node_num};
+
+const BUS_NAME: &str = "org.edda.Edda";
+const OBJECT_PATH: &str = "/org/edda/Mesh";
+
+struct Received {
+    from: NodeNum,
+    sender: String,
+    channel: u32,
+    text: String,
+    notify: bool,
+}
+
+/// Handle to the D-Bus thread.
+pub struct DesktopBus {
+    received: mpsc::UnboundedSender<Received>,
+}
+
+impl DesktopBus {
+    /// Connect to the session bus on a background thread. Failures are logged, not fatal.
+    ///
+    /// The returned receiver yields the messages D-Bus clients asked to send.
+    pub fn spawn() -> (Self, mpsc::Receiver<(NodeNum, String)>) {
+        let (received, received_rx) = mpsc::unbounded_channel();
+        let (outgoing_tx, outgoing) = mpsc::channel(100);
+        thread::spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    log::error!("Failed to start D-Bus runtime: {}", e);
+                    return;
+                }
+            };
+            if let Err(e) = runtime.block_on(serve(received_rx, outgoing_tx)) {
+                log::error!("D-Bus integration stopped: {}", e);
+            }
+        });
+        (Self { received }, outgoing)
+    }
+
+    /// Emit the `MessageReceived` signal for an incoming message, and show a notification
+    /// unless `mode` is muted.
+    pub fn message_received(
+        &self,
+        from: NodeNum,
+        sender: String,
+        channel: u32,
+        text: &str,
+        mode: NotifyMode,
+    ) {
+        // The thread only goes away if the bus is unavailable, which was already logged.
+        let _ = self.received.send(Received {
+            from,
+            sender,
+            channel,
+            text: text.to_string(),
+            notify: mode != NotifyMode::Muted,
+        });
+    }
+}
+
+struct Mesh {
+    outgoing: mpsc::Sender<(NodeNum, String)>,
+}
+
+#[interface(name = "org.edda.Mesh1")]
+impl Mesh {
+    async fn send(&self, to: &str, text: &str) -> fdo::Result<()> {
+        let Some(to) = parse_node_num(to) else {
+            return Err(fdo::Error::InvalidArgs("Invalid destination".to_string()));
+        };
+        if text.is_empty() || text.len() > PACKET_BYTE_LIMIT {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "Text must be 1 to {} bytes",
+                PACKET_BYTE_LIMIT
+            )));
+        }
+        self.outgoing
+            .send((to, text.to_string()))
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    #[zbus(signal)]
+    async fn message_received(
+        emitter: &SignalEmitter<'_>,
+        from: &str,
+        channel: u32,
+        text: &str,
+    ) -> zbus::Result<()>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+async fn serve(
+    mut received: mpsc::UnboundedReceiver<Received>,
+    outgoing: mpsc::Sender<(NodeNum, String)>,
+) -> zbus::Result<()> {
+    let connection = connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, Mesh { outgoing })?
+        .build()
+        .await?;
+    let notifications = NotificationsProxy::builder(&connection)
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await?;
+    let emitter = SignalEmitter::new(&connection, OBJECT_PATH)?;
+    log::info!("Serving {} on the session bus", BUS_NAME);
+
+    while let Some(message) = received.recv().await {
+        Mesh::message_received(
+            &emitter,
+            &format_node_num(message.from),
+            message.channel,
+            &message.text,
+        )
+        .await?;
+        if !message.notify {
+            continue;
+        }
+        // No notification daemon running is common on headless machines.
+        if let Err(e) = notifications
+            .notify(
+                "edda",
+                0,
+                "",
+                &message.sender,
+                &message.text,
+                &[],
+                HashMap::new(),
+                -1,
+            )
+            .await
+        {
+            log::warn!("Failed to show notification: {}", e);
+        }
+    }
+    Ok(())
+}
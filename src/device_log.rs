@@ -0,0 +1,122 @@
+//! The radio's own log, which firmware streams as `LogRecord`s once its debug log API is enabled
+//! (security settings), kept for the device log pane as a replacement for a serial console.
+
+use std::collections::VecDeque;
+
+use meshtastic::protobufs::LogRecord;
+use meshtastic::protobufs::log_record::Level;
+
+/// Records kept; the oldest are dropped beyond this.
+const CAPACITY: usize = 2000;
+
+/// Levels to filter by, least severe first.
+const LEVELS: [Level; 6] = [
+    Level::Trace,
+    Level::Debug,
+    Level::Info,
+    Level::Warning,
+    Level::Error,
+    Level::Critical,
+];
+
+pub struct DeviceLog {
+    records: VecDeque<LogRecord>,
+    /// Least severe level shown. Records without a level are always shown.
+    pub min_level: Level,
+    /// Keep the newest record in view as records arrive.
+    pub follow: bool,
+    /// Shown records below the view, while not following.
+    offset: usize,
+}
+
+impl Default for DeviceLog {
+    fn default() -> Self {
+        Self {
+            records: VecDeque::new(),
+            min_level: Level::Trace,
+            follow: true,
+            offset: 0,
+        }
+    }
+}
+
+impl DeviceLog {
+    pub fn push(&mut self, record: LogRecord) {
+        if self.records.len() == CAPACITY {
+            self.records.pop_front();
+        }
+        // Hold the view still while reading back.
+        if !self.follow && self.shows(&record) {
+            self.offset += 1;
+        }
+        self.records.push_back(record);
+    }
+
+    fn shows(&self, record: &LogRecord) -> bool {
+        let level = level(record);
+        level == Level::Unset || level >= self.min_level
+    }
+
+    /// The records passing the level filter, oldest first, ending with the last one in view.
+    pub fn visible(&self) -> Vec<&LogRecord> {
+        let mut shown: Vec<&LogRecord> = self
+            .records
+            .iter()
+            .filter(|record| self.shows(record))
+            .collect();
+        shown.truncate(shown.len().saturating_sub(self.offset));
+        shown
+    }
+
+    /// Scroll towards older records, which stops following.
+    pub fn scroll_up(&mut self, lines: usize) {
+        let shown = self.records.iter().filter(|r| self.shows(r)).count();
+        self.offset = (self.offset + lines).min(shown.saturating_sub(1));
+        self.follow = false;
+    }
+
+    /// Scroll towards newer records, following again once at the newest.
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.offset = self.offset.saturating_sub(lines);
+        if self.offset == 0 {
+            self.follow = true;
+        }
+    }
+
+    /// Shown records below the view; 0 while following.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Go back to reading `offset` records up from the newest, following at 0.
+    pub fn scroll_to(&mut self, offset: usize) {
+        self.offset = 0;
+        self.follow = true;
+        if offset > 0 {
+            self.scroll_up(offset);
+            self.follow = self.offset == 0;
+        }
+    }
+
+    pub fn toggle_follow(&mut self) {
+        self.follow = !self.follow;
+        if self.follow {
+            self.offset = 0;
+        }
+    }
+
+    /// Show only more severe records, starting over from all of them after `Critical`.
+    pub fn cycle_level(&mut self) {
+        let next = LEVELS
+            .iter()
+            .position(|level| *level == self.min_level)
+            .map_or(0, |i| (i + 1) % LEVELS.len());
+        self.min_level = LEVELS[next];
+        self.offset = 0;
+        self.follow = true;
+    }
+}
+
+pub fn level(record: &LogRecord) -> Level {
+    Level::try_from(record.level).unwrap_or(Level::Unset)
+}
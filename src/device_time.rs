@@ -0,0 +1,28 @@
+//! Times reported by the device we are connected to.
+//!
+//! A device without an RTC or GPS fix counts from the epoch when it boots, so its timestamps
+//! land in 1970. Those are not shown as dates: receive times fall back to our own clock and are
+//! flagged as estimates, and other times are treated as unknown.
+
+use chrono::{DateTime, Local};
+
+/// 2020-01-01. A device whose clock was set never reports anything earlier.
+const EARLIEST_PLAUSIBLE: i64 = 1_577_836_800;
+
+/// A device timestamp in seconds, or `None` if it is unset or the device's clock is not set.
+pub fn plausible(seconds: u32) -> Option<DateTime<Local>> {
+    let seconds = i64::from(seconds);
+    if seconds < EARLIEST_PLAUSIBLE {
+        return None;
+    }
+    Some(DateTime::from_timestamp(seconds, 0)?.with_timezone(&Local))
+}
+
+/// When a packet reached the device: its `rx_time` if plausible, otherwise now, flagged as an
+/// estimate with `true`.
+pub fn received_at(rx_time: u32) -> (DateTime<Local>, bool) {
+    match plausible(rx_time) {
+        Some(time) => (time, false),
+        None => (Local::now(), true),
+    }
+}
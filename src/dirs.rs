@@ -0,0 +1,125 @@
+//! Where edda keeps its files: the platform's usual places for configuration, data and logs
+//! (the XDG base directories on Linux and other Unixes, `~/Library` on macOS, `%APPDATA%` and
+//! `%LOCALAPPDATA%` on Windows), unless `EDDA_CONFIG_DIR`, `EDDA_DATA_DIR` or `EDDA_LOG_DIR`
+//! point elsewhere. Exports go to the downloads folder, or `EDDA_EXPORT_DIR`. Without a home
+//! directory to go by, the working directory is used.
+
+use std::env;
+use std::path::PathBuf;
+
+const APP: &str = "edda";
+
+/// Holds `config.toml`.
+pub fn config_dir() -> PathBuf {
+    dir("EDDA_CONFIG_DIR", platform::config_dir)
+}
+
+/// Holds the database, and anything else the configuration names by a relative path.
+pub fn data_dir() -> PathBuf {
+    dir("EDDA_DATA_DIR", platform::data_dir)
+}
+
+/// Holds the application logs.
+pub fn log_dir() -> PathBuf {
+    dir("EDDA_LOG_DIR", platform::log_dir)
+}
+
+/// Holds exports the user asks for, such as node maps and reports: the downloads folder if there
+/// is one, and the data directory otherwise.
+pub fn export_dir() -> PathBuf {
+    env::var_os("EDDA_EXPORT_DIR")
+        .map(PathBuf::from)
+        .or_else(|| platform::download_dir().filter(|dir| dir.is_dir()))
+        .unwrap_or_else(data_dir)
+}
+
+fn dir(variable: &str, default: fn() -> Option<PathBuf>) -> PathBuf {
+    env::var_os(variable)
+        .map(PathBuf::from)
+        .or_else(default)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// A directory from an environment variable, ignoring relative paths as the XDG specification
+/// asks.
+fn env_dir(variable: &str) -> Option<PathBuf> {
+    env::var_os(variable)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::{APP, env_dir};
+    use std::path::PathBuf;
+
+    pub fn config_dir() -> Option<PathBuf> {
+        Some(env_dir("APPDATA")?.join(APP))
+    }
+
+    pub fn data_dir() -> Option<PathBuf> {
+        config_dir()
+    }
+
+    pub fn log_dir() -> Option<PathBuf> {
+        Some(env_dir("LOCALAPPDATA")?.join(APP).join("logs"))
+    }
+
+    pub fn download_dir() -> Option<PathBuf> {
+        Some(env_dir("USERPROFILE")?.join("Downloads"))
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::{APP, env_dir};
+    use std::path::PathBuf;
+
+    pub fn config_dir() -> Option<PathBuf> {
+        Some(
+            env_dir("HOME")?
+                .join("Library/Application Support")
+                .join(APP),
+        )
+    }
+
+    pub fn data_dir() -> Option<PathBuf> {
+        config_dir()
+    }
+
+    pub fn log_dir() -> Option<PathBuf> {
+        Some(env_dir("HOME")?.join("Library/Logs").join(APP))
+    }
+
+    pub fn download_dir() -> Option<PathBuf> {
+        Some(env_dir("HOME")?.join("Downloads"))
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+mod platform {
+    use super::{APP, env_dir};
+    use std::path::PathBuf;
+
+    fn xdg(variable: &str, fallback: &str) -> Option<PathBuf> {
+        let base = env_dir(variable).or_else(|| Some(env_dir("HOME")?.join(fallback)))?;
+        Some(base.join(APP))
+    }
+
+    pub fn config_dir() -> Option<PathBuf> {
+        xdg("XDG_CONFIG_HOME", ".config")
+    }
+
+    pub fn data_dir() -> Option<PathBuf> {
+        xdg("XDG_DATA_HOME", ".local/share")
+    }
+
+    pub fn log_dir() -> Option<PathBuf> {
+        xdg("XDG_STATE_HOME", ".local/state")
+    }
+
+    /// `XDG_DOWNLOAD_DIR` is usually only set in `user-dirs.dirs`, read by the desktop session.
+    pub fn download_dir() -> Option<PathBuf> {
+        env_dir("XDG_DOWNLOAD_DIR").or_else(|| Some(env_dir("HOME")?.join("Downloads")))
+    }
+}
@@ -0,0 +1,122 @@
+//! Finding networked nodes on the LAN, which announce themselves over mDNS as `_meshtastic._tcp`
+//! services, so connecting to one does not take knowing its address.
+//!
+//! The services are browsed for the length of the wait; whatever resolved by then is what was
+//! found.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+
+const SERVICE: &str = "_meshtastic._tcp.local.";
+
+/// A node found on the network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkDevice {
+    /// The name it announces, e.g. `Meshtastic_1a2b`.
+    pub name: String,
+    pub address: SocketAddr,
+}
+
+impl NetworkDevice {
+    /// The port to connect to it by, as given on the command line.
+    pub fn port(&self) -> String {
+        format!("tcp://{}", self.address)
+    }
+}
+
+impl fmt::Display for NetworkDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.port(), self.name)
+    }
+}
+
+/// Nodes answering on the LAN within `wait`, sorted by name. Finding none is not an error: the
+/// network may have no nodes or not allow multicast.
+pub fn browse(wait: Duration) -> Vec<NetworkDevice> {
+    match resolve(wait) {
+        Ok(devices) => devices,
+        Err(e) => {
+            log::error!("Failed to look for nodes on the network: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn resolve(wait: Duration) -> mdns_sd::Result<Vec<NetworkDevice>> {
+    let daemon = ServiceDaemon::new()?;
+    let events = daemon.browse(SERVICE)?;
+    let deadline = Instant::now() + wait;
+    // By full name, as an instance resolves again whenever its records are refreshed.
+    let mut found = HashMap::new();
+    while let Ok(event) = events.recv_deadline(deadline) {
+        if let ServiceEvent::ServiceResolved(info) = event
+            && let Some(device) = device(&info)
+        {
+            found.insert(info.get_fullname().to_string(), device);
+        }
+    }
+    if let Err(e) = daemon.shutdown() {
+        log::warn!("Failed to stop browsing the network: {}", e);
+    }
+    let mut devices: Vec<_> = found.into_values().collect();
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(devices)
+}
+
+/// The node a resolved instance is, at its first IPv4 address, or else its first IPv6 one.
+fn device(info: &ServiceInfo) -> Option<NetworkDevice> {
+    let ip = info
+        .get_addresses()
+        .iter()
+        .min_by_key(|ip| (ip.is_ipv6(), **ip))
+        .copied()?;
+    let name = info
+        .get_fullname()
+        .strip_suffix(SERVICE)
+        .and_then(|name| name.strip_suffix('.'))
+        .unwrap_or(info.get_fullname())
+        .to_string();
+    Some(NetworkDevice {
+        name,
+        address: SocketAddr::new(ip, info.get_port()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    fn info(addresses: &[IpAddr]) -> ServiceInfo {
+        ServiceInfo::new(
+            SERVICE,
+            "Meshtastic_1a2b",
+            "meshtastic_1a2b.local.",
+            addresses,
+            4403,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn resolved_instances_are_named_and_addressed() {
+        let addresses = [
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 40)),
+        ];
+        assert_eq!(
+            device(&info(&addresses)),
+            Some(NetworkDevice {
+                name: "Meshtastic_1a2b".to_string(),
+                address: "192.168.1.40:4403".parse().unwrap(),
+            })
+        );
+        assert_eq!(device(&info(&[])), None);
+    }
+}
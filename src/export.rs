@@ -0,0 +1,166 @@
+//! Snapshots of the mesh for sharing with people who aren't running edda.
+
+use std::{fs, io, path::Path};
+
+use chrono::{DateTime, Local};
+use meshtastic::protobufs::{HardwareModel, NodeInfo, config::device_config::Role};
+use serde_json::{Map, Value, json};
+
+use crate::types::{Message, NodeNum, format_node_num};
+
+/// Meshtastic encodes coordinates as integers in units of 1e-7 degrees.
+pub const DEGREES_PER_UNIT: f64 = 1e-7;
+
+/// Build a GeoJSON `FeatureCollection` with a point for every node that has reported a position.
+pub fn nodes_geojson<'a>(nodes: impl IntoIterator<Item = &'a NodeInfo>) -> Value {
+    let features: Vec<Value> = nodes
+        .into_iter()
+        .filter_map(|node| {
+            let position = node.position.as_ref()?;
+            let latitude = f64::from(position.latitude_i?) * DEGREES_PER_UNIT;
+            let longitude = f64::from(position.longitude_i?) * DEGREES_PER_UNIT;
+            let mut coordinates = vec![json!(longitude), json!(latitude)];
+            if let Some(altitude) = position.altitude {
+                coordinates.push(json!(altitude));
+            }
+
+            let user = node.user.as_ref();
+            let last_heard = DateTime::from_timestamp(i64::from(node.last_heard), 0)
+                .map(|t| t.with_timezone(&Local).to_rfc3339());
+            Some(json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": coordinates },
+                "properties": {
+                    "id": format_node_num(node.num),
+                    "long_name": user.map(|u| u.long_name.as_str()),
+                    "short_name": user.map(|u| u.short_name.as_str()),
+                    "last_heard": last_heard,
+                    "hops_away": node.hops_away,
+                    "snr": node.snr,
+                    "battery_level": node.device_metrics.as_ref().and_then(|m| m.battery_level),
+                },
+            }))
+        })
+        .collect();
+
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+/// Write the GeoJSON to `path`, replacing it atomically so map tools polling the file never see a
+/// partial write.
+pub fn write_nodes_geojson<'a>(
+    nodes: impl IntoIterator<Item = &'a NodeInfo>,
+    path: &Path,
+) -> io::Result<()> {
+    let geojson = nodes_geojson(nodes);
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(&geojson)?)?;
+    fs::rename(tmp, path)
+}
+
+/// Nodes and messages in the shapes meshtastic-python uses, so scripts written against
+/// `interface.nodes` and received packet dicts can read edda's exports unchanged.
+pub fn python_json<'a>(
+    my_node: Option<&'a NodeInfo>,
+    nodes: impl IntoIterator<Item = &'a NodeInfo>,
+    messages: impl IntoIterator<Item = (NodeNum, &'a Message)>,
+) -> Value {
+    let my_num = my_node.map(|n| n.num).unwrap_or_default();
+    let nodes: Map<String, Value> = my_node
+        .into_iter()
+        .chain(nodes)
+        .map(|node| (format_node_num(node.num), python_node(node)))
+        .collect();
+    let messages: Vec<Value> = messages
+        .into_iter()
+        .map(|(num, message)| {
+            let (from, to) = if message.outgoing {
+                (my_num, num)
+            } else {
+                (num, my_num)
+            };
+            let mut value = json!({
+                "from": from,
+                "to": to,
+                "fromId": format_node_num(from),
+                "toId": format_node_num(to),
+                "channel": message.channel,
+                "rxTime": message.ts.timestamp(),
+                "decoded": {
+                    "portnum": "TEXT_MESSAGE_APP",
+                    "text": message.text,
+                },
+            });
+            // Local to edda; meshtastic-python has no such field.
+            if let Some(note) = &message.note {
+                value["note"] = json!(note);
+            }
+            value
+        })
+        .collect();
+
+    json!({
+        "myInfo": { "myNodeNum": my_num },
+        "nodes": nodes,
+        "messages": messages,
+    })
+}
+
+/// A node in the shape of meshtastic-python's `interface.nodes` values.
+pub fn python_node(node: &NodeInfo) -> Value {
+    let mut value = Map::new();
+    value.insert("num".into(), json!(node.num));
+    if let Some(user) = &node.user {
+        let hw_model = HardwareModel::try_from(user.hw_model)
+            .map(|m| m.as_str_name())
+            .unwrap_or("UNSET");
+        let role = Role::try_from(user.role)
+            .map(|r| r.as_str_name())
+            .unwrap_or("CLIENT");
+        value.insert(
+            "user".into(),
+            json!({
+                "id": user.id,
+                "longName": user.long_name,
+                "shortName": user.short_name,
+                "hwModel": hw_model,
+                "role": role,
+                "isLicensed": user.is_licensed,
+            }),
+        );
+    }
+    if let Some(position) = &node.position
+        && let (Some(latitude_i), Some(longitude_i)) = (position.latitude_i, position.longitude_i)
+    {
+        value.insert(
+            "position".into(),
+            json!({
+                "latitudeI": latitude_i,
+                "longitudeI": longitude_i,
+                "altitude": position.altitude,
+                "time": position.time,
+                "latitude": f64::from(latitude_i) * DEGREES_PER_UNIT,
+                "longitude": f64::from(longitude_i) * DEGREES_PER_UNIT,
+            }),
+        );
+    }
+    value.insert("snr".into(), json!(node.snr));
+    value.insert("lastHeard".into(), json!(node.last_heard));
+    if let Some(metrics) = &node.device_metrics {
+        value.insert(
+            "deviceMetrics".into(),
+            json!({
+                "batteryLevel": metrics.battery_level,
+                "voltage": metrics.voltage,
+                "channelUtilization": metrics.channel_utilization,
+                "airUtilTx": metrics.air_util_tx,
+                "uptimeSeconds": metrics.uptime_seconds,
+            }),
+        );
+    }
+    if let Some(hops_away) = node.hops_away {
+        value.insert("hopsAway".into(), json!(hops_away));
+    }
+    value.insert("isFavorite".into(), json!(node.is_favorite));
+    Value::Object(value)
+}
@@ -0,0 +1,241 @@
+//! A Meshtastic device simulated in memory, speaking the stream protocol over a pipe, so the
+//! whole stack can run without a radio. Mock mode (`edda --mock <scenario>`) plays scenarios on
+//! it and the end-to-end tests script it directly.
+//!
+//! `Conditions` make its link to the mesh lossy, slow or prone to duplicates. The losses and
+//! duplicates come from a seeded generator, so they are the same on every run with the same seed.
+
+use std::io;
+use std::time::Duration;
+
+use meshtastic::Message as _;
+use meshtastic::protobufs::{
+    Data, FromRadio, MeshPacket, MyNodeInfo, NodeInfo, PortNum, Routing, ToRadio, User, from_radio,
+    mesh_packet, routing, to_radio,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+use crate::types::{NodeNum, format_node_num};
+
+/// What happens to packets on their way from the mesh to the device.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Conditions {
+    /// Chance that a packet never arrives.
+    pub loss: f64,
+    /// How long each packet takes to arrive.
+    pub delay: Duration,
+    /// Chance that a packet arrives twice.
+    pub duplicate: f64,
+}
+
+/// The device end of the connection.
+pub struct FakeDevice {
+    /// What the client sent, read on a task of its own so waiting for it can be cancelled.
+    incoming: mpsc::UnboundedReceiver<to_radio::PayloadVariant>,
+    reader: JoinHandle<()>,
+    writer: WriteHalf<DuplexStream>,
+    next_id: u32,
+    /// The device's own node.
+    pub me: NodeNum,
+    pub conditions: Conditions,
+    rng: StdRng,
+}
+
+impl FakeDevice {
+    /// A device for the client at the other end of `stream`. Must be called within a Tokio
+    /// runtime.
+    pub fn new(stream: DuplexStream, me: NodeNum, seed: u64) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        let (sender, incoming) = mpsc::unbounded_channel();
+        let reader = tokio::spawn(async move {
+            if let Err(e) = read_packets(reader, sender).await {
+                log::debug!("Fake device stopped reading: {}", e);
+            }
+        });
+        Self {
+            incoming,
+            reader,
+            writer,
+            next_id: 1,
+            me,
+            conditions: Conditions::default(),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// The next packet the client sent, skipping heartbeats.
+    pub async fn recv(&mut self) -> io::Result<to_radio::PayloadVariant> {
+        self.incoming
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+    }
+
+    pub async fn send(&mut self, variant: from_radio::PayloadVariant) -> io::Result<()> {
+        let packet = FromRadio {
+            id: self.next_id,
+            payload_variant: Some(variant),
+        };
+        self.next_id += 1;
+        let data = packet.encode_to_vec();
+        let mut framed = vec![0x94, 0xc3];
+        framed.extend((data.len() as u16).to_be_bytes());
+        framed.extend(data);
+        self.writer.write_all(&framed).await
+    }
+
+    /// Answer the client's config request with our node and the node database.
+    pub async fn answer_config(&mut self, config_id: u32, nodes: Vec<NodeInfo>) -> io::Result<()> {
+        self.send(from_radio::PayloadVariant::MyInfo(MyNodeInfo {
+            my_node_num: self.me,
+            ..Default::default()
+        }))
+        .await?;
+        for node in nodes {
+            self.send(from_radio::PayloadVariant::NodeInfo(node))
+                .await?;
+        }
+        self.send(from_radio::PayloadVariant::ConfigCompleteId(config_id))
+            .await
+    }
+
+    /// A packet from `from` to `to`, with a new packet ID.
+    pub fn packet(
+        &mut self,
+        from: NodeNum,
+        to: NodeNum,
+        port: PortNum,
+        payload: Vec<u8>,
+        request_id: u32,
+    ) -> MeshPacket {
+        MeshPacket {
+            from,
+            to,
+            id: self.next_id,
+            rx_snr: 6.5,
+            hop_start: 3,
+            hop_limit: 3,
+            payload_variant: Some(mesh_packet::PayloadVariant::Decoded(Data {
+                portnum: port as i32,
+                payload,
+                request_id,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Deliver a packet from the mesh to the client, subject to the link conditions.
+    pub async fn deliver_packet(&mut self, packet: MeshPacket) -> io::Result<()> {
+        if self.rng.random_bool(self.conditions.loss) {
+            return Ok(());
+        }
+        sleep(self.conditions.delay).await;
+        let variant = from_radio::PayloadVariant::Packet(packet);
+        if self.rng.random_bool(self.conditions.duplicate) {
+            self.send(variant.clone()).await?;
+        }
+        self.send(variant).await
+    }
+
+    /// Deliver a packet addressed to our node.
+    pub async fn deliver(
+        &mut self,
+        from: NodeNum,
+        port: PortNum,
+        payload: Vec<u8>,
+        request_id: u32,
+    ) -> io::Result<()> {
+        let packet = self.packet(from, self.me, port, payload, request_id);
+        self.deliver_packet(packet).await
+    }
+
+    /// Report the outcome of a packet the client sent: an ACK from its destination, or a
+    /// failure reported by our own node.
+    pub async fn ack(&mut self, packet: &MeshPacket, error: routing::Error) -> io::Result<()> {
+        let from = if error == routing::Error::None {
+            packet.to
+        } else {
+            self.me
+        };
+        let routing = Routing {
+            variant: Some(routing::Variant::ErrorReason(error as i32)),
+        };
+        self.deliver(
+            from,
+            PortNum::RoutingApp,
+            routing.encode_to_vec(),
+            packet.id,
+        )
+        .await
+    }
+
+    /// Report that our own node heard a packet the client sent being rebroadcast.
+    pub async fn relayed(&mut self, packet: &MeshPacket) -> io::Result<()> {
+        let routing = Routing {
+            variant: Some(routing::Variant::ErrorReason(routing::Error::None as i32)),
+        };
+        self.deliver(
+            self.me,
+            PortNum::RoutingApp,
+            routing.encode_to_vec(),
+            packet.id,
+        )
+        .await
+    }
+}
+
+impl Drop for FakeDevice {
+    fn drop(&mut self) {
+        // Closing both halves of the pipe unplugs the device.
+        self.reader.abort();
+    }
+}
+
+/// Decode what the client sends until it goes away.
+async fn read_packets(
+    mut reader: ReadHalf<DuplexStream>,
+    sender: mpsc::UnboundedSender<to_radio::PayloadVariant>,
+) -> io::Result<()> {
+    loop {
+        let mut header = [0; 4];
+        reader.read_exact(&mut header).await?;
+        if header[..2] != [0x94, 0xc3] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bad framing from client",
+            ));
+        }
+        let mut data = vec![0; usize::from(u16::from_be_bytes([header[2], header[3]]))];
+        reader.read_exact(&mut data).await?;
+        let packet = ToRadio::decode(data.as_slice())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        match packet.payload_variant {
+            Some(to_radio::PayloadVariant::Heartbeat(_)) | None => {}
+            Some(variant) => {
+                if sender.send(variant).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// A node as the device reports it, with the short name taken from the long one.
+pub fn node(num: NodeNum, name: &str) -> NodeInfo {
+    NodeInfo {
+        num,
+        user: Some(User {
+            id: format_node_num(num),
+            long_name: name.to_string(),
+            short_name: name.chars().take(4).collect(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
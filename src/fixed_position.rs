@@ -0,0 +1,123 @@
+//! Entering a fixed position for GPS-less nodes such as base stations.
+
+use meshtastic::protobufs::Position;
+
+use crate::export::DEGREES_PER_UNIT;
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum PositionField {
+    #[default]
+    Latitude,
+    Longitude,
+    Altitude,
+}
+
+impl PositionField {
+    pub const ALL: [PositionField; 3] = [
+        PositionField::Latitude,
+        PositionField::Longitude,
+        PositionField::Altitude,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PositionField::Latitude => "LATITUDE",
+            PositionField::Longitude => "LONGITUDE",
+            PositionField::Altitude => "ALTITUDE (m)",
+        }
+    }
+}
+
+/// The position being typed, along with which field has the cursor.
+#[derive(Debug, Default)]
+pub struct PositionDraft {
+    pub latitude: String,
+    pub longitude: String,
+    pub altitude: String,
+    pub field: PositionField,
+}
+
+impl PositionDraft {
+    /// Start from the node's current position, if it has one.
+    pub fn from_position(position: Option<&Position>) -> Self {
+        let degrees = |value: Option<i32>| {
+            value
+                .map(|v| format!("{:.7}", f64::from(v) * DEGREES_PER_UNIT))
+                .unwrap_or_default()
+        };
+        Self {
+            latitude: degrees(position.and_then(|p| p.latitude_i)),
+            longitude: degrees(position.and_then(|p| p.longitude_i)),
+            altitude: position
+                .and_then(|p| p.altitude)
+                .map(|a| a.to_string())
+                .unwrap_or_default(),
+            field: PositionField::default(),
+        }
+    }
+
+    pub fn next_field(&mut self) {
+        self.field = match self.field {
+            PositionField::Latitude => PositionField::Longitude,
+            PositionField::Longitude => PositionField::Altitude,
+            PositionField::Altitude => PositionField::Latitude,
+        };
+    }
+
+    pub fn previous_field(&mut self) {
+        self.field = match self.field {
+            PositionField::Latitude => PositionField::Altitude,
+            PositionField::Longitude => PositionField::Latitude,
+            PositionField::Altitude => PositionField::Longitude,
+        };
+    }
+
+    fn text_mut(&mut self) -> &mut String {
+        match self.field {
+            PositionField::Latitude => &mut self.latitude,
+            PositionField::Longitude => &mut self.longitude,
+            PositionField::Altitude => &mut self.altitude,
+        }
+    }
+
+    pub fn push(&mut self, c: char) {
+        if c.is_ascii_digit() || c == '.' || c == '-' {
+            self.text_mut().push(c);
+        }
+    }
+
+    pub fn pop(&mut self) {
+        self.text_mut().pop();
+    }
+
+    pub fn value(&self, field: PositionField) -> &str {
+        match field {
+            PositionField::Latitude => &self.latitude,
+            PositionField::Longitude => &self.longitude,
+            PositionField::Altitude => &self.altitude,
+        }
+    }
+
+    /// Latitude and longitude in degrees, if both are valid.
+    pub fn coordinates(&self) -> Option<(f64, f64)> {
+        let latitude: f64 = self.latitude.parse().ok()?;
+        let longitude: f64 = self.longitude.parse().ok()?;
+        ((-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude))
+            .then_some((latitude, longitude))
+    }
+
+    /// The entered position, or `None` if it is incomplete or out of range. Altitude is optional.
+    pub fn position(&self) -> Option<Position> {
+        let (latitude, longitude) = self.coordinates()?;
+        let altitude = match self.altitude.as_str() {
+            "" => None,
+            altitude => Some(altitude.parse().ok()?),
+        };
+        Some(Position {
+            latitude_i: Some((latitude / DEGREES_PER_UNIT).round() as i32),
+            longitude_i: Some((longitude / DEGREES_PER_UNIT).round() as i32),
+            altitude,
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,207 @@
+//! Which pane has the keyboard, how Tab and BackTab move between panes, and which keys are taken
+//! before the focused pane sees them. Popups are views over the panes (see `view`) and take every
+//! key while open.
+//!
+//! The panes Tab cycles through are a list rather than a fixed match, so views such as a log,
+//! map or statistics pane can join the cycle, and leave it, while edda runs. The note editor is
+//! never part of the cycle; Tab from it starts the cycle over.
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::types::Focus;
+
+/// What a key press is for.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Route {
+    /// Leave the focused pane (Esc).
+    Unfocus,
+    /// Open the quick switcher (Ctrl+K).
+    Switcher,
+    /// Open the global search (Ctrl+Shift+F; most terminals send Ctrl+F for it).
+    GlobalSearch,
+    /// Move to the next pane in the cycle (Tab).
+    Next,
+    /// Move to the previous pane in the cycle (BackTab).
+    Previous,
+    /// For the focused pane to handle.
+    Pane(Focus),
+    /// A command key, pressed with nothing focused.
+    Command,
+    /// For the screen on top of the stack, which takes every key while open.
+    Screen,
+}
+
+pub struct FocusManager {
+    current: Option<Focus>,
+    /// The panes Tab moves through, in order.
+    cycle: Vec<Focus>,
+}
+
+impl Default for FocusManager {
+    fn default() -> Self {
+        Self {
+            current: None,
+            cycle: vec![
+                Focus::Search,
+                Focus::Input,
+                Focus::Conversation,
+                Focus::NodeList,
+            ],
+        }
+    }
+}
+
+impl FocusManager {
+    pub fn current(&self) -> Option<Focus> {
+        self.current
+    }
+
+    pub fn is(&self, focus: Focus) -> bool {
+        self.current == Some(focus)
+    }
+
+    pub fn set(&mut self, focus: Focus) {
+        self.current = Some(focus);
+    }
+
+    pub fn clear(&mut self) {
+        self.current = None;
+    }
+
+    /// Add a pane to the end of the Tab cycle, unless it is already in it.
+    pub fn add_pane(&mut self, pane: Focus) {
+        if !self.cycle.contains(&pane) {
+            self.cycle.push(pane);
+        }
+    }
+
+    /// Take a pane out of the Tab cycle, unfocusing it if it had the keyboard.
+    pub fn remove_pane(&mut self, pane: Focus) {
+        self.cycle.retain(|&p| p != pane);
+        if self.current == Some(pane) {
+            self.current = None;
+        }
+    }
+
+    pub fn panes(&self) -> &[Focus] {
+        &self.cycle
+    }
+
+    pub fn next(&mut self) {
+        self.step(1);
+    }
+
+    pub fn previous(&mut self) {
+        self.step(-1);
+    }
+
+    /// Move `step` panes along the cycle. With nothing or the note editor focused, go to the
+    /// first.
+    fn step(&mut self, step: isize) {
+        let position = self
+            .current
+            .and_then(|current| self.cycle.iter().position(|&p| p == current));
+        self.current = match position {
+            Some(position) => {
+                let len = self.cycle.len() as isize;
+                let next = (position as isize + step).rem_euclid(len);
+                Some(self.cycle[next as usize])
+            }
+            None => self.cycle.first().copied(),
+        };
+    }
+
+    /// Decide what `key` is for: a key every pane shares, the focused pane, or a command.
+    pub fn route(&self, key: &KeyEvent) -> Route {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => Route::Unfocus,
+            KeyCode::Char('k') if control => Route::Switcher,
+            KeyCode::Char('f' | 'F') if control => Route::GlobalSearch,
+            KeyCode::Tab => Route::Next,
+            KeyCode::BackTab => Route::Previous,
+            _ => self.current.map_or(Route::Command, Route::Pane),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn tab_cycles_through_the_panes() {
+        let mut focus = FocusManager::default();
+        focus.next();
+        assert_eq!(focus.current(), Some(Focus::Search));
+        focus.next();
+        focus.next();
+        focus.next();
+        assert_eq!(focus.current(), Some(Focus::NodeList));
+        focus.next();
+        assert_eq!(focus.current(), Some(Focus::Search));
+        focus.previous();
+        assert_eq!(focus.current(), Some(Focus::NodeList));
+    }
+
+    #[test]
+    fn backtab_from_nothing_starts_at_the_first_pane() {
+        let mut focus = FocusManager::default();
+        focus.previous();
+        assert_eq!(focus.current(), Some(Focus::Search));
+    }
+
+    #[test]
+    fn the_note_editor_leaves_the_cycle() {
+        let mut focus = FocusManager::default();
+        focus.set(Focus::Note);
+        focus.next();
+        assert_eq!(focus.current(), Some(Focus::Search));
+        focus.set(Focus::Note);
+        focus.previous();
+        assert_eq!(focus.current(), Some(Focus::Search));
+    }
+
+    #[test]
+    fn panes_can_be_added_and_removed() {
+        let mut focus = FocusManager::default();
+        focus.add_pane(Focus::Note);
+        focus.add_pane(Focus::Note);
+        assert_eq!(focus.panes().len(), 5);
+        focus.set(Focus::NodeList);
+        focus.next();
+        assert_eq!(focus.current(), Some(Focus::Note));
+        focus.next();
+        assert_eq!(focus.current(), Some(Focus::Search));
+
+        focus.set(Focus::Note);
+        focus.remove_pane(Focus::Note);
+        assert_eq!(focus.current(), None);
+        focus.set(Focus::NodeList);
+        focus.next();
+        assert_eq!(focus.current(), Some(Focus::Search));
+    }
+
+    #[test]
+    fn keys_are_routed() {
+        let mut focus = FocusManager::default();
+        assert_eq!(focus.route(&key(KeyCode::Char('q'))), Route::Command);
+        assert_eq!(focus.route(&key(KeyCode::Tab)), Route::Next);
+        let control_f = KeyEvent::new(
+            KeyCode::Char('F'),
+            KeyModifiers::CONTROL | KeyModifiers::SHIFT,
+        );
+        assert_eq!(focus.route(&control_f), Route::GlobalSearch);
+
+        focus.set(Focus::Input);
+        assert_eq!(
+            focus.route(&key(KeyCode::Char('q'))),
+            Route::Pane(Focus::Input)
+        );
+        assert_eq!(focus.route(&key(KeyCode::Esc)), Route::Unfocus);
+    }
+}
@@ -0,0 +1,204 @@
+//! Structured emergency traffic in the style of the ICS-213 general message form.
+//!
+//! Forms are sent as ordinary text messages so that other clients still see something readable:
+//! `ICS213|<number>|<precedence>|<to>|<from>|<subject>|<body>`. The recipient answers with a
+//! receipt, `ICS213R|<number>`.
+
+const PREFIX: &str = "ICS213";
+const RECEIPT_PREFIX: &str = "ICS213R";
+const SEPARATOR: char = '|';
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum Precedence {
+    #[default]
+    Routine,
+    Welfare,
+    Priority,
+    Emergency,
+}
+
+impl Precedence {
+    pub fn code(self) -> char {
+        match self {
+            Precedence::Routine => 'R',
+            Precedence::Welfare => 'W',
+            Precedence::Priority => 'P',
+            Precedence::Emergency => 'E',
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "R" => Some(Precedence::Routine),
+            "W" => Some(Precedence::Welfare),
+            "P" => Some(Precedence::Priority),
+            "E" => Some(Precedence::Emergency),
+            _ => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Precedence::Routine => "ROUTINE",
+            Precedence::Welfare => "WELFARE",
+            Precedence::Priority => "PRIORITY",
+            Precedence::Emergency => "EMERGENCY",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Precedence::Routine => Precedence::Welfare,
+            Precedence::Welfare => Precedence::Priority,
+            Precedence::Priority => Precedence::Emergency,
+            Precedence::Emergency => Precedence::Routine,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Ics213 {
+    pub number: u32,
+    pub precedence: Precedence,
+    pub to: String,
+    pub from: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Ics213 {
+    /// Serialize the form into its compact on-air representation.
+    ///
+    /// The separator is stripped from every field but the body, which is always last.
+    pub fn encode(&self) -> String {
+        let clean = |s: &str| s.replace(SEPARATOR, "/");
+        format!(
+            "{PREFIX}{SEPARATOR}{}{SEPARATOR}{}{SEPARATOR}{}{SEPARATOR}{}{SEPARATOR}{}{SEPARATOR}{}",
+            self.number,
+            self.precedence.code(),
+            clean(&self.to),
+            clean(&self.from),
+            clean(&self.subject),
+            self.body
+        )
+    }
+
+    /// Parse a received text message, returning `None` if it is not a form.
+    pub fn parse(message: &str) -> Option<Self> {
+        let mut fields = message.splitn(7, SEPARATOR);
+        if fields.next()? != PREFIX {
+            return None;
+        }
+        Some(Self {
+            number: fields.next()?.parse().ok()?,
+            precedence: Precedence::from_code(fields.next()?)?,
+            to: fields.next()?.to_string(),
+            from: fields.next()?.to_string(),
+            subject: fields.next()?.to_string(),
+            body: fields.next()?.to_string(),
+        })
+    }
+}
+
+/// The acknowledgement sent back when a form with the given number is received.
+pub fn encode_receipt(number: u32) -> String {
+    format!("{RECEIPT_PREFIX}{SEPARATOR}{number}")
+}
+
+/// Parse a received receipt, returning the number of the form it acknowledges.
+pub fn parse_receipt(message: &str) -> Option<u32> {
+    let (prefix, number) = message.split_once(SEPARATOR)?;
+    if prefix != RECEIPT_PREFIX {
+        return None;
+    }
+    number.parse().ok()
+}
+
+/// The form currently being composed, along with which field has the cursor.
+#[derive(Debug, Default)]
+pub struct FormDraft {
+    pub form: Ics213,
+    pub field: FormField,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum FormField {
+    #[default]
+    Precedence,
+    To,
+    From,
+    Subject,
+    Body,
+}
+
+impl FormField {
+    pub const ALL: [FormField; 5] = [
+        FormField::Precedence,
+        FormField::To,
+        FormField::From,
+        FormField::Subject,
+        FormField::Body,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FormField::Precedence => "PRECEDENCE",
+            FormField::To => "TO",
+            FormField::From => "FROM",
+            FormField::Subject => "SUBJECT",
+            FormField::Body => "MESSAGE",
+        }
+    }
+}
+
+impl FormDraft {
+    pub fn next_field(&mut self) {
+        let index = FormField::ALL
+            .iter()
+            .position(|f| *f == self.field)
+            .unwrap_or(0);
+        self.field = FormField::ALL[(index + 1) % FormField::ALL.len()];
+    }
+
+    pub fn previous_field(&mut self) {
+        let index = FormField::ALL
+            .iter()
+            .position(|f| *f == self.field)
+            .unwrap_or(0);
+        self.field = FormField::ALL[(index + FormField::ALL.len() - 1) % FormField::ALL.len()];
+    }
+
+    fn text_mut(&mut self) -> Option<&mut String> {
+        match self.field {
+            FormField::Precedence => None,
+            FormField::To => Some(&mut self.form.to),
+            FormField::From => Some(&mut self.form.from),
+            FormField::Subject => Some(&mut self.form.subject),
+            FormField::Body => Some(&mut self.form.body),
+        }
+    }
+
+    pub fn push(&mut self, c: char) {
+        match self.text_mut() {
+            Some(text) => text.push(c),
+            None if c == ' ' => self.form.precedence = self.form.precedence.next(),
+            None => {}
+        }
+    }
+
+    pub fn pop(&mut self) {
+        if let Some(text) = self.text_mut() {
+            text.pop();
+        }
+    }
+
+    pub fn value(&self, field: FormField) -> &str {
+        match field {
+            FormField::Precedence => self.form.precedence.label(),
+            FormField::To => &self.form.to,
+            FormField::From => &self.form.from,
+            FormField::Subject => &self.form.subject,
+            FormField::Body => &self.form.body,
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! ASCII fallbacks for terminals that can't show box drawing, block or braille characters.
+//!
+//! Rather than every widget choosing its symbols, frames are drawn as usual and the affected
+//! characters are swapped in the buffer afterwards: borders and scrollbars become `-`, `|` and
+//! `+`, blocks become `#`, arrows become `^`, `v`, `<` and `>` and the braille dots of the map become `.`.
+
+use std::env;
+
+use ratatui::buffer::Buffer;
+
+/// Whether the terminal should get ASCII only: as configured, or else guessed from the locale
+/// and terminal type.
+pub fn ascii_only(configured: Option<bool>) -> bool {
+    if let Some(ascii) = configured {
+        return ascii;
+    }
+    let term = env::var("TERM").unwrap_or_default();
+    if matches!(term.as_str(), "linux" | "vt100" | "vt220" | "dumb") {
+        return true;
+    }
+    // The first locale variable that is set decides, as for any other program.
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default()
+        .to_lowercase();
+    !(locale.contains("utf-8") || locale.contains("utf8"))
+}
+
+pub fn to_ascii(buffer: &mut Buffer) {
+    for cell in buffer.content.iter_mut() {
+        if let Some(ascii) = cell.symbol().chars().next().and_then(ascii) {
+            cell.set_char(ascii);
+        }
+    }
+}
+
+fn ascii(c: char) -> Option<char> {
+    match c {
+        '─' | '━' | '═' | '┄' | '┅' | '┈' | '┉' | '╌' | '╍' | '╴' | '╶' | '╸' | '╺' => {
+            Some('-')
+        }
+        '│' | '┃' | '║' | '┆' | '┇' | '┊' | '┋' | '╎' | '╏' | '╵' | '╷' | '╹' | '╻' => {
+            Some('|')
+        }
+        '\u{2500}'..='\u{257f}' => Some('+'),
+        '\u{2580}'..='\u{259f}' => Some('#'),
+        '•' => Some('*'),
+        '↑' => Some('^'),
+        '↓' => Some('v'),
+        '←' => Some('<'),
+        '→' => Some('>'),
+        '\u{2800}' => Some(' '),
+        '\u{2801}'..='\u{28ff}' => Some('.'),
+        _ => None,
+    }
+}
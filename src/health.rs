@@ -0,0 +1,120 @@
+//! A summary of how well the mesh around us is doing, and hints when it changes for the worse.
+
+use std::collections::HashMap;
+
+use meshtastic::protobufs::NodeInfo;
+
+use crate::types::{NodeNum, format_node_num};
+
+/// Only nodes heard this recently count towards the summary.
+pub const RECENT_SECONDS: i64 = 2 * 60 * 60;
+/// The firmware starts holding back its own traffic above this channel utilization.
+const BUSY_UTILIZATION: f32 = 25.0;
+const HIGH_LOSS: f64 = 0.5;
+/// A node that was heard directly and now arrives over this many hops is flagged.
+const HOP_JUMP: u32 = 2;
+/// Hints kept for display, oldest dropped first.
+pub const MAX_ANOMALIES: usize = 20;
+
+/// Delivery outcomes of our direct messages, per destination.
+#[derive(Debug, Default)]
+pub struct Deliveries {
+    outcomes: HashMap<NodeNum, (usize, usize)>,
+}
+
+impl Deliveries {
+    pub fn record(&mut self, to: NodeNum, delivered: bool) {
+        let (ok, failed) = self.outcomes.entry(to).or_default();
+        if delivered {
+            *ok += 1;
+        } else {
+            *failed += 1;
+        }
+    }
+
+    /// Fraction of messages to the given nodes that were not delivered, if any were sent.
+    fn loss(&self, nodes: impl Iterator<Item = NodeNum>) -> Option<f64> {
+        let (ok, failed) = nodes
+            .filter_map(|n| self.outcomes.get(&n))
+            .fold((0, 0), |(ok, failed), (o, f)| (ok + o, failed + f));
+        (ok + failed > 0).then(|| failed as f64 / (ok + failed) as f64)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Health {
+    /// Nodes heard without any relay in between.
+    pub direct_neighbors: usize,
+    pub average_hops: Option<f64>,
+    /// Fraction of direct messages to favorite nodes that were not delivered.
+    pub favorite_loss: Option<f64>,
+    /// Channel utilization seen by our own node, in percent.
+    pub channel_utilization: Option<f32>,
+}
+
+impl Health {
+    pub fn compute<'a>(
+        nodes: impl IntoIterator<Item = &'a NodeInfo>,
+        now: i64,
+        deliveries: &Deliveries,
+        channel_utilization: Option<f32>,
+    ) -> Self {
+        let recent: Vec<&NodeInfo> = nodes
+            .into_iter()
+            .filter(|n| now - i64::from(n.last_heard) <= RECENT_SECONDS)
+            .collect();
+        let hops: Vec<u32> = recent.iter().filter_map(|n| n.hops_away).collect();
+        Self {
+            direct_neighbors: hops.iter().filter(|h| **h == 0).count(),
+            average_hops: (!hops.is_empty())
+                .then(|| hops.iter().sum::<u32>() as f64 / hops.len() as f64),
+            favorite_loss: deliveries.loss(recent.iter().filter(|n| n.is_favorite).map(|n| n.num)),
+            channel_utilization,
+        }
+    }
+
+    /// Problems worth pointing out in the summary.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.direct_neighbors == 0 {
+            warnings.push("No nodes heard directly recently".to_string());
+        }
+        if let Some(utilization) = self.channel_utilization
+            && utilization > BUSY_UTILIZATION
+        {
+            warnings.push(format!(
+                "Channel is busy ({:.0}% utilization), expect delays",
+                utilization
+            ));
+        }
+        if let Some(loss) = self.favorite_loss
+            && loss >= HIGH_LOSS
+        {
+            warnings.push(format!(
+                "{:.0}% of messages to favorites were not delivered",
+                loss * 100.0
+            ));
+        }
+        warnings
+    }
+}
+
+/// Watches how many hops each node's packets take to reach us.
+#[derive(Debug, Default)]
+pub struct HopTracker {
+    hops: HashMap<NodeNum, u32>,
+}
+
+impl HopTracker {
+    /// Note the hops of a packet from `node`, returning a hint if its route got much longer.
+    pub fn record(&mut self, node: NodeNum, hops: u32) -> Option<String> {
+        let previous = self.hops.insert(node, hops)?;
+        (previous == 0 && hops >= HOP_JUMP).then(|| {
+            format!(
+                "{} was heard directly, now arrives via {} hops",
+                format_node_num(node),
+                hops
+            )
+        })
+    }
+}
@@ -0,0 +1,120 @@
+//! Import message history exported from the Meshtastic Android app's "Export data" CSV.
+//!
+//! Messages already in the store are skipped, so importing the same export twice is harmless.
+
+use std::{collections::HashSet, fs, path::Path};
+
+use chrono::{Local, NaiveDateTime, TimeZone};
+use color_eyre::eyre::{Result, eyre};
+use meshtastic::protobufs::{NodeInfo, User};
+
+use crate::store::Store;
+use crate::types::{Message, NodeNum, format_node_num, parse_node_num};
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub messages: usize,
+    pub duplicates: usize,
+    pub nodes: usize,
+}
+
+pub fn import_android_csv(path: &Path, store: &mut dyn Store) -> Result<ImportSummary> {
+    let contents = fs::read_to_string(path)?;
+    let mut lines = contents.lines();
+    let header = parse_csv_line(
+        lines
+            .next()
+            .ok_or_else(|| eyre!("{} is empty", path.display()))?,
+    );
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| eyre!("{} has no {:?} column", path.display(), name))
+    };
+    let (date, time, from, sender, payload) = (
+        column("date")?,
+        column("time")?,
+        column("from")?,
+        column("sender name")?,
+        column("payload")?,
+    );
+
+    let mut known: HashSet<NodeNum> = store.get_nodes()?.iter().map(|n| n.num).collect();
+    let mut summary = ImportSummary::default();
+    for line in lines {
+        let fields = parse_csv_line(line);
+        let field = |index: usize| fields.get(index).map(String::as_str).unwrap_or_default();
+        let Some(num) = parse_node_num(field(from)) else {
+            log::warn!("Skipping import row without a sender: {}", line);
+            continue;
+        };
+
+        let name = field(sender);
+        if !name.is_empty() && known.insert(num) {
+            store.upsert_node(&NodeInfo {
+                num,
+                user: Some(User {
+                    id: format_node_num(num),
+                    long_name: name.to_string(),
+                    short_name: name.chars().take(4).collect(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })?;
+            summary.nodes += 1;
+        }
+
+        let text = field(payload);
+        if text.is_empty() {
+            continue;
+        }
+        let Some(ts) = NaiveDateTime::parse_from_str(
+            &format!("{} {}", field(date), field(time)),
+            "%Y-%m-%d %H:%M:%S",
+        )
+        .ok()
+        .and_then(|t| Local.from_local_datetime(&t).earliest()) else {
+            log::warn!("Skipping import row with unreadable time: {}", line);
+            continue;
+        };
+        let message = Message {
+            outgoing: false,
+            ts,
+            channel: 0,
+            text: text.to_string(),
+            snr: None,
+            packet_id: None,
+            estimated_time: false,
+            note: None,
+            reply_id: None,
+        };
+        if store.append_message(num, &message)? {
+            summary.messages += 1;
+        } else {
+            summary.duplicates += 1;
+        }
+    }
+    Ok(summary)
+}
+
+/// Split a CSV line into fields, handling quoted fields and doubled quotes inside them.
+fn parse_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
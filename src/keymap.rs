@@ -0,0 +1,114 @@
+//! Keys for the actions taken with nothing focused, which `[ui.keys]` rebinds by name, e.g.
+//! `probe = "K"`. A key bound to an action no longer does what it did before; the action's own
+//! key still works unless something else is bound to it.
+
+use std::collections::HashMap;
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent};
+
+/// The actions that can be rebound, with their built-in keys.
+pub const ACTIONS: [(&str, char); 29] = [
+    ("command_line", ':'),
+    ("quit", 'q'),
+    ("form", 'f'),
+    ("note", 'n'),
+    ("profiles", 'p'),
+    ("reboot", 'R'),
+    ("lora", 'r'),
+    ("position", 'l'),
+    ("channels", 'c'),
+    ("admin_keys", 'a'),
+    ("mute", 'm'),
+    ("mute_channel", 'M'),
+    ("health", 'w'),
+    ("device_log", 'd'),
+    ("monitor", 'x'),
+    ("probe", 'P'),
+    ("bulk_admin", 'B'),
+    ("structured", 'e'),
+    ("thumbnail", 'i'),
+    ("outbox", 'o'),
+    ("leaderboard", 's'),
+    ("merge", 'u'),
+    ("undo", 'U'),
+    ("transcript", 'T'),
+    ("flows", 'v'),
+    ("export_map", 'g'),
+    ("export_survey", 'h'),
+    ("export_json", 'j'),
+    ("export_traffic", 't'),
+];
+
+/// Configured keys, each mapped to the built-in key of the action bound to it.
+#[derive(Debug, Default)]
+pub struct Keymap(HashMap<char, char>);
+
+impl Keymap {
+    pub fn new(keys: &HashMap<String, String>) -> Self {
+        let mut bindings = HashMap::new();
+        for (action, key) in keys {
+            let Some((_, builtin)) = ACTIONS.iter().find(|(name, _)| name == action) else {
+                log::error!("Failed to bind {:?}: there is no such action", action);
+                continue;
+            };
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(key), None) => {
+                    bindings.insert(key, *builtin);
+                }
+                _ => log::error!("Failed to bind {}: {:?} is not a single key", action, key),
+            }
+        }
+        Self(bindings)
+    }
+
+    /// The key as the built-in bindings know it.
+    pub fn translate(&self, mut key: KeyEvent) -> KeyEvent {
+        if let KeyCode::Char(c) = key.code
+            && let Some(builtin) = self.0.get(&c)
+        {
+            key.code = KeyCode::Char(*builtin);
+        }
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(keys: &[(&str, &str)]) -> Keymap {
+        Keymap::new(
+            &keys
+                .iter()
+                .map(|(action, key)| (action.to_string(), key.to_string()))
+                .collect(),
+        )
+    }
+
+    fn translate(keymap: &Keymap, c: char) -> KeyCode {
+        keymap.translate(KeyEvent::from(KeyCode::Char(c))).code
+    }
+
+    #[test]
+    fn bound_keys_stand_for_their_actions() {
+        let keymap = keymap(&[("probe", "K"), ("undo", "z")]);
+        assert_eq!(translate(&keymap, 'K'), KeyCode::Char('P'));
+        assert_eq!(translate(&keymap, 'z'), KeyCode::Char('U'));
+        assert_eq!(translate(&keymap, 'P'), KeyCode::Char('P'));
+        assert_eq!(translate(&keymap, 'w'), KeyCode::Char('w'));
+    }
+
+    #[test]
+    fn keys_can_be_swapped() {
+        let keymap = keymap(&[("health", "d"), ("device_log", "w")]);
+        assert_eq!(translate(&keymap, 'd'), KeyCode::Char('w'));
+        assert_eq!(translate(&keymap, 'w'), KeyCode::Char('d'));
+    }
+
+    #[test]
+    fn unknown_actions_and_long_keys_are_skipped() {
+        let keymap = keymap(&[("fly", "F"), ("probe", "ctrl-p")]);
+        assert!(keymap.0.is_empty());
+    }
+}
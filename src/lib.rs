@@ -0,0 +1,77 @@
+#![allow(dead_code)]
+
+//! The pieces of edda, shared by the binary, its tests and benchmarks.
+
+pub mod admin_keys;
+pub mod announce;
+pub mod aprs;
+pub mod archive;
+pub mod auto_reply;
+pub mod battery;
+pub mod bridge;
+pub mod bulk_admin;
+pub mod busy_channel;
+pub mod channel_badges;
+pub mod channels;
+pub mod command_line;
+pub mod compression;
+pub mod config;
+#[cfg(unix)]
+pub mod control;
+pub mod conversation_view;
+pub mod daemon;
+#[cfg(all(feature = "dbus", target_os = "linux"))]
+pub mod dbus;
+pub mod device_log;
+pub mod device_time;
+pub mod dirs;
+pub mod discovery;
+pub mod export;
+pub mod fake_device;
+pub mod fixed_position;
+pub mod focus;
+pub mod forms;
+pub mod glyphs;
+pub mod health;
+pub mod import;
+pub mod keymap;
+pub mod lora;
+pub mod macros;
+pub mod mesh;
+pub mod message_actions;
+#[cfg(test)]
+mod mock;
+pub mod monitor;
+pub mod notify;
+pub mod one_shot;
+pub mod outbox;
+pub mod pager;
+pub mod palette;
+pub mod plain;
+pub mod port_picker;
+pub mod presence;
+pub mod probe;
+pub mod profiles;
+pub mod push;
+pub mod radios;
+pub mod remote_reboot;
+pub mod replay;
+pub mod router;
+pub mod scenario;
+pub mod search;
+pub mod session_log;
+pub mod smart_views;
+pub mod stats;
+pub mod store;
+pub mod structured;
+pub mod survey;
+pub mod switcher;
+pub mod threads;
+pub mod thumbnail;
+pub mod traffic;
+pub mod tui;
+pub mod types;
+pub mod undo;
+pub mod view;
+pub mod watchdog;
+pub mod write_behind;
@@ -0,0 +1,277 @@
+//! Guided LoRa setup: region, modem preset and frequency slot, checked against each region's
+//! band plan before anything is sent to the device.
+//!
+//! Band edges, duty cycle and power limits follow the firmware's region table.
+
+use meshtastic::protobufs::config::{
+    LoRaConfig,
+    lo_ra_config::{ModemPreset, RegionCode},
+};
+
+struct Band {
+    start_mhz: f32,
+    end_mhz: f32,
+    /// Percentage of time the radio may transmit.
+    duty_cycle: u8,
+    /// Legal transmit power limit in dBm, or 0 if the firmware default applies.
+    max_power_dbm: u8,
+}
+
+fn band(region: RegionCode) -> Option<Band> {
+    let (start_mhz, end_mhz, duty_cycle, max_power_dbm) = match region {
+        RegionCode::Unset => return None,
+        RegionCode::Us => (902.0, 928.0, 100, 30),
+        RegionCode::Eu433 => (433.0, 434.0, 10, 10),
+        RegionCode::Eu868 => (869.4, 869.65, 10, 27),
+        RegionCode::Cn => (470.0, 510.0, 100, 19),
+        RegionCode::Jp => (920.5, 923.5, 100, 13),
+        RegionCode::Anz => (915.0, 928.0, 100, 30),
+        RegionCode::Kr => (920.0, 923.0, 100, 0),
+        RegionCode::Tw => (920.0, 925.0, 100, 27),
+        RegionCode::Ru => (868.7, 869.2, 100, 20),
+        RegionCode::In => (865.0, 867.0, 100, 30),
+        RegionCode::Nz865 => (864.0, 868.0, 100, 36),
+        RegionCode::Th => (920.0, 925.0, 100, 16),
+        RegionCode::Lora24 => (2400.0, 2483.5, 100, 10),
+        RegionCode::Ua433 => (433.0, 434.7, 10, 10),
+        RegionCode::Ua868 => (868.0, 868.6, 1, 14),
+        RegionCode::My433 => (433.0, 435.0, 100, 20),
+        RegionCode::My919 => (919.0, 924.0, 100, 27),
+        RegionCode::Sg923 => (917.0, 925.0, 100, 20),
+        RegionCode::Ph433 => (433.0, 434.7, 100, 10),
+        RegionCode::Ph868 => (868.0, 869.4, 100, 14),
+        RegionCode::Ph915 => (915.0, 918.0, 100, 24),
+        RegionCode::Anz433 => (433.05, 434.79, 100, 14),
+        RegionCode::Kz433 => (433.075, 434.775, 100, 10),
+        RegionCode::Kz863 => (863.0, 868.0, 100, 30),
+        RegionCode::Np865 => (865.0, 868.0, 100, 30),
+        RegionCode::Br902 => (902.0, 907.5, 100, 30),
+    };
+    Some(Band {
+        start_mhz,
+        end_mhz,
+        duty_cycle,
+        max_power_dbm,
+    })
+}
+
+/// Channel bandwidth of a preset in kHz. 2.4 GHz radios use wider channels.
+fn bandwidth_khz(preset: ModemPreset, region: RegionCode) -> f32 {
+    let wide = region == RegionCode::Lora24;
+    match preset {
+        ModemPreset::ShortTurbo if wide => 1625.0,
+        ModemPreset::ShortTurbo => 500.0,
+        ModemPreset::LongSlow | ModemPreset::LongModerate if wide => 406.25,
+        ModemPreset::LongSlow | ModemPreset::LongModerate => 125.0,
+        ModemPreset::VeryLongSlow if wide => 203.125,
+        ModemPreset::VeryLongSlow => 62.5,
+        _ if wide => 812.5,
+        _ => 250.0,
+    }
+}
+
+/// Spreading factor and coding rate denominator (4/5 to 4/8) of a preset.
+fn modulation(preset: ModemPreset) -> (u32, u32) {
+    match preset {
+        ModemPreset::LongFast => (11, 5),
+        ModemPreset::LongSlow | ModemPreset::VeryLongSlow => (12, 8),
+        ModemPreset::LongModerate => (11, 8),
+        ModemPreset::MediumSlow => (10, 5),
+        ModemPreset::MediumFast => (9, 5),
+        ModemPreset::ShortSlow => (8, 5),
+        ModemPreset::ShortFast | ModemPreset::ShortTurbo => (7, 5),
+    }
+}
+
+/// Preamble length the firmware transmits, in symbols.
+const PREAMBLE_SYMBOLS: f64 = 16.0;
+
+/// Estimated time on air in milliseconds for a packet of `bytes` (header included), using the
+/// Semtech SX127x formula with an explicit header and CRC.
+pub fn airtime_ms(config: &LoRaConfig, bytes: usize) -> f64 {
+    let region = RegionCode::try_from(config.region).unwrap_or_default();
+    let (sf, cr, bandwidth) = if config.use_preset {
+        let preset = ModemPreset::try_from(config.modem_preset).unwrap_or_default();
+        let (sf, cr) = modulation(preset);
+        (sf, cr, bandwidth_khz(preset, region))
+    } else {
+        (
+            config.spread_factor,
+            config.coding_rate,
+            config.bandwidth as f32,
+        )
+    };
+    // LoRa only defines spreading factors 5 to 12; anything else is a bogus custom config.
+    if !(5..=12).contains(&sf) || bandwidth <= 0.0 {
+        return 0.0;
+    }
+    let symbol_ms = f64::from(1u32 << sf) / f64::from(bandwidth);
+    // Low data rate optimisation is required once symbols exceed 16 ms.
+    let low_data_rate = if symbol_ms > 16.0 { 2.0 } else { 0.0 };
+    let sf = f64::from(sf);
+    let payload_symbols = 8.0
+        + (((8 * bytes) as f64 - 4.0 * sf + 28.0 + 16.0) / (4.0 * (sf - low_data_rate)))
+            .ceil()
+            .max(0.0)
+            * f64::from(cr);
+    (PREAMBLE_SYMBOLS + 4.25 + payload_symbols) * symbol_ms
+}
+
+/// Number of frequency slots the preset fits into the region's band.
+pub fn slot_count(region: RegionCode, preset: ModemPreset) -> u32 {
+    band(region).map_or(0, |b| {
+        ((b.end_mhz - b.start_mhz) * 1000.0 / bandwidth_khz(preset, region)).floor() as u32
+    })
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum LoraField {
+    #[default]
+    Region,
+    Preset,
+    Slot,
+}
+
+impl LoraField {
+    pub const ALL: [LoraField; 3] = [LoraField::Region, LoraField::Preset, LoraField::Slot];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LoraField::Region => "REGION",
+            LoraField::Preset => "PRESET",
+            LoraField::Slot => "SLOT",
+        }
+    }
+}
+
+/// Settings being chosen on the LoRa setup screen.
+#[derive(Debug, Default)]
+pub struct LoraDraft {
+    pub region: RegionCode,
+    pub preset: ModemPreset,
+    /// Frequency slot, starting at 1. 0 lets the firmware derive it from the channel name.
+    pub slot: u32,
+    pub field: LoraField,
+}
+
+impl LoraDraft {
+    pub fn from_config(config: &LoRaConfig) -> Self {
+        Self {
+            region: RegionCode::try_from(config.region).unwrap_or_default(),
+            preset: ModemPreset::try_from(config.modem_preset).unwrap_or_default(),
+            slot: config.channel_num,
+            field: LoraField::default(),
+        }
+    }
+
+    pub fn next_field(&mut self) {
+        self.field = match self.field {
+            LoraField::Region => LoraField::Preset,
+            LoraField::Preset => LoraField::Slot,
+            LoraField::Slot => LoraField::Region,
+        };
+    }
+
+    pub fn previous_field(&mut self) {
+        self.field = match self.field {
+            LoraField::Region => LoraField::Slot,
+            LoraField::Preset => LoraField::Region,
+            LoraField::Slot => LoraField::Preset,
+        };
+    }
+
+    /// Step the selected setting forwards or backwards, wrapping around.
+    pub fn step(&mut self, forward: bool) {
+        match self.field {
+            LoraField::Region => {
+                self.region = step_enum(self.region as i32, forward, |v| {
+                    RegionCode::try_from(v).ok()
+                });
+            }
+            LoraField::Preset => {
+                self.preset = step_enum(self.preset as i32, forward, |v| {
+                    ModemPreset::try_from(v).ok()
+                });
+            }
+            LoraField::Slot => {
+                let count = slot_count(self.region, self.preset);
+                self.slot = match (forward, self.slot) {
+                    (true, s) if s >= count => 0,
+                    (true, s) => s + 1,
+                    (false, 0) => count,
+                    (false, s) => s - 1,
+                };
+            }
+        }
+    }
+
+    pub fn value(&self, field: LoraField) -> String {
+        match field {
+            LoraField::Region => self.region.as_str_name().to_string(),
+            LoraField::Preset => self.preset.as_str_name().to_string(),
+            LoraField::Slot if self.slot == 0 => "default (from channel name)".to_string(),
+            LoraField::Slot => format!("{} of {}", self.slot, slot_count(self.region, self.preset)),
+        }
+    }
+
+    /// Why this combination cannot be applied, if it cannot.
+    pub fn error(&self) -> Option<String> {
+        if self.region == RegionCode::Unset {
+            return Some("Choose a region".to_string());
+        }
+        let count = slot_count(self.region, self.preset);
+        if count == 0 {
+            return Some(format!(
+                "{} is too wide for the {} band",
+                self.preset.as_str_name(),
+                self.region.as_str_name()
+            ));
+        }
+        if self.slot > count {
+            return Some(format!("Slot must be at most {}", count));
+        }
+        None
+    }
+
+    /// Regulatory reminders for the chosen region.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = vec!["Only use the region you are physically operating in.".to_string()];
+        if let Some(band) = band(self.region) {
+            warnings.push(format!(
+                "Band {:.3}-{:.3} MHz",
+                band.start_mhz, band.end_mhz
+            ));
+            if band.duty_cycle < 100 {
+                warnings.push(format!(
+                    "Transmissions limited to {}% duty cycle",
+                    band.duty_cycle
+                ));
+            }
+            if band.max_power_dbm > 0 {
+                warnings.push(format!("Legal power limit {} dBm", band.max_power_dbm));
+            }
+        }
+        warnings
+    }
+
+    /// The current LoRa configuration with this draft applied.
+    pub fn apply(&self, current: &LoRaConfig) -> LoRaConfig {
+        LoRaConfig {
+            use_preset: true,
+            region: self.region as i32,
+            modem_preset: self.preset as i32,
+            channel_num: self.slot,
+            ..current.clone()
+        }
+    }
+}
+
+fn step_enum<T>(value: i32, forward: bool, from: impl Fn(i32) -> Option<T>) -> T {
+    let count = (0..).take_while(|v| from(*v).is_some()).count() as i32;
+    let next = if forward {
+        (value + 1) % count
+    } else {
+        (value + count - 1) % count
+    };
+    from(next).expect("enum values are contiguous from zero")
+}
@@ -0,0 +1,121 @@
+//! Key macros, recorded into registers and replayed like vim's, for repetitive tasks such as
+//! sending the same structured report to several nodes.
+//!
+//! With nothing focused, `Q` and a letter or digit start recording into that register, and `Q`
+//! again stops. `@` and the register replay it. Macros last until edda quits.
+
+use std::collections::HashMap;
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent};
+
+#[derive(Default)]
+pub struct Macros {
+    registers: HashMap<char, Vec<KeyEvent>>,
+    /// The register being recorded into, and the keys so far.
+    recording: Option<(char, Vec<KeyEvent>)>,
+    /// `Q` or `@` was pressed, and the register comes next.
+    pending: Option<Pending>,
+}
+
+#[derive(Clone, Copy)]
+enum Pending {
+    Record,
+    Replay,
+}
+
+impl Macros {
+    /// The register being recorded into.
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|(register, _)| *register)
+    }
+
+    /// Take a key pressed, returning the keys to act on: none for keys that control macros, a
+    /// whole macro on replaying one. `command` is whether the key was pressed with nothing
+    /// focused.
+    pub fn keys(&mut self, key: KeyEvent, command: bool) -> Vec<KeyEvent> {
+        if let Some(pending) = self.pending.take() {
+            // Anything but a register cancels.
+            let KeyCode::Char(register) = key.code else {
+                return Vec::new();
+            };
+            if !register.is_ascii_alphanumeric() {
+                return Vec::new();
+            }
+            return match pending {
+                Pending::Record => {
+                    self.recording = Some((register, Vec::new()));
+                    Vec::new()
+                }
+                Pending::Replay => {
+                    let keys = self.registers.get(&register).cloned().unwrap_or_default();
+                    if let Some((_, recorded)) = &mut self.recording {
+                        recorded.extend(&keys);
+                    }
+                    keys
+                }
+            };
+        }
+        match key.code {
+            KeyCode::Char('Q') if command => {
+                match self.recording.take() {
+                    Some((register, keys)) => {
+                        self.registers.insert(register, keys);
+                    }
+                    None => self.pending = Some(Pending::Record),
+                }
+                Vec::new()
+            }
+            KeyCode::Char('@') if command => {
+                self.pending = Some(Pending::Replay);
+                Vec::new()
+            }
+            _ => {
+                if let Some((_, recorded)) = &mut self.recording {
+                    recorded.push(key);
+                }
+                vec![key]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(macros: &mut Macros, c: char, command: bool) -> Vec<KeyEvent> {
+        macros.keys(KeyEvent::from(KeyCode::Char(c)), command)
+    }
+
+    #[test]
+    fn macros_are_recorded_and_replayed() {
+        let mut macros = Macros::default();
+        assert!(press(&mut macros, 'Q', true).is_empty());
+        assert!(press(&mut macros, 'a', false).is_empty());
+        assert_eq!(macros.recording(), Some('a'));
+        // Keys still act while recording.
+        assert_eq!(press(&mut macros, 'e', true).len(), 1);
+        assert_eq!(press(&mut macros, 'Q', false).len(), 1);
+        assert!(press(&mut macros, 'Q', true).is_empty());
+        assert_eq!(macros.recording(), None);
+
+        // `@` and `Q` are typed as usual into a focused pane.
+        assert_eq!(press(&mut macros, '@', false).len(), 1);
+        assert!(press(&mut macros, '@', true).is_empty());
+        let replayed = press(&mut macros, 'a', false);
+        assert_eq!(
+            replayed,
+            [
+                KeyEvent::from(KeyCode::Char('e')),
+                KeyEvent::from(KeyCode::Char('Q'))
+            ]
+        );
+
+        // Unknown registers replay nothing; other keys cancel.
+        press(&mut macros, '@', true);
+        assert!(press(&mut macros, 'z', true).is_empty());
+        press(&mut macros, '@', true);
+        assert!(macros.keys(KeyEvent::from(KeyCode::Esc), true).is_empty());
+        assert_eq!(press(&mut macros, 'a', false).len(), 1);
+    }
+}
@@ -1,5 +1,3 @@
-#![allow(dead_code)]
-
 //! https://docs.rs/meshtastic/latest/meshtastic/
 //! https://docs.rs/sqlite/latest/sqlite/
 //! https://docs.rs/ratatui/latest/ratatui/
@@ -9,55 +7,495 @@
 //! - clear UI for sending messages
 //! - support direct messages
 
-use std::fs::OpenOptions;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
 use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
 use env_logger::Builder;
 use tokio::sync::mpsc;
 
-use crate::tui::App;
+use edda::channel_badges::ChannelBadges;
+use edda::config::{Config, RadioConfig};
+use edda::profiles::Profile;
+use edda::radios::Radio;
+use edda::scenario::{self, Scenario};
+use edda::store::{self, SqliteStore, Store};
+use edda::tui::App;
+use edda::types::{MeshEvent, NodeNum, UiEvent, parse_node_num};
+use edda::write_behind::WriteBehind;
+use edda::{archive, bridge, daemon, dirs, discovery, import, mesh, one_shot, pager, port_picker};
+
+/// How long to wait for nodes on the network to answer.
+const DISCOVERY_WAIT: Duration = Duration::from_millis(1500);
+
+/// The command line. Without a subcommand, edda runs the terminal UI.
+fn cli() -> Command {
+    device_args(Command::new("edda"))
+        .about("A terminal client for Meshtastic")
+        .arg(
+            Arg::new("mock")
+                .long("mock")
+                .value_name("SCENARIO")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all(["device", "port", "tcp"])
+                .help("Run against a simulated device playing a scenario file"),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("N")
+                .value_parser(value_parser!(u64))
+                .requires("mock")
+                .help("Seed for the scenario's losses and delays, to repeat a run"),
+        )
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .global(true)
+                .help("Configuration file to use instead of config.toml"),
+        )
+        .arg(
+            Arg::new("log-file")
+                .long("log-file")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .global(true)
+                .help("File to log to instead of a new one in the log directory"),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Import message history exported from the Meshtastic Android app")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("show")
+                .about("Print the conversation with a node, given by number or name")
+                .arg(Arg::new("node").required(true)),
+        )
+        .subcommand(
+            Command::new("view")
+                .about("Browse stored nodes and history without a radio")
+                .arg(
+                    Arg::new("db")
+                        .long("db")
+                        .value_name("PATH")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Database to browse instead of the configured one"),
+                )
+                .arg(
+                    Arg::new("readonly")
+                        .long("readonly")
+                        .action(ArgAction::SetTrue)
+                        .help("Write nothing to it, e.g. while a daemon is still recording to it"),
+                ),
+        )
+        .subcommand(Command::new("ports").about(
+            "List the serial ports, nodes on the network and Bluetooth radios a device may be on",
+        ))
+        .subcommand(
+            Command::new("bridge")
+                .about("Relay the configured channels between two radios")
+                .arg(Arg::new("first").value_name("PORT").required(true))
+                .arg(Arg::new("second").value_name("PORT").required(true)),
+        )
+        .subcommand(
+            device_args(Command::new("daemon"))
+                .about("Run without a terminal UI, storing everything and serving the API"),
+        )
+        .subcommand(
+            port_args(Command::new("send"))
+                .about("Send a text message and exit once the radio has taken it")
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_name("NODE")
+                        .value_parser(node_num)
+                        .help("Node to send to, by ID or number; broadcast without one"),
+                )
+                .arg(
+                    Arg::new("channel")
+                        .long("channel")
+                        .value_name("INDEX")
+                        .value_parser(value_parser!(u32))
+                        .default_value("0")
+                        .help("Channel to send on"),
+                )
+                .arg(
+                    Arg::new("ack")
+                        .long("ack")
+                        .action(ArgAction::SetTrue)
+                        .requires("to")
+                        .help("Wait for the recipient to acknowledge the message"),
+                )
+                .arg(
+                    Arg::new("text")
+                        .required(true)
+                        .help("The message, or - to read it from standard input"),
+                ),
+        )
+        .subcommand(
+            port_args(Command::new("nodes"))
+                .about("Print the device's node database")
+                .arg(json_arg()),
+        )
+        .subcommand(
+            port_args(Command::new("listen"))
+                .about("Print incoming text messages as they arrive")
+                .arg(json_arg()),
+        )
+}
+
+/// The ways to give the device's port, for the UI and the daemon.
+fn device_args(command: Command) -> Command {
+    port_args(command).arg(
+        Arg::new("device")
+            .value_name("PORT")
+            .conflicts_with_all(["port", "tcp"])
+            .help("The device's serial port, tcp://host[:port] or the path of a unix socket"),
+    )
+}
+
+/// The ways to give the device's port as options, for subcommands with arguments of their own.
+fn port_args(command: Command) -> Command {
+    command
+        .arg(
+            Arg::new("port")
+                .short('p')
+                .long("port")
+                .value_name("PORT")
+                .help("The device's serial port, tcp://host[:port] or the path of a unix socket"),
+        )
+        .arg(
+            Arg::new("tcp")
+                .long("tcp")
+                .value_name("HOST[:PORT]")
+                .conflicts_with("port")
+                .help("Connect to a networked node or meshtasticd, on port 4403 unless given"),
+        )
+}
+
+fn json_arg() -> Arg {
+    Arg::new("json")
+        .long("json")
+        .action(ArgAction::SetTrue)
+        .help("Print JSON instead of text")
+}
 
-mod mesh;
-mod router;
-mod tui;
-mod types;
+fn node_num(value: &str) -> Result<NodeNum, String> {
+    parse_node_num(value).ok_or_else(|| format!("{value:?} is not a node ID such as !a1b2c3d4"))
+}
 
-fn setup_logger() {
-    let start = SystemTime::now();
-    let since_the_epoch = start
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards");
+fn setup_logger(file: Option<&PathBuf>) {
+    let path = match file {
+        Some(file) => file.clone(),
+        None => {
+            let start = SystemTime::now();
+            let since_the_epoch = start
+                .duration_since(UNIX_EPOCH)
+                .expect("Time went backwards");
 
+            let log_dir = dirs::log_dir();
+            fs::create_dir_all(&log_dir).expect("Failed to create log directory");
+            log_dir.join(format!("{}_app.log", since_the_epoch.as_secs()))
+        }
+    };
     let target = Box::new(
         OpenOptions::new()
             .create(true)
             .append(true)
-            .open(format!("{}_app.log", since_the_epoch.as_secs()))
+            .open(path)
             .expect("Failed to open log file"),
     );
 
-    Builder::from_default_env()
-        .target(env_logger::Target::Pipe(target))
-        .init();
+    let mut builder = Builder::from_default_env();
+    // Takes the same filters as RUST_LOG, e.g. `info` or `edda=debug`.
+    if let Ok(level) = env::var("EDDA_LOG_LEVEL") {
+        builder.parse_filters(&level);
+    }
+    builder.target(env_logger::Target::Pipe(target)).init();
 }
 
 fn main() -> Result<()> {
-    setup_logger();
+    let matches = cli().get_matches();
+    setup_logger(matches.get_one("log-file"));
     color_eyre::install()?;
-    let (ui_tx, ui_rx) = mpsc::channel(100);
-    let (mesh_tx, mesh_rx) = mpsc::channel(100);
+    let config = match matches.get_one::<PathBuf>("config") {
+        Some(file) => Config::load_from(file)?,
+        None => Config::load()?,
+    };
+    if let Some(("view", view)) = matches.subcommand() {
+        return run_view(config, view);
+    }
+    // Before the real database is created or opened, which the demo never touches.
+    if matches.subcommand().is_none()
+        && let Some(file) = matches.get_one::<PathBuf>("mock")
+    {
+        let scenario = Scenario::load(file)?;
+        // Without a seed one is picked at random and logged, so a run worth repeating can be.
+        let seed = matches
+            .get_one("seed")
+            .copied()
+            .unwrap_or_else(rand::random);
+        log::info!("Playing mock scenario with seed {}", seed);
+        let (ui_tx, mesh_rx) = scenario::spawn(config.clone(), scenario, seed);
+        // A demo must not leave anything behind in the real database.
+        return run_tui(
+            &config,
+            SqliteStore::open_in_memory()?,
+            None,
+            &[],
+            ui_tx,
+            mesh_rx,
+        );
+    }
+    if let Some(dir) = config.database.parent() {
+        fs::create_dir_all(dir).wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let mut store = SqliteStore::open(&config.database)?;
+
+    match matches.subcommand() {
+        Some(("import", import)) => {
+            let file: &PathBuf = import.get_one("file").expect("required");
+            let summary = import::import_android_csv(file, &mut store)?;
+            println!(
+                "Imported {} messages ({} duplicates skipped) and {} new nodes",
+                summary.messages, summary.duplicates, summary.nodes
+            );
+            return Ok(());
+        }
+        Some(("show", show)) => {
+            let node: &String = show.get_one("node").expect("required");
+            let badges = ChannelBadges::new(&config.ui.channels);
+            return pager::show(&store, node, &badges);
+        }
+        Some(("ports", _)) => {
+            for port in mesh::available_ports() {
+                println!("{port}");
+            }
+            for device in discovery::browse(DISCOVERY_WAIT) {
+                println!("{device}");
+            }
+            #[cfg(feature = "ble")]
+            for port in mesh::ble_ports(mesh::BLE_SCAN) {
+                println!("{port}");
+            }
+            return Ok(());
+        }
+        Some(("bridge", ports)) => {
+            let port = |name| ports.get_one::<String>(name).expect("required").clone();
+            return bridge::run(config, port("first"), port("second"));
+        }
+        Some(("daemon", daemon)) => {
+            let port = device_port(given_port(daemon).as_ref(), &config, false)?;
+            let profile = startup_profile(&config, &store)?;
+            store.enforce_retention(&config.retention);
+            store::spawn_retention_task(config.database.clone(), config.retention.clone());
+            if let Some(archive) = config.archive.clone() {
+                archive::spawn(config.database.clone(), archive);
+            }
+            return daemon::run(config, store, port, profile);
+        }
+        Some(("send", send)) => {
+            let port = device_port(given_port(send).as_ref(), &config, false)?;
+            let mut text = send.get_one::<String>("text").expect("required").clone();
+            if text == "-" {
+                text.clear();
+                io::stdin().read_to_string(&mut text)?;
+                text = text.trim_end().to_string();
+            }
+            return one_shot::send(
+                &config,
+                &mut store,
+                &port,
+                send.get_one("to").copied(),
+                *send.get_one("channel").expect("defaulted"),
+                text,
+                send.get_flag("ack"),
+            );
+        }
+        Some(("nodes", nodes)) => {
+            let port = device_port(given_port(nodes).as_ref(), &config, false)?;
+            return one_shot::nodes(&config, &port, nodes.get_flag("json"));
+        }
+        Some(("listen", listen)) => {
+            let port = device_port(given_port(listen).as_ref(), &config, false)?;
+            return one_shot::listen(&config, &port, listen.get_flag("json"));
+        }
+        _ => {}
+    }
+    // All the configured radios at once, unless a port is given.
+    let transport = match given_port(&matches) {
+        None if !config.radios.is_empty() => None,
+        port => Some(mesh::Transport::parse(&device_port(
+            port.as_ref(),
+            &config,
+            true,
+        )?)),
+    };
+    let profile = startup_profile(&config, &store)?;
+
+    // Before the app loads the nodes, so pruned ones do not show up.
+    store.enforce_retention(&config.retention);
+    store::spawn_retention_task(config.database.clone(), config.retention.clone());
 
     // Run a seperate thread that listens to the Meshtastic interface.
-    std::thread::spawn(move || {
-        if let Err(e) = mesh::run_meshtastic(ui_rx, mesh_tx) {
-            eprintln!("Meshtastic thread error: {}", e);
+    let (ui_tx, mesh_rx) = match &transport {
+        Some(transport) => mesh::spawn(config.clone(), transport.clone()),
+        None => {
+            let radios = config
+                .radios
+                .iter()
+                .map(|radio| Radio {
+                    name: radio.name.clone(),
+                    transport: mesh::Transport::parse(&radio.port),
+                })
+                .collect();
+            mesh::spawn_radios(config.clone(), radios)
         }
+    };
+    if let Some(profile) = profile {
+        mesh::apply_on_connect(&ui_tx, profile);
+    }
+    let radios = match transport {
+        Some(_) => &[][..],
+        None => &config.radios[..],
+    };
+    run_tui(&config, store, transport, radios, ui_tx, mesh_rx)
+}
+
+/// Browse a database without a radio, e.g. one a daemon in the field is still recording to.
+fn run_view(mut config: Config, view: &ArgMatches) -> Result<()> {
+    let database = view
+        .get_one::<PathBuf>("db")
+        .cloned()
+        .unwrap_or_else(|| config.database.clone());
+    let read_only = view.get_flag("readonly");
+    let store = if read_only {
+        SqliteStore::open_read_only(&database)
+    } else {
+        SqliteStore::open(&database)
+    }
+    .wrap_err_with(|| format!("Failed to open {}", database.display()))?;
+    // Nothing reaches the mesh or the outside world from here, and the control socket is left to
+    // whatever is recording.
+    config.control_socket = None;
+    config.aprs = None;
+    config.push.clear();
+    config.announce_file = None;
+    config.session_log_dir = None;
+
+    // In place of the radio: what the app hands it goes nowhere, and it never disconnects.
+    let (ui_tx, mut ui_rx) = mpsc::channel(100);
+    let (mesh_tx, mesh_rx) = mpsc::channel::<MeshEvent>(1);
+    thread::spawn(move || {
+        let _mesh_tx = mesh_tx;
+        while ui_rx.blocking_recv().is_some() {}
     });
 
+    let mut terminal = ratatui::init();
+    let mut app = App::new(ui_tx, mesh_rx, &config, Box::new(WriteBehind::new(store)));
+    app.browse_offline(read_only);
+    let app_result = app.run(&mut terminal);
+    ratatui::restore();
+    app_result
+}
+
+/// The port given with `PORT`, `--port` or `--tcp`, if any.
+fn given_port(matches: &ArgMatches) -> Option<String> {
+    if let Some(host) = matches.get_one::<String>("tcp") {
+        return Some(format!("tcp://{host}"));
+    }
+    // Not every subcommand takes the port as PORT.
+    matches
+        .get_one::<String>("port")
+        .or_else(|| matches.try_get_one("device").ok().flatten())
+        .cloned()
+}
+
+/// The port given on the command line, or else the configured one, or else the serial port the
+/// device is on: the only one with a likely Meshtastic device, or the only one at all. Failing
+/// that, nodes on the network are looked for, and the user picks one of them or of the serial
+/// ports if `pick` is set. With no serial ports, a single node on the network is used.
+fn device_port(given: Option<&String>, config: &Config, pick: bool) -> Result<String> {
+    if let Some(port) = given.or(config.port.as_ref()) {
+        return Ok(port.clone());
+    }
+    let ports = mesh::available_ports();
+    if let [port] = ports
+        .iter()
+        .filter(|port| port.likely_device)
+        .collect::<Vec<_>>()[..]
+    {
+        log::info!("Using {}, the only likely Meshtastic device found", port);
+        return Ok(port.name.clone());
+    }
+    if let [port] = ports.as_slice() {
+        log::info!("Using {}, the only serial port found", port);
+        return Ok(port.name.clone());
+    }
+    let devices = discovery::browse(DISCOVERY_WAIT);
+    match (ports.as_slice(), devices.as_slice()) {
+        ([], [device]) => {
+            log::info!("Using {}, the only node found on the network", device);
+            Ok(device.port())
+        }
+        ([], []) => Err(eyre!(
+            "No device port given and no serial ports or nodes on the network found: pass one, or set port or EDDA_PORT"
+        )),
+        (ports, devices) if pick => {
+            port_picker::pick(ports, devices)?.ok_or_else(|| eyre!("No device port picked"))
+        }
+        (ports, devices) => Err(eyre!(
+            "No device port given: pass one of {}, or set port or EDDA_PORT",
+            ports
+                .iter()
+                .map(|port| port.name.clone())
+                .chain(devices.iter().map(|device| device.port()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// The saved profile the configuration asks to apply on connecting, which must exist.
+fn startup_profile(config: &Config, store: &SqliteStore) -> Result<Option<Profile>> {
+    let Some(name) = &config.profile else {
+        return Ok(None);
+    };
+    let profile = store
+        .get_profiles()?
+        .into_iter()
+        .find(|profile| profile.name == *name)
+        .ok_or_else(|| eyre!("No saved profile named {}", name))?;
+    Ok(Some(profile))
+}
+
+fn run_tui(
+    config: &Config,
+    store: SqliteStore,
+    transport: Option<mesh::Transport>,
+    radios: &[RadioConfig],
+    ui_tx: mpsc::Sender<UiEvent>,
+    mesh_rx: mpsc::Receiver<MeshEvent>,
+) -> Result<()> {
     // Generate the terminal handlers and run the Ratatui application.
     let mut terminal = ratatui::init();
-    let mut app = App::new(ui_tx, mesh_rx);
+    let mut app = App::new(ui_tx, mesh_rx, config, Box::new(WriteBehind::new(store)));
+    app.transport = transport;
+    app.command_line.radios = radios.iter().map(|radio| radio.name.clone()).collect();
     // Take a receiver to transport information between the Meshtastic thread and the terminal thread.
     let app_result = app.run(&mut terminal);
     ratatui::restore();
@@ -1,63 +1,994 @@
-//! Handle communication with a Meshtastic device connected over serial.
+//! Handle communication with a Meshtastic device connected over serial, or with one reached
+//! over TCP or a unix socket, such as `meshtasticd` on the same machine. With the `ble` feature,
+//! a radio can also be paired with over Bluetooth LE, the way the phone apps do.
+//!
+//! Serial ports are named differently on each platform: `/dev/ttyUSB0` or `/dev/ttyACM0` on
+//! Linux, `/dev/cu.usbserial-0001` on macOS, and `COM3` on Windows. The `platform` module keeps
+//! those differences in one place.
 
-use std::env;
+use std::fmt;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::Duration;
 
-use meshtastic::api::StreamApi;
-use meshtastic::types::EncodedMeshPacketData;
-use meshtastic::packet::PacketDestination::Node;
-use meshtastic::{protobufs::PortNum::TextMessageApp, utils};
-use tokio::sync::mpsc;
+use meshtastic::Message as _;
+use meshtastic::api::{ConnectedStreamApi, StreamApi, StreamHandle};
+use meshtastic::errors::Error;
+use meshtastic::packet::PacketDestination::{Local, Node};
+use meshtastic::protobufs::{
+    self, AdminMessage, DeviceMetrics, FromRadio, PortNum::AdminApp, PortNum::PositionApp,
+    PortNum::PrivateApp, PortNum::TelemetryApp, PortNum::TextMessageApp, PortNum::TracerouteApp,
+    Position, RouteDiscovery, Telemetry, admin_message, from_radio, telemetry,
+};
+use meshtastic::types::{EncodedMeshPacketData, NodeId};
+use meshtastic::utils;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::mpsc,
+    time::{Instant, sleep_until},
+};
 
+use crate::compression;
+use crate::config::Config;
+use crate::device_time;
+use crate::monitor;
+use crate::profiles::Profile;
+use crate::radios::{self, Radio};
+use crate::remote_reboot::REBOOT_DELAY_SECONDS;
 use crate::router::Router;
-use crate::types::{MeshEvent, UiEvent};
+use crate::structured::Structured;
+use crate::survey::Survey;
+use crate::types::{
+    BROADCAST, ConnectionStatus, MeshEvent, NodeNum, PACKET_BYTE_LIMIT, UiEvent, format_node_num,
+};
 
+/// Port `meshtasticd` and networked nodes listen on.
+pub(crate) const TCP_PORT: u16 = 4403;
+
+/// Wait before the first attempt to connect again, doubled after each failed attempt up to
+/// `MAX_BACKOFF`.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long to scan for a Bluetooth radio, to connect to it or to list the ones in range.
+#[cfg(feature = "ble")]
+pub const BLE_SCAN: Duration = Duration::from_secs(5);
+
+/// How long the device may take to send its configuration and node database after connecting.
+const CONFIG_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What went wrong talking to the device. All but `ProtoDecode` end the connection.
+#[derive(Debug)]
+pub enum MeshError {
+    /// The serial port could not be opened.
+    Connect(Error),
+    /// The device did not finish sending its configuration in time.
+    ConfigTimeout,
+    /// The device went away, e.g. it was unplugged.
+    SerialGone,
+    /// A packet's payload could not be decoded.
+    ProtoDecode {
+        from: NodeNum,
+        what: &'static str,
+        error: String,
+    },
+    /// The device did not take a packet we sent.
+    SendFailed(Error),
+}
+
+impl MeshError {
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, MeshError::ProtoDecode { .. })
+    }
+
+    /// What the user can do about it.
+    pub fn guidance(&self) -> &'static str {
+        match self {
+            MeshError::Connect(_) => platform::CONNECT_GUIDANCE,
+            MeshError::ConfigTimeout => {
+                "Close other apps connected to the device and wait for it to finish booting; edda \
+                 tries again on its own."
+            }
+            MeshError::SerialGone => {
+                "Check the cable and the device's power; edda reconnects on its own."
+            }
+            MeshError::ProtoDecode { .. } => {
+                "The sender's firmware may be much older or newer than this version of edda \
+                 supports."
+            }
+            MeshError::SendFailed(_) => {
+                "edda reconnects on its own; reboot the device if that does not help."
+            }
+        }
+    }
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshError::Connect(e) => write!(f, "Failed to open the device: {e}"),
+            MeshError::ConfigTimeout => write!(
+                f,
+                "The device did not send its configuration within {} s",
+                CONFIG_TIMEOUT.as_secs()
+            ),
+            MeshError::SerialGone => write!(f, "Lost the connection to the device"),
+            MeshError::ProtoDecode { from, what, error } => write!(
+                f,
+                "Failed to decode {what} from {}: {error}",
+                format_node_num(*from)
+            ),
+            MeshError::SendFailed(e) => write!(f, "Failed to send to the device: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+/// Everything sent after connecting goes through `send_mesh_packet`, so any other library
+/// error is a failed send.
+impl From<Error> for MeshError {
+    fn from(e: Error) -> Self {
+        MeshError::SendFailed(e)
+    }
+}
+
+/// Run the Meshtastic connection on its own thread, returning the channels used to talk to it.
+/// The connection is made again whenever it fails, until the receiver of events is dropped.
+pub fn spawn(
+    config: Config,
+    transport: Transport,
+) -> (mpsc::Sender<UiEvent>, mpsc::Receiver<MeshEvent>) {
+    let (ui_tx, ui_rx) = mpsc::channel(100);
+    let (mesh_tx, mesh_rx) = mpsc::channel(100);
+
+    if let Some(monitor) = config.monitor.clone() {
+        monitor::spawn(monitor, mesh_tx.clone());
+    }
+    std::thread::spawn(move || run_meshtastic(config, transport, ui_rx, mesh_tx));
+
+    (ui_tx, mesh_rx)
+}
+
+/// Like `spawn`, with a connection of its own to each of several radios, the first active. See
+/// `radios` for what the UI sees of each.
+pub fn spawn_radios(
+    config: Config,
+    radios: Vec<Radio>,
+) -> (mpsc::Sender<UiEvent>, mpsc::Receiver<MeshEvent>) {
+    let (ui_tx, ui_rx) = mpsc::channel(100);
+    let (mesh_tx, mesh_rx) = mpsc::channel(100);
+
+    if let Some(monitor) = config.monitor.clone() {
+        monitor::spawn(monitor, mesh_tx.clone());
+    }
+    let links = radios
+        .iter()
+        .map(|radio| {
+            let (radio_tx, radio_rx) = mpsc::channel(100);
+            let (events_tx, events_rx) = mpsc::channel(100);
+            let config = config.clone();
+            let transport = radio.transport.clone();
+            std::thread::spawn(move || run_meshtastic(config, transport, radio_rx, events_tx));
+            (radio_tx, events_rx)
+        })
+        .collect();
+    std::thread::spawn(move || radios::run(radios, links, ui_rx, mesh_tx));
+
+    (ui_tx, mesh_rx)
+}
+
+/// Have the Meshtastic thread apply a saved profile once the device has reported its settings.
+pub fn apply_on_connect(transmitter: &mpsc::Sender<UiEvent>, profile: Profile) {
+    let name = profile.name.clone();
+    if transmitter
+        .try_send(UiEvent::ApplyProfile(profile))
+        .is_err()
+    {
+        log::error!("Failed to queue profile {} for applying", name);
+    }
+}
+
+/// USB vendors of the serial chips and microcontrollers Meshtastic boards use: Silicon Labs
+/// CP210x, WCH CH340 and CH9102, Espressif's native USB, the Adafruit bootloader of nRF52 boards
+/// (RAK4631, T-Echo), Raspberry Pi RP2040 and Seeed.
+const DEVICE_VENDORS: [u16; 6] = [0x10c4, 0x1a86, 0x303a, 0x239a, 0x2e8a, 0x2886];
+
+/// A serial port a device may be on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerialPort {
+    pub name: String,
+    /// The USB product name, if the port is a USB one that has it.
+    pub product: Option<String>,
+    /// Whether the port's USB vendor is one Meshtastic boards use.
+    pub likely_device: bool,
+}
+
+/// Serial ports that may have a device on them, the likely Meshtastic ones first, leaving out the
+/// ones that never do, such as built-in serial ports and Bluetooth ones.
+pub fn available_ports() -> Vec<SerialPort> {
+    match serialport::available_ports() {
+        Ok(ports) => {
+            let mut ports: Vec<SerialPort> = ports
+                .into_iter()
+                .filter(|port| platform::is_device_port(&port.port_name))
+                .map(|port| {
+                    let usb = match port.port_type {
+                        serialport::SerialPortType::UsbPort(usb) => Some(usb),
+                        _ => None,
+                    };
+                    SerialPort {
+                        name: port.port_name,
+                        likely_device: usb
+                            .as_ref()
+                            .is_some_and(|usb| DEVICE_VENDORS.contains(&usb.vid)),
+                        product: usb.and_then(|usb| usb.product),
+                    }
+                })
+                .collect();
+            ports.sort_by_key(|port| !port.likely_device);
+            ports
+        }
+        Err(e) => {
+            log::error!("Failed to list serial ports: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Bluetooth radios in range after scanning for `scan`, as the ports to connect to them by: by
+/// name where they advertise one, as the MAC address is hidden on some platforms.
+#[cfg(feature = "ble")]
+pub fn ble_ports(scan: Duration) -> Vec<String> {
+    let devices = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| Error::StreamBuildError {
+            source: Box::new(e),
+            description: "Failed to start scanning for Bluetooth radios".to_string(),
+        })
+        .and_then(|runtime| runtime.block_on(utils::stream::available_ble_devices(scan)));
+    match devices {
+        Ok(devices) => {
+            let mut ports: Vec<String> = devices
+                .into_iter()
+                .map(|device| match device.name {
+                    Some(name) => format!("ble://{name}"),
+                    None => format!("ble://{}", device.mac_address),
+                })
+                .collect();
+            ports.sort();
+            ports
+        }
+        Err(e) => {
+            log::error!("Failed to scan for Bluetooth radios: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+impl fmt::Display for SerialPort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(product) = &self.product {
+            write!(f, " ({product})")?;
+        }
+        Ok(())
+    }
+}
+
+/// The name to open a port by, accepting the ways users tend to write it, e.g. `com3:`.
+pub fn port_name(port: &str) -> String {
+    platform::port_name(port.trim())
+}
+
+/// How to reach the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    Serial(String),
+    /// `tcp://host[:port]`, on port 4403 unless given.
+    Tcp(String),
+    /// `unix:///path/to/socket`, or just the path of the socket.
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// `ble://name` or `ble://aa:bb:cc:dd:ee:ff`, a radio reached over Bluetooth LE.
+    #[cfg(feature = "ble")]
+    Ble(String),
+}
+
+impl Transport {
+    pub fn parse(port: &str) -> Self {
+        let port = port.trim();
+        if let Some(address) = port.strip_prefix("tcp://") {
+            let address = address.trim_end_matches('/');
+            let has_port = !address.ends_with(']')
+                && address
+                    .rsplit_once(':')
+                    .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+            return if has_port {
+                Transport::Tcp(address.to_string())
+            } else {
+                Transport::Tcp(format!("{address}:{TCP_PORT}"))
+            };
+        }
+        #[cfg(feature = "ble")]
+        if let Some(device) = port.strip_prefix("ble://") {
+            return Transport::Ble(device.trim_end_matches('/').to_string());
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+
+            if let Some(path) = port.strip_prefix("unix://") {
+                return Transport::Unix(PathBuf::from(path));
+            }
+            let is_socket =
+                std::fs::metadata(port).is_ok_and(|metadata| metadata.file_type().is_socket());
+            if is_socket {
+                return Transport::Unix(PathBuf::from(port));
+            }
+        }
+        Transport::Serial(port_name(port))
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Transport::Serial(port) => write!(f, "{port}"),
+            Transport::Tcp(address) => write!(f, "tcp://{address}"),
+            #[cfg(unix)]
+            Transport::Unix(path) => write!(f, "unix://{}", path.display()),
+            #[cfg(feature = "ble")]
+            Transport::Ble(device) => write!(f, "ble://{device}"),
+        }
+    }
+}
+
+/// Connect to the device, and connect again each time the connection fails, waiting longer after
+/// each failure, until the UI goes away. The UI may switch to another device meanwhile.
 #[tokio::main]
 pub async fn run_meshtastic(
+    config: Config,
+    mut transport: Transport,
     mut rx: mpsc::Receiver<UiEvent>,
     tx: mpsc::Sender<MeshEvent>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
-    assert_eq!(args.len(), 2);
-    let port = args[1].clone();
+) {
+    let mut backoff = MIN_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        let started = Instant::now();
+        let error = match connect(config.clone(), &transport, &mut rx, tx.clone()).await {
+            Ok(Some(next)) => {
+                transport = next;
+                backoff = MIN_BACKOFF;
+                attempt = 0;
+                continue;
+            }
+            Ok(None) => return,
+            Err(e) => e,
+        };
+        log::error!("Meshtastic thread error: {}", error);
+        // A connection that lasted starts the backoff over.
+        if started.elapsed() > MAX_BACKOFF {
+            backoff = MIN_BACKOFF;
+            attempt = 0;
+        }
+        attempt += 1;
+        let status = ConnectionStatus::Reconnecting {
+            attempt,
+            after: backoff,
+        };
+        if tx.send(MeshEvent::Error(error)).await.is_err()
+            || tx.send(MeshEvent::ConnectionStatus(status)).await.is_err()
+        {
+            return;
+        }
+        let retry = Instant::now() + backoff;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+        loop {
+            tokio::select! {
+                () = sleep_until(retry) => break,
+                ui_event = rx.recv() => match ui_event {
+                    None => return,
+                    // Try the other device straight away.
+                    Some(UiEvent::Connect(next)) => {
+                        transport = next;
+                        backoff = MIN_BACKOFF;
+                        attempt = 0;
+                        break;
+                    }
+                    Some(_) => {
+                        log::warn!("Not connected to the device, dropping a request from the UI");
+                    }
+                },
+            }
+        }
+    }
+}
 
-    let stream_api = StreamApi::new();
+/// Connect to the device once, and talk to it until either side goes away or the UI asks to
+/// connect elsewhere, returning where.
+async fn connect(
+    config: Config,
+    transport: &Transport,
+    rx: &mut mpsc::Receiver<UiEvent>,
+    tx: mpsc::Sender<MeshEvent>,
+) -> Result<Option<Transport>, MeshError> {
+    log::info!("Connecting to {}", transport);
+    match transport {
+        Transport::Serial(port) => {
+            let stream = utils::stream::build_serial_stream(port.clone(), None, None, None)
+                .map_err(MeshError::Connect)?;
+            run_stream(stream, config, rx, tx).await
+        }
+        Transport::Tcp(address) => {
+            let stream = utils::stream::build_tcp_stream(address.clone())
+                .await
+                .map_err(MeshError::Connect)?;
+            run_stream(stream, config, rx, tx).await
+        }
+        #[cfg(unix)]
+        Transport::Unix(path) => {
+            let stream = tokio::net::UnixStream::connect(path).await.map_err(|e| {
+                MeshError::Connect(Error::StreamBuildError {
+                    source: Box::new(e),
+                    description: format!("Failed to connect to {}", path.display()),
+                })
+            })?;
+            run_stream(StreamHandle::from_stream(stream), config, rx, tx).await
+        }
+        #[cfg(feature = "ble")]
+        Transport::Ble(device) => {
+            use utils::stream::BleId;
 
-    let serial_stream = utils::stream::build_serial_stream(port, None, None, None)?;
-    let (mut pkt_receiver, stream_api) = stream_api.connect(serial_stream).await;
+            // A MAC address, or else the name the radio advertises, such as `ZG1_2ef4`.
+            let id = BleId::from_mac_address(device).unwrap_or_else(|_| BleId::from_name(device));
+            let stream = utils::stream::build_ble_stream(id, BLE_SCAN)
+                .await
+                .map_err(MeshError::Connect)?;
+            run_stream(stream, config, rx, tx).await
+        }
+    }
+}
+
+/// Talk to a device over any stream carrying the Meshtastic stream protocol, until either side
+/// goes away or the UI asks to connect elsewhere, returning where.
+pub async fn run_stream<S>(
+    stream: StreamHandle<S>,
+    config: Config,
+    rx: &mut mpsc::Receiver<UiEvent>,
+    tx: mpsc::Sender<MeshEvent>,
+) -> Result<Option<Transport>, MeshError>
+where
+    S: AsyncReadExt + AsyncWriteExt + Send + 'static,
+{
+    let stream_api = StreamApi::new();
+    let (mut pkt_receiver, stream_api) = stream_api.connect(stream).await;
 
     let config_id = utils::generate_rand_id();
     let mut stream_api = stream_api.configure(config_id).await?;
 
-    let mut router = Router::new(tx);
+    let survey = config.survey_file.map(Survey::new);
+    let status = tx.clone();
+    let mut router = Router::new(tx, survey);
+    let retention = config.retention;
+    // Nodes the device reports while connecting that are due for removal from it.
+    let mut stale_nodes = Vec::new();
+    let mut my_node_num = None;
+    let config_deadline = Instant::now() + CONFIG_TIMEOUT;
+    let mut configured = false;
+    // A profile asked for before the device reported its owner, applied once it has.
+    let mut pending_profile = None;
 
     loop {
         tokio::select! {
-            Some(packet) = pkt_receiver.recv() => {
+            packet = pkt_receiver.recv() => {
+                let Some(packet) = packet else {
+                    return Err(MeshError::SerialGone);
+                };
+                let config_complete = matches!(
+                    packet.payload_variant,
+                    Some(from_radio::PayloadVariant::ConfigCompleteId(_))
+                );
+                if let Some(from_radio::PayloadVariant::MyInfo(info)) = &packet.payload_variant {
+                    my_node_num = Some(info.my_node_num);
+                }
+                if let Some(days) = retention
+                    .nodes_days
+                    .filter(|&days| days > 0 && retention.remove_from_device)
+                    && let Some(num) = stale_node(&packet, days)
+                    && Some(num) != my_node_num
+                {
+                    stale_nodes.push(num);
+                }
                 router.handle_packet_from_radio(packet);
+                if config_complete {
+                    configured = true;
+                    let connected = MeshEvent::ConnectionStatus(ConnectionStatus::Connected);
+                    let _ = status.send(connected).await;
+                    for num in stale_nodes.drain(..) {
+                        log::info!("Removing stale node {} from the device", num);
+                        send_admin(
+                            &mut stream_api,
+                            &mut router,
+                            admin_message::PayloadVariant::RemoveByNodenum(num),
+                        )
+                        .await?;
+                    }
+                    if let Some(profile) = pending_profile.take() {
+                        apply_profile(&mut stream_api, &mut router, &profile).await?;
+                    }
+                }
+            }
+            () = sleep_until(config_deadline), if !configured => {
+                return Err(MeshError::ConfigTimeout);
             }
-            Some(ui_event) = rx.recv() => {
+            ui_event = rx.recv() => {
+                // The UI has gone away.
+                let Some(ui_event) = ui_event else {
+                    break;
+                };
                 match ui_event {
-                    UiEvent::Message { node_id, message } => {
-                        let encoded = EncodedMeshPacketData::new(message.bytes().collect());
+                    UiEvent::Message { node_id, message, channel, reply_id, emoji, outbox } => {
+                        // Only direct messages are acknowledged by their recipient.
+                        let want_ack = node_id.id() != BROADCAST;
+                        let (payload, port) = text_payload(&router, node_id.id(), message);
+                        let encoded = EncodedMeshPacketData::new(payload);
+                        router.set_sending(outbox);
                         stream_api.send_mesh_packet(
                             &mut router,
                             encoded,
-                            TextMessageApp,
+                            port,
                             Node(node_id),
-                            0.into(), // Channel
-                            false, // Want acknowledged
+                            channel.into(),
+                            want_ack,
                             false, // Want response
-                            false, // Echo response
-                            None, // Reply ID
-                            None).await?; // emoji
+                            true, // Echo response, to learn the packet ID for the ACK
+                            reply_id,
+                            emoji.then_some(1)).await?;
+                        router.set_sending(None);
+                        if let Some(outbox) = outbox {
+                            router.report_sent(outbox);
+                        }
+                    }
+                    UiEvent::ApplyProfile(profile) if !configured => {
+                        pending_profile = Some(profile);
+                    }
+                    UiEvent::ApplyProfile(profile) => {
+                        apply_profile(&mut stream_api, &mut router, &profile).await?;
+                    }
+                    UiEvent::SetLoraConfig(lora) => {
+                        log::info!("Applying LoRa config");
+                        let change = set_config(protobufs::config::PayloadVariant::Lora(lora.clone()));
+                        send_settings(&mut stream_api, &mut router, vec![change]).await?;
+                        router.report_applied(MeshEvent::LoraConfig(lora));
+                    }
+                    UiEvent::SetSecurityConfig(security) => {
+                        log::info!("Applying security config");
+                        let change =
+                            set_config(protobufs::config::PayloadVariant::Security(security.clone()));
+                        send_settings(&mut stream_api, &mut router, vec![change]).await?;
+                        router.report_applied(MeshEvent::SecurityConfig(security));
+                    }
+                    UiEvent::SetChannel(channel) => {
+                        log::info!("Updating channel {}", channel.index);
+                        send_admin(
+                            &mut stream_api,
+                            &mut router,
+                            admin_message::PayloadVariant::SetChannel(channel.clone()),
+                        )
+                        .await?;
+                        router.report_applied(MeshEvent::Channel(channel));
+                    }
+                    UiEvent::SetFixedPosition(position) => {
+                        log::info!("Setting fixed position");
+                        send_admin(
+                            &mut stream_api,
+                            &mut router,
+                            admin_message::PayloadVariant::SetFixedPosition(position),
+                        )
+                        .await?;
+                    }
+                    UiEvent::RequestAdminSession(node) => {
+                        log::info!("Requesting an admin session from {}", node);
+                        send_remote_admin(
+                            &mut stream_api,
+                            &mut router,
+                            node,
+                            admin_message::PayloadVariant::GetDeviceMetadataRequest(true),
+                            Vec::new(),
+                        )
+                        .await?;
+                    }
+                    UiEvent::PrivateData { to, payload } => {
+                        stream_api.send_mesh_packet(
+                            &mut router,
+                            payload.into(),
+                            PrivateApp,
+                            Node(NodeId::new(to)),
+                            0.into(),
+                            false, // Lost pieces are asked for again
+                            false,
+                            false,
+                            None,
+                            None).await?;
+                    }
+                    UiEvent::Probe(node) => {
+                        log::info!("Probing {}", node);
+                        send_remote_admin(
+                            &mut stream_api,
+                            &mut router,
+                            node,
+                            admin_message::PayloadVariant::GetDeviceMetadataRequest(true),
+                            Vec::new(),
+                        )
+                        .await?;
+                        let telemetry = Telemetry {
+                            variant: Some(telemetry::Variant::DeviceMetrics(DeviceMetrics::default())),
+                            ..Telemetry::default()
+                        };
+                        let requests = [
+                            (PositionApp, Position::default().encode_to_vec()),
+                            (TelemetryApp, telemetry.encode_to_vec()),
+                            (TracerouteApp, RouteDiscovery::default().encode_to_vec()),
+                        ];
+                        for (port, payload) in requests {
+                            stream_api.send_mesh_packet(
+                                &mut router,
+                                payload.into(),
+                                port,
+                                Node(NodeId::new(node)),
+                                0.into(),
+                                false,
+                                true, // Want response
+                                false,
+                                None,
+                                None).await?;
+                        }
+                    }
+                    UiEvent::RemoteAdmin { node, payload, passkey } => {
+                        log::info!("Sending an admin message to {}", node);
+                        send_remote_admin(&mut stream_api, &mut router, node, *payload, passkey)
+                            .await?;
+                    }
+                    UiEvent::RebootNode { node, passkey } => {
+                        log::info!("Rebooting {}", node);
+                        send_remote_admin(
+                            &mut stream_api,
+                            &mut router,
+                            node,
+                            admin_message::PayloadVariant::RebootSeconds(REBOOT_DELAY_SECONDS),
+                            passkey,
+                        )
+                        .await?;
+                    }
+                    UiEvent::Connect(transport) => return Ok(Some(transport)),
+                    UiEvent::SwitchRadio(name) => {
+                        log::warn!("Connected to a single radio, cannot switch to {}", name);
                     }
                 }
             }
-            else => {
-                break;
-            }
         }
     }
 
+    Ok(None)
+}
+
+/// The payload and port to send a text message on. Structured messages go as JSON to nodes we
+/// know run edda and as plain text to everyone else, and text too long for one packet goes
+/// compressed, which only edda clients can read.
+fn text_payload(router: &Router, to: NodeNum, message: String) -> (Vec<u8>, protobufs::PortNum) {
+    if let Some(structured) = Structured::from_text(&message) {
+        return if to != BROADCAST && router.is_edda(to) {
+            (structured.encode(), PrivateApp)
+        } else {
+            (structured.fallback().into_bytes(), TextMessageApp)
+        };
+    }
+    let compressed = (message.len() > PACKET_BYTE_LIMIT)
+        .then(|| compression::compress(&message))
+        .flatten();
+    match compressed {
+        Some(payload) => (payload, PrivateApp),
+        None => (message.into_bytes(), TextMessageApp),
+    }
+}
+
+/// The node a device reports in its node database, if it was last heard more than `days` ago and
+/// is not a favorite.
+fn stale_node(packet: &FromRadio, days: u32) -> Option<u32> {
+    let Some(from_radio::PayloadVariant::NodeInfo(node)) = &packet.payload_variant else {
+        return None;
+    };
+    let last_heard = device_time::plausible(node.last_heard)?;
+    let stale = chrono::Local::now() - last_heard > chrono::Duration::days(i64::from(days));
+    (stale && !node.is_favorite).then_some(node.num)
+}
+
+/// Send an admin message to the device we are connected to.
+async fn send_admin(
+    stream_api: &mut ConnectedStreamApi,
+    router: &mut Router,
+    payload: admin_message::PayloadVariant,
+) -> Result<(), Error> {
+    let message = AdminMessage {
+        payload_variant: Some(payload),
+        session_passkey: Vec::new(),
+    };
+    stream_api
+        .send_mesh_packet(
+            router,
+            message.encode_to_vec().into(),
+            AdminApp,
+            Local,
+            0.into(),
+            true,
+            false,
+            false,
+            None,
+            None,
+        )
+        .await
+}
+
+/// Send an admin message to another node, which answers requests to us. The firmware encrypts
+/// it with our node's key, which must be one of the remote node's admin keys.
+async fn send_remote_admin(
+    stream_api: &mut ConnectedStreamApi,
+    router: &mut Router,
+    node: NodeNum,
+    payload: admin_message::PayloadVariant,
+    session_passkey: Vec<u8>,
+) -> Result<(), Error> {
+    let message = AdminMessage {
+        payload_variant: Some(payload),
+        session_passkey,
+    };
+    stream_api
+        .send_mesh_packet(
+            router,
+            message.encode_to_vec().into(),
+            AdminApp,
+            Node(NodeId::new(node)),
+            0.into(),
+            true,
+            true,
+            true, // Echo response, to learn the packet ID for the ACK
+            None,
+            None,
+        )
+        .await
+}
+
+/// Send settings changes as one transaction, so the device only restarts once.
+async fn send_settings(
+    stream_api: &mut ConnectedStreamApi,
+    router: &mut Router,
+    changes: Vec<admin_message::PayloadVariant>,
+) -> Result<(), Error> {
+    send_admin(
+        stream_api,
+        router,
+        admin_message::PayloadVariant::BeginEditSettings(true),
+    )
+    .await?;
+    for change in changes {
+        send_admin(stream_api, router, change).await?;
+    }
+    send_admin(
+        stream_api,
+        router,
+        admin_message::PayloadVariant::CommitEditSettings(true),
+    )
+    .await
+}
+
+fn set_config(config: protobufs::config::PayloadVariant) -> admin_message::PayloadVariant {
+    admin_message::PayloadVariant::SetConfig(protobufs::Config {
+        payload_variant: Some(config),
+    })
+}
+
+async fn apply_profile(
+    stream_api: &mut ConnectedStreamApi,
+    router: &mut Router,
+    profile: &Profile,
+) -> Result<(), Error> {
+    let Some(owner) = router.user_mut().map(|user| profile.owner(user)) else {
+        log::warn!(
+            "Cannot apply profile {} before the device has reported its owner",
+            profile.name
+        );
+        return Ok(());
+    };
+    let device = router.device_config_mut().map(|d| profile.device_config(d));
+    log::info!("Applying profile {}", profile.name);
+
+    let mut changes = vec![admin_message::PayloadVariant::SetOwner(owner.clone())];
+    match &device {
+        Some(device) => changes.push(set_config(protobufs::config::PayloadVariant::Device(
+            device.clone(),
+        ))),
+        None => log::warn!("Device config unknown, not changing role"),
+    }
+    send_settings(stream_api, router, changes).await?;
+
+    if let Some(user) = router.user_mut() {
+        *user = owner;
+    }
+    if let (Some(current), Some(device)) = (router.device_config_mut(), device) {
+        *current = device;
+    }
     Ok(())
 }
+
+#[cfg(windows)]
+mod platform {
+    pub const CONNECT_GUIDANCE: &str = "Check the COM port in Device Manager, that the board is \
+        plugged in and its USB driver (CP210x or CH9102) installed, and that no other app has \
+        the port open.";
+
+    pub fn is_device_port(port: &str) -> bool {
+        port.starts_with("COM")
+    }
+
+    /// Port names are case insensitive, and ports past `COM9` need the device namespace, which
+    /// the serial library adds.
+    pub fn port_name(port: &str) -> String {
+        let port = port.strip_suffix(':').unwrap_or(port);
+        if port.len() > 3
+            && port
+                .get(..3)
+                .is_some_and(|com| com.eq_ignore_ascii_case("com"))
+        {
+            port.to_ascii_uppercase()
+        } else {
+            port.to_string()
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    pub const CONNECT_GUIDANCE: &str = "Check the port name, that the board is plugged in, and \
+        that no other app has the port open. Use the /dev/cu.* name of the port.";
+
+    /// Every port shows up twice, as `/dev/tty.*` for incoming calls and `/dev/cu.*` for
+    /// outgoing ones, and only the latter opens without waiting for a modem.
+    pub fn is_device_port(port: &str) -> bool {
+        port.starts_with("/dev/cu.")
+            && !port.contains("Bluetooth")
+            && !port.ends_with("debug-console")
+    }
+
+    pub fn port_name(port: &str) -> String {
+        match port.strip_prefix("/dev/tty.") {
+            Some(name) => format!("/dev/cu.{name}"),
+            None => port.to_string(),
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+mod platform {
+    pub const CONNECT_GUIDANCE: &str = "Check the port name, that the board is plugged in, and \
+        that you may open it (membership of the dialout group, or uucp on some distributions).";
+
+    /// USB serial adapters and boards with native USB; `/dev/ttyS*` are built-in ports.
+    pub fn is_device_port(port: &str) -> bool {
+        port.starts_with("/dev/ttyUSB") || port.starts_with("/dev/ttyACM")
+    }
+
+    pub fn port_name(port: &str) -> String {
+        port.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(windows)]
+    #[test]
+    fn com_ports_are_normalised() {
+        assert_eq!(port_name("com3"), "COM3");
+        assert_eq!(port_name(" COM12: "), "COM12");
+        assert_eq!(port_name(r"\\.\COM4"), r"\\.\COM4");
+        assert!(platform::is_device_port("COM3"));
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn call_out_devices_are_used() {
+        assert_eq!(
+            port_name("/dev/tty.usbserial-0001"),
+            "/dev/cu.usbserial-0001"
+        );
+        assert!(platform::is_device_port("/dev/cu.usbmodem101"));
+        assert!(!platform::is_device_port("/dev/tty.usbmodem101"));
+        assert!(!platform::is_device_port("/dev/cu.Bluetooth-Incoming-Port"));
+    }
+
+    #[cfg(not(any(windows, target_os = "macos")))]
+    #[test]
+    fn usb_serial_devices_are_listed() {
+        assert_eq!(port_name(" /dev/ttyUSB0\n"), "/dev/ttyUSB0");
+        assert!(platform::is_device_port("/dev/ttyACM0"));
+        assert!(!platform::is_device_port("/dev/ttyS0"));
+    }
+
+    #[test]
+    fn network_addresses_get_the_default_port() {
+        assert_eq!(
+            Transport::parse("tcp://localhost"),
+            Transport::Tcp("localhost:4403".to_string())
+        );
+        assert_eq!(
+            Transport::parse("tcp://192.168.1.20:4404/"),
+            Transport::Tcp("192.168.1.20:4404".to_string())
+        );
+        assert_eq!(
+            Transport::parse("tcp://[::1]"),
+            Transport::Tcp("[::1]:4403".to_string())
+        );
+    }
+
+    #[cfg(feature = "ble")]
+    #[test]
+    fn bluetooth_radios_are_named_by_scheme() {
+        let transport = Transport::parse("ble://ZG1_2ef4/");
+        assert_eq!(transport, Transport::Ble("ZG1_2ef4".to_string()));
+        assert_eq!(transport.to_string(), "ble://ZG1_2ef4");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_sockets_are_recognised() {
+        let dir = std::env::temp_dir().join(format!("edda-socket-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("meshtasticd.sock");
+        let _ = std::fs::remove_file(&path);
+        let _listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let port = path.to_str().unwrap();
+        assert_eq!(Transport::parse(port), Transport::Unix(path.clone()));
+        assert_eq!(
+            Transport::parse(&format!("unix://{port}")),
+            Transport::Unix(path.clone())
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn listing_ports_does_not_fail() {
+        // Whatever is plugged in, listing the ports must work on every platform CI runs on.
+        for port in available_ports() {
+            assert_eq!(port_name(&port.name), port.name);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_connections_are_retried_with_backoff() {
+        let (ui_tx, ui_rx) = mpsc::channel(1);
+        let (tx, mut rx) = mpsc::channel(10);
+        let transport = Transport::Unix(PathBuf::from("/nonexistent/edda.sock"));
+        let thread =
+            std::thread::spawn(move || run_meshtastic(Config::default(), transport, ui_rx, tx));
+
+        for (attempt, seconds) in [(1, 1), (2, 2)] {
+            assert!(matches!(
+                rx.blocking_recv(),
+                Some(MeshEvent::Error(MeshError::Connect(_)))
+            ));
+            assert!(matches!(
+                rx.blocking_recv(),
+                Some(MeshEvent::ConnectionStatus(ConnectionStatus::Reconnecting { attempt: a, after }))
+                    if a == attempt && after == Duration::from_secs(seconds)
+            ));
+        }
+        // Stops once the UI has gone away.
+        drop(ui_tx);
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+//! Actions on a single message, offered in one menu opened with ENTER on a selected message.
+
+use std::io::{self, Write};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use meshtastic::Message as _;
+use meshtastic::protobufs::{MeshPacket, PortNum, mesh_packet};
+
+use crate::device_time;
+use crate::types::Message;
+
+/// The reaction sent by the react action.
+pub const REACTION: &str = "👍";
+
+/// Whether a packet is an emoji reaction to another message rather than a message of its own.
+pub fn is_reaction(packet: &MeshPacket) -> bool {
+    match &packet.payload_variant {
+        Some(mesh_packet::PayloadVariant::Decoded(data)) => data.emoji != 0,
+        _ => false,
+    }
+}
+
+/// Packet ID of the message a packet replies or reacts to, if any.
+pub fn reply_id(packet: &MeshPacket) -> Option<u32> {
+    match &packet.payload_variant {
+        Some(mesh_packet::PayloadVariant::Decoded(data)) => {
+            (data.reply_id != 0).then_some(data.reply_id)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageAction {
+    Reply,
+    React,
+    Copy,
+    Details,
+    Note,
+    Resend,
+    Delete,
+}
+
+impl MessageAction {
+    pub const ALL: [MessageAction; 7] = [
+        MessageAction::Reply,
+        MessageAction::React,
+        MessageAction::Copy,
+        MessageAction::Details,
+        MessageAction::Note,
+        MessageAction::Resend,
+        MessageAction::Delete,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MessageAction::Reply => "Reply",
+            MessageAction::React => "React",
+            MessageAction::Copy => "Copy text",
+            MessageAction::Details => "Packet details",
+            MessageAction::Note => "Note",
+            MessageAction::Resend => "Re-send",
+            MessageAction::Delete => "Delete locally",
+        }
+    }
+}
+
+/// What is known about the packet a message arrived in or left as. `route` describes how the
+/// acknowledgement of a delivered message came back.
+pub fn details(message: &Message, contact: &str, route: Option<String>) -> Vec<String> {
+    let direction = if message.outgoing {
+        format!("To: {contact}")
+    } else {
+        format!("From: {contact}")
+    };
+    let mut details = vec![
+        direction,
+        if message.estimated_time {
+            format!(
+                "Time: ~{} (device clock not set)",
+                message.ts.format("%Y-%m-%d %H:%M:%S")
+            )
+        } else {
+            format!("Time: {}", message.ts.format("%Y-%m-%d %H:%M:%S"))
+        },
+        format!("Channel: {}", message.channel),
+        match message.snr {
+            Some(snr) => format!("SNR: {snr:.1} dB"),
+            None => "SNR: unknown".to_string(),
+        },
+        match message.packet_id {
+            Some(id) => format!("Packet ID: {id:#010x}"),
+            None => "Packet ID: unknown".to_string(),
+        },
+        format!("Length: {} bytes", message.text.len()),
+    ];
+    if let Some(note) = &message.note {
+        details.push(format!("Note: {note}"));
+    }
+    if let Some(route) = route {
+        details.push(format!("ACK route: {route}"));
+    }
+    details
+}
+
+/// Receive metadata of a packet, beyond what is stored with its message.
+pub fn packet_details(packet: &MeshPacket) -> Vec<String> {
+    let port = match &packet.payload_variant {
+        Some(mesh_packet::PayloadVariant::Decoded(data)) => PortNum::try_from(data.portnum)
+            .map_or_else(
+                |_| data.portnum.to_string(),
+                |port| port.as_str_name().to_string(),
+            ),
+        _ => "encrypted".to_string(),
+    };
+    let mut details = vec![
+        format!("Port: {port}"),
+        format!("RSSI: {} dBm", packet.rx_rssi),
+        format!("Hop limit: {} of {}", packet.hop_limit, packet.hop_start),
+    ];
+    match device_time::plausible(packet.rx_time) {
+        Some(time) => details.push(format!("Radio time: {}", time.format("%Y-%m-%d %H:%M:%S"))),
+        None if packet.rx_time > 0 => details.push(format!(
+            "Radio time: clock not set ({}s since boot?)",
+            packet.rx_time
+        )),
+        None => {}
+    }
+    if packet.relay_node != 0 {
+        details.push(format!(
+            "Relayed by: node ending in {:02x}",
+            packet.relay_node
+        ));
+    }
+    if packet.via_mqtt {
+        details.push("Via MQTT".to_string());
+    }
+    if packet.pki_encrypted {
+        details.push("Encrypted with PKI".to_string());
+    }
+    details
+}
+
+/// A packet decoded field by field, then its payload and its whole protobuf encoding in hex.
+pub fn raw_packet(packet: &MeshPacket) -> Vec<String> {
+    // Debug formatting puts every byte on a line of its own, so byte fields are dumped apart.
+    let mut fields = packet.clone();
+    let public_key = std::mem::take(&mut fields.public_key);
+    let payload = match &mut fields.payload_variant {
+        Some(mesh_packet::PayloadVariant::Decoded(data)) => std::mem::take(&mut data.payload),
+        Some(mesh_packet::PayloadVariant::Encrypted(bytes)) => std::mem::take(bytes),
+        None => Vec::new(),
+    };
+    let mut lines: Vec<String> = format!("{fields:#?}").lines().map(String::from).collect();
+    if !public_key.is_empty() {
+        lines.push("Public key:".to_string());
+        lines.extend(hex_dump(&public_key));
+    }
+    lines.push("Payload:".to_string());
+    lines.extend(hex_dump(&payload));
+    lines.push("Packet:".to_string());
+    lines.extend(hex_dump(&packet.encode_to_vec()));
+    lines
+}
+
+fn hex_dump(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+            format!("{:04x}  {}", row * 16, hex.join(" "))
+        })
+        .collect()
+}
+
+/// Put text on the clipboard with an OSC 52 escape. The terminal does the copying, so this
+/// works over SSH too, in terminals that allow it.
+pub fn copy_to_clipboard(text: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", STANDARD.encode(text))?;
+    stdout.flush()
+}
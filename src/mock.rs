@@ -0,0 +1,1393 @@
+//! End-to-end tests of the whole stack against a scripted fake device, so they run without a
+//! radio.
+//!
+//! The real mesh thread (`mesh::run_stream` with its `Router`) connects to a `FakeDevice`, and an
+//! `App` backed by a write-behind in-memory store consumes the events, so each test covers radio
+//! → router → app → store and back. Tests script the device: what it answers to the config
+//! handshake, which packets it "receives" from the mesh, how it acknowledges what we send, and
+//! how lossy, slow or prone to duplicates the link is, to exercise retries, ACKs and
+//! deduplication.
+
+use std::time::Duration;
+
+use meshtastic::Message as _;
+use meshtastic::api::StreamHandle;
+use meshtastic::protobufs::{
+    AdminMessage, DeviceMetadata, DeviceMetrics, LogRecord, MeshPacket, MyNodeInfo, NodeInfo,
+    PortNum, Position, RouteDiscovery, Telemetry, Waypoint, admin_message, config, from_radio,
+    log_record::Level, mesh_packet, routing, telemetry, to_radio,
+};
+use ratatui::{
+    Frame,
+    crossterm::event::{KeyCode, KeyEvent, KeyModifiers},
+    layout::Rect,
+};
+use tokio::{
+    sync::mpsc,
+    time::{Instant, sleep},
+};
+
+use crate::bulk_admin::{BulkAdmin, NodeState};
+use crate::compression;
+use crate::config::{Config, ExperimentalConfig, SmartViewConfig};
+use crate::fake_device::{Conditions, FakeDevice, node};
+use crate::mesh::{self, MeshError, Transport};
+use crate::message_actions::MessageAction;
+use crate::outbox::OutboxState;
+use crate::remote_reboot::Stage;
+use crate::scenario::{self, Scenario};
+use crate::search::SearchResult;
+use crate::stats::{self, Flow};
+use crate::store::SqliteStore;
+use crate::structured::Structured;
+use crate::threads;
+use crate::thumbnail::{Thumbnail, Transfers};
+use crate::tui::{
+    App, CommandLineView, DeviceLogView, HealthView, MessageActionsView, OutboxView, SwitcherView,
+};
+use crate::types::{BROADCAST, Focus, MeshEvent, NodeNum, format_node_num};
+use crate::view::{Action, View};
+use crate::write_behind::WriteBehind;
+
+const MY_NODE: NodeNum = 0x0000_1234;
+const PEER: NodeNum = 0xa1b2_c3d4;
+/// How long a test waits for something to happen before failing.
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Seeds the link conditions, so every run loses and duplicates the same packets.
+const SEED: u64 = 42;
+
+impl FakeDevice {
+    /// Start the mesh thread and an app connected to a new fake device.
+    fn connect() -> (Self, App) {
+        Self::connect_with(Config::default())
+    }
+
+    fn connect_with(config: Config) -> (Self, App) {
+        let (device, client) = tokio::io::duplex(64 * 1024);
+        let (ui_tx, mut ui_rx) = mpsc::channel(100);
+        let (mesh_tx, mesh_rx) = mpsc::channel(100);
+        let mesh_config = config.clone();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            let stream = StreamHandle::from_stream(client);
+            // Ends with an error once the test drops the device, reported like `mesh::spawn` does.
+            let result = runtime.block_on(mesh::run_stream(
+                stream,
+                mesh_config,
+                &mut ui_rx,
+                mesh_tx.clone(),
+            ));
+            if let Err(e) = result {
+                let _ = mesh_tx.blocking_send(MeshEvent::Error(e));
+            }
+        });
+        let store = SqliteStore::open_in_memory().unwrap();
+        let app = App::new(ui_tx, mesh_rx, &config, Box::new(WriteBehind::new(store)));
+        (FakeDevice::new(device, MY_NODE, SEED), app)
+    }
+
+    async fn recv_packet(&mut self) -> MeshPacket {
+        match self.recv().await.unwrap() {
+            to_radio::PayloadVariant::Packet(packet) => packet,
+            other => panic!("expected a mesh packet, got {:?}", other),
+        }
+    }
+
+    /// Answer the client's config request with our node and one favorite neighbor.
+    async fn handshake(&mut self) {
+        let config_id = match self.recv().await.unwrap() {
+            to_radio::PayloadVariant::WantConfigId(id) => id,
+            other => panic!("expected a config request, got {:?}", other),
+        };
+        let peer = NodeInfo {
+            is_favorite: true,
+            hops_away: Some(0),
+            last_heard: chrono::Local::now().timestamp() as u32,
+            ..node(PEER, "Peer")
+        };
+        self.answer_config(config_id, vec![node(MY_NODE, "Base"), peer])
+            .await
+            .unwrap();
+    }
+}
+
+/// Let the app handle events until `done` holds, failing the test after `TIMEOUT`.
+async fn until(app: &mut App, what: &str, done: impl Fn(&App) -> bool) {
+    let deadline = Instant::now() + TIMEOUT;
+    while !done(app) {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        app.update();
+        sleep(Duration::from_millis(5)).await;
+    }
+}
+
+#[tokio::test]
+async fn handshake_reports_own_and_neighbor_nodes() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+
+    until(&mut app, "our own node", |app| app.my_node.is_some()).await;
+    until(&mut app, "the neighbor", |app| {
+        app.nodes.contains_key(&PEER)
+    })
+    .await;
+    assert_eq!(app.my_node.as_ref().unwrap().num, MY_NODE);
+    assert!(app.nodes[&PEER].is_favorite);
+    assert_eq!(app.store.get_nodes().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn received_message_is_shown_and_stored() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    device
+        .deliver(PEER, PortNum::TextMessageApp, b"hello base".to_vec(), 0)
+        .await
+        .unwrap();
+
+    until(&mut app, "the message", |app| {
+        app.conversations.contains_key(&PEER)
+    })
+    .await;
+    let message = &app.conversations[&PEER][0];
+    assert!(!message.outgoing);
+    assert_eq!(message.text, "hello base");
+    assert_eq!(message.snr, Some(6.5));
+    // The fake device's clock is not set.
+    assert!(message.estimated_time);
+    let packet = &app.raw_packets[&message.packet_id.unwrap()];
+    assert_eq!(packet.from, PEER);
+    let stored = app.store.get_messages().unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].0, PEER);
+}
+
+#[tokio::test]
+async fn direct_message_is_sent_and_acknowledged() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    until(&mut app, "the neighbor", |app| {
+        app.nodes.contains_key(&PEER)
+    })
+    .await;
+
+    app.send_message(PEER, "ping".to_string());
+    let sent = device.recv_packet().await;
+    assert_eq!(sent.to, PEER);
+    assert!(sent.want_ack);
+    match &sent.payload_variant {
+        Some(mesh_packet::PayloadVariant::Decoded(data)) => {
+            assert_eq!(data.portnum, PortNum::TextMessageApp as i32);
+            assert_eq!(data.payload, b"ping");
+        }
+        other => panic!("expected a decoded packet, got {:?}", other),
+    }
+    assert!(app.conversations[&PEER][0].outgoing);
+
+    device.ack(&sent, routing::Error::None).await.unwrap();
+    until(&mut app, "the delivery", |app| {
+        app.health().favorite_loss == Some(0.0)
+    })
+    .await;
+    assert!(app.outbox.is_empty());
+}
+
+#[tokio::test]
+async fn failed_delivery_counts_as_loss() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    until(&mut app, "the neighbor", |app| {
+        app.nodes.contains_key(&PEER)
+    })
+    .await;
+
+    app.send_message(PEER, "anyone there?".to_string());
+    let sent = device.recv_packet().await;
+    device
+        .ack(&sent, routing::Error::MaxRetransmit)
+        .await
+        .unwrap();
+    until(&mut app, "the failure", |app| {
+        app.health().favorite_loss == Some(1.0)
+    })
+    .await;
+    assert_eq!(app.outbox.len(), 1);
+    assert_eq!(app.outbox[0].state, OutboxState::Failed);
+}
+
+#[tokio::test]
+async fn rebroadcast_is_shown_apart_from_delivery() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    until(&mut app, "the neighbor", |app| {
+        app.nodes.contains_key(&PEER)
+    })
+    .await;
+
+    app.send_message(PEER, "over the hill".to_string());
+    let sent = device.recv_packet().await;
+    let outbox = app.outbox[0].id;
+    device.relayed(&sent).await.unwrap();
+    until(&mut app, "the rebroadcast", |app| {
+        app.acks.get(&outbox).is_some_and(|ack| ack.relayed)
+    })
+    .await;
+    assert!(!app.acks[&outbox].delivered);
+    assert_eq!(app.outbox[0].state, OutboxState::Relayed);
+
+    device.ack(&sent, routing::Error::None).await.unwrap();
+    until(&mut app, "the delivery", |app| app.outbox.is_empty()).await;
+    assert!(app.acks[&outbox].relayed && app.acks[&outbox].delivered);
+    assert_eq!(app.acks[&outbox].hops, Some(0));
+    let message = &app.conversations[&PEER][0];
+    assert_eq!(message.ts.timestamp_micros(), outbox);
+}
+
+#[tokio::test]
+async fn stale_nodes_are_removed_from_the_device() {
+    let mut config = Config::default();
+    config.retention.nodes_days = Some(30);
+    config.retention.remove_from_device = true;
+    let (mut device, _app) = FakeDevice::connect_with(config);
+    let stale = 0x0bad_f00d;
+    let long_ago = chrono::Local::now().timestamp() as u32 - 60 * 24 * 60 * 60;
+
+    let config_id = match device.recv().await.unwrap() {
+        to_radio::PayloadVariant::WantConfigId(id) => id,
+        other => panic!("expected a config request, got {:?}", other),
+    };
+    device
+        .send(from_radio::PayloadVariant::MyInfo(MyNodeInfo {
+            my_node_num: MY_NODE,
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+    for (num, is_favorite) in [(stale, false), (PEER, true)] {
+        device
+            .send(from_radio::PayloadVariant::NodeInfo(NodeInfo {
+                is_favorite,
+                last_heard: long_ago,
+                ..node(num, "Old")
+            }))
+            .await
+            .unwrap();
+    }
+    device
+        .send(from_radio::PayloadVariant::ConfigCompleteId(config_id))
+        .await
+        .unwrap();
+
+    let sent = device.recv_packet().await;
+    let Some(mesh_packet::PayloadVariant::Decoded(data)) = &sent.payload_variant else {
+        panic!("expected a decoded packet, got {:?}", sent);
+    };
+    assert_eq!(data.portnum, PortNum::AdminApp as i32);
+    let admin = AdminMessage::decode(data.payload.as_slice()).unwrap();
+    assert_eq!(
+        admin.payload_variant,
+        Some(admin_message::PayloadVariant::RemoveByNodenum(stale))
+    );
+}
+
+#[tokio::test]
+async fn remote_reboot_uses_the_session_passkey() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    until(&mut app, "the neighbor", |app| {
+        app.nodes.contains_key(&PEER)
+    })
+    .await;
+
+    app.reboot_node(PEER);
+    app.reboot_node(PEER);
+    let request = device.recv_packet().await;
+    assert_eq!(request.to, PEER);
+    let answer = AdminMessage {
+        session_passkey: vec![7; 8],
+        ..Default::default()
+    };
+    device
+        .deliver(PEER, PortNum::AdminApp, answer.encode_to_vec(), request.id)
+        .await
+        .unwrap();
+
+    until(&mut app, "the session", |app| {
+        app.remote_reboot
+            .as_ref()
+            .is_some_and(|reboot| matches!(reboot.stage, Stage::Rebooting { .. }))
+    })
+    .await;
+    let sent = device.recv_packet().await;
+    assert_eq!(sent.to, PEER);
+    let Some(mesh_packet::PayloadVariant::Decoded(data)) = &sent.payload_variant else {
+        panic!("expected a decoded packet, got {:?}", sent);
+    };
+    let reboot = AdminMessage::decode(data.payload.as_slice()).unwrap();
+    assert_eq!(reboot.session_passkey, vec![7; 8]);
+    assert!(matches!(
+        reboot.payload_variant,
+        Some(admin_message::PayloadVariant::RebootSeconds(_))
+    ));
+}
+
+#[tokio::test]
+async fn duplicated_packets_are_shown_once() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    device.conditions.duplicate = 1.0;
+    device
+        .deliver(PEER, PortNum::TextMessageApp, b"once".to_vec(), 0)
+        .await
+        .unwrap();
+    device
+        .deliver(PEER, PortNum::TextMessageApp, b"and once more".to_vec(), 0)
+        .await
+        .unwrap();
+
+    until(&mut app, "both messages", |app| {
+        app.conversations
+            .get(&PEER)
+            .is_some_and(|messages| messages.iter().any(|m| m.text == "and once more"))
+    })
+    .await;
+    assert_eq!(app.conversations[&PEER].len(), 2);
+}
+
+#[tokio::test]
+async fn lost_acknowledgement_is_retried() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    until(&mut app, "the neighbor", |app| {
+        app.nodes.contains_key(&PEER)
+    })
+    .await;
+    device.conditions = Conditions {
+        loss: 1.0,
+        delay: Duration::from_millis(50),
+        ..Default::default()
+    };
+
+    app.send_message(PEER, "are you there?".to_string());
+    let sent = device.recv_packet().await;
+    until(&mut app, "the send", |app| {
+        app.outbox[0].state == OutboxState::Sent
+    })
+    .await;
+    device.ack(&sent, routing::Error::None).await.unwrap();
+
+    let outbox = app.outbox[0].id;
+    app.retry_outbox_entry(outbox);
+    let resent = device.recv_packet().await;
+    assert_ne!(resent.id, sent.id);
+    assert_eq!(app.outbox[0].attempts, 2);
+    device.conditions.loss = 0.0;
+    device.ack(&resent, routing::Error::None).await.unwrap();
+    until(&mut app, "the delivery", |app| app.outbox.is_empty()).await;
+    assert!(app.acks[&outbox].delivered);
+}
+
+#[tokio::test]
+async fn scenario_plays_messages_and_connection_drops() {
+    let scenario = Scenario::parse(
+        r#"
+        me = { id = "!00001234", name = "Base" }
+        nodes = [{ id = "!a1b2c3d4", name = "Peer", favorite = true }]
+
+        [[events]]
+        at = 0
+        from = "!a1b2c3d4"
+        text = "anyone on?"
+
+        [[events]]
+        at = 0.1
+        drop = 60
+        "#,
+    )
+    .unwrap();
+    let config = Config::default();
+    let (ui_tx, mesh_rx) = scenario::spawn(config.clone(), scenario, SEED);
+    let store = SqliteStore::open_in_memory().unwrap();
+    let mut app = App::new(ui_tx, mesh_rx, &config, Box::new(WriteBehind::new(store)));
+
+    until(&mut app, "the message", |app| {
+        app.conversations.contains_key(&PEER)
+    })
+    .await;
+    assert_eq!(app.conversations[&PEER][0].text, "anyone on?");
+    sleep(Duration::from_millis(200)).await;
+
+    app.send_message(PEER, "yes".to_string());
+    until(&mut app, "the failure", |app| {
+        app.outbox
+            .first()
+            .is_some_and(|entry| entry.state == OutboxState::Failed)
+    })
+    .await;
+}
+
+#[test]
+fn demo_scenario_parses() {
+    let scenario = Scenario::parse(include_str!("../scenarios/demo.toml")).unwrap();
+    assert_eq!(scenario.nodes.len(), 3);
+    assert!(scenario.events.is_sorted_by(|a, b| a.at <= b.at));
+}
+
+#[tokio::test]
+async fn unplugged_device_is_reported() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    until(&mut app, "our own node", |app| app.my_node.is_some()).await;
+
+    drop(device);
+    until(&mut app, "the disconnection", |app| {
+        matches!(app.connection_error, Some(MeshError::SerialGone))
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn device_log_is_filtered_by_level() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    for (level, message) in [(Level::Debug, "radio idle"), (Level::Error, "NodeDB full")] {
+        device
+            .send(from_radio::PayloadVariant::LogRecord(LogRecord {
+                message: message.to_string(),
+                source: "Router".to_string(),
+                level: level as i32,
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+    }
+    until(&mut app, "the device log", |app| {
+        app.device_log.visible().len() == 2
+    })
+    .await;
+
+    // Trace, debug, info, warning, error.
+    for _ in 0..4 {
+        app.device_log.cycle_level();
+    }
+    let shown = app.device_log.visible();
+    assert_eq!(shown.len(), 1);
+    assert_eq!(shown[0].message, "NodeDB full");
+}
+
+#[tokio::test]
+async fn packet_flows_are_counted_per_destination() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    device
+        .deliver(PEER, PortNum::TextMessageApp, b"to base".to_vec(), 0)
+        .await
+        .unwrap();
+    let broadcast = device.packet(PEER, BROADCAST, PortNum::PositionApp, Vec::new(), 0);
+    device.deliver_packet(broadcast).await.unwrap();
+
+    until(&mut app, "both packets stored", |app| {
+        app.store.get_flows(0).is_ok_and(|flows| flows.len() == 2)
+    })
+    .await;
+    let mut flows = app.store.get_flows(0).unwrap();
+    flows.sort_by_key(|flow| flow.to);
+    assert_eq!(
+        flows,
+        [
+            Flow {
+                from: PEER,
+                to: MY_NODE,
+                packets: 1,
+                messages: 1,
+            },
+            Flow {
+                from: PEER,
+                to: BROADCAST,
+                packets: 1,
+                messages: 0,
+            },
+        ]
+    );
+    assert_eq!(stats::busiest_nodes(&flows, 5), [PEER, MY_NODE]);
+}
+
+#[tokio::test]
+async fn renumbered_node_history_is_merged() {
+    const REFLASHED: NodeNum = 0x0b0b_0b0b;
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    device
+        .deliver(PEER, PortNum::TextMessageApp, b"before".to_vec(), 0)
+        .await
+        .unwrap();
+    until(&mut app, "the message", |app| {
+        app.conversations.contains_key(&PEER)
+    })
+    .await;
+
+    let key = vec![7; 32];
+    for num in [PEER, REFLASHED] {
+        let mut info = node(num, "Peer");
+        info.user.as_mut().unwrap().public_key = key.clone();
+        device
+            .send(from_radio::PayloadVariant::NodeInfo(info))
+            .await
+            .unwrap();
+    }
+    until(&mut app, "the new number", |app| {
+        app.renumbered.get(&REFLASHED) == Some(&PEER)
+    })
+    .await;
+
+    app.merge_renumbered(REFLASHED);
+    assert!(!app.conversations.contains_key(&PEER));
+    assert_eq!(app.conversations[&REFLASHED][0].text, "before");
+    assert!(!app.nodes.contains_key(&PEER));
+    let stored = app.store.get_messages().unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].0, REFLASHED);
+}
+
+#[tokio::test]
+async fn conversation_follows_a_user_to_a_new_node_number() {
+    const REPLACED: NodeNum = 0x0c0c_0c0c;
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    let mut info = node(PEER, "Peer");
+    info.user.as_mut().unwrap().public_key = vec![9; 32];
+    let user = info.user.clone().unwrap();
+    device
+        .send(from_radio::PayloadVariant::NodeInfo(info))
+        .await
+        .unwrap();
+    device
+        .deliver(PEER, PortNum::TextMessageApp, b"old radio".to_vec(), 0)
+        .await
+        .unwrap();
+    until(&mut app, "the message", |app| {
+        app.conversations.contains_key(&PEER)
+    })
+    .await;
+
+    // The same user announces itself from new hardware.
+    device
+        .deliver(REPLACED, PortNum::NodeinfoApp, user.encode_to_vec(), 0)
+        .await
+        .unwrap();
+    until(&mut app, "the new number", |app| {
+        app.current_node(PEER) == REPLACED
+    })
+    .await;
+    assert!(!app.conversations.contains_key(&PEER));
+    assert_eq!(app.conversations[&REPLACED][0].text, "old radio");
+    assert_eq!(app.renumbered.get(&REPLACED), Some(&PEER));
+    // Nothing is rewritten in the store until the history is merged.
+    let stored = app.store.get_messages().unwrap();
+    assert_eq!(stored[0].0, PEER);
+    assert_eq!(app.store.get_contacts().unwrap()[0].node(), Some(REPLACED));
+}
+
+#[tokio::test]
+async fn read_direct_messages_get_a_receipt() {
+    let mut config = Config::default();
+    config.ui.read_receipts = true;
+    let (mut device, mut app) = FakeDevice::connect_with(config);
+    device.handshake().await;
+    let broadcast = device.packet(PEER, BROADCAST, PortNum::TextMessageApp, b"all".to_vec(), 0);
+    device.deliver_packet(broadcast).await.unwrap();
+    device
+        .deliver(PEER, PortNum::TextMessageApp, b"just you".to_vec(), 0)
+        .await
+        .unwrap();
+    until(&mut app, "the messages", |app| {
+        app.conversations.get(&PEER).is_some_and(|m| m.len() == 2)
+    })
+    .await;
+    assert!(app.outbox.is_empty());
+
+    app.open_conversation(PEER);
+    let direct = app.conversations[&PEER][1].packet_id.unwrap();
+    let sent = device.recv_packet().await;
+    assert_eq!(sent.to, PEER);
+    match &sent.payload_variant {
+        Some(mesh_packet::PayloadVariant::Decoded(data)) => {
+            assert_eq!(data.payload, "👀".as_bytes());
+            assert_eq!(data.reply_id, direct);
+            assert_ne!(data.emoji, 0);
+        }
+        other => panic!("expected a decoded packet, got {:?}", other),
+    }
+    // Only the direct message gets one, and only once.
+    assert_eq!(app.outbox.len(), 1);
+    app.open_conversation(PEER);
+    assert_eq!(app.outbox.len(), 1);
+}
+
+#[tokio::test]
+async fn broadcasts_wait_for_a_quieter_channel() {
+    let config = Config {
+        defer_broadcasts_above: Some(25.0),
+        ..Config::default()
+    };
+    let (mut device, mut app) = FakeDevice::connect_with(config);
+    device.handshake().await;
+    until(&mut app, "our own node", |app| app.my_node.is_some()).await;
+    let utilization = |percent: f32| {
+        Telemetry {
+            variant: Some(telemetry::Variant::DeviceMetrics(DeviceMetrics {
+                channel_utilization: Some(percent),
+                ..DeviceMetrics::default()
+            })),
+            ..Telemetry::default()
+        }
+        .encode_to_vec()
+    };
+
+    let busy = device.packet(
+        MY_NODE,
+        BROADCAST,
+        PortNum::TelemetryApp,
+        utilization(40.0),
+        0,
+    );
+    device.deliver_packet(busy).await.unwrap();
+    until(&mut app, "the busy channel", |app| {
+        app.busy_channel.is_busy()
+    })
+    .await;
+    app.send_message(BROADCAST, "net check".to_string());
+    app.send_message(PEER, "direct".to_string());
+    assert_eq!(app.outbox[0].state, OutboxState::Deferred);
+    // Direct messages are never held.
+    assert_eq!(device.recv_packet().await.to, PEER);
+
+    let clear = device.packet(
+        MY_NODE,
+        BROADCAST,
+        PortNum::TelemetryApp,
+        utilization(10.0),
+        0,
+    );
+    device.deliver_packet(clear).await.unwrap();
+    until(&mut app, "the quieter channel", |app| {
+        app.outbox[0].state == OutboxState::Queued
+    })
+    .await;
+    let sent = device.recv_packet().await;
+    assert_eq!(sent.to, BROADCAST);
+    match &sent.payload_variant {
+        Some(mesh_packet::PayloadVariant::Decoded(data)) => assert_eq!(data.payload, b"net check"),
+        other => panic!("expected a decoded packet, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn thumbnails_arrive_in_chunks() {
+    let config = Config {
+        experimental: ExperimentalConfig { thumbnails: true },
+        ..Config::default()
+    };
+    let (mut device, mut app) = FakeDevice::connect_with(config);
+    device.handshake().await;
+    until(&mut app, "our own node", |app| app.my_node.is_some()).await;
+
+    let gray: Vec<u8> = (0..64 * 48).map(|i| (i % 256) as u8).collect();
+    let thumbnail = Thumbnail::from_gray(64, 48, &gray);
+    let chunks = Transfers::default().send(MY_NODE, &thumbnail);
+    assert!(chunks.len() > 1);
+    for chunk in chunks {
+        device
+            .deliver(PEER, PortNum::PrivateApp, chunk, 0)
+            .await
+            .unwrap();
+    }
+    until(&mut app, "the thumbnail", |app| {
+        app.conversations.contains_key(&PEER)
+    })
+    .await;
+    let message = &app.conversations[&PEER][0];
+    assert!(!message.outgoing);
+    assert_eq!(Thumbnail::from_text(&message.text), Some(thumbnail));
+}
+
+#[tokio::test]
+async fn long_messages_travel_compressed() {
+    let config = Config {
+        compress_long_messages: true,
+        ..Config::default()
+    };
+    let (mut device, mut app) = FakeDevice::connect_with(config);
+    device.handshake().await;
+    until(&mut app, "our own node", |app| app.my_node.is_some()).await;
+    let text = "All stations: net control check-in at the hour. ".repeat(8);
+
+    app.send_message(PEER, text.clone());
+    let sent = device.recv_packet().await;
+    match &sent.payload_variant {
+        Some(mesh_packet::PayloadVariant::Decoded(data)) => {
+            assert_eq!(data.portnum, PortNum::PrivateApp as i32);
+            assert_eq!(compression::decompress(&data.payload), Some(text.clone()));
+        }
+        other => panic!("expected a decoded packet, got {:?}", other),
+    }
+
+    let reply = compression::compress(&text.to_uppercase()).unwrap();
+    device
+        .deliver(PEER, PortNum::PrivateApp, reply, 0)
+        .await
+        .unwrap();
+    until(&mut app, "the compressed reply", |app| {
+        app.conversations[&PEER].len() == 2
+    })
+    .await;
+    assert_eq!(app.conversations[&PEER][1].text, text.to_uppercase());
+}
+
+#[tokio::test]
+async fn structured_messages_fall_back_to_text_until_the_peer_runs_edda() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    until(&mut app, "our own node", |app| app.my_node.is_some()).await;
+    let checklist = Structured::parse("Go bag: water, x radio").unwrap();
+    let decoded = |packet: MeshPacket| match packet.payload_variant {
+        Some(mesh_packet::PayloadVariant::Decoded(data)) => (data.portnum, data.payload),
+        other => panic!("expected a decoded packet, got {:?}", other),
+    };
+
+    app.send_message(PEER, checklist.to_text());
+    let (port, payload) = decoded(device.recv_packet().await);
+    assert_eq!(port, PortNum::TextMessageApp as i32);
+    assert_eq!(payload, checklist.fallback().into_bytes());
+
+    let status = Structured::parse("Shelter 2: power=mains").unwrap();
+    device
+        .deliver(PEER, PortNum::PrivateApp, status.encode(), 0)
+        .await
+        .unwrap();
+    until(&mut app, "the status report", |app| {
+        app.conversations[&PEER].len() == 2
+    })
+    .await;
+    assert_eq!(app.conversations[&PEER][1].text, status.to_text());
+
+    app.send_message(PEER, checklist.to_text());
+    let (port, payload) = decoded(device.recv_packet().await);
+    assert_eq!(port, PortNum::PrivateApp as i32);
+    assert_eq!(Structured::decode(&payload), Some(checklist));
+}
+
+#[tokio::test]
+async fn probe_collects_every_answer_into_one_report() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    until(&mut app, "our own node", |app| app.my_node.is_some()).await;
+    app.open_conversation(PEER);
+    app.start_probe();
+
+    let mut requests = Vec::new();
+    for _ in 0..4 {
+        let packet = device.recv_packet().await;
+        match &packet.payload_variant {
+            Some(mesh_packet::PayloadVariant::Decoded(data)) => {
+                requests.push((PortNum::try_from(data.portnum).unwrap(), packet.id))
+            }
+            other => panic!("expected a decoded packet, got {:?}", other),
+        }
+    }
+    let ports: Vec<PortNum> = requests.iter().map(|(port, _)| *port).collect();
+    assert_eq!(
+        ports,
+        [
+            PortNum::AdminApp,
+            PortNum::PositionApp,
+            PortNum::TelemetryApp,
+            PortNum::TracerouteApp
+        ]
+    );
+
+    let answers = [
+        AdminMessage {
+            payload_variant: Some(admin_message::PayloadVariant::GetDeviceMetadataResponse(
+                DeviceMetadata {
+                    firmware_version: "2.6.11".to_string(),
+                    has_wifi: true,
+                    ..DeviceMetadata::default()
+                },
+            )),
+            ..AdminMessage::default()
+        }
+        .encode_to_vec(),
+        Position {
+            latitude_i: Some(450_000_000),
+            longitude_i: Some(-750_000_000),
+            ..Position::default()
+        }
+        .encode_to_vec(),
+        Telemetry {
+            variant: Some(telemetry::Variant::DeviceMetrics(DeviceMetrics {
+                battery_level: Some(80),
+                ..DeviceMetrics::default()
+            })),
+            ..Telemetry::default()
+        }
+        .encode_to_vec(),
+        RouteDiscovery {
+            snr_towards: vec![24],
+            snr_back: vec![20],
+            ..RouteDiscovery::default()
+        }
+        .encode_to_vec(),
+    ];
+    for ((port, id), answer) in requests.into_iter().zip(answers) {
+        device.deliver(PEER, port, answer, id).await.unwrap();
+    }
+    until(&mut app, "every answer", |app| {
+        app.probe.as_ref().is_some_and(|probe| probe.is_complete())
+    })
+    .await;
+    let report = app.probe.as_ref().unwrap().report(format_node_num);
+    assert!(report.contains(&"Firmware:     2.6.11".to_string()));
+    assert!(report.contains(&"Capabilities: wifi".to_string()));
+    assert!(report.contains(&"Position:     45.00000, -75.00000".to_string()));
+    assert!(report.contains(&"Route there:  me > !a1b2c3d4 (6.00 dB)".to_string()));
+}
+
+#[tokio::test]
+async fn bulk_admin_changes_each_selected_node() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    until(&mut app, "our own node", |app| app.my_node.is_some()).await;
+    app.team.insert(PEER);
+    app.bulk_channel = false;
+    app.bulk_value = "900".to_string();
+    app.start_bulk_admin();
+
+    let read = device.recv_packet().await;
+    assert_eq!(read.to, PEER);
+    let answer = AdminMessage {
+        payload_variant: Some(admin_message::PayloadVariant::GetConfigResponse(
+            meshtastic::protobufs::Config {
+                payload_variant: Some(config::PayloadVariant::Position(config::PositionConfig {
+                    gps_update_interval: 120,
+                    ..config::PositionConfig::default()
+                })),
+            },
+        )),
+        session_passkey: vec![1, 2, 3],
+    };
+    device
+        .deliver(PEER, PortNum::AdminApp, answer.encode_to_vec(), read.id)
+        .await
+        .unwrap();
+    until(&mut app, "the change to be sent", |app| {
+        app.bulk_admin
+            .as_ref()
+            .is_some_and(|bulk| matches!(bulk.nodes[0].1, NodeState::Applying { .. }))
+    })
+    .await;
+
+    let change = device.recv_packet().await;
+    let Some(mesh_packet::PayloadVariant::Decoded(data)) = &change.payload_variant else {
+        panic!("expected a decoded packet");
+    };
+    let sent = AdminMessage::decode(data.payload.as_slice()).unwrap();
+    assert_eq!(sent.session_passkey, vec![1, 2, 3]);
+    let Some(admin_message::PayloadVariant::SetConfig(meshtastic::protobufs::Config {
+        payload_variant: Some(config::PayloadVariant::Position(position)),
+    })) = sent.payload_variant
+    else {
+        panic!("expected a position config, got {:?}", sent.payload_variant);
+    };
+    assert_eq!(position.position_broadcast_secs, 900);
+    assert_eq!(position.gps_update_interval, 120);
+
+    device.ack(&change, routing::Error::None).await.unwrap();
+    until(&mut app, "the change to be acknowledged", |app| {
+        app.bulk_admin.as_ref().is_some_and(BulkAdmin::is_finished)
+    })
+    .await;
+    assert_eq!(
+        app.bulk_admin.as_ref().unwrap().nodes,
+        [(PEER, NodeState::Done)]
+    );
+}
+
+#[tokio::test]
+async fn global_search_finds_nodes_messages_and_waypoints() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    let waypoint = Waypoint {
+        id: 7,
+        latitude_i: Some(450_000_000),
+        longitude_i: Some(-750_000_000),
+        name: "Shelter".to_string(),
+        description: "Net control tent".to_string(),
+        ..Waypoint::default()
+    };
+    device
+        .deliver(PEER, PortNum::WaypointApp, waypoint.encode_to_vec(), 0)
+        .await
+        .unwrap();
+    // Sent after the waypoint, so it is stored once they have arrived.
+    for text in ["net check at 19:00", "see you at the shelter"] {
+        device
+            .deliver(PEER, PortNum::TextMessageApp, text.as_bytes().to_vec(), 0)
+            .await
+            .unwrap();
+    }
+    until(&mut app, "both messages", |app| {
+        app.conversations.get(&PEER).is_some_and(|m| m.len() == 2)
+    })
+    .await;
+
+    app.global_search = "shel".to_string();
+    app.refresh_search_results();
+    assert_eq!(app.search_results.len(), 2);
+    assert!(matches!(
+        &app.search_results[0],
+        SearchResult::Message { message, .. } if message.text == "see you at the shelter"
+    ));
+    assert!(matches!(
+        &app.search_results[1],
+        SearchResult::Waypoint { from: PEER, waypoint } if waypoint.id == 7
+    ));
+
+    app.global_search = "NET".to_string();
+    app.refresh_search_results();
+    assert_eq!(app.search_results.len(), 2);
+    app.open_search_result();
+    assert_eq!(app.current_contact, Some(PEER));
+    assert_eq!(app.selected_message, Some(0));
+
+    app.global_search = "peer".to_string();
+    app.refresh_search_results();
+    assert!(matches!(app.search_results[..], [SearchResult::Node(PEER)]));
+}
+
+#[tokio::test]
+async fn smart_views_filter_the_node_list_live() {
+    let mut config = Config::default();
+    config.ui.smart_views = vec![
+        SmartViewConfig {
+            name: "Nearby".to_string(),
+            query: "heard in last 15 min and hops == 0".to_string(),
+        },
+        SmartViewConfig {
+            name: "Net".to_string(),
+            query: "messages containing 'net'".to_string(),
+        },
+    ];
+    let (mut device, mut app) = FakeDevice::connect_with(config);
+    device.handshake().await;
+    until(&mut app, "the neighbor", |app| {
+        app.nodes.contains_key(&PEER)
+    })
+    .await;
+    let visible = |app: &App| -> Vec<NodeNum> {
+        app.get_visible_nodes()
+            .iter()
+            .map(|node| node.num)
+            .collect()
+    };
+    // A node never heard from is not nearby.
+    app.nodes.insert(0x0000_beef, node(0x0000_beef, "Far"));
+    assert_eq!(visible(&app).len(), 2);
+
+    app.cycle_smart_view(1);
+    assert_eq!(visible(&app), [PEER]);
+    app.cycle_smart_view(1);
+    assert!(visible(&app).is_empty());
+
+    device
+        .deliver(
+            PEER,
+            PortNum::TextMessageApp,
+            b"net starts at 7".to_vec(),
+            0,
+        )
+        .await
+        .unwrap();
+    until(&mut app, "the message to match", |app| {
+        app.get_visible_nodes().len() == 1
+    })
+    .await;
+    app.cycle_smart_view(1);
+    assert_eq!(app.smart_view, None);
+}
+
+#[tokio::test]
+async fn screens_stack_over_the_panes() {
+    /// Opens the health summary on Enter.
+    struct Opener;
+
+    impl View for Opener {
+        fn draw(&mut self, _app: &mut App, _frame: &mut Frame, _rect: Rect) {}
+
+        fn handle_action(&mut self, _app: &mut App, key: KeyEvent) -> Action {
+            match key.code {
+                KeyCode::Enter => Action::Open(Box::new(HealthView)),
+                KeyCode::Char('q') => Action::Close,
+                _ => Action::Handled,
+            }
+        }
+    }
+
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    app.open_screen(Box::new(Opener));
+    app.screen_key(KeyEvent::from(KeyCode::Enter));
+    assert_eq!(app.screens.len(), 2);
+    // The health summary has no keys of its own; Esc uncovers the view below.
+    app.screen_key(KeyEvent::from(KeyCode::Char('q')));
+    assert_eq!(app.screens.len(), 2);
+    app.screen_key(KeyEvent::from(KeyCode::Esc));
+    assert_eq!(app.screens.len(), 1);
+    app.screen_key(KeyEvent::from(KeyCode::Char('q')));
+    assert!(app.screens.is_empty());
+}
+
+#[tokio::test]
+async fn screens_keep_their_state_when_closed() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    app.open_screen(Box::new(DeviceLogView));
+    // Trace, then Debug, then Info.
+    app.screen_key(KeyEvent::from(KeyCode::Char('l')));
+    app.screen_key(KeyEvent::from(KeyCode::Char('l')));
+    app.screen_key(KeyEvent::from(KeyCode::Esc));
+    assert_eq!(
+        app.screens.states["device log"].filter,
+        Level::Info.as_str_name()
+    );
+
+    app.device_log.min_level = Level::Trace;
+    app.open_screen(Box::new(DeviceLogView));
+    assert_eq!(app.device_log.min_level, Level::Info);
+}
+
+#[tokio::test]
+async fn commands_run_from_the_command_line() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    let run = |app: &mut App, line: &str| {
+        assert!(app.press(KeyEvent::from(KeyCode::Char(':'))).is_continue());
+        for c in line.chars() {
+            assert!(app.press(KeyEvent::from(KeyCode::Char(c))).is_continue());
+        }
+        assert!(app.press(KeyEvent::from(KeyCode::Enter)).is_continue());
+    };
+
+    run(&mut app, "theme plain");
+    assert!(app.plain);
+    assert!(app.screens.is_empty());
+    run(&mut app, "filter peer");
+    assert_eq!(app.search, "peer");
+
+    // A failing command stays on the line with its error.
+    run(&mut app, "theme sepia");
+    assert!(app.screens.on_top::<CommandLineView>());
+    assert!(app.command_line.error.is_some());
+    assert!(app.press(KeyEvent::from(KeyCode::Esc)).is_continue());
+
+    run(&mut app, "connect tcp://radio.local");
+    assert_eq!(
+        app.transport,
+        Some(Transport::Tcp("radio.local:4403".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn key_macros_replay_what_was_recorded() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    until(&mut app, "the neighbor", |app| {
+        app.nodes.contains_key(&PEER)
+    })
+    .await;
+    app.open_conversation(PEER);
+    let press = |app: &mut App, keys: &[KeyCode]| {
+        for key in keys {
+            assert!(app.press(KeyEvent::from(*key)).is_continue());
+        }
+    };
+
+    // Record focusing the input box, typing a message, sending it and unfocusing.
+    press(&mut app, &[KeyCode::Char('Q'), KeyCode::Char('r')]);
+    press(
+        &mut app,
+        &[
+            KeyCode::Tab,
+            KeyCode::Tab,
+            KeyCode::Char('o'),
+            KeyCode::Char('k'),
+            KeyCode::Enter,
+            KeyCode::Esc,
+        ],
+    );
+    press(&mut app, &[KeyCode::Char('Q')]);
+    assert_eq!(device.recv_packet().await.to, PEER);
+
+    press(&mut app, &[KeyCode::Char('@'), KeyCode::Char('r')]);
+    let sent = device.recv_packet().await;
+    assert_eq!(sent.to, PEER);
+    match &sent.payload_variant {
+        Some(mesh_packet::PayloadVariant::Decoded(data)) => assert_eq!(data.payload, b"ok"),
+        other => panic!("expected a decoded packet, got {:?}", other),
+    }
+    assert!(app.focus.current().is_none());
+}
+
+#[tokio::test]
+async fn deleted_messages_can_be_undone() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    device
+        .deliver(PEER, PortNum::TextMessageApp, b"keep me".to_vec(), 0)
+        .await
+        .unwrap();
+    until(&mut app, "the message", |app| {
+        app.conversations.contains_key(&PEER)
+    })
+    .await;
+
+    app.open_conversation(PEER);
+    app.selected_message = Some(0);
+    app.run_message_action(MessageAction::Delete);
+    assert!(app.conversations[&PEER].is_empty());
+    assert!(app.store.get_messages().unwrap().is_empty());
+
+    // A message arriving in the meantime stays after the restored one.
+    device
+        .deliver(PEER, PortNum::TextMessageApp, b"later".to_vec(), 0)
+        .await
+        .unwrap();
+    until(&mut app, "the next message", |app| {
+        !app.conversations[&PEER].is_empty()
+    })
+    .await;
+
+    app.focus.clear();
+    assert!(app.press(KeyEvent::from(KeyCode::Char('U'))).is_continue());
+    let texts: Vec<_> = app.conversations[&PEER].iter().map(|m| &m.text).collect();
+    assert_eq!(texts, ["keep me", "later"]);
+    assert_eq!(app.store.get_messages().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn notes_on_messages_are_stored() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    device
+        .deliver(PEER, PortNum::TextMessageApp, b"at the shelter".to_vec(), 0)
+        .await
+        .unwrap();
+    until(&mut app, "the message", |app| {
+        app.conversations.contains_key(&PEER)
+    })
+    .await;
+
+    app.open_conversation(PEER);
+    app.selected_message = Some(0);
+    app.run_message_action(MessageAction::Note);
+    for c in "by voice".chars() {
+        assert!(app.press(KeyEvent::from(KeyCode::Char(c))).is_continue());
+    }
+    assert!(app.press(KeyEvent::from(KeyCode::Enter)).is_continue());
+    assert_eq!(
+        app.conversations[&PEER][0].note.as_deref(),
+        Some("by voice")
+    );
+    let stored = app.store.get_messages().unwrap();
+    assert_eq!(stored[0].1.note.as_deref(), Some("by voice"));
+
+    // Emptying the note removes it.
+    app.run_message_action(MessageAction::Note);
+    assert_eq!(app.message_note, "by voice");
+    app.message_note.clear();
+    assert!(app.press(KeyEvent::from(KeyCode::Enter)).is_continue());
+    assert_eq!(app.store.get_messages().unwrap()[0].1.note, None);
+}
+
+#[tokio::test]
+async fn browsing_read_only_sends_and_changes_nothing() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    device
+        .deliver(
+            PEER,
+            PortNum::TextMessageApp,
+            b"recorded in the field".to_vec(),
+            0,
+        )
+        .await
+        .unwrap();
+    until(&mut app, "the message", |app| {
+        app.conversations.contains_key(&PEER)
+    })
+    .await;
+    app.browse_offline(true);
+
+    app.focus.set(Focus::Conversation);
+    app.current_contact = Some(PEER);
+    assert!(app.press(KeyEvent::from(KeyCode::Down)).is_continue());
+    assert!(app.press(KeyEvent::from(KeyCode::Enter)).is_continue());
+    assert!(app.screens.on_top::<MessageActionsView>());
+    // The first action is to reply.
+    assert!(app.press(KeyEvent::from(KeyCode::Enter)).is_continue());
+    assert!(app.screens.on_top::<MessageActionsView>());
+
+    assert!(app.press(KeyEvent::from(KeyCode::Esc)).is_continue());
+    app.focus.clear();
+    assert!(app.press(KeyEvent::from(KeyCode::Char('n'))).is_continue());
+    assert_eq!(app.focus.current(), None);
+    assert!(app.outbox.is_empty());
+    assert_eq!(app.conversations[&PEER].len(), 1);
+}
+
+#[tokio::test]
+async fn replies_are_threaded_under_what_they_answer() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    until(&mut app, "the neighbor", |app| {
+        app.nodes.contains_key(&PEER)
+    })
+    .await;
+
+    app.send_message(PEER, "anyone at the shelter?".to_string());
+    let sent = device.recv_packet().await;
+    // The message learns the ID it went out with, so replies to it can be threaded.
+    until(&mut app, "the packet ID", |app| {
+        app.conversations[&PEER][0].packet_id == Some(sent.id)
+    })
+    .await;
+    device
+        .deliver(PEER, PortNum::TextMessageApp, b"unrelated".to_vec(), 0)
+        .await
+        .unwrap();
+    let mut reply = device.packet(
+        PEER,
+        MY_NODE,
+        PortNum::TextMessageApp,
+        b"yes, two of us".to_vec(),
+        0,
+    );
+    if let Some(mesh_packet::PayloadVariant::Decoded(data)) = &mut reply.payload_variant {
+        data.reply_id = sent.id;
+    }
+    device.deliver_packet(reply).await.unwrap();
+    until(&mut app, "the reply", |app| {
+        app.conversations[&PEER].len() == 3
+    })
+    .await;
+    let stored = app.store.get_messages().unwrap();
+    assert_eq!(stored[0].1.packet_id, Some(sent.id));
+    assert_eq!(stored[2].1.reply_id, Some(sent.id));
+
+    let shown = |app: &App| -> Vec<(usize, usize, usize)> {
+        threads::order(&app.conversations[&PEER], &app.collapsed)
+            .into_iter()
+            .map(|entry| (entry.index, entry.depth, entry.hidden))
+            .collect()
+    };
+    assert_eq!(shown(&app), [(0, 0, 0), (2, 1, 0), (1, 0, 0)]);
+
+    app.current_contact = Some(PEER);
+    app.focus.set(Focus::Conversation);
+    assert!(app.press(KeyEvent::from(KeyCode::Char('t'))).is_continue());
+    assert!(app.threaded);
+    app.selected_message = Some(0);
+    assert!(app.press(KeyEvent::from(KeyCode::Char(' '))).is_continue());
+    assert_eq!(shown(&app), [(0, 0, 1), (1, 0, 0)]);
+    assert!(app.press(KeyEvent::from(KeyCode::Char(' '))).is_continue());
+    assert!(app.collapsed.is_empty());
+}
+
+#[tokio::test]
+async fn conversation_history_is_read_back_per_node() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    let other: NodeNum = 0x0bad_cafe;
+    for (from, text) in [(PEER, "first"), (other, "elsewhere"), (PEER, "second")] {
+        device
+            .deliver(from, PortNum::TextMessageApp, text.as_bytes().to_vec(), 0)
+            .await
+            .unwrap();
+    }
+    until(&mut app, "the messages", |app| {
+        app.conversations.get(&PEER).is_some_and(|c| c.len() == 2)
+            && app.conversations.contains_key(&other)
+    })
+    .await;
+
+    let texts: Vec<_> = app
+        .store
+        .get_conversation(PEER, i64::MIN..i64::MAX)
+        .unwrap()
+        .into_iter()
+        .map(|message| message.text)
+        .collect();
+    assert_eq!(texts, ["first", "second"]);
+    let later = chrono::Local::now().timestamp() + 60;
+    assert!(
+        app.store
+            .get_conversation(PEER, later..i64::MAX)
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn a_full_radio_queue_fails_messages_instead_of_panicking() {
+    let config = Config::default();
+    // Nothing takes events off the queue, as when the mesh thread is stuck reconnecting.
+    let (ui_tx, _ui_rx) = mpsc::channel(2);
+    let (_mesh_tx, mesh_rx) = mpsc::channel(1);
+    let store = SqliteStore::open_in_memory().unwrap();
+    let mut app = App::new(ui_tx, mesh_rx, &config, Box::new(WriteBehind::new(store)));
+
+    for text in ["one", "two", "three"] {
+        app.send_message(PEER, text.to_string());
+    }
+    let states: Vec<_> = app.outbox.iter().map(|entry| entry.state).collect();
+    assert_eq!(
+        states,
+        [
+            OutboxState::Queued,
+            OutboxState::Queued,
+            OutboxState::Failed
+        ]
+    );
+    let stored = app.store.get_outbox().unwrap();
+    assert_eq!(stored[2].state, OutboxState::Failed);
+}
+
+#[tokio::test]
+async fn popups_open_over_the_panes_and_close_when_done() {
+    let (mut device, mut app) = FakeDevice::connect();
+    device.handshake().await;
+    until(&mut app, "the neighbor", |app| {
+        app.nodes.contains_key(&PEER)
+    })
+    .await;
+
+    // The switcher opens from any pane, and takes every key while open, Tab included.
+    app.focus.set(Focus::Input);
+    let control_k = KeyEvent::new(KeyCode::Char('k'), KeyModifiers::CONTROL);
+    assert!(app.press(control_k).is_continue());
+    assert!(app.screens.on_top::<SwitcherView>());
+    assert!(app.press(KeyEvent::from(KeyCode::Tab)).is_continue());
+    assert!(app.screens.on_top::<SwitcherView>());
+    assert!(app.press(KeyEvent::from(KeyCode::Enter)).is_continue());
+    assert!(app.screens.is_empty());
+    assert_eq!(app.focus.current(), Some(Focus::Input));
+
+    app.focus.clear();
+    assert!(app.press(KeyEvent::from(KeyCode::Char('o'))).is_continue());
+    assert!(app.screens.on_top::<OutboxView>());
+    assert!(app.press(KeyEvent::from(KeyCode::Esc)).is_continue());
+    assert!(app.screens.is_empty());
+}
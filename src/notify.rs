@@ -0,0 +1,69 @@
+//! Per-node and per-channel notification overrides.
+//!
+//! Muting only silences push and desktop notifications; the messages are still stored and shown.
+//! A node's own setting wins over the setting of the channel the message arrived on.
+
+use std::collections::HashMap;
+
+use crate::types::NodeNum;
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum NotifyMode {
+    #[default]
+    Default,
+    Muted,
+    /// Notify for every message, even if no push keyword matches.
+    Always,
+}
+
+impl NotifyMode {
+    /// The mode after this one, cycling through all three.
+    pub fn next(self) -> Self {
+        match self {
+            NotifyMode::Default => NotifyMode::Muted,
+            NotifyMode::Muted => NotifyMode::Always,
+            NotifyMode::Always => NotifyMode::Default,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            NotifyMode::Default => "default",
+            NotifyMode::Muted => "muted",
+            NotifyMode::Always => "always",
+        }
+    }
+
+    pub fn parse(label: &str) -> Option<Self> {
+        match label {
+            "default" => Some(NotifyMode::Default),
+            "muted" => Some(NotifyMode::Muted),
+            "always" => Some(NotifyMode::Always),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct NotifyOverrides {
+    pub nodes: HashMap<NodeNum, NotifyMode>,
+    pub channels: HashMap<u32, NotifyMode>,
+}
+
+impl NotifyOverrides {
+    pub fn node(&self, node: NodeNum) -> NotifyMode {
+        self.nodes.get(&node).copied().unwrap_or_default()
+    }
+
+    pub fn channel(&self, channel: u32) -> NotifyMode {
+        self.channels.get(&channel).copied().unwrap_or_default()
+    }
+
+    /// How to notify for a message from `node` on `channel`.
+    pub fn mode(&self, node: NodeNum, channel: u32) -> NotifyMode {
+        match self.node(node) {
+            NotifyMode::Default => self.channel(channel),
+            mode => mode,
+        }
+    }
+}
@@ -0,0 +1,333 @@
+//! Subcommands for scripts, cron jobs and shell pipelines that connect to the device, do one
+//! thing and exit: `edda send`, `edda nodes` and `edda listen`.
+//!
+//! They go through the same connection and router as the UI, waiting for the device to send its
+//! configuration first, so they see the same node database and send the same packets.
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Write};
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use color_eyre::eyre::{Result, bail, eyre};
+use meshtastic::protobufs::NodeInfo;
+use meshtastic::types::NodeId;
+use serde_json::{Map, Value, json};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+use crate::config::Config;
+use crate::export;
+use crate::mesh::{self, Transport};
+use crate::store::{SqliteStore, Store};
+use crate::types::{
+    BROADCAST, ConnectionStatus, MeshEvent, Message, NodeNum, PACKET_BYTE_LIMIT, UiEvent,
+    format_node_num,
+};
+
+/// How long the device may take to connect and send its configuration.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long the radio may take to send a message, or its recipient to acknowledge it.
+const SEND_TIMEOUT: Duration = Duration::from_secs(120);
+/// Tags the message sent, to know when the radio took it; there is no outbox entry for it.
+const OUTBOX: i64 = -1;
+
+/// The connection to the device, with what it said about the mesh while connecting.
+struct Connection {
+    transmitter: mpsc::Sender<UiEvent>,
+    receiver: mpsc::Receiver<MeshEvent>,
+    nodes: HashMap<NodeNum, NodeInfo>,
+}
+
+impl Connection {
+    /// Connect, returning once the device has sent its configuration.
+    async fn open(config: &Config, port: &str) -> Result<Self> {
+        let (transmitter, mut receiver) = mesh::spawn(config.clone(), Transport::parse(port));
+        let mut nodes = HashMap::new();
+        let connected = async {
+            while let Some(event) = receiver.recv().await {
+                match event {
+                    MeshEvent::NodeAvailable(node) => {
+                        nodes.insert(node.num, *node);
+                    }
+                    MeshEvent::ConnectionStatus(ConnectionStatus::Connected) => return Ok(()),
+                    MeshEvent::Error(error) if error.is_fatal() => {
+                        bail!("{}. {}", error, error.guidance())
+                    }
+                    _ => {}
+                }
+            }
+            Err(eyre!("The connection to the device stopped"))
+        };
+        timeout(CONNECT_TIMEOUT, connected)
+            .await
+            .map_err(|_| eyre!("Timed out connecting to {}", port))??;
+        Ok(Self {
+            transmitter,
+            receiver,
+            nodes,
+        })
+    }
+
+    /// Wait for an event `wanted` picks something out of, failing after `within`.
+    async fn wait_for<T>(
+        &mut self,
+        within: Duration,
+        mut wanted: impl FnMut(MeshEvent) -> Option<T>,
+    ) -> Result<T> {
+        let waited = async {
+            while let Some(event) = self.receiver.recv().await {
+                if let MeshEvent::Error(error) = &event
+                    && error.is_fatal()
+                {
+                    bail!("{}. {}", error, error.guidance());
+                }
+                if let Some(found) = wanted(event) {
+                    return Ok(found);
+                }
+            }
+            Err(eyre!("The connection to the device stopped"))
+        };
+        timeout(within, waited)
+            .await
+            .map_err(|_| eyre!("Timed out after {} s", within.as_secs()))?
+    }
+
+    fn name(&self, num: NodeNum) -> String {
+        self.nodes
+            .get(&num)
+            .and_then(|node| node.user.as_ref())
+            .map_or_else(|| format_node_num(num), |user| user.long_name.clone())
+    }
+}
+
+fn runtime() -> Result<tokio::runtime::Runtime> {
+    Ok(tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?)
+}
+
+/// Send `text` to `to`, or broadcast it on `channel` without one, returning once the radio took
+/// it, or with `ack` once the recipient acknowledged it. The message is stored, so it shows in
+/// the conversation in the UI.
+pub fn send(
+    config: &Config,
+    store: &mut SqliteStore,
+    port: &str,
+    to: Option<NodeNum>,
+    channel: u32,
+    text: String,
+    ack: bool,
+) -> Result<()> {
+    if ack && to.is_none() {
+        bail!("Only direct messages are acknowledged: give --to");
+    }
+    if text.is_empty() || text.len() > PACKET_BYTE_LIMIT {
+        bail!("Text must be 1 to {} bytes", PACKET_BYTE_LIMIT);
+    }
+    runtime()?.block_on(async {
+        let mut connection = Connection::open(config, port).await?;
+        connection
+            .transmitter
+            .send(UiEvent::Message {
+                node_id: NodeId::new(to.unwrap_or(BROADCAST)),
+                message: text.clone(),
+                channel,
+                reply_id: None,
+                emoji: false,
+                outbox: Some(OUTBOX),
+            })
+            .await?;
+        let packet_id = connection
+            .wait_for(SEND_TIMEOUT, |event| match event {
+                MeshEvent::Sent {
+                    outbox: OUTBOX,
+                    packet_id,
+                } => Some(packet_id),
+                _ => None,
+            })
+            .await?;
+        let message = Message {
+            outgoing: true,
+            ts: Local::now(),
+            channel,
+            text,
+            snr: None,
+            packet_id,
+            estimated_time: false,
+            note: None,
+            reply_id: None,
+        };
+        if let Err(e) = store.append_message(to.unwrap_or(BROADCAST), &message) {
+            log::error!("Failed to store message: {}", e);
+        }
+        if ack {
+            let delivered = connection
+                .wait_for(SEND_TIMEOUT, |event| match event {
+                    MeshEvent::Delivery {
+                        delivered,
+                        outbox: Some(OUTBOX),
+                        ..
+                    } => Some(delivered),
+                    _ => None,
+                })
+                .await?;
+            if !delivered {
+                bail!(
+                    "{} did not acknowledge the message",
+                    connection.name(to.unwrap_or(BROADCAST))
+                );
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Print the device's node database, as a table or as JSON keyed by node ID in
+/// meshtastic-python's shape.
+pub fn nodes(config: &Config, port: &str, as_json: bool) -> Result<()> {
+    let connection = runtime()?.block_on(Connection::open(config, port))?;
+    let mut nodes: Vec<&NodeInfo> = connection.nodes.values().collect();
+    nodes.sort_by_key(|node| std::cmp::Reverse(node.last_heard));
+    let mut out = io::stdout().lock();
+    if as_json {
+        let nodes: Map<String, Value> = nodes
+            .iter()
+            .map(|node| (format_node_num(node.num), export::python_node(node)))
+            .collect();
+        return ignore_broken_pipe(writeln!(
+            out,
+            "{}",
+            serde_json::to_string_pretty(&Value::Object(nodes))?
+        ));
+    }
+    let now = Local::now().timestamp();
+    for node in nodes {
+        let (short_name, long_name) = node
+            .user
+            .as_ref()
+            .map_or(("", ""), |user| (&*user.short_name, &*user.long_name));
+        let heard = match i64::from(node.last_heard) {
+            0 => "never".to_string(),
+            heard => format!("{} min ago", (now - heard).max(0) / 60),
+        };
+        let hops = node
+            .hops_away
+            .map_or_else(String::new, |hops| format!("{hops} hops"));
+        let printed = writeln!(
+            out,
+            "{}  {:<4}  {:<24}  {:>12}  {:>6.1} dB  {}",
+            format_node_num(node.num),
+            short_name,
+            long_name,
+            heard,
+            node.snr,
+            hops
+        );
+        ignore_broken_pipe(printed)?;
+    }
+    Ok(())
+}
+
+/// Print incoming text messages as they arrive, one per line or as JSON lines, until the
+/// connection stops or stdout is closed. Reconnections are waited out.
+pub fn listen(config: &Config, port: &str, as_json: bool) -> Result<()> {
+    runtime()?.block_on(async {
+        let mut connection = Connection::open(config, port).await?;
+        while let Some(event) = connection.receiver.recv().await {
+            let (from, to, channel, text, snr) = match event {
+                MeshEvent::NodeAvailable(node) => {
+                    connection.nodes.insert(node.num, *node);
+                    continue;
+                }
+                MeshEvent::Message {
+                    node_id,
+                    to,
+                    channel,
+                    message,
+                    snr,
+                    ..
+                } => (node_id.id(), to.id(), channel, message, snr),
+                _ => continue,
+            };
+            let line = if as_json {
+                json!({
+                    "time": Local::now().to_rfc3339(),
+                    "from": format_node_num(from),
+                    "to": format_node_num(to),
+                    "channel": channel,
+                    "text": text,
+                    "rxSnr": snr,
+                })
+                .to_string()
+            } else {
+                message_line(
+                    Local::now(),
+                    &connection.name(from),
+                    from,
+                    to,
+                    channel,
+                    &text,
+                )
+            };
+            let mut out = io::stdout().lock();
+            match writeln!(out, "{line}").and_then(|()| out.flush()) {
+                Err(e) if e.kind() == ErrorKind::BrokenPipe => return Ok(()),
+                printed => printed?,
+            }
+        }
+        Err(eyre!("The connection to the device stopped"))
+    })
+}
+
+/// A message as `listen` prints it, marking direct messages from broadcasts.
+fn message_line(
+    time: DateTime<Local>,
+    name: &str,
+    from: NodeNum,
+    to: NodeNum,
+    channel: u32,
+    text: &str,
+) -> String {
+    let to = if to == BROADCAST {
+        format!("ch{channel}")
+    } else {
+        "DM".to_string()
+    };
+    format!(
+        "{} {} ({}) [{}]: {}",
+        time.format("%Y-%m-%d %H:%M:%S"),
+        name,
+        format_node_num(from),
+        to,
+        text
+    )
+}
+
+/// A reader closing early, e.g. `head`, is not an error.
+fn ignore_broken_pipe(printed: io::Result<()>) -> Result<()> {
+    match printed {
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+        printed => Ok(printed?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn direct_messages_are_told_from_broadcasts() {
+        let time = Local.with_ymd_and_hms(2026, 10, 17, 14, 32, 5).unwrap();
+        assert_eq!(
+            message_line(time, "Base", 0xa1b2c3d4, BROADCAST, 1, "net at 15:00"),
+            "2026-10-17 14:32:05 Base (!a1b2c3d4) [ch1]: net at 15:00"
+        );
+        assert_eq!(
+            message_line(time, "Base", 0xa1b2c3d4, 0x1234, 0, "copy"),
+            "2026-10-17 14:32:05 Base (!a1b2c3d4) [DM]: copy"
+        );
+    }
+}
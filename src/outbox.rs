@@ -0,0 +1,109 @@
+//! Outgoing messages that have not been confirmed yet, kept in the store so they survive
+//! restarts.
+//!
+//! A message enters the outbox when it is handed to the Meshtastic thread. Broadcasts leave it
+//! once the radio has taken them; direct messages stay until their recipient acknowledges them.
+//! A message whose delivery failed stays, marked failed, until it is retried or cancelled.
+//! Broadcasts held back while the channel is busy wait in the outbox, deferred, until it clears.
+//!
+//! Our own node acknowledging a direct message only means it heard a neighbor rebroadcast it;
+//! that is kept apart from the recipient's acknowledgement so both can be shown.
+
+use chrono::{DateTime, Local};
+
+use crate::forms::{Ics213, Precedence};
+use crate::types::{BROADCAST, NodeNum};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboxState {
+    /// Handed to the Meshtastic thread, but the radio has not taken it yet.
+    Queued,
+    /// Sent, waiting for the recipient's acknowledgement.
+    Sent,
+    /// Heard being rebroadcast by another node, still waiting for the recipient.
+    Relayed,
+    /// The radio gave up on delivering it.
+    Failed,
+    /// Held back until the channel is less busy.
+    Deferred,
+}
+
+impl OutboxState {
+    pub fn label(self) -> &'static str {
+        match self {
+            OutboxState::Queued => "queued",
+            OutboxState::Sent => "sent",
+            OutboxState::Relayed => "relayed",
+            OutboxState::Failed => "failed",
+            OutboxState::Deferred => "deferred",
+        }
+    }
+
+    pub fn parse(label: &str) -> Option<Self> {
+        match label {
+            "queued" => Some(OutboxState::Queued),
+            "sent" => Some(OutboxState::Sent),
+            "relayed" => Some(OutboxState::Relayed),
+            "failed" => Some(OutboxState::Failed),
+            "deferred" => Some(OutboxState::Deferred),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub to: NodeNum,
+    pub text: String,
+    /// Packet ID of the message this replies or reacts to.
+    pub reply_id: Option<u32>,
+    pub emoji: bool,
+    pub state: OutboxState,
+    /// When the message was last handed to the radio, or deferred.
+    pub queued: DateTime<Local>,
+    /// How many times it was handed to the radio.
+    pub attempts: u32,
+}
+
+impl OutboxEntry {
+    pub fn new(to: NodeNum, text: String, reply_id: Option<u32>, emoji: bool) -> Self {
+        let queued = Local::now();
+        Self {
+            // Microseconds are unique enough, also between instances sharing the store.
+            id: queued.timestamp_micros(),
+            to,
+            text,
+            reply_id,
+            emoji,
+            state: OutboxState::Queued,
+            queued,
+            attempts: 1,
+        }
+    }
+
+    /// Whether the message may wait for a quieter channel: a broadcast that is not a PRIORITY
+    /// or EMERGENCY ICS-213 form.
+    pub fn deferrable(&self) -> bool {
+        let urgent = Ics213::parse(&self.text).is_some_and(|form| {
+            matches!(
+                form.precedence,
+                Precedence::Priority | Precedence::Emergency
+            )
+        });
+        self.to == BROADCAST && !urgent
+    }
+}
+
+/// What we heard back about a direct message we sent, shown next to it in the conversation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Acknowledgement {
+    /// Our node heard another node rebroadcast it (an implicit ACK): the mesh carried it on.
+    pub relayed: bool,
+    /// The recipient's radio acknowledged it.
+    pub delivered: bool,
+    /// Last byte of the node number that passed the recipient's acknowledgement on to us.
+    pub relay_node: Option<u8>,
+    /// How many times the recipient's acknowledgement was relayed on its way back.
+    pub hops: Option<u32>,
+}
@@ -0,0 +1,271 @@
+//! A conversation as a read-only transcript, for after-action reviews: `T` opens the current
+//! conversation full screen, like a pager, and `edda show <node>` prints it for piping to `less`.
+//!
+//! In the pager, the arrow and page keys scroll (`j`, `k`, space and `b` too), `g` and `G` go to
+//! the start and the end, and `/` searches, `n` and `N` going to the next and previous match.
+
+use std::io::{self, ErrorKind, Write};
+
+use color_eyre::eyre::{Result, eyre};
+use ratatui::{
+    Frame,
+    crossterm::event::{KeyCode, KeyEvent},
+    layout::Rect,
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, Clear, Paragraph},
+};
+
+use crate::channel_badges::ChannelBadges;
+use crate::conversation_view::{self, transcript};
+use crate::store::Store;
+use crate::tui::App;
+use crate::types::{NodeNum, format_node_num, parse_node_num};
+use crate::view::{Action, View};
+
+pub struct Pager {
+    title: String,
+    transcript: Vec<Line<'static>>,
+    /// The transcript wrapped to the width it was last drawn at.
+    lines: Vec<Line<'static>>,
+    width: u16,
+    /// Lines shown at once, as last drawn.
+    height: usize,
+    /// First line shown.
+    top: usize,
+    /// The search being typed after `/`.
+    typing: Option<String>,
+    /// The last search made, lower-cased.
+    query: String,
+    /// Line of the match gone to last.
+    found: Option<usize>,
+}
+
+impl Pager {
+    pub fn new(title: String, transcript: Vec<Line<'static>>) -> Self {
+        Self {
+            title,
+            transcript,
+            lines: Vec::new(),
+            width: 0,
+            height: 0,
+            top: 0,
+            typing: None,
+            query: String::new(),
+            found: None,
+        }
+    }
+
+    fn scroll_down(&mut self, lines: usize) {
+        let last = self.lines.len().saturating_sub(self.height);
+        self.top = (self.top + lines).min(last);
+    }
+
+    fn scroll_up(&mut self, lines: usize) {
+        self.top = self.top.saturating_sub(lines);
+    }
+
+    /// Go to the next line matching the search after the one gone to last, or before it if not
+    /// `forward`, wrapping around.
+    fn find(&mut self, forward: bool) {
+        if self.query.is_empty() || self.lines.is_empty() {
+            return;
+        }
+        let count = self.lines.len();
+        let from = self.found.unwrap_or(if forward { count - 1 } else { 0 });
+        let found = (1..=count)
+            .map(|step| {
+                if forward {
+                    (from + step) % count
+                } else {
+                    (from + count - step) % count
+                }
+            })
+            .find(|&index| self.matches(index));
+        if let Some(index) = found {
+            self.found = Some(index);
+            if index < self.top || index >= self.top + self.height.max(1) {
+                self.top = index.saturating_sub(self.height / 2);
+            }
+        }
+    }
+
+    fn matches(&self, index: usize) -> bool {
+        !self.query.is_empty() && text(&self.lines[index]).contains(&self.query)
+    }
+
+    fn wrap(&mut self, width: u16) {
+        if width == self.width {
+            return;
+        }
+        self.lines = self
+            .transcript
+            .iter()
+            .flat_map(|line| conversation_view::wrap(line.clone(), usize::from(width)))
+            .collect();
+        self.width = width;
+        self.found = None;
+        self.top = self.top.min(self.lines.len().saturating_sub(1));
+    }
+}
+
+impl View for Pager {
+    /// Over the whole screen rather than `rect`.
+    fn draw(&mut self, _app: &mut App, frame: &mut Frame, _rect: Rect) {
+        let area = frame.area();
+        let block = Block::bordered().title(format!(" {} ", self.title).bold());
+        let block = match &self.typing {
+            Some(query) => block.title_bottom(format!("/{query}")),
+            None => block.title_bottom("/ search  n/N next/previous  g/G start/end  ESC close"),
+        };
+        let inner = block.inner(area);
+        self.wrap(inner.width);
+        self.height = usize::from(inner.height);
+        let shown: Vec<Line> = (self.top..self.lines.len())
+            .take(self.height)
+            .map(|index| {
+                let line = self.lines[index].clone();
+                if Some(index) == self.found {
+                    line.reversed()
+                } else if self.matches(index) {
+                    line.style(Style::default().fg(Color::Yellow))
+                } else {
+                    line
+                }
+            })
+            .collect();
+        frame.render_widget(Clear, area);
+        frame.render_widget(Paragraph::new(shown).block(block), area);
+    }
+
+    fn handle_action(&mut self, _app: &mut App, key: KeyEvent) -> Action {
+        if let Some(query) = &mut self.typing {
+            match key.code {
+                KeyCode::Char(c) => query.push(c),
+                // Backspace on an empty search leaves it.
+                KeyCode::Backspace if query.pop().is_none() => self.typing = None,
+                KeyCode::Enter => {
+                    self.query = query.to_lowercase();
+                    self.typing = None;
+                    self.found = None;
+                    self.find(true);
+                }
+                _ => {}
+            }
+            return Action::Handled;
+        }
+        let page = self.height.max(1);
+        match key.code {
+            KeyCode::Down | KeyCode::Char('j') => self.scroll_down(1),
+            KeyCode::Up | KeyCode::Char('k') => self.scroll_up(1),
+            KeyCode::PageDown | KeyCode::Char(' ') => self.scroll_down(page),
+            KeyCode::PageUp | KeyCode::Char('b') => self.scroll_up(page),
+            KeyCode::Home | KeyCode::Char('g') => self.top = 0,
+            KeyCode::End | KeyCode::Char('G') => self.scroll_down(self.lines.len()),
+            KeyCode::Char('/') => self.typing = Some(String::new()),
+            KeyCode::Char('n') => self.find(true),
+            KeyCode::Char('N') => self.find(false),
+            KeyCode::Char('q') => return Action::Close,
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// The text of a line, lower-cased for searching.
+fn text(line: &Line) -> String {
+    line.spans
+        .iter()
+        .map(|span| span.content.as_ref())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Print the conversation with `node`, given by number (`!a1b2c3d4` or decimal) or by long or
+/// short name, as `edda show` does. A pager closing early is not an error.
+pub fn show(store: &dyn Store, node: &str, badges: &ChannelBadges) -> Result<()> {
+    let (num, name) = find_node(store, node)?;
+    let messages = store.get_conversation(num, i64::MIN..i64::MAX)?;
+    let mut out = io::stdout().lock();
+    for line in transcript(&name, &messages, badges) {
+        let printed = line
+            .spans
+            .iter()
+            .try_for_each(|span| out.write_all(span.content.as_bytes()))
+            .and_then(|()| writeln!(out));
+        match printed {
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => return Ok(()),
+            printed => printed?,
+        }
+    }
+    Ok(())
+}
+
+/// The number and name of the node known by `node`.
+fn find_node(store: &dyn Store, node: &str) -> Result<(NodeNum, String)> {
+    let nodes = store.get_nodes()?;
+    let name_of = |num: NodeNum| {
+        nodes
+            .iter()
+            .find(|n| n.num == num)
+            .and_then(|n| n.user.as_ref())
+            .map_or_else(|| format_node_num(num), |user| user.long_name.clone())
+    };
+    if let Some(num) = parse_node_num(node) {
+        return Ok((num, name_of(num)));
+    }
+    nodes
+        .iter()
+        .find(|n| {
+            n.user.as_ref().is_some_and(|user| {
+                user.long_name.eq_ignore_ascii_case(node)
+                    || user.short_name.eq_ignore_ascii_case(node)
+            })
+        })
+        .map(|n| (n.num, name_of(n.num)))
+        .ok_or_else(|| eyre!("No node called {node}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pager() -> Pager {
+        let lines = ["alpha", "bravo", "charlie", "alpha again", "delta"]
+            .into_iter()
+            .map(Line::from)
+            .collect();
+        let mut pager = Pager::new("test".to_string(), lines);
+        pager.wrap(40);
+        pager.height = 2;
+        pager
+    }
+
+    #[test]
+    fn search_goes_both_ways_and_wraps_around() {
+        let mut pager = pager();
+        pager.query = "alpha".to_string();
+        pager.find(true);
+        assert_eq!(pager.found, Some(0));
+        pager.find(true);
+        assert_eq!(pager.found, Some(3));
+        assert_eq!(pager.top, 2);
+        pager.find(true);
+        assert_eq!(pager.found, Some(0));
+        pager.find(false);
+        assert_eq!(pager.found, Some(3));
+
+        pager.query = "zulu".to_string();
+        pager.find(true);
+        assert_eq!(pager.found, Some(3));
+    }
+
+    #[test]
+    fn scrolling_stops_at_the_last_page() {
+        let mut pager = pager();
+        pager.scroll_down(10);
+        assert_eq!(pager.top, 3);
+        pager.scroll_up(1);
+        assert_eq!(pager.top, 2);
+    }
+}
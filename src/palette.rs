@@ -0,0 +1,71 @@
+//! The UI colours `[ui.colors]` sets: the accent of focused borders and popups, and the
+//! background of selected rows.
+
+use std::str::FromStr;
+
+use ratatui::style::Color;
+
+use crate::config::ColorsConfig;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    pub accent: Color,
+    pub selection: Color,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            accent: Color::Yellow,
+            selection: Color::DarkGray,
+        }
+    }
+}
+
+impl Palette {
+    /// The configured colours, keeping the default for any that does not parse.
+    pub fn new(colors: &ColorsConfig) -> Self {
+        let default = Self::default();
+        Self {
+            accent: color(colors.accent.as_deref(), "accent", default.accent),
+            selection: color(colors.selection.as_deref(), "selection", default.selection),
+        }
+    }
+}
+
+fn color(name: Option<&str>, what: &str, default: Color) -> Color {
+    match name.map(Color::from_str) {
+        Some(Ok(color)) => color,
+        Some(Err(_)) => {
+            log::error!(
+                "Failed to parse {} colour {:?}",
+                what,
+                name.unwrap_or_default()
+            );
+            default
+        }
+        None => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colours_parse_by_name_or_hex_and_fall_back() {
+        let palette = Palette::new(&ColorsConfig {
+            accent: Some("light-blue".to_string()),
+            selection: Some("#203040".to_string()),
+        });
+        assert_eq!(palette.accent, Color::LightBlue);
+        assert_eq!(palette.selection, Color::Rgb(0x20, 0x30, 0x40));
+
+        let palette = Palette::new(&ColorsConfig {
+            accent: Some("sparkly".to_string()),
+            selection: None,
+        });
+        assert_eq!(palette.accent, Color::Yellow);
+        assert_eq!(palette.selection, Color::DarkGray);
+    }
+}
@@ -0,0 +1,27 @@
+//! Plain rendering for slow SSH or tmux sessions from field sites.
+//!
+//! Every frame is drawn as usual and then reduced: colours are dropped, and line art becomes
+//! ASCII as in `glyphs`. Each changed cell then costs a byte or two instead of a multi-byte
+//! character wrapped in colour escapes. Bold and reverse video are kept, and highlighted
+//! backgrounds become reverse video so selections stay visible.
+
+use ratatui::buffer::Buffer;
+use ratatui::style::{Color, Modifier};
+
+use crate::glyphs;
+
+/// At most this many redraws a second in plain mode, whatever the configured rate.
+pub const MAX_REDRAWS_PER_SECOND: u32 = 2;
+
+pub fn simplify(buffer: &mut Buffer) {
+    glyphs::to_ascii(buffer);
+    for cell in buffer.content.iter_mut() {
+        let mut modifier = cell.modifier & (Modifier::BOLD | Modifier::REVERSED);
+        if cell.bg != Color::Reset {
+            modifier |= Modifier::REVERSED;
+        }
+        cell.fg = Color::Reset;
+        cell.bg = Color::Reset;
+        cell.modifier = modifier;
+    }
+}
@@ -0,0 +1,83 @@
+//! Choosing the device's serial port at startup, when none was given and more than one could be
+//! it. Likely Meshtastic devices are listed first, marked, and the first of them selected; nodes
+//! found on the network come after the serial ports.
+
+use color_eyre::eyre::Result;
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode},
+    layout::{Constraint, Flex, Layout},
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, List, ListState},
+};
+
+use crate::discovery::NetworkDevice;
+use crate::mesh::SerialPort;
+
+/// The name of the port picked, or none if the user quit.
+pub fn pick(ports: &[SerialPort], devices: &[NetworkDevice]) -> Result<Option<String>> {
+    let mut terminal = ratatui::init();
+    let picked = run(&mut terminal, ports, devices);
+    ratatui::restore();
+    picked
+}
+
+fn run(
+    terminal: &mut DefaultTerminal,
+    ports: &[SerialPort],
+    devices: &[NetworkDevice],
+) -> Result<Option<String>> {
+    let mut state = ListState::default().with_selected(Some(0));
+    loop {
+        terminal.draw(|frame| draw(frame, ports, devices, &mut state))?;
+        if let Event::Key(key) = event::read()? {
+            match key.code {
+                KeyCode::Char('j') | KeyCode::Down => state.select_next(),
+                KeyCode::Char('k') | KeyCode::Up => state.select_previous(),
+                KeyCode::Enter => {
+                    return Ok(state.selected().and_then(|i| match ports.get(i) {
+                        Some(port) => Some(port.name.clone()),
+                        None => devices.get(i - ports.len()).map(NetworkDevice::port),
+                    }));
+                }
+                KeyCode::Esc | KeyCode::Char('q') => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, ports: &[SerialPort], devices: &[NetworkDevice], state: &mut ListState) {
+    let mut items: Vec<Line> = ports
+        .iter()
+        .map(|port| {
+            let mut line = Line::from(port.to_string());
+            if port.likely_device {
+                line.push_span(Span::styled(
+                    "  likely a Meshtastic device",
+                    Style::default().fg(Color::Green),
+                ));
+            }
+            line
+        })
+        .collect();
+    items.extend(devices.iter().map(|device| {
+        Line::from(vec![
+            Span::raw(device.to_string()),
+            Span::styled("  on the network", Style::default().fg(Color::Cyan)),
+        ])
+    }));
+    let height = items.len() as u16 + 2;
+    let [rect] = Layout::vertical([Constraint::Length(height)])
+        .flex(Flex::Center)
+        .areas(frame.area());
+    let list = List::new(items)
+        .block(
+            Block::bordered()
+                .title("WHICH PORT IS THE DEVICE ON?".bold())
+                .title_bottom("ENTER: connect  ESC: quit"),
+        )
+        .highlight_style(Style::default().reversed());
+    frame.render_stateful_widget(list, rect, state);
+}
@@ -0,0 +1,37 @@
+//! Who is "in the room" on each channel: the nodes recently heard transmitting on it.
+
+use std::collections::HashMap;
+
+use crate::types::NodeNum;
+
+/// A node counts as present on a channel for this long after its last packet there.
+pub const PRESENCE_SECONDS: i64 = 15 * 60;
+
+#[derive(Default)]
+pub struct ChannelPresence {
+    /// Per channel index, when each node was last heard on it.
+    last_heard: HashMap<u32, HashMap<NodeNum, i64>>,
+}
+
+impl ChannelPresence {
+    pub fn record(&mut self, channel: u32, node: NodeNum, time: i64) {
+        self.last_heard
+            .entry(channel)
+            .or_default()
+            .insert(node, time);
+    }
+
+    /// Nodes heard on a channel within `PRESENCE_SECONDS` of `now`, most recent first.
+    pub fn present(&self, channel: u32, now: i64) -> Vec<NodeNum> {
+        let Some(heard) = self.last_heard.get(&channel) else {
+            return Vec::new();
+        };
+        let mut present: Vec<(NodeNum, i64)> = heard
+            .iter()
+            .filter(|&(_, &time)| now - time <= PRESENCE_SECONDS)
+            .map(|(&node, &time)| (node, time))
+            .collect();
+        present.sort_by_key(|&(node, time)| (std::cmp::Reverse(time), node));
+        present.into_iter().map(|(node, _)| node).collect()
+    }
+}
@@ -0,0 +1,236 @@
+//! Probing a remote node: asking it for its metadata, position and device telemetry and tracing
+//! the route to it, all at once, and putting what comes back into one report that can be read
+//! on screen or exported as text.
+//!
+//! Metadata is an admin request, so only nodes that accept our admin key answer it; the others
+//! are answered by any node.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use meshtastic::protobufs::{
+    DeviceMetadata, DeviceMetrics, HardwareModel, Position, RouteDiscovery,
+    config::device_config::Role,
+};
+
+use crate::export::DEGREES_PER_UNIT;
+use crate::types::{NodeNum, format_node_num};
+
+/// Stop waiting for answers after this long.
+const TIMEOUT: Duration = Duration::from_secs(3 * 60);
+/// SNR in a route discovery is in quarters of a dB, with this meaning unknown.
+const SNR_UNKNOWN: i32 = i8::MIN as i32;
+
+pub struct Probe {
+    pub node: NodeNum,
+    pub started: DateTime<Local>,
+    since: Instant,
+    pub metadata: Option<DeviceMetadata>,
+    pub position: Option<Position>,
+    pub metrics: Option<DeviceMetrics>,
+    pub route: Option<RouteDiscovery>,
+}
+
+impl Probe {
+    pub fn new(node: NodeNum) -> Self {
+        Self {
+            node,
+            started: Local::now(),
+            since: Instant::now(),
+            metadata: None,
+            position: None,
+            metrics: None,
+            route: None,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.metadata.is_some()
+            && self.position.is_some()
+            && self.metrics.is_some()
+            && self.route.is_some()
+    }
+
+    /// Whether answers are still worth waiting for.
+    pub fn is_waiting(&self) -> bool {
+        !self.is_complete() && self.since.elapsed() < TIMEOUT
+    }
+
+    /// The report, line by line, with `name` giving the names of the nodes along the route.
+    pub fn report(&self, name: impl Fn(NodeNum) -> String) -> Vec<String> {
+        let missing = if self.is_waiting() {
+            "waiting..."
+        } else {
+            "no answer"
+        };
+        let mut lines = vec![
+            format!(
+                "CAPABILITY REPORT: {} ({})",
+                name(self.node),
+                format_node_num(self.node)
+            ),
+            format!("Probed {}", self.started.format("%Y-%m-%d %H:%M:%S")),
+            String::new(),
+        ];
+
+        match &self.metadata {
+            Some(metadata) => {
+                let hardware = HardwareModel::try_from(metadata.hw_model)
+                    .map_or("unknown", |model| model.as_str_name());
+                let role =
+                    Role::try_from(metadata.role).map_or("unknown", |role| role.as_str_name());
+                lines.push(format!("Firmware:     {}", metadata.firmware_version));
+                lines.push(format!("Hardware:     {hardware}"));
+                lines.push(format!("Role:         {role}"));
+                let capabilities: Vec<&str> = [
+                    (metadata.has_wifi, "wifi"),
+                    (metadata.has_bluetooth, "bluetooth"),
+                    (metadata.has_ethernet, "ethernet"),
+                    (metadata.has_remote_hardware, "remote hardware"),
+                    (metadata.has_pkc, "public key encryption"),
+                    (metadata.can_shutdown, "shutdown"),
+                ]
+                .into_iter()
+                .filter_map(|(has, capability)| has.then_some(capability))
+                .collect();
+                lines.push(format!("Capabilities: {}", capabilities.join(", ")));
+            }
+            None => lines.push(format!("Metadata:     {missing} (needs admin access)")),
+        }
+
+        match &self.position {
+            Some(Position {
+                latitude_i: Some(latitude),
+                longitude_i: Some(longitude),
+                altitude,
+                ..
+            }) => {
+                let mut line = format!(
+                    "Position:     {:.5}, {:.5}",
+                    f64::from(*latitude) * DEGREES_PER_UNIT,
+                    f64::from(*longitude) * DEGREES_PER_UNIT
+                );
+                if let Some(altitude) = altitude {
+                    line.push_str(&format!(", {altitude} m"));
+                }
+                lines.push(line);
+            }
+            Some(_) => lines.push("Position:     not shared".to_string()),
+            None => lines.push(format!("Position:     {missing}")),
+        }
+
+        match &self.metrics {
+            Some(metrics) => {
+                let mut health = Vec::new();
+                if let Some(level) = metrics.battery_level {
+                    // The firmware reports external power as over 100%.
+                    health.push(if level > 100 {
+                        "powered".to_string()
+                    } else {
+                        format!("battery {level}%")
+                    });
+                }
+                if let Some(voltage) = metrics.voltage {
+                    health.push(format!("{voltage:.2} V"));
+                }
+                if let Some(utilization) = metrics.channel_utilization {
+                    health.push(format!("channel {utilization:.1}% busy"));
+                }
+                if let Some(air) = metrics.air_util_tx {
+                    health.push(format!("transmitting {air:.1}% of the time"));
+                }
+                if let Some(uptime) = metrics.uptime_seconds {
+                    health.push(format!(
+                        "up {}d {}h",
+                        uptime / 86_400,
+                        uptime % 86_400 / 3600
+                    ));
+                }
+                lines.push(format!("Health:       {}", health.join(", ")));
+            }
+            None => lines.push(format!("Health:       {missing}")),
+        }
+
+        match &self.route {
+            Some(route) => {
+                let mut there = vec![None];
+                there.extend(route.route.iter().map(|hop| Some(*hop)));
+                there.push(Some(self.node));
+                lines.push(format!(
+                    "Route there:  {}",
+                    hops(&there, &route.snr_towards, &name)
+                ));
+                let mut back = vec![Some(self.node)];
+                back.extend(route.route_back.iter().map(|hop| Some(*hop)));
+                back.push(None);
+                lines.push(format!(
+                    "Route back:   {}",
+                    hops(&back, &route.snr_back, &name)
+                ));
+            }
+            None => lines.push(format!("Route:        {missing}")),
+        }
+        lines
+    }
+
+    /// Write the report to a text file in `dir`, returning its path.
+    pub fn export(&self, dir: &Path, name: impl Fn(NodeNum) -> String) -> io::Result<PathBuf> {
+        let path = dir.join(format!(
+            "probe_{}_{}.txt",
+            format_node_num(self.node).trim_start_matches('!'),
+            self.started.format("%Y%m%d_%H%M%S")
+        ));
+        let mut text = self.report(name).join("\n");
+        text.push('\n');
+        fs::write(&path, text)?;
+        Ok(path)
+    }
+}
+
+/// Nodes along a route, `None` standing for us, with the SNR each hop was heard at.
+fn hops(nodes: &[Option<NodeNum>], snr: &[i32], name: impl Fn(NodeNum) -> String) -> String {
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let name = node.map_or_else(|| "me".to_string(), &name);
+            // The first node sent the packet; the others heard it.
+            match i.checked_sub(1).and_then(|hop| snr.get(hop)) {
+                Some(&snr) if snr != SNR_UNKNOWN => format!("{name} ({:.2} dB)", snr as f32 / 4.0),
+                _ => name,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" > ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_lists_what_answered() {
+        let mut probe = Probe::new(0xa1b2_c3d4);
+        probe.route = Some(RouteDiscovery {
+            route: vec![0x0000_beef],
+            snr_towards: vec![22, -10],
+            route_back: vec![],
+            snr_back: vec![SNR_UNKNOWN],
+        });
+        probe.metrics = Some(DeviceMetrics {
+            battery_level: Some(101),
+            channel_utilization: Some(12.5),
+            ..DeviceMetrics::default()
+        });
+        let report = probe.report(format_node_num);
+        assert!(report.contains(&"Health:       powered, channel 12.5% busy".to_string()));
+        assert!(report.contains(
+            &"Route there:  me > !0000beef (5.50 dB) > !a1b2c3d4 (-2.50 dB)".to_string()
+        ));
+        assert!(report.contains(&"Route back:   !a1b2c3d4 > me".to_string()));
+        assert!(report.contains(&"Position:     waiting...".to_string()));
+    }
+}
@@ -0,0 +1,51 @@
+//! Saved owner identities for our own device, e.g. "Event mode" vs "Home mode".
+//!
+//! A profile covers the owner names, the licensed flag and the device role. Applying one sends
+//! all of it to the device in a single settings transaction.
+
+use meshtastic::protobufs::{User, config::DeviceConfig, config::device_config::Role};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub long_name: String,
+    pub short_name: String,
+    pub is_licensed: bool,
+    /// A `config::device_config::Role`.
+    pub role: i32,
+}
+
+impl Profile {
+    /// Capture the device's current identity under a new name.
+    pub fn from_owner(name: String, user: &User, device: Option<&DeviceConfig>) -> Self {
+        Self {
+            name,
+            long_name: user.long_name.clone(),
+            short_name: user.short_name.clone(),
+            is_licensed: user.is_licensed,
+            role: device.map_or(Role::Client as i32, |d| d.role),
+        }
+    }
+
+    pub fn role_name(&self) -> &'static str {
+        Role::try_from(self.role).map_or("UNKNOWN", |r| r.as_str_name())
+    }
+
+    /// The current owner with this profile's fields applied, keeping the node ID and hardware.
+    pub fn owner(&self, current: &User) -> User {
+        User {
+            long_name: self.long_name.clone(),
+            short_name: self.short_name.clone(),
+            is_licensed: self.is_licensed,
+            ..current.clone()
+        }
+    }
+
+    /// The current device configuration with this profile's role applied.
+    pub fn device_config(&self, current: &DeviceConfig) -> DeviceConfig {
+        DeviceConfig {
+            role: self.role,
+            ..current.clone()
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Forward messages to ntfy or Gotify so they reach phones away from the terminal.
+//!
+//! ICS-213 forms marked PRIORITY or EMERGENCY are treated as alerts: they are pushed to every
+//! target regardless of keywords, at high priority, even from muted nodes and channels.
+
+use std::{
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+use serde_json::json;
+
+use crate::config::{PushService, PushTarget};
+use crate::forms::{Ics213, Precedence};
+use crate::notify::NotifyMode;
+
+struct Notification {
+    title: String,
+    body: String,
+    alert: bool,
+}
+
+/// Handle to the thread doing the (blocking) HTTP requests.
+pub struct Pusher {
+    targets: Vec<PushTarget>,
+    sender: Sender<(usize, Notification)>,
+}
+
+impl Pusher {
+    /// Start pushing to the given targets, or return `None` if there are none.
+    pub fn spawn(targets: Vec<PushTarget>) -> Option<Self> {
+        if targets.is_empty() {
+            return None;
+        }
+        let (sender, receiver) = mpsc::channel::<(usize, Notification)>();
+        let worker_targets = targets.clone();
+        thread::spawn(move || {
+            for (index, notification) in receiver {
+                let target = &worker_targets[index];
+                if let Err(e) = push(target, &notification) {
+                    log::error!("Failed to push to {}: {}", target.url, e);
+                }
+            }
+        });
+        Some(Self { targets, sender })
+    }
+
+    /// Push an incoming message to every target it matches, as overridden by `mode`.
+    pub fn message(&self, sender: &str, text: &str, mode: NotifyMode) {
+        let (title, alert) = match Ics213::parse(text) {
+            Some(form) => (
+                format!("ICS-213 {} from {}", form.precedence.label(), sender),
+                matches!(
+                    form.precedence,
+                    Precedence::Priority | Precedence::Emergency
+                ),
+            ),
+            None => (sender.to_string(), false),
+        };
+        if mode == NotifyMode::Muted && !alert {
+            return;
+        }
+        let lowercase = text.to_lowercase();
+        for (index, target) in self.targets.iter().enumerate() {
+            let matched = mode == NotifyMode::Always
+                || target.keywords.is_empty()
+                || target
+                    .keywords
+                    .iter()
+                    .any(|k| lowercase.contains(&k.to_lowercase()));
+            if !matched && !alert {
+                continue;
+            }
+            let notification = Notification {
+                title: title.clone(),
+                body: text.to_string(),
+                alert,
+            };
+            // The worker only stops if this handle is dropped.
+            let _ = self.sender.send((index, notification));
+        }
+    }
+
+    /// Push an alert about edda or the device itself to every target.
+    pub fn alert(&self, text: &str) {
+        for index in 0..self.targets.len() {
+            let notification = Notification {
+                title: "edda".to_string(),
+                body: text.to_string(),
+                alert: true,
+            };
+            let _ = self.sender.send((index, notification));
+        }
+    }
+}
+
+fn push(target: &PushTarget, notification: &Notification) -> Result<(), ureq::Error> {
+    match target.service {
+        PushService::Ntfy => {
+            let mut request = ureq::post(&target.url)
+                .header("Title", &notification.title)
+                .header("Priority", if notification.alert { "5" } else { "3" });
+            if let Some(token) = &target.token {
+                request = request.header("Authorization", &format!("Bearer {}", token));
+            }
+            request.send(&notification.body)?;
+        }
+        PushService::Gotify => {
+            let url = format!("{}/message", target.url.trim_end_matches('/'));
+            let mut request = ureq::post(&url).header("Content-Type", "application/json");
+            if let Some(token) = &target.token {
+                request = request.header("X-Gotify-Key", token);
+            }
+            let body = json!({
+                "title": notification.title,
+                "message": notification.body,
+                "priority": if notification.alert { 8 } else { 5 },
+            });
+            request.send(body.to_string())?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,287 @@
+//! Several radios at once, such as a base station and a portable one, monitored by one edda.
+//!
+//! Each radio has a Meshtastic thread of its own, connecting and reconnecting on its own. What
+//! the UI asks for goes to the active radio, and the UI hears everything the active radio
+//! reports. Of the other radios it hears what adds to the node list and the conversations:
+//! nodes, positions, metrics, waypoints and messages, a message heard by several radios once.
+//!
+//! Switching radios tells the UI again what the new one said about itself (its node,
+//! configuration, channels and connection), so the UI is as if it had been connected to it all
+//! along.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use meshtastic::protobufs::{
+    Channel, NodeInfo,
+    config::{DeviceConfig, LoRaConfig, SecurityConfig},
+};
+use tokio::sync::mpsc;
+
+use crate::mesh::Transport;
+use crate::types::{ConnectionStatus, MeshEvent, NodeNum, UiEvent};
+
+/// How many recent messages are remembered to drop copies heard by another radio.
+const RECENT_MESSAGES: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Radio {
+    pub name: String,
+    pub transport: Transport,
+}
+
+/// What a radio said about itself.
+#[derive(Default)]
+struct Device {
+    my_node: Option<NodeInfo>,
+    device_config: Option<DeviceConfig>,
+    lora_config: Option<LoRaConfig>,
+    security_config: Option<SecurityConfig>,
+    channels: BTreeMap<i32, Channel>,
+    status: Option<ConnectionStatus>,
+}
+
+struct Radios {
+    radios: Vec<(Radio, Device)>,
+    active: usize,
+    /// Sender and ID of the messages passed on last.
+    recent_messages: VecDeque<(NodeNum, u32)>,
+}
+
+impl Radios {
+    fn new(radios: Vec<Radio>) -> Self {
+        Self {
+            radios: radios
+                .into_iter()
+                .map(|radio| (radio, Device::default()))
+                .collect(),
+            active: 0,
+            recent_messages: VecDeque::new(),
+        }
+    }
+
+    /// Note what `event` from the radio at `index` says about it, returning the event if the UI
+    /// is to hear it.
+    fn event(&mut self, index: usize, event: MeshEvent) -> Option<MeshEvent> {
+        let (radio, device) = &mut self.radios[index];
+        match &event {
+            MeshEvent::MyNode(node_info) => device.my_node = Some((**node_info).clone()),
+            MeshEvent::DeviceConfig(config) => device.device_config = Some(config.clone()),
+            MeshEvent::LoraConfig(config) => device.lora_config = Some(config.clone()),
+            MeshEvent::SecurityConfig(config) => device.security_config = Some(config.clone()),
+            MeshEvent::Channel(channel) => {
+                device.channels.insert(channel.index, channel.clone());
+            }
+            MeshEvent::ConnectionStatus(status) => device.status = Some(*status),
+            MeshEvent::Message { node_id, id, .. } => {
+                return (!self.is_duplicate(node_id.id(), *id)).then_some(event);
+            }
+            MeshEvent::NodeAvailable(_)
+            | MeshEvent::User { .. }
+            | MeshEvent::Position { .. }
+            | MeshEvent::DeviceMetrics { .. }
+            | MeshEvent::Waypoint { .. } => return Some(event),
+            MeshEvent::Error(error) if index != self.active => {
+                log::error!("Radio {}: {}", radio.name, error);
+            }
+            _ => {}
+        }
+        (index == self.active).then_some(event)
+    }
+
+    /// Whether a message was passed on already. Messages with no ID cannot be told apart and are
+    /// never duplicates.
+    fn is_duplicate(&mut self, from: NodeNum, id: u32) -> bool {
+        if id == 0 {
+            return false;
+        }
+        let key = (from, id);
+        if self.recent_messages.contains(&key) {
+            return true;
+        }
+        if self.recent_messages.len() == RECENT_MESSAGES {
+            self.recent_messages.pop_front();
+        }
+        self.recent_messages.push_back(key);
+        false
+    }
+
+    /// Make the radio called `name` the active one, returning what the UI is to hear of it, or
+    /// none if there is no such radio.
+    fn switch(&mut self, name: &str) -> Option<Vec<MeshEvent>> {
+        self.active = self
+            .radios
+            .iter()
+            .position(|(radio, _)| radio.name == name)?;
+        Some(self.active_events())
+    }
+
+    /// The events telling the UI about the active radio.
+    fn active_events(&self) -> Vec<MeshEvent> {
+        let (radio, device) = &self.radios[self.active];
+        let mut events = vec![MeshEvent::Radio {
+            name: radio.name.clone(),
+            transport: radio.transport.clone(),
+        }];
+        if let Some(node_info) = &device.my_node {
+            events.push(MeshEvent::MyNode(Box::new(node_info.clone())));
+        }
+        events.extend(device.device_config.clone().map(MeshEvent::DeviceConfig));
+        events.extend(device.lora_config.clone().map(MeshEvent::LoraConfig));
+        events.extend(
+            device
+                .security_config
+                .clone()
+                .map(MeshEvent::SecurityConfig),
+        );
+        events.extend(device.channels.values().cloned().map(MeshEvent::Channel));
+        events.extend(device.status.map(MeshEvent::ConnectionStatus));
+        events
+    }
+}
+
+/// Pass events between the UI and the radios, each linked by the sender of what the UI asks of it
+/// and the receiver of its events, until the UI goes away.
+#[tokio::main]
+pub async fn run(
+    radios: Vec<Radio>,
+    links: Vec<(mpsc::Sender<UiEvent>, mpsc::Receiver<MeshEvent>)>,
+    mut ui_rx: mpsc::Receiver<UiEvent>,
+    tx: mpsc::Sender<MeshEvent>,
+) {
+    let mut radios = Radios::new(radios);
+    let (events_tx, mut events_rx) = mpsc::channel(100);
+    let mut senders = Vec::new();
+    for (index, (sender, mut receiver)) in links.into_iter().enumerate() {
+        senders.push(sender);
+        let events_tx = events_tx.clone();
+        tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                if events_tx.send((index, event)).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+    for event in radios.active_events() {
+        if tx.send(event).await.is_err() {
+            return;
+        }
+    }
+    loop {
+        tokio::select! {
+            ui_event = ui_rx.recv() => {
+                let events = match ui_event {
+                    None => return,
+                    Some(UiEvent::SwitchRadio(name)) => match radios.switch(&name) {
+                        Some(events) => events,
+                        None => {
+                            log::warn!("There is no radio called {}", name);
+                            continue;
+                        }
+                    },
+                    Some(ui_event) => {
+                        if let UiEvent::Connect(transport) = &ui_event {
+                            radios.radios[radios.active].0.transport = transport.clone();
+                        }
+                        if let Err(e) = senders[radios.active].send(ui_event).await {
+                            log::error!("Failed to pass a request on to the radio: {}", e);
+                        }
+                        continue;
+                    }
+                };
+                for event in events {
+                    if tx.send(event).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            Some((index, event)) = events_rx.recv() => {
+                if let Some(event) = radios.event(index, event)
+                    && tx.send(event).await.is_err()
+                {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs::{MeshPacket, Position};
+    use meshtastic::types::NodeId;
+
+    use super::*;
+
+    fn radios() -> Radios {
+        Radios::new(vec![
+            Radio {
+                name: "base".to_string(),
+                transport: Transport::Serial("/dev/ttyACM0".to_string()),
+            },
+            Radio {
+                name: "portable".to_string(),
+                transport: Transport::Tcp("portable.local:4403".to_string()),
+            },
+        ])
+    }
+
+    fn message(from: NodeNum, id: u32) -> MeshEvent {
+        MeshEvent::Message {
+            node_id: NodeId::new(from),
+            to: NodeId::new(0x1234),
+            channel: 0,
+            message: "hello".to_string(),
+            snr: 5.0,
+            id,
+            packet: Box::new(MeshPacket::default()),
+        }
+    }
+
+    #[test]
+    fn the_other_radios_only_add_nodes_and_messages() {
+        let mut radios = radios();
+        let lora = LoRaConfig::default();
+        assert!(
+            radios
+                .event(0, MeshEvent::LoraConfig(lora.clone()))
+                .is_some()
+        );
+        assert!(radios.event(1, MeshEvent::LoraConfig(lora)).is_none());
+        assert!(radios.event(1, MeshEvent::Rebooted).is_none());
+        let position = MeshEvent::Position {
+            node_id: NodeId::new(0xa1b2),
+            position: Position::default(),
+        };
+        assert!(radios.event(1, position).is_some());
+
+        // Both radios hear the same message.
+        assert!(radios.event(1, message(0xa1b2, 7)).is_some());
+        assert!(radios.event(0, message(0xa1b2, 7)).is_none());
+        assert!(radios.event(0, message(0xa1b2, 8)).is_some());
+    }
+
+    #[test]
+    fn switching_tells_what_the_radio_said_about_itself() {
+        let mut radios = radios();
+        let channel = Channel {
+            index: 1,
+            ..Channel::default()
+        };
+        radios.event(1, MeshEvent::Channel(channel.clone()));
+        radios.event(1, MeshEvent::ConnectionStatus(ConnectionStatus::Connected));
+
+        assert!(radios.switch("garage").is_none());
+        let events = radios.switch("portable").unwrap();
+        assert!(matches!(
+            &events[..],
+            [
+                MeshEvent::Radio { name, .. },
+                MeshEvent::Channel(replayed),
+                MeshEvent::ConnectionStatus(ConnectionStatus::Connected),
+            ] if name == "portable" && *replayed == channel
+        ));
+        assert!(radios.event(0, MeshEvent::Rebooted).is_none());
+        assert!(radios.event(1, MeshEvent::Rebooted).is_some());
+    }
+}
@@ -0,0 +1,132 @@
+//! Rebooting a remote node and confirming it comes back, a routine check on router nodes.
+//!
+//! Remote admin messages need a session passkey, which a node hands out in its answer to any
+//! admin request. So the workflow first asks the node for its metadata, sends the reboot with
+//! the passkey from the answer, then waits for the node's next NodeInfo or telemetry and reports
+//! how long it was down.
+
+use std::time::{Duration, Instant};
+
+use meshtastic::protobufs::PortNum;
+
+use crate::stats;
+use crate::types::NodeNum;
+
+/// Seconds the node waits before rebooting, so it can acknowledge the request first.
+pub const REBOOT_DELAY_SECONDS: i32 = 5;
+/// How long to wait for the node to answer the session request.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long to wait for the node to come back before giving up on it.
+const RETURN_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+pub enum Stage {
+    /// Waiting for the operator to confirm.
+    Confirm,
+    /// Asked the node for a session passkey.
+    AwaitingSession {
+        since: Instant,
+    },
+    /// Sent the reboot, waiting to hear from the node again.
+    Rebooting {
+        since: Instant,
+    },
+    Back {
+        downtime: Duration,
+    },
+    Failed(&'static str),
+}
+
+pub struct RemoteReboot {
+    pub node: NodeNum,
+    pub stage: Stage,
+}
+
+impl RemoteReboot {
+    pub fn new(node: NodeNum) -> Self {
+        Self {
+            node,
+            stage: Stage::Confirm,
+        }
+    }
+
+    /// The operator confirmed; returns `true` if the session should now be requested.
+    pub fn confirm(&mut self) -> bool {
+        if !matches!(self.stage, Stage::Confirm) {
+            return false;
+        }
+        self.stage = Stage::AwaitingSession {
+            since: Instant::now(),
+        };
+        true
+    }
+
+    /// The node answered with a session passkey; returns `true` if the reboot should now be
+    /// sent.
+    pub fn session_opened(&mut self) -> bool {
+        if !matches!(self.stage, Stage::AwaitingSession { .. }) {
+            return false;
+        }
+        self.stage = Stage::Rebooting {
+            since: Instant::now(),
+        };
+        true
+    }
+
+    /// A packet arrived from the node; returns `true` if it shows the node is back.
+    pub fn heard(&mut self, port: i32) -> bool {
+        let Stage::Rebooting { since } = self.stage else {
+            return false;
+        };
+        let announces = port == PortNum::NodeinfoApp as i32 || port == PortNum::TelemetryApp as i32;
+        // Anything before the reboot delay is up was sent before the node went down.
+        let delay = Duration::from_secs(REBOOT_DELAY_SECONDS as u64);
+        if !announces || since.elapsed() < delay {
+            return false;
+        }
+        self.stage = Stage::Back {
+            downtime: since.elapsed() - delay,
+        };
+        true
+    }
+
+    /// The radio could not take the request for the current stage.
+    pub fn not_sent(&mut self) {
+        self.stage = Stage::Failed("the radio could not take the request");
+    }
+
+    /// Whether the node came back or the reboot failed, so it may be started over.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.stage, Stage::Back { .. } | Stage::Failed(_))
+    }
+
+    /// Give up on a stage that has waited too long; returns `true` if it did.
+    pub fn check_timeout(&mut self) -> bool {
+        match self.stage {
+            Stage::AwaitingSession { since } if since.elapsed() > SESSION_TIMEOUT => {
+                self.stage = Stage::Failed("no answer; is our node's key one of its admin keys?");
+                true
+            }
+            Stage::Rebooting { since } if since.elapsed() > RETURN_TIMEOUT => {
+                self.stage = Stage::Failed("not heard from since the reboot");
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn status(&self) -> String {
+        match &self.stage {
+            Stage::Confirm => "press R again to reboot this node".to_string(),
+            Stage::AwaitingSession { .. } => "asking the node for a session".to_string(),
+            Stage::Rebooting { since } => format!(
+                "rebooting, {} so far",
+                stats::format_duration(since.elapsed().as_secs_f64())
+            ),
+            Stage::Back { downtime } => format!(
+                "back after {}",
+                stats::format_duration(downtime.as_secs_f64())
+            ),
+            Stage::Failed(reason) => format!("failed: {reason}"),
+        }
+    }
+}
@@ -0,0 +1,431 @@
+//! Stored history played back over a time window, for looking at how an exercise unfolded:
+//! `:replay` opens it full screen, with the nodes heard so far, a map of where they were and the
+//! messages of the last while, all as they stood at the replay clock.
+//!
+//! Space pauses and resumes, `+` and `-` change the speed, the arrow keys skip a twentieth of the
+//! window, and `g` and `G` go to its start and end.
+
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use chrono::{DateTime, Local};
+use meshtastic::protobufs::Position;
+use ratatui::{
+    Frame,
+    crossterm::event::{KeyCode, KeyEvent},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Stylize},
+    text::Line,
+    widgets::{
+        Block, Clear, List, Paragraph,
+        canvas::{Canvas, Map, MapResolution, Points},
+    },
+};
+
+use crate::export::DEGREES_PER_UNIT;
+use crate::tui::App;
+use crate::types::{Message, NodeNum, format_node_num};
+use crate::view::{Action, View};
+
+/// Replay seconds per second of real time.
+const SPEEDS: [f64; 6] = [1.0, 10.0, 60.0, 300.0, 1800.0, 3600.0];
+
+/// Nodes heard within this long of the replay clock are highlighted on the map.
+const RECENT_SECONDS: i64 = 5 * 60;
+
+enum Event {
+    /// A node reported being at this longitude and latitude.
+    Position { node: NodeNum, x: f64, y: f64 },
+    /// A message in the conversation with a node.
+    Message { node: NodeNum, message: Message },
+}
+
+impl Event {
+    fn node(&self) -> NodeNum {
+        match self {
+            Event::Position { node, .. } | Event::Message { node, .. } => *node,
+        }
+    }
+}
+
+pub struct Replay {
+    from: i64,
+    to: i64,
+    /// What happened in the window, with when, in chronological order.
+    events: Vec<(i64, Event)>,
+    /// Longitudes and latitudes shown on the map: everywhere a node went in the window.
+    bounds: ([f64; 2], [f64; 2]),
+    /// The replay clock, in seconds since the epoch.
+    clock: f64,
+    speed: usize,
+    /// When the clock was last advanced, while playing.
+    playing: Option<Instant>,
+}
+
+impl Replay {
+    /// A replay from `from` to `to` of the positions recorded and the messages exchanged with the
+    /// node each is with, starting paused at the beginning.
+    pub fn new(
+        from: DateTime<Local>,
+        to: DateTime<Local>,
+        positions: Vec<(NodeNum, i64, Position)>,
+        messages: Vec<(NodeNum, Message)>,
+    ) -> Self {
+        let (from, to) = (from.timestamp(), to.timestamp());
+        let mut events: Vec<(i64, Event)> = positions
+            .into_iter()
+            .filter_map(|(node, time, position)| {
+                let x = f64::from(position.longitude_i?) * DEGREES_PER_UNIT;
+                let y = f64::from(position.latitude_i?) * DEGREES_PER_UNIT;
+                Some((time, Event::Position { node, x, y }))
+            })
+            .chain(
+                messages
+                    .into_iter()
+                    .map(|(node, message)| {
+                        (message.ts.timestamp(), Event::Message { node, message })
+                    })
+                    .filter(|(time, _)| (from..=to).contains(time)),
+            )
+            .collect();
+        // Stable, so positions and messages at the same second keep their own order.
+        events.sort_by_key(|(time, _)| *time);
+        let bounds = bounds(&events);
+        Self {
+            from,
+            to,
+            events,
+            bounds,
+            clock: from as f64,
+            speed: 2,
+            playing: None,
+        }
+    }
+
+    fn now(&self) -> i64 {
+        self.clock as i64
+    }
+
+    /// Move the clock on by the real time passed since it last was, stopping at the end.
+    fn advance(&mut self) {
+        let Some(last) = self.playing else {
+            return;
+        };
+        let now = Instant::now();
+        self.clock += now.duration_since(last).as_secs_f64() * SPEEDS[self.speed];
+        self.playing = Some(now);
+        if self.clock >= self.to as f64 {
+            self.clock = self.to as f64;
+            self.playing = None;
+        }
+    }
+
+    fn seek(&mut self, seconds: f64) {
+        self.clock = (self.clock + seconds).clamp(self.from as f64, self.to as f64);
+    }
+
+    /// The events up to the clock.
+    fn past(&self) -> &[(i64, Event)] {
+        let now = self.now();
+        let shown = self.events.partition_point(|(time, _)| *time <= now);
+        &self.events[..shown]
+    }
+
+    /// Every node heard so far, with when it was last heard and the positions it reported.
+    fn nodes(&self) -> BTreeMap<NodeNum, (i64, Vec<(f64, f64)>)> {
+        let mut nodes: BTreeMap<NodeNum, (i64, Vec<(f64, f64)>)> = BTreeMap::new();
+        for (time, event) in self.past() {
+            let (heard, track) = nodes.entry(event.node()).or_default();
+            *heard = *time;
+            if let Event::Position { x, y, .. } = event {
+                track.push((*x, *y));
+            }
+        }
+        nodes
+    }
+
+    fn draw_nodes(&self, app: &App, frame: &mut Frame, rect: Rect) {
+        let now = self.now();
+        let mut nodes: Vec<(NodeNum, i64)> = self
+            .nodes()
+            .into_iter()
+            .map(|(node, (heard, _))| (node, heard))
+            .collect();
+        nodes.sort_by_key(|(_, heard)| -heard);
+        let items: Vec<Line> = nodes
+            .into_iter()
+            .map(|(node, heard)| {
+                let line = Line::from(format!("{} {}", ago(now - heard), app.node_name(node)));
+                if now - heard < RECENT_SECONDS {
+                    line.yellow()
+                } else {
+                    line
+                }
+            })
+            .collect();
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title("NODES".bold())),
+            rect,
+        );
+    }
+
+    fn draw_map(&self, app: &App, frame: &mut Frame, rect: Rect) {
+        let now = self.now();
+        let nodes = self.nodes();
+        let ([west, east], [south, north]) = self.bounds;
+        let map = Canvas::default()
+            .block(Block::bordered().title("MAP".bold()))
+            .x_bounds([west, east])
+            .y_bounds([south, north])
+            .paint(|ctx| {
+                ctx.draw(&Map {
+                    color: Color::DarkGray,
+                    resolution: MapResolution::High,
+                });
+                for (_, track) in nodes.values() {
+                    ctx.draw(&Points {
+                        coords: track,
+                        color: Color::Gray,
+                    });
+                }
+                ctx.layer();
+                for (node, (heard, track)) in &nodes {
+                    let Some(&(x, y)) = track.last() else {
+                        continue;
+                    };
+                    let label = short_name(app, *node);
+                    if now - heard < RECENT_SECONDS {
+                        ctx.print(x, y, label.yellow().bold());
+                    } else {
+                        ctx.print(x, y, label.cyan());
+                    }
+                }
+            });
+        frame.render_widget(map, rect);
+    }
+
+    fn draw_messages(&self, app: &App, frame: &mut Frame, rect: Rect) {
+        let shown = usize::from(rect.height.saturating_sub(2));
+        let messages: Vec<Line> = self
+            .past()
+            .iter()
+            .filter_map(|(_, event)| match event {
+                Event::Message { node, message } => Some((*node, message)),
+                Event::Position { .. } => None,
+            })
+            .rev()
+            .take(shown)
+            .map(|(node, message)| {
+                let (who, colour) = if message.outgoing {
+                    (format!("me to {}", app.node_name(node)), Color::Yellow)
+                } else {
+                    (app.node_name(node), Color::Blue)
+                };
+                Line::from(vec![
+                    message.ts.format("%H:%M:%S ").to_string().into(),
+                    who.fg(colour),
+                    format!("> {}", message.text).into(),
+                ])
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect();
+        frame.render_widget(
+            Paragraph::new(messages).block(Block::bordered().title("MESSAGES".bold())),
+            rect,
+        );
+    }
+}
+
+impl View for Replay {
+    /// Over the whole screen rather than `rect`.
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, _rect: Rect) {
+        self.advance();
+        let area = frame.area();
+        let time = |seconds: i64| {
+            DateTime::from_timestamp(seconds, 0)
+                .unwrap_or_default()
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+        };
+        let state = if self.playing.is_some() {
+            "playing"
+        } else {
+            "paused"
+        };
+        let title = format!(
+            " REPLAY {}  {}x {} ",
+            time(self.now()),
+            SPEEDS[self.speed],
+            state
+        );
+        let progress = (self.clock - self.from as f64) / (self.to - self.from).max(1) as f64;
+        let block = Block::bordered()
+            .title(title.bold())
+            .title(Line::from(format!(" {:.0}% ", progress * 100.0)).right_aligned())
+            .title_bottom("SPACE play/pause  +/- speed  ←/→ skip  g/G start/end  ESC close");
+        let inner = block.inner(area);
+        frame.render_widget(Clear, area);
+        frame.render_widget(block, area);
+
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(30), Constraint::Min(0)]).areas(inner);
+        let [map, messages] =
+            Layout::vertical([Constraint::Percentage(65), Constraint::Min(0)]).areas(right);
+        self.draw_nodes(app, frame, left);
+        self.draw_map(app, frame, map);
+        self.draw_messages(app, frame, messages);
+    }
+
+    fn handle_action(&mut self, _app: &mut App, key: KeyEvent) -> Action {
+        self.advance();
+        let skip = (self.to - self.from) as f64 / 20.0;
+        match key.code {
+            KeyCode::Char(' ') if self.playing.is_some() => self.playing = None,
+            KeyCode::Char(' ') => {
+                if self.now() >= self.to {
+                    self.clock = self.from as f64;
+                }
+                self.playing = Some(Instant::now());
+            }
+            KeyCode::Char('+') => self.speed = (self.speed + 1).min(SPEEDS.len() - 1),
+            KeyCode::Char('-') => self.speed = self.speed.saturating_sub(1),
+            KeyCode::Right => self.seek(skip),
+            KeyCode::Left => self.seek(-skip),
+            KeyCode::Home | KeyCode::Char('g') => self.clock = self.from as f64,
+            KeyCode::End | KeyCode::Char('G') => self.clock = self.to as f64,
+            KeyCode::Char('q') => return Action::Close,
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// The area around every position in `events`, with a margin, or the whole world if there are
+/// none.
+fn bounds(events: &[(i64, Event)]) -> ([f64; 2], [f64; 2]) {
+    let points: Vec<(f64, f64)> = events
+        .iter()
+        .filter_map(|(_, event)| match event {
+            Event::Position { x, y, .. } => Some((*x, *y)),
+            Event::Message { .. } => None,
+        })
+        .collect();
+    if points.is_empty() {
+        return ([-180.0, 180.0], [-90.0, 90.0]);
+    }
+    let fold = |pick: fn(&(f64, f64)) -> f64| {
+        points
+            .iter()
+            .map(pick)
+            .fold((f64::MAX, f64::MIN), |(low, high), v| {
+                (low.min(v), high.max(v))
+            })
+    };
+    let (west, east) = fold(|p| p.0);
+    let (south, north) = fold(|p| p.1);
+    // At least about a kilometre across, so a single position is not a point.
+    let margin_x = ((east - west) * 0.1).max(0.01);
+    let margin_y = ((north - south) * 0.1).max(0.01);
+    (
+        [west - margin_x, east + margin_x],
+        [south - margin_y, north + margin_y],
+    )
+}
+
+fn short_name(app: &App, node: NodeNum) -> String {
+    app.nodes
+        .get(&node)
+        .and_then(|n| n.user.as_ref())
+        .map_or_else(|| format_node_num(node), |user| user.short_name.clone())
+}
+
+/// How long ago, to the minute, such as `12m` or `3h05m`.
+fn ago(seconds: i64) -> String {
+    let minutes = seconds / 60;
+    if minutes < 60 {
+        format!("{minutes:>2}m")
+    } else {
+        format!("{}h{:02}m", minutes / 60, minutes % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(time: i64, text: &str) -> Message {
+        Message {
+            outgoing: false,
+            ts: DateTime::from_timestamp(time, 0)
+                .unwrap()
+                .with_timezone(&Local),
+            channel: 0,
+            text: text.to_string(),
+            snr: None,
+            packet_id: None,
+            estimated_time: false,
+            note: None,
+            reply_id: None,
+        }
+    }
+
+    fn position(latitude: i32, longitude: i32) -> Position {
+        Position {
+            latitude_i: Some(latitude),
+            longitude_i: Some(longitude),
+            ..Position::default()
+        }
+    }
+
+    #[test]
+    fn the_clock_shows_what_happened_by_then() {
+        let at = |seconds| {
+            DateTime::from_timestamp(seconds, 0)
+                .unwrap()
+                .with_timezone(&Local)
+        };
+        let mut replay = Replay::new(
+            at(1000),
+            at(2000),
+            vec![
+                (1, 1100, position(100, 200)),
+                (1, 1500, position(110, 210)),
+                (2, 1900, position(0, 0)),
+            ],
+            vec![
+                (2, message(1200, "on my way")),
+                (3, message(900, "before the window")),
+            ],
+        );
+        assert!(replay.nodes().is_empty());
+
+        replay.seek(300.0);
+        let nodes = replay.nodes();
+        assert_eq!(nodes.keys().copied().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(nodes[&1].1.len(), 1);
+        assert_eq!(nodes[&2], (1200, Vec::new()));
+
+        replay.seek(10_000.0);
+        assert_eq!(replay.now(), 2000);
+        assert_eq!(replay.nodes()[&1].1.len(), 2);
+        assert_eq!(replay.past().len(), 4);
+    }
+
+    #[test]
+    fn the_map_fits_the_positions() {
+        let replay = Replay::new(
+            Local::now(),
+            Local::now(),
+            vec![
+                (1, 0, position(450_000_000, -750_000_000)),
+                (2, 0, position(451_000_000, -751_000_000)),
+            ],
+            Vec::new(),
+        );
+        let ([west, east], [south, north]) = replay.bounds;
+        assert!(west < -75.1 && east > -75.0);
+        assert!(south < 45.0 && north > 45.1);
+    }
+}
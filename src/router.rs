@@ -1,115 +1,670 @@
 //! A `Router` acts as middleware that can do work whenever a given message is sent or received.
 
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use meshtastic::Message as _;
 use meshtastic::errors::Error;
 use meshtastic::packet::PacketRouter;
 use meshtastic::protobufs::{
-    FromRadio, MeshPacket, PortNum, User, from_radio::PayloadVariant, mesh_packet,
+    AdminMessage, Data, FromRadio, MeshPacket, PortNum, Position, RouteDiscovery, Routing,
+    Telemetry, User, Waypoint, admin_message, config,
+    config::{DeviceConfig, LoRaConfig},
+    from_radio::PayloadVariant,
+    mesh_packet, routing, telemetry,
 };
 use meshtastic::types::NodeId;
 use tokio::sync::mpsc::Sender;
 
-use crate::types::MeshEvent;
+use crate::compression;
+use crate::device_time;
+use crate::lora;
+use crate::mesh::MeshError;
+use crate::structured::Structured;
+use crate::survey::Survey;
+use crate::types::{MeshEvent, NodeNum};
+
+/// Size of the unencrypted header in front of every packet on air.
+const PACKET_HEADER_BYTES: usize = 16;
+/// How many recent packets are remembered to drop copies of them.
+const RECENT_PACKETS: usize = 64;
 
 pub struct Router {
     user: Option<User>,
     node_num: Option<NodeId>,
+    position: Option<Position>,
+    device_config: Option<DeviceConfig>,
+    /// Needed to estimate airtime. Until it arrives the firmware defaults are assumed.
+    lora_config: LoRaConfig,
     ui_channel: Sender<MeshEvent>,
+    survey: Option<Survey>,
+    /// Destinations and outbox entries of messages we sent that are waiting for an
+    /// acknowledgement, by packet ID.
+    awaiting_ack: HashMap<u32, (NodeNum, Option<i64>)>,
+    /// Outbox entry of the message being sent right now.
+    sending: Option<i64>,
+    /// ID of the last packet sent for an outbox entry, until reported.
+    sent_packet: Option<u32>,
+    /// Sender and ID of the packets received last, as the same packet can reach us more than
+    /// once, e.g. over both LoRa and MQTT.
+    recent_packets: VecDeque<(NodeNum, u32)>,
+    /// Nodes we heard edda's own traffic from on the private port, which can take structured
+    /// messages as JSON.
+    edda_nodes: HashSet<NodeNum>,
 }
 
 impl Router {
-    pub fn new(ui_channel: Sender<MeshEvent>) -> Self {
+    pub fn new(ui_channel: Sender<MeshEvent>, survey: Option<Survey>) -> Self {
         Router {
             user: None,
             node_num: None,
+            position: None,
+            device_config: None,
+            lora_config: LoRaConfig {
+                use_preset: true,
+                ..Default::default()
+            },
             ui_channel,
+            survey,
+            awaiting_ack: HashMap::new(),
+            sending: None,
+            sent_packet: None,
+            recent_packets: VecDeque::new(),
+            edda_nodes: HashSet::new(),
         }
     }
 
+    /// Whether `node` is known to run edda.
+    pub fn is_edda(&self, node: NodeNum) -> bool {
+        self.edda_nodes.contains(&node)
+    }
+
     pub fn handle_packet_from_radio(&mut self, packet: FromRadio) {
-        match packet.payload_variant.as_ref() {
-            // TODO(aidenfoxivey): This must be turned into a logger stmt instead.
-            None => panic!("Unexpected packet from_radio"),
-            Some(variant) => {
-                match variant {
-                    PayloadVariant::Packet(packet) => {
-                        let is_for_me = self
-                            .node_num
-                            .map(|n| n == packet.to || packet.to == 0xFFFFFFFF)
-                            .unwrap_or(false);
-
-                        if is_for_me {
-                            if let Some(mesh_packet::PayloadVariant::Decoded(data)) =
-                                &packet.payload_variant
-                            {
-                                if data.portnum == PortNum::TextMessageApp as i32 {
-                                    if let Ok(msg) = String::from_utf8(data.payload.clone()) {
-                                        log::info!("Received text message from {}", packet.from);
-                                        if let Err(e) =
-                                            self.ui_channel.try_send(MeshEvent::Message {
-                                                node_id: NodeId::from(packet.from),
-                                                message: msg,
-                                            })
-                                        {
-                                            log::error!("Failed to send Message event: {}", e);
-                                        }
-                                    }
-                                }
-                            }
-                        }
+        let Some(variant) = packet.payload_variant.as_ref() else {
+            log::warn!("Ignoring empty packet {} from radio", packet.id);
+            return;
+        };
+        match variant {
+            PayloadVariant::Packet(packet) => {
+                if self.is_duplicate(packet) {
+                    log::debug!("Dropping duplicate packet {} from radio", packet.id);
+                    return;
+                }
+                let is_from_me = self.node_num.is_some_and(|n| n == packet.from);
+                if !is_from_me
+                    && let (Some(survey), Some(position)) = (&self.survey, &self.position)
+                {
+                    survey.record(position, packet);
+                }
+                if !is_from_me {
+                    self.packet_heard(packet);
+                }
+
+                let is_for_me = self
+                    .node_num
+                    .map(|n| n == packet.to || packet.to == 0xFFFFFFFF)
+                    .unwrap_or(false);
+
+                if is_for_me
+                    && let Some(mesh_packet::PayloadVariant::Decoded(data)) =
+                        &packet.payload_variant
+                {
+                    self.handle_decoded_packet(packet, data);
+                }
+            }
+            PayloadVariant::MyInfo(info) => {
+                if let Some(node_num) = self.node_num
+                    && node_num != info.my_node_num
+                {
+                    // Only happens if the device was reset; forget what we knew.
+                    log::warn!(
+                        "Device changed node number from {} to {}",
+                        node_num,
+                        info.my_node_num
+                    );
+                    self.user = None;
+                    self.position = None;
+                    self.awaiting_ack.clear();
+                }
+                log::info!("Setting current node num to {}", info.my_node_num);
+                self.node_num = Some(NodeId::from(info.my_node_num));
+            }
+            PayloadVariant::NodeInfo(info) => {
+                if let Some(node_num) = self.node_num
+                    && node_num == info.num
+                {
+                    log::info!("Receiving current node user information");
+                    self.user = info.user.clone();
+                    self.position = info.position;
+                    self.send_event(MeshEvent::MyNode(Box::new(info.clone())));
+                } else {
+                    let mut info = info.clone();
+                    // A device without a set clock reports times from 1970; treat them as unknown.
+                    if device_time::plausible(info.last_heard).is_none() {
+                        info.last_heard = 0;
+                    }
+                    self.send_event(MeshEvent::NodeAvailable(Box::new(info)));
+                }
+            }
+            PayloadVariant::Config(config) => match &config.payload_variant {
+                Some(config::PayloadVariant::Device(device)) => {
+                    self.device_config = Some(device.clone());
+                    self.send_event(MeshEvent::DeviceConfig(device.clone()));
+                }
+                Some(config::PayloadVariant::Lora(lora)) => {
+                    self.lora_config = lora.clone();
+                    self.send_event(MeshEvent::LoraConfig(lora.clone()));
+                }
+                Some(config::PayloadVariant::Security(security)) => {
+                    self.send_event(MeshEvent::SecurityConfig(security.clone()));
+                }
+                _ => {}
+            },
+            PayloadVariant::LogRecord(record) => {
+                self.send_event(MeshEvent::DeviceLog(record.clone()));
+            }
+            PayloadVariant::ConfigCompleteId(_) => {}
+            PayloadVariant::Rebooted(_) => {
+                log::warn!("Device rebooted");
+                self.send_event(MeshEvent::Rebooted);
+            }
+            PayloadVariant::ModuleConfig(_) => {}
+            PayloadVariant::Channel(channel) => {
+                self.send_event(MeshEvent::Channel(channel.clone()));
+            }
+            PayloadVariant::QueueStatus(_) => {}
+            PayloadVariant::XmodemPacket(_) => {}
+            PayloadVariant::Metadata(_) => {}
+            PayloadVariant::MqttClientProxyMessage(_) => {}
+            PayloadVariant::FileInfo(_) => {}
+            PayloadVariant::ClientNotification(_) => {}
+            PayloadVariant::DeviceuiConfig(_) => {}
+        }
+    }
+}
+
+impl Router {
+    /// The owner of the node we are connected to, once known.
+    pub fn user_mut(&mut self) -> Option<&mut User> {
+        self.user.as_mut()
+    }
+
+    pub fn device_config_mut(&mut self) -> Option<&mut DeviceConfig> {
+        self.device_config.as_mut()
+    }
+
+    /// Tag the packets sent until the next call with an outbox entry.
+    pub fn set_sending(&mut self, outbox: Option<i64>) {
+        self.sending = outbox;
+    }
+
+    /// Report that the radio took the message of an outbox entry, and the packet ID it went out
+    /// with.
+    pub fn report_sent(&mut self, outbox: i64) {
+        let packet_id = self.sent_packet.take();
+        self.send_event(MeshEvent::Sent { outbox, packet_id });
+    }
+
+    /// Report settings we applied, as the device only reports its settings on connect.
+    pub fn report_applied(&self, event: MeshEvent) {
+        self.send_event(event);
+    }
+
+    fn handle_decoded_packet(&mut self, packet: &MeshPacket, data: &Data) {
+        match PortNum::try_from(data.portnum) {
+            Ok(PortNum::TextMessageApp) => {
+                if let Ok(msg) = String::from_utf8(data.payload.clone()) {
+                    log::info!("Received text message from {}", packet.from);
+                    self.send_event(MeshEvent::Message {
+                        node_id: NodeId::from(packet.from),
+                        to: NodeId::from(packet.to),
+                        channel: packet.channel,
+                        message: msg,
+                        snr: packet.rx_snr,
+                        id: packet.id,
+                        packet: Box::new(packet.clone()),
+                    });
+                }
+            }
+            Ok(PortNum::PositionApp) => match Position::decode(data.payload.as_slice()) {
+                Ok(position) => {
+                    log::debug!("Received position from {}", packet.from);
+                    if self.node_num.is_some_and(|n| n == packet.from) {
+                        self.position = Some(position);
                     }
-                    PayloadVariant::MyInfo(info) => {
-                        // TODO(aidenfoxivey): I don't know that this case can happen, but want to be sure.
-                        if self.node_num.is_some() {
-                            panic!("Node number already set. Unexpected to set again.");
-                        }
-                        log::info!("Setting current node num to {}", info.my_node_num);
-                        self.node_num = Some(NodeId::from(info.my_node_num));
+                    self.send_event(MeshEvent::Position {
+                        node_id: NodeId::from(packet.from),
+                        position,
+                    });
+                }
+                Err(e) => self.decode_failed(packet, "position", e),
+            },
+            Ok(PortNum::RoutingApp) => {
+                let Some(&(to, outbox)) = self.awaiting_ack.get(&data.request_id) else {
+                    return;
+                };
+                let error = match Routing::decode(data.payload.as_slice()) {
+                    Ok(Routing {
+                        variant: Some(routing::Variant::ErrorReason(error)),
+                    }) => error,
+                    _ => return,
+                };
+                // Our own node acknowledging only means it heard the message being relayed.
+                let delivered = error == routing::Error::None as i32;
+                if delivered && packet.from != to {
+                    if let Some(outbox) = outbox {
+                        log::debug!("Message to {} was relayed", to);
+                        self.send_event(MeshEvent::Relayed { outbox });
                     }
-                    PayloadVariant::NodeInfo(info) => {
-                        if let Some(node_num) = self.node_num
-                            && node_num == info.num
-                        {
-                            log::info!("Receiving current node user information");
-                            self.user = info.user.clone();
-                        } else {
-                            if let Err(e) = self
-                                .ui_channel
-                                .try_send(MeshEvent::NodeAvailable(Box::new(info.clone())))
-                            {
-                                log::error!("Failed to send NodeAvailable event: {}", e);
-                            }
-                        }
+                    return;
+                }
+                self.awaiting_ack.remove(&data.request_id);
+                log::debug!("Delivery to {}: {}", to, delivered);
+                self.send_event(MeshEvent::Delivery {
+                    to,
+                    delivered,
+                    outbox,
+                    relay_node: (packet.relay_node != 0).then_some(packet.relay_node as u8),
+                    hops: hops(packet),
+                });
+            }
+            Ok(PortNum::NodeinfoApp) => match User::decode(data.payload.as_slice()) {
+                Ok(user) if self.node_num.is_none_or(|n| n != packet.from) => {
+                    log::debug!("Received user information from {}", packet.from);
+                    self.send_event(MeshEvent::User {
+                        from: packet.from,
+                        user,
+                    });
+                }
+                Ok(_) => {}
+                Err(e) => self.decode_failed(packet, "user information", e),
+            },
+            Ok(PortNum::PrivateApp) => {
+                let text = match Structured::decode(&data.payload) {
+                    Some(structured) => Some(structured.to_text()),
+                    None => compression::decompress(&data.payload),
+                };
+                if let Some(msg) = text {
+                    log::info!("Received edda text message from {}", packet.from);
+                    self.edda_nodes.insert(packet.from);
+                    self.send_event(MeshEvent::Message {
+                        node_id: NodeId::from(packet.from),
+                        to: NodeId::from(packet.to),
+                        channel: packet.channel,
+                        message: msg,
+                        snr: packet.rx_snr,
+                        id: packet.id,
+                        packet: Box::new(packet.clone()),
+                    });
+                    return;
+                }
+                self.send_event(MeshEvent::PrivateData {
+                    from: packet.from,
+                    payload: data.payload.clone(),
+                });
+            }
+            Ok(PortNum::AdminApp) => match AdminMessage::decode(data.payload.as_slice()) {
+                // Our own device answers too, but needs no session.
+                Ok(admin) if self.node_num.is_none_or(|n| n != packet.from) => {
+                    match admin.payload_variant {
+                        Some(admin_message::PayloadVariant::GetDeviceMetadataResponse(
+                            metadata,
+                        )) => self.send_event(MeshEvent::Metadata {
+                            from: packet.from,
+                            metadata,
+                        }),
+                        Some(admin_message::PayloadVariant::GetConfigResponse(config)) => self
+                            .send_event(MeshEvent::RemoteConfig {
+                                from: packet.from,
+                                config,
+                            }),
+                        _ => {}
                     }
-                    PayloadVariant::Config(_) => {}
-                    PayloadVariant::LogRecord(_) => {}
-                    PayloadVariant::ConfigCompleteId(_) => {}
-                    PayloadVariant::Rebooted(_) => {}
-                    PayloadVariant::ModuleConfig(_) => {}
-                    PayloadVariant::Channel(_) => {}
-                    PayloadVariant::QueueStatus(_) => {}
-                    PayloadVariant::XmodemPacket(_) => {}
-                    PayloadVariant::Metadata(_) => {}
-                    PayloadVariant::MqttClientProxyMessage(_) => {}
-                    PayloadVariant::FileInfo(_) => {}
-                    PayloadVariant::ClientNotification(_) => {}
-                    PayloadVariant::DeviceuiConfig(_) => {}
+                    if !admin.session_passkey.is_empty() {
+                        log::debug!("Admin session opened by {}", packet.from);
+                        self.send_event(MeshEvent::AdminSession {
+                            from: packet.from,
+                            passkey: admin.session_passkey,
+                        });
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => self.decode_failed(packet, "admin message", e),
+            },
+            // Only answers to our own traceroutes; the device answers requests itself.
+            Ok(PortNum::TracerouteApp) if data.request_id != 0 => {
+                match RouteDiscovery::decode(data.payload.as_slice()) {
+                    Ok(route) => self.send_event(MeshEvent::Traceroute {
+                        from: packet.from,
+                        route,
+                    }),
+                    Err(e) => self.decode_failed(packet, "traceroute", e),
                 }
             }
+            Ok(PortNum::WaypointApp) => match Waypoint::decode(data.payload.as_slice()) {
+                Ok(waypoint) => self.send_event(MeshEvent::Waypoint {
+                    from: packet.from,
+                    waypoint,
+                }),
+                Err(e) => self.decode_failed(packet, "waypoint", e),
+            },
+            Ok(PortNum::TelemetryApp) => match Telemetry::decode(data.payload.as_slice()) {
+                Ok(Telemetry {
+                    variant: Some(telemetry::Variant::DeviceMetrics(metrics)),
+                    ..
+                }) => {
+                    self.send_event(MeshEvent::DeviceMetrics {
+                        from: packet.from,
+                        metrics,
+                    });
+                }
+                Ok(_) => {}
+                Err(e) => self.decode_failed(packet, "telemetry", e),
+            },
+            _ => {}
+        }
+    }
+
+    /// Whether a packet was received before, remembering it if not. Packets without an ID
+    /// cannot be told apart and are never duplicates.
+    fn is_duplicate(&mut self, packet: &MeshPacket) -> bool {
+        if packet.id == 0 {
+            return false;
+        }
+        let key = (packet.from, packet.id);
+        if self.recent_packets.contains(&key) {
+            return true;
+        }
+        if self.recent_packets.len() == RECENT_PACKETS {
+            self.recent_packets.pop_front();
+        }
+        self.recent_packets.push_back(key);
+        false
+    }
+
+    fn packet_heard(&self, packet: &MeshPacket) {
+        let (port, payload_bytes, channel) = match &packet.payload_variant {
+            Some(mesh_packet::PayloadVariant::Decoded(data)) => {
+                (data.portnum, data.encoded_len(), Some(packet.channel))
+            }
+            // The channel field of an encrypted packet is a hash, not an index.
+            Some(mesh_packet::PayloadVariant::Encrypted(bytes)) => (-1, bytes.len(), None),
+            None => return,
+        };
+        self.send_event(MeshEvent::PacketHeard {
+            from: packet.from,
+            to: packet.to,
+            port,
+            hops: hops(packet),
+            airtime_ms: lora::airtime_ms(&self.lora_config, PACKET_HEADER_BYTES + payload_bytes),
+            channel,
+        });
+    }
+
+    fn decode_failed(&self, packet: &MeshPacket, what: &'static str, error: impl ToString) {
+        self.send_event(MeshEvent::Error(MeshError::ProtoDecode {
+            from: packet.from,
+            what,
+            error: error.to_string(),
+        }));
+    }
+
+    fn send_event(&self, event: MeshEvent) {
+        if let Err(e) = self.ui_channel.try_send(event) {
+            log::error!("Failed to send event to UI: {}", e);
         }
     }
 }
 
+/// How many times a packet was relayed before reaching us. Older firmware does not set
+/// hop_start, so the hop count is unknown.
+fn hops(packet: &MeshPacket) -> Option<u32> {
+    (packet.hop_start > 0).then(|| packet.hop_start.saturating_sub(packet.hop_limit))
+}
+
 impl PacketRouter<(), Error> for Router {
     fn handle_packet_from_radio(&mut self, packet: FromRadio) -> Result<(), Error> {
         self.handle_packet_from_radio(packet);
         Ok(())
     }
 
-    fn handle_mesh_packet(&mut self, _packet: MeshPacket) -> Result<(), Error> {
-        todo!()
+    /// Called with the packets we send, so acknowledgements can be matched up with them.
+    fn handle_mesh_packet(&mut self, packet: MeshPacket) -> Result<(), Error> {
+        if self.sending.is_some() {
+            self.sent_packet = Some(packet.id);
+        }
+        if packet.want_ack {
+            self.awaiting_ack
+                .insert(packet.id, (packet.to, self.sending));
+        }
+        Ok(())
     }
 
     fn source_node_id(&self) -> NodeId {
         self.node_num.unwrap_or(NodeId::new(0))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs::{
+        DeviceMetrics, MyNodeInfo, NodeInfo, Telemetry, config::lo_ra_config::ModemPreset,
+    };
+    use proptest::prelude::*;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::types::BROADCAST;
+
+    const ME: NodeNum = 0x0000_1234;
+    const PEER: NodeNum = 0xa1b2_c3d4;
+    const PORTS: [PortNum; 6] = [
+        PortNum::TextMessageApp,
+        PortNum::PositionApp,
+        PortNum::RoutingApp,
+        PortNum::TelemetryApp,
+        PortNum::NodeinfoApp,
+        PortNum::AdminApp,
+    ];
+    /// The firmware never lets a packet travel further than this.
+    const MAX_HOPS: u32 = 7;
+
+    fn node_num() -> impl Strategy<Value = NodeNum> {
+        prop_oneof![Just(ME), Just(PEER), Just(BROADCAST), any::<NodeNum>()]
+    }
+
+    /// Payloads that are sometimes valid for some port, and often garbage.
+    fn payload() -> impl Strategy<Value = Vec<u8>> {
+        prop_oneof![
+            prop::collection::vec(any::<u8>(), 0..64),
+            ".{0,40}".prop_map(String::into_bytes),
+            any::<i32>().prop_map(|error| Routing {
+                variant: Some(routing::Variant::ErrorReason(error)),
+            }
+            .encode_to_vec()),
+            (any::<Option<u32>>(), any::<Option<f32>>()).prop_map(
+                |(battery_level, utilization)| {
+                    Telemetry {
+                        time: 0,
+                        variant: Some(telemetry::Variant::DeviceMetrics(DeviceMetrics {
+                            battery_level,
+                            channel_utilization: utilization,
+                            ..Default::default()
+                        })),
+                    }
+                    .encode_to_vec()
+                }
+            ),
+            (any::<Option<i32>>(), any::<Option<i32>>()).prop_map(|(latitude_i, longitude_i)| {
+                Position {
+                    latitude_i,
+                    longitude_i,
+                    ..Default::default()
+                }
+                .encode_to_vec()
+            }),
+        ]
+    }
+
+    fn mesh_packet() -> impl Strategy<Value = MeshPacket> {
+        let port = prop_oneof![
+            prop::sample::select(&PORTS[..]).prop_map(|p| p as i32),
+            any::<i32>(),
+        ];
+        let data = (port, payload(), any::<u32>()).prop_map(|(portnum, payload, request_id)| {
+            mesh_packet::PayloadVariant::Decoded(Data {
+                portnum,
+                payload,
+                request_id,
+                ..Default::default()
+            })
+        });
+        let payload_variant = prop_oneof![
+            data.prop_map(Some),
+            prop::collection::vec(any::<u8>(), 0..64)
+                .prop_map(|bytes| Some(mesh_packet::PayloadVariant::Encrypted(bytes))),
+            Just(None),
+        ];
+        (
+            (node_num(), node_num(), 0u32..4, any::<bool>()),
+            (0..=MAX_HOPS, 0..=MAX_HOPS, any::<f32>()),
+            payload_variant,
+        )
+            .prop_map(
+                |((from, to, id, want_ack), (hop_start, hop_limit, rx_snr), payload_variant)| {
+                    MeshPacket {
+                        from,
+                        to,
+                        // Few distinct IDs, so acknowledgements often match something we sent.
+                        id,
+                        want_ack,
+                        hop_start,
+                        hop_limit,
+                        rx_snr,
+                        payload_variant,
+                        ..Default::default()
+                    }
+                },
+            )
+    }
+
+    fn lora_config() -> impl Strategy<Value = LoRaConfig> {
+        (
+            any::<bool>(),
+            any::<i32>(),
+            any::<i32>(),
+            any::<u32>(),
+            any::<u32>(),
+            any::<u32>(),
+        )
+            .prop_map(
+                |(use_preset, modem_preset, region, spread_factor, bandwidth, coding_rate)| {
+                    LoRaConfig {
+                        use_preset,
+                        modem_preset: modem_preset % (ModemPreset::ShortTurbo as i32 + 2),
+                        region,
+                        spread_factor: spread_factor % 16,
+                        bandwidth,
+                        coding_rate: coding_rate % 10,
+                        ..Default::default()
+                    }
+                },
+            )
+    }
+
+    fn from_radio() -> impl Strategy<Value = FromRadio> {
+        let variant = prop_oneof![
+            mesh_packet().prop_map(PayloadVariant::Packet),
+            node_num().prop_map(|my_node_num| PayloadVariant::MyInfo(MyNodeInfo {
+                my_node_num,
+                ..Default::default()
+            })),
+            (node_num(), any::<Option<u32>>(), any::<bool>()).prop_map(
+                |(num, hops_away, has_user)| PayloadVariant::NodeInfo(NodeInfo {
+                    num,
+                    hops_away,
+                    user: has_user.then(User::default),
+                    ..Default::default()
+                })
+            ),
+            lora_config().prop_map(|lora| PayloadVariant::Config(protobufs_config(lora))),
+            any::<bool>().prop_map(PayloadVariant::Rebooted),
+            any::<u32>().prop_map(PayloadVariant::ConfigCompleteId),
+        ];
+        (any::<u32>(), prop::option::weighted(0.95, variant)).prop_map(|(id, payload_variant)| {
+            FromRadio {
+                id,
+                payload_variant,
+            }
+        })
+    }
+
+    fn protobufs_config(lora: LoRaConfig) -> meshtastic::protobufs::Config {
+        meshtastic::protobufs::Config {
+            payload_variant: Some(config::PayloadVariant::Lora(lora)),
+        }
+    }
+
+    /// Feed packets through a fresh router, returning every event it produced.
+    fn run(sent: Vec<MeshPacket>, received: Vec<FromRadio>) -> Vec<MeshEvent> {
+        let (tx, mut rx) = mpsc::channel(10_000);
+        let mut router = Router::new(tx, None);
+        router.handle_packet_from_radio(FromRadio {
+            id: 0,
+            payload_variant: Some(PayloadVariant::MyInfo(MyNodeInfo {
+                my_node_num: ME,
+                ..Default::default()
+            })),
+        });
+        for packet in sent {
+            PacketRouter::handle_mesh_packet(&mut router, packet).unwrap();
+        }
+        for packet in received {
+            router.handle_packet_from_radio(packet);
+        }
+        std::iter::from_fn(|| rx.try_recv().ok()).collect()
+    }
+
+    fn assert_well_formed(event: &MeshEvent, sent: &[MeshPacket]) {
+        match event {
+            MeshEvent::PacketHeard {
+                hops, airtime_ms, ..
+            } => {
+                assert!(hops.is_none_or(|h| h <= MAX_HOPS), "hops {:?}", hops);
+                assert!(
+                    airtime_ms.is_finite() && *airtime_ms >= 0.0,
+                    "airtime {}",
+                    airtime_ms
+                );
+            }
+            MeshEvent::Delivery { to, .. } => {
+                assert!(
+                    sent.iter().any(|p| p.want_ack && p.to == *to),
+                    "delivery report for {} which we never sent to",
+                    to
+                );
+            }
+            _ => {}
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn router_handles_any_packet_sequence(
+            sent in prop::collection::vec(mesh_packet(), 0..4),
+            received in prop::collection::vec(from_radio(), 0..40),
+        ) {
+            for event in run(sent.clone(), received) {
+                assert_well_formed(&event, &sent);
+            }
+        }
+
+        #[test]
+        fn router_handles_arbitrary_bytes(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+            if let Ok(packet) = FromRadio::decode(bytes.as_slice()) {
+                for event in run(Vec::new(), vec![packet]) {
+                    assert_well_formed(&event, &[]);
+                }
+            }
+        }
+    }
+}
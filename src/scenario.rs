@@ -0,0 +1,227 @@
+//! Scripted scenarios for mock mode (`edda --mock demo.toml --seed 42`), which runs the
+//! full UI against a simulated device instead of a radio, for demos and for regression tests of
+//! the whole stack.
+//!
+//! A scenario lists our node and the nodes around it, the quality of the link, and a timeline of
+//! messages arriving and connection drops. Messages sent to the scenario's nodes are
+//! acknowledged, unless the connection is down. The seed drives the link's losses and
+//! duplicates, so a scenario plays out the same way every time it is run with the same seed.
+
+use std::io;
+use std::iter;
+use std::path::Path;
+use std::time::Duration;
+use std::{fs, result};
+
+use color_eyre::eyre::{Result, WrapErr};
+use meshtastic::api::StreamHandle;
+use meshtastic::protobufs::{NodeInfo, PortNum, routing, to_radio};
+use serde::{Deserialize, Deserializer, de};
+use tokio::sync::mpsc;
+use tokio::time::{Instant, sleep_until};
+
+use crate::config::Config;
+use crate::fake_device::{self, Conditions, FakeDevice};
+use crate::mesh;
+use crate::types::{BROADCAST, MeshEvent, NodeNum, UiEvent, parse_node_num};
+
+#[derive(Debug, Deserialize)]
+pub struct Scenario {
+    /// Our own node.
+    pub me: ScenarioNode,
+    #[serde(default)]
+    pub nodes: Vec<ScenarioNode>,
+    #[serde(default)]
+    pub link: Link,
+    /// What happens when, in order of time once loaded.
+    #[serde(default)]
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScenarioNode {
+    /// Node ID, e.g. `!a1b2c3d4`.
+    #[serde(deserialize_with = "node_id")]
+    pub id: NodeNum,
+    pub name: String,
+    #[serde(default)]
+    pub favorite: bool,
+    pub hops: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Link {
+    /// Chance that a packet is lost, from 0 to 1.
+    pub loss: f64,
+    pub delay_ms: u64,
+    /// Chance that a packet arrives twice, from 0 to 1.
+    pub duplicate: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Event {
+    /// Seconds since the scenario started.
+    pub at: f64,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Action {
+    /// A text message arrives, sent to us unless `broadcast` is set.
+    Message {
+        #[serde(deserialize_with = "node_id")]
+        from: NodeNum,
+        text: String,
+        #[serde(default)]
+        channel: u32,
+        #[serde(default)]
+        broadcast: bool,
+    },
+    /// The device loses the mesh for this many seconds: nothing arrives and nothing sent gets
+    /// through.
+    Drop { drop: f64 },
+}
+
+fn node_id<'de, D: Deserializer<'de>>(deserializer: D) -> result::Result<NodeNum, D::Error> {
+    let id = String::deserialize(deserializer)?;
+    parse_node_num(&id).ok_or_else(|| de::Error::custom(format!("invalid node ID {id}")))
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&contents).wrap_err_with(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut scenario: Self = toml::from_str(contents)?;
+        scenario.events.sort_by(|a, b| a.at.total_cmp(&b.at));
+        Ok(scenario)
+    }
+}
+
+impl ScenarioNode {
+    fn info(&self) -> NodeInfo {
+        NodeInfo {
+            is_favorite: self.favorite,
+            hops_away: self.hops,
+            last_heard: chrono::Local::now().timestamp() as u32,
+            ..fake_device::node(self.id, &self.name)
+        }
+    }
+}
+
+/// Run the Meshtastic connection against a simulated device playing `scenario` on its own
+/// thread, returning the channels used to talk to it, like `mesh::spawn`.
+pub fn spawn(
+    config: Config,
+    scenario: Scenario,
+    seed: u64,
+) -> (mpsc::Sender<UiEvent>, mpsc::Receiver<MeshEvent>) {
+    let (ui_tx, mut ui_rx) = mpsc::channel(100);
+    let (mesh_tx, mesh_rx) = mpsc::channel(100);
+
+    std::thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                log::error!("Failed to start the mock device: {}", e);
+                return;
+            }
+        };
+        runtime.block_on(async move {
+            let (device, client) = tokio::io::duplex(64 * 1024);
+            let device = FakeDevice::new(device, scenario.me.id, seed);
+            let stream = StreamHandle::from_stream(client);
+            tokio::select! {
+                result = mesh::run_stream(stream, config, &mut ui_rx, mesh_tx.clone()) => {
+                    if let Err(e) = result {
+                        log::error!("Meshtastic thread error: {}", e);
+                        let _ = mesh_tx.send(MeshEvent::Error(e)).await;
+                    }
+                }
+                result = play(device, scenario) => {
+                    if let Err(e) = result {
+                        log::error!("Mock device stopped: {}", e);
+                    }
+                }
+            }
+        });
+    });
+
+    (ui_tx, mesh_rx)
+}
+
+/// Answer the config request, then play the timeline while acknowledging what the client sends.
+async fn play(mut device: FakeDevice, scenario: Scenario) -> io::Result<()> {
+    device.conditions = Conditions {
+        loss: scenario.link.loss,
+        delay: Duration::from_millis(scenario.link.delay_ms),
+        duplicate: scenario.link.duplicate,
+    };
+    let config_id = loop {
+        if let to_radio::PayloadVariant::WantConfigId(id) = device.recv().await? {
+            break id;
+        }
+    };
+    let nodes = iter::once(&scenario.me)
+        .chain(&scenario.nodes)
+        .map(ScenarioNode::info)
+        .collect();
+    device.answer_config(config_id, nodes).await?;
+
+    let start = Instant::now();
+    let mut down_until = start;
+    let mut events = scenario.events.into_iter().peekable();
+    loop {
+        let next = events
+            .peek()
+            .map(|event| start + Duration::from_secs_f64(event.at.max(0.0)));
+        tokio::select! {
+            variant = device.recv() => {
+                let to_radio::PayloadVariant::Packet(packet) = variant? else {
+                    continue;
+                };
+                if !packet.want_ack {
+                    continue;
+                }
+                let reached = Instant::now() >= down_until
+                    && scenario.nodes.iter().any(|node| node.id == packet.to);
+                let error = if reached {
+                    routing::Error::None
+                } else {
+                    routing::Error::MaxRetransmit
+                };
+                device.ack(&packet, error).await?;
+            }
+            () = sleep_until(next.unwrap_or(start)), if next.is_some() => {
+                let Some(event) = events.next() else {
+                    continue;
+                };
+                match event.action {
+                    Action::Message { from, text, channel, broadcast } => {
+                        if Instant::now() < down_until {
+                            continue;
+                        }
+                        let to = if broadcast { BROADCAST } else { device.me };
+                        let mut packet =
+                            device.packet(from, to, PortNum::TextMessageApp, text.into_bytes(), 0);
+                        packet.channel = channel;
+                        device.deliver_packet(packet).await?;
+                    }
+                    Action::Drop { drop } => {
+                        log::info!("Mock device losing the mesh for {} s", drop);
+                        down_until = Instant::now() + Duration::from_secs_f64(drop.max(0.0));
+                    }
+                }
+            }
+        }
+    }
+}
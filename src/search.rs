@@ -0,0 +1,47 @@
+//! Global search (Ctrl+Shift+F): the nodes, messages and waypoints matching one query, in one
+//! list. Messages and waypoints are looked up through the store's full-text indices, so all of
+//! history is searched, not just what is loaded; nodes are matched among those known.
+
+use color_eyre::eyre::Result;
+use meshtastic::protobufs::Waypoint;
+
+use crate::store::Store;
+use crate::types::{Message, NodeNum};
+
+pub enum SearchResult {
+    Node(NodeNum),
+    Message { node: NodeNum, message: Message },
+    Waypoint { from: NodeNum, waypoint: Waypoint },
+}
+
+/// Everything matching `query`: nodes whose description (names, ID, note...) contains it,
+/// ignoring case, then matching messages and waypoints, newest first.
+pub fn search(
+    query: &str,
+    nodes: impl IntoIterator<Item = (NodeNum, String)>,
+    store: &dyn Store,
+) -> Result<Vec<SearchResult>> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Ok(Vec::new());
+    }
+    let lowercase = query.to_lowercase();
+    let mut results: Vec<SearchResult> = nodes
+        .into_iter()
+        .filter(|(_, description)| description.to_lowercase().contains(&lowercase))
+        .map(|(num, _)| SearchResult::Node(num))
+        .collect();
+    results.extend(
+        store
+            .search_messages(query)?
+            .into_iter()
+            .map(|(node, message)| SearchResult::Message { node, message }),
+    );
+    results.extend(
+        store
+            .search_waypoints(query)?
+            .into_iter()
+            .map(|(from, waypoint)| SearchResult::Waypoint { from, waypoint }),
+    );
+    Ok(results)
+}
@@ -0,0 +1,45 @@
+//! Human-readable daily logs of all text traffic, for groups that must keep records.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use chrono::Local;
+
+pub struct SessionLog {
+    dir: PathBuf,
+}
+
+impl SessionLog {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Append a message to today's log file, creating it if needed.
+    pub fn record(&self, from: &str, to: &str, channel: u32, message: &str) {
+        if let Err(e) = self.append(from, to, channel, message) {
+            log::error!("Failed to write session log: {}", e);
+        }
+    }
+
+    /// Append a remark about the session, such as a busy channel, to today's log file.
+    pub fn annotate(&self, remark: &str) {
+        if let Err(e) = self.append_line(&format!("-- {remark}")) {
+            log::error!("Failed to write session log: {}", e);
+        }
+    }
+
+    fn append(&self, from: &str, to: &str, channel: u32, message: &str) -> io::Result<()> {
+        self.append_line(&format!("[ch{channel}] {from} -> {to}: {message}"))
+    }
+
+    fn append_line(&self, line: &str) -> io::Result<()> {
+        let now = Local::now();
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("{}.log", now.format("%Y-%m-%d")));
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{} {}", now.format("%H:%M:%S"), line)
+    }
+}
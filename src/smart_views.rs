@@ -0,0 +1,271 @@
+//! Smart views: saved node filters offered above the node list, such as `battery < 30%`,
+//! `heard in last 15 min and hops == 0` or `messages containing 'net'`, re-evaluated as nodes
+//! and messages come in.
+//!
+//! A query is conditions joined by `and`, each one of:
+//! - `battery`, `hops` or `snr`, then `<`, `<=`, `>`, `>=`, `==` or `!=`, then a number;
+//! - `heard in last <n> <s|min|h|d>`;
+//! - `messages containing '<words>'`, for nodes whose conversation has a message with every word;
+//! - `favorite`.
+
+use std::collections::HashSet;
+
+use color_eyre::eyre::{Result, bail, eyre};
+use meshtastic::protobufs::NodeInfo;
+
+use crate::config::SmartViewConfig;
+use crate::store::Store;
+use crate::types::NodeNum;
+
+pub struct SmartView {
+    pub name: String,
+    conditions: Vec<Condition>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Condition {
+    Compare {
+        field: Field,
+        op: Op,
+        value: f64,
+    },
+    HeardWithin(i64),
+    /// The nodes matching are looked up in the store by `SmartView::refresh`.
+    MessagesContaining {
+        words: String,
+        nodes: HashSet<NodeNum>,
+    },
+    Favorite,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Field {
+    Battery,
+    Hops,
+    Snr,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Op {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Op {
+    fn holds(self, left: f64, right: f64) -> bool {
+        match self {
+            Op::Less => left < right,
+            Op::LessOrEqual => left <= right,
+            Op::Greater => left > right,
+            Op::GreaterOrEqual => left >= right,
+            Op::Equal => left == right,
+            Op::NotEqual => left != right,
+        }
+    }
+}
+
+impl SmartView {
+    pub fn parse(config: &SmartViewConfig) -> Result<Self> {
+        let tokens = tokenize(&config.query)?;
+        let conditions = tokens
+            .split(|token| token.eq_ignore_ascii_case("and"))
+            .map(condition)
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| eyre!("smart view {:?}: {}", config.name, e))?;
+        Ok(Self {
+            name: config.name.clone(),
+            conditions,
+        })
+    }
+
+    /// Look up again which nodes have messages matching the view, as new messages may match.
+    pub fn refresh(&mut self, store: &dyn Store) -> Result<()> {
+        for condition in &mut self.conditions {
+            if let Condition::MessagesContaining { words, nodes } = condition {
+                *nodes = store.nodes_with_messages(words)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the view refers to messages, and so needs refreshing as they arrive.
+    pub fn uses_messages(&self) -> bool {
+        self.conditions
+            .iter()
+            .any(|condition| matches!(condition, Condition::MessagesContaining { .. }))
+    }
+
+    /// Whether `node` meets every condition, `now` being seconds since the epoch.
+    pub fn matches(&self, node: &NodeInfo, now: i64) -> bool {
+        self.conditions.iter().all(|condition| match condition {
+            Condition::Compare { field, op, value } => {
+                let actual = match field {
+                    Field::Battery => node
+                        .device_metrics
+                        .and_then(|metrics| metrics.battery_level)
+                        .map(f64::from),
+                    Field::Hops => node.hops_away.map(f64::from),
+                    // An SNR of exactly 0 is what nodes never heard directly have.
+                    Field::Snr => (node.snr != 0.0).then_some(f64::from(node.snr)),
+                };
+                actual.is_some_and(|actual| op.holds(actual, *value))
+            }
+            // A last heard time of 0 is unknown.
+            Condition::HeardWithin(seconds) => {
+                node.last_heard > 0 && now - i64::from(node.last_heard) <= *seconds
+            }
+            Condition::MessagesContaining { nodes, .. } => nodes.contains(&node.num),
+            Condition::Favorite => node.is_favorite,
+        })
+    }
+}
+
+/// Words, quoted strings (without their quotes) and comparison operators.
+fn tokenize(query: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            let quoted: String = chars.by_ref().take_while(|&next| next != c).collect();
+            tokens.push(quoted);
+        } else if "<>=!".contains(c) {
+            let mut op = String::new();
+            while let Some(&next) = chars.peek().filter(|next| "<>=!".contains(**next)) {
+                op.push(next);
+                chars.next();
+            }
+            tokens.push(op);
+        } else {
+            let mut word = String::new();
+            while let Some(&next) = chars
+                .peek()
+                .filter(|next| !next.is_whitespace() && !"<>=!'\"".contains(**next))
+            {
+                word.push(next);
+                chars.next();
+            }
+            tokens.push(word);
+        }
+    }
+    if tokens.is_empty() {
+        bail!("the query is empty");
+    }
+    Ok(tokens)
+}
+
+fn condition(tokens: &[String]) -> Result<Condition> {
+    let words: Vec<String> = tokens.iter().map(|token| token.to_lowercase()).collect();
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    match words[..] {
+        ["favorite"] => Ok(Condition::Favorite),
+        ["heard", "in", "last", ref duration @ ..] | ["heard", "within", ref duration @ ..] => {
+            Ok(Condition::HeardWithin(seconds(duration)?))
+        }
+        ["messages", "containing" | "contain", _] => Ok(Condition::MessagesContaining {
+            words: tokens[2].clone(),
+            nodes: HashSet::new(),
+        }),
+        [field, op, value] => {
+            let field = match field {
+                "battery" => Field::Battery,
+                "hops" => Field::Hops,
+                "snr" => Field::Snr,
+                other => bail!("unknown field {:?}", other),
+            };
+            let op = match op {
+                "<" => Op::Less,
+                "<=" => Op::LessOrEqual,
+                ">" => Op::Greater,
+                ">=" => Op::GreaterOrEqual,
+                "==" | "=" => Op::Equal,
+                "!=" => Op::NotEqual,
+                other => bail!("unknown comparison {:?}", other),
+            };
+            let value = value
+                .trim_end_matches('%')
+                .parse()
+                .map_err(|_| eyre!("{:?} is not a number", value))?;
+            Ok(Condition::Compare { field, op, value })
+        }
+        _ => bail!("cannot understand {:?}", tokens.join(" ")),
+    }
+}
+
+/// A duration such as `15 min`, `15min` or `2 h`, in seconds.
+pub(crate) fn seconds(words: &[&str]) -> Result<i64> {
+    let joined = words.concat();
+    let split = joined
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(joined.len());
+    let (count, unit) = joined.split_at(split);
+    let count: i64 = count
+        .parse()
+        .map_err(|_| eyre!("{:?} is not a duration", words.join(" ")))?;
+    let unit = match unit {
+        "s" | "sec" | "secs" | "seconds" => 1,
+        "m" | "min" | "mins" | "minutes" => 60,
+        "h" | "hour" | "hours" => 60 * 60,
+        "d" | "day" | "days" => 24 * 60 * 60,
+        other => bail!("unknown unit {:?}", other),
+    };
+    Ok(count * unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs::DeviceMetrics;
+
+    use super::*;
+
+    fn view(query: &str) -> Result<SmartView> {
+        SmartView::parse(&SmartViewConfig {
+            name: "test".to_string(),
+            query: query.to_string(),
+        })
+    }
+
+    #[test]
+    fn queries_select_nodes() {
+        let now = 1_000_000;
+        let node = NodeInfo {
+            num: 1,
+            last_heard: (now - 10 * 60) as u32,
+            hops_away: Some(0),
+            device_metrics: Some(DeviceMetrics {
+                battery_level: Some(25),
+                ..DeviceMetrics::default()
+            }),
+            ..NodeInfo::default()
+        };
+        assert!(view("battery < 30%").unwrap().matches(&node, now));
+        assert!(
+            view("heard in last 15 min and hops == 0")
+                .unwrap()
+                .matches(&node, now)
+        );
+        assert!(!view("heard within 5m").unwrap().matches(&node, now));
+        assert!(!view("snr > -5").unwrap().matches(&node, now));
+        assert!(!view("favorite").unwrap().matches(&node, now));
+
+        let mut messages = view("messages containing 'net and more'").unwrap();
+        assert_eq!(messages.conditions.len(), 1);
+        assert!(messages.uses_messages());
+        assert!(!messages.matches(&node, now));
+        messages.conditions = vec![Condition::MessagesContaining {
+            words: "net".to_string(),
+            nodes: HashSet::from([1]),
+        }];
+        assert!(messages.matches(&node, now));
+
+        assert!(view("battery about 30").is_err());
+        assert!(view("heard in last soon").is_err());
+        assert!(view("").is_err());
+    }
+}
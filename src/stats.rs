@@ -0,0 +1,155 @@
+//! Activity statistics computed from the message history in the store.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local};
+
+use crate::types::{BROADCAST, NodeNum};
+
+/// Replies slower than this are treated as new conversations rather than responses.
+pub const RESPONSE_WINDOW_SECONDS: i64 = 60 * 60;
+
+/// How active a contact is, over the whole stored conversation.
+#[derive(Debug, Default, Clone)]
+pub struct ConversationStats {
+    pub received: usize,
+    pub sent: usize,
+    pub first_contact: Option<DateTime<Local>>,
+    pub last_contact: Option<DateTime<Local>>,
+    /// Mean SNR of received messages in dB.
+    pub average_snr: Option<f64>,
+    /// Mean time the contact took to reply to one of our messages, in seconds.
+    pub response_latency: Option<f64>,
+}
+
+/// Time spans the leaderboard can rank activity over.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum Window {
+    Hour,
+    #[default]
+    Day,
+    Week,
+}
+
+impl Window {
+    pub fn seconds(self) -> i64 {
+        match self {
+            Window::Hour => 60 * 60,
+            Window::Day => 24 * 60 * 60,
+            Window::Week => 7 * 24 * 60 * 60,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Window::Hour => "1h",
+            Window::Day => "24h",
+            Window::Week => "7d",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Window::Hour => Window::Day,
+            Window::Day => Window::Week,
+            Window::Week => Window::Hour,
+        }
+    }
+
+    pub fn previous(self) -> Self {
+        match self {
+            Window::Hour => Window::Week,
+            Window::Day => Window::Hour,
+            Window::Week => Window::Day,
+        }
+    }
+}
+
+/// What the leaderboard is ranked by.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum Ranking {
+    #[default]
+    Packets,
+    Messages,
+    Airtime,
+}
+
+impl Ranking {
+    pub const ALL: [Ranking; 3] = [Ranking::Packets, Ranking::Messages, Ranking::Airtime];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Ranking::Packets => "PACKETS",
+            Ranking::Messages => "MESSAGES",
+            Ranking::Airtime => "AIRTIME",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Ranking::Packets => Ranking::Messages,
+            Ranking::Messages => Ranking::Airtime,
+            Ranking::Airtime => Ranking::Packets,
+        }
+    }
+}
+
+/// What one node put on the air over a window.
+#[derive(Debug, Clone)]
+pub struct NodeActivity {
+    pub node: NodeNum,
+    pub packets: usize,
+    /// Text messages, to us or broadcast.
+    pub messages: usize,
+    pub airtime_ms: f64,
+}
+
+/// Packets one node sent to another, or broadcast, over a window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flow {
+    pub from: NodeNum,
+    /// Destination, or `BROADCAST`.
+    pub to: NodeNum,
+    pub packets: usize,
+    /// Text messages among the packets.
+    pub messages: usize,
+}
+
+/// The busiest nodes by packets sent and received, busiest first, at most `limit` of them. The
+/// broadcast address is not a node and is left out.
+pub fn busiest_nodes(flows: &[Flow], limit: usize) -> Vec<NodeNum> {
+    let mut totals: HashMap<NodeNum, usize> = HashMap::new();
+    for flow in flows {
+        *totals.entry(flow.from).or_default() += flow.packets;
+        if flow.to != BROADCAST {
+            *totals.entry(flow.to).or_default() += flow.packets;
+        }
+    }
+    let mut nodes: Vec<(NodeNum, usize)> = totals.into_iter().collect();
+    nodes.sort_by_key(|&(node, total)| (Reverse(total), node));
+    nodes
+        .into_iter()
+        .take(limit)
+        .map(|(node, _)| node)
+        .collect()
+}
+
+/// Sort nodes most active first by the given measure.
+pub fn rank(activity: &mut [NodeActivity], ranking: Ranking) {
+    match ranking {
+        Ranking::Packets => activity.sort_by_key(|a| Reverse(a.packets)),
+        Ranking::Messages => activity.sort_by_key(|a| Reverse(a.messages)),
+        Ranking::Airtime => activity.sort_by(|a, b| b.airtime_ms.total_cmp(&a.airtime_ms)),
+    }
+}
+
+/// A duration as a short human-readable string, e.g. `45s`, `3m` or `2h10m`.
+pub fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as i64;
+    match seconds {
+        s if s < 60 => format!("{}s", s),
+        s if s < 60 * 60 => format!("{}m", s / 60),
+        s => format!("{}h{:02}m", s / 3600, s % 3600 / 60),
+    }
+}
@@ -0,0 +1,1553 @@
+//! Persistent storage of mesh state, so history survives restarts.
+//!
+//! The schema is versioned: `MIGRATIONS` is applied in order and each applied migration is
+//! recorded in the `schema_version` table. Before upgrading an existing database a copy is taken
+//! next to it, so a failed or unwanted upgrade never loses history.
+//!
+//! The database is in WAL mode, so one process can keep writing (a daemon, say) while others read
+//! it live: `sqlite3`, scripts, or another edda opened with `SqliteStore::open_read_only`. Readers
+//! see the last committed write and never block the writer.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use chrono::{DateTime, Local};
+use color_eyre::eyre::{Result, bail};
+use meshtastic::Message as _;
+use meshtastic::protobufs::{NodeInfo, PortNum, Position, User, Waypoint};
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Row, params};
+
+use crate::admin_keys::AdminKeypair;
+use crate::battery;
+use crate::config::Retention;
+use crate::notify::{NotifyMode, NotifyOverrides};
+use crate::outbox::{OutboxEntry, OutboxState};
+use crate::profiles::Profile;
+use crate::stats::{ConversationStats, Flow, NodeActivity, RESPONSE_WINDOW_SECONDS};
+use crate::types::{Contact, ContactId, Focus, Message, NodeNum, SessionState, ViewState};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Ordered schema migrations. Never edit or reorder an entry once released; append a new one.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE nodes (
+        num INTEGER PRIMARY KEY,
+        last_heard INTEGER NOT NULL,
+        info BLOB NOT NULL
+    );",
+    "CREATE TABLE positions (
+        node INTEGER NOT NULL,
+        time INTEGER NOT NULL,
+        latitude_i INTEGER NOT NULL,
+        longitude_i INTEGER NOT NULL,
+        altitude INTEGER
+    );
+    CREATE INDEX positions_time ON positions (time);",
+    "CREATE TABLE messages (
+        id INTEGER PRIMARY KEY,
+        node INTEGER NOT NULL,
+        outgoing INTEGER NOT NULL,
+        time INTEGER NOT NULL,
+        channel INTEGER NOT NULL,
+        text TEXT NOT NULL,
+        UNIQUE (node, outgoing, time, text)
+    );",
+    "CREATE TABLE node_notes (
+        num INTEGER PRIMARY KEY,
+        note TEXT NOT NULL
+    );",
+    "CREATE TABLE read_state (
+        node INTEGER PRIMARY KEY,
+        last_read INTEGER NOT NULL
+    );
+    CREATE TABLE drafts (
+        node INTEGER PRIMARY KEY,
+        text TEXT NOT NULL
+    );",
+    "CREATE TABLE profiles (
+        name TEXT PRIMARY KEY,
+        long_name TEXT NOT NULL,
+        short_name TEXT NOT NULL,
+        is_licensed INTEGER NOT NULL,
+        role INTEGER NOT NULL
+    );",
+    "CREATE TABLE psk_rotations (
+        channel INTEGER PRIMARY KEY,
+        rotated INTEGER NOT NULL
+    );",
+    "CREATE TABLE admin_keypair (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        public BLOB NOT NULL,
+        private BLOB NOT NULL
+    );",
+    "CREATE TABLE notify_overrides (
+        kind TEXT NOT NULL CHECK (kind IN ('node', 'channel')),
+        id INTEGER NOT NULL,
+        mode TEXT NOT NULL,
+        PRIMARY KEY (kind, id)
+    );",
+    "ALTER TABLE messages ADD COLUMN snr REAL;",
+    "CREATE TABLE packets (
+        node INTEGER NOT NULL,
+        time INTEGER NOT NULL,
+        port INTEGER NOT NULL,
+        airtime_ms REAL NOT NULL
+    );
+    CREATE INDEX packets_time ON packets (time);",
+    // Covers the conversation statistics, so they never touch the message text.
+    "CREATE INDEX messages_node_time ON messages (node, time, id, outgoing, snr);",
+    "CREATE TABLE session_state (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        contact INTEGER,
+        scroll INTEGER NOT NULL,
+        focus TEXT
+    );",
+    "ALTER TABLE messages ADD COLUMN packet_id INTEGER;",
+    "CREATE TABLE outbox (
+        id INTEGER PRIMARY KEY,
+        node INTEGER NOT NULL,
+        text TEXT NOT NULL,
+        reply_id INTEGER,
+        emoji INTEGER NOT NULL,
+        state TEXT NOT NULL,
+        queued INTEGER NOT NULL,
+        attempts INTEGER NOT NULL
+    );",
+    "ALTER TABLE messages ADD COLUMN estimated_time INTEGER NOT NULL DEFAULT 0;",
+    "CREATE TABLE archive_state (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        last INTEGER NOT NULL
+    );",
+    "ALTER TABLE packets ADD COLUMN destination INTEGER;",
+    "CREATE TABLE contacts (
+        contact TEXT NOT NULL,
+        node INTEGER NOT NULL,
+        last_seen INTEGER NOT NULL,
+        PRIMARY KEY (contact, node)
+    );",
+    // Full-text indices for the global search, kept up to date by triggers.
+    "CREATE VIRTUAL TABLE messages_text USING fts5 (text, content = 'messages', content_rowid = 'id');
+    INSERT INTO messages_text (messages_text) VALUES ('rebuild');
+    CREATE TRIGGER messages_text_insert AFTER INSERT ON messages BEGIN
+        INSERT INTO messages_text (rowid, text) VALUES (new.id, new.text);
+    END;
+    CREATE TRIGGER messages_text_delete AFTER DELETE ON messages BEGIN
+        INSERT INTO messages_text (messages_text, rowid, text) VALUES ('delete', old.id, old.text);
+    END;
+    CREATE TABLE waypoints (
+        id INTEGER PRIMARY KEY,
+        node INTEGER NOT NULL,
+        time INTEGER NOT NULL,
+        expire INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        description TEXT NOT NULL,
+        info BLOB NOT NULL
+    );
+    CREATE VIRTUAL TABLE waypoints_text USING fts5 (
+        name, description, content = 'waypoints', content_rowid = 'id'
+    );
+    CREATE TRIGGER waypoints_text_insert AFTER INSERT ON waypoints BEGIN
+        INSERT INTO waypoints_text (rowid, name, description)
+        VALUES (new.id, new.name, new.description);
+    END;
+    CREATE TRIGGER waypoints_text_update AFTER UPDATE ON waypoints BEGIN
+        INSERT INTO waypoints_text (waypoints_text, rowid, name, description)
+        VALUES ('delete', old.id, old.name, old.description);
+        INSERT INTO waypoints_text (rowid, name, description)
+        VALUES (new.id, new.name, new.description);
+    END;",
+    "CREATE TABLE view_state (
+        view TEXT PRIMARY KEY,
+        selected INTEGER,
+        scroll INTEGER NOT NULL,
+        filter TEXT NOT NULL
+    );",
+    "ALTER TABLE messages ADD COLUMN note TEXT;",
+    "ALTER TABLE messages ADD COLUMN reply_id INTEGER;",
+    "CREATE TABLE battery (
+        node INTEGER NOT NULL,
+        time INTEGER NOT NULL,
+        level INTEGER NOT NULL
+    );
+    CREATE INDEX battery_node_time ON battery (node, time);",
+];
+
+/// Results of each kind the global search returns at most.
+const SEARCH_LIMIT: usize = 50;
+
+pub trait Store {
+    fn upsert_node(&mut self, node: &NodeInfo) -> Result<()>;
+    fn get_nodes(&self) -> Result<Vec<NodeInfo>>;
+    fn record_position(&mut self, node: NodeNum, position: &Position) -> Result<()>;
+    /// Positions recorded from `from` to `to` (seconds since the epoch) in chronological order,
+    /// with the node and time of each.
+    fn get_positions(&self, from: i64, to: i64) -> Result<Vec<(NodeNum, i64, Position)>>;
+    /// Store a battery level a node reported. Only the levels of the last
+    /// `battery::TREND_WINDOW` are kept.
+    fn record_battery(&mut self, node: NodeNum, level: u32) -> Result<()>;
+    /// Battery levels recorded since `since` in chronological order, with the node and time of
+    /// each.
+    fn get_battery(&self, since: i64) -> Result<Vec<(NodeNum, i64, u32)>>;
+    /// Store a message, returning `false` if an identical one was already stored.
+    fn append_message(&mut self, node: NodeNum, message: &Message) -> Result<bool>;
+    /// Delete a stored message, matched by conversation, direction, time and text.
+    fn delete_message(&mut self, node: NodeNum, message: &Message) -> Result<()>;
+    /// Replace the local note on a stored message, matched as for deleting. An empty note
+    /// deletes it.
+    fn set_message_note(&mut self, node: NodeNum, message: &Message, note: &str) -> Result<()>;
+    /// Record the packet ID a stored outgoing message went out with, matched as for deleting.
+    fn set_message_packet_id(
+        &mut self,
+        node: NodeNum,
+        message: &Message,
+        packet_id: u32,
+    ) -> Result<()>;
+    /// All stored messages in chronological order, with the node each conversation is with.
+    fn get_messages(&self) -> Result<Vec<(NodeNum, Message)>>;
+    /// The first `limit` messages stored with a time after `since` (seconds since the epoch),
+    /// only those with `node` if given, in chronological order.
+    fn get_messages_page(
+        &self,
+        node: Option<NodeNum>,
+        since: i64,
+        limit: usize,
+    ) -> Result<Vec<(NodeNum, Message)>>;
+    /// The messages exchanged with a node at times in `range` (seconds since the epoch), oldest
+    /// first.
+    fn get_conversation(&self, node: NodeNum, range: Range<i64>) -> Result<Vec<Message>>;
+    /// Message counts, contact times, signal and reply latency for the conversation with a node.
+    fn get_conversation_stats(&self, node: NodeNum) -> Result<ConversationStats>;
+    /// Note a packet heard from a node to `to`, for activity statistics.
+    fn record_packet(
+        &mut self,
+        node: NodeNum,
+        to: NodeNum,
+        port: i32,
+        airtime_ms: f64,
+    ) -> Result<()>;
+    /// Per node activity in the packets heard since `since` (seconds since the epoch).
+    fn get_activity(&self, since: i64) -> Result<Vec<NodeActivity>>;
+    /// Packets heard since `since` per sender and destination, leaving out packets stored
+    /// before destinations were.
+    fn get_flows(&self, since: i64) -> Result<Vec<Flow>>;
+    /// Locally kept notes about nodes, e.g. who operates them.
+    fn get_notes(&self) -> Result<HashMap<NodeNum, String>>;
+    /// Replace the note for a node. An empty note deletes it.
+    fn set_note(&mut self, node: NodeNum, note: &str) -> Result<()>;
+    /// For each conversation, the time of the newest message that has been read.
+    fn get_read_state(&self) -> Result<HashMap<NodeNum, i64>>;
+    /// Mark a conversation read up to `time`. Read state never moves backwards, so instances
+    /// sharing a store can all mark freely.
+    fn mark_read(&mut self, node: NodeNum, time: i64) -> Result<()>;
+    fn get_draft(&self, node: NodeNum) -> Result<Option<String>>;
+    /// Replace the unsent draft for a conversation. An empty draft deletes it.
+    fn set_draft(&mut self, node: NodeNum, text: &str) -> Result<()>;
+    fn get_profiles(&self) -> Result<Vec<Profile>>;
+    /// Save a profile, replacing any existing one with the same name.
+    fn save_profile(&mut self, profile: &Profile) -> Result<()>;
+    fn delete_profile(&mut self, name: &str) -> Result<()>;
+    /// For each channel index, when its PSK was last changed (or first seen).
+    fn get_psk_rotations(&self) -> Result<HashMap<i32, i64>>;
+    fn record_psk_rotation(&mut self, channel: i32, time: i64) -> Result<()>;
+    /// This machine's keypair for remote administration, if one has been generated.
+    fn get_admin_keypair(&self) -> Result<Option<AdminKeypair>>;
+    fn save_admin_keypair(&mut self, keypair: &AdminKeypair) -> Result<()>;
+    fn get_notify_overrides(&self) -> Result<NotifyOverrides>;
+    /// Set how a node notifies. `NotifyMode::Default` removes the override.
+    fn set_node_notify(&mut self, node: NodeNum, mode: NotifyMode) -> Result<()>;
+    /// Set how a channel notifies. `NotifyMode::Default` removes the override.
+    fn set_channel_notify(&mut self, channel: u32, mode: NotifyMode) -> Result<()>;
+    /// Outgoing messages not confirmed yet, oldest first.
+    fn get_outbox(&self) -> Result<Vec<OutboxEntry>>;
+    /// Add an outbox entry, or update it if it already exists.
+    fn save_outbox_entry(&mut self, entry: &OutboxEntry) -> Result<()>;
+    fn delete_outbox_entry(&mut self, id: i64) -> Result<()>;
+    /// Where the TUI was left last time, or the default if it never saved one.
+    fn get_session_state(&self) -> Result<SessionState>;
+    fn save_session_state(&mut self, state: &SessionState) -> Result<()>;
+    /// The state each pane and screen was left in, by view name.
+    fn get_view_states(&self) -> Result<HashMap<String, ViewState>>;
+    fn save_view_state(&mut self, view: &str, state: &ViewState) -> Result<()>;
+    /// Note that the person `contact` was heard under `node` at `time`.
+    fn record_contact(&mut self, contact: &ContactId, node: NodeNum, time: i64) -> Result<()>;
+    /// Note that `user` was heard under `node` at `time`, by each identity they have.
+    fn record_user(&mut self, node: NodeNum, user: &User, time: i64) -> Result<()> {
+        for contact in ContactId::of(user) {
+            self.record_contact(&contact, node, time)?;
+        }
+        Ok(())
+    }
+    /// Everyone heard with a public key or user ID, with the node numbers they used.
+    fn get_contacts(&self) -> Result<Vec<Contact>>;
+    /// Move everything stored about `old` to `new`, for a node that was reflashed and came back
+    /// with a new number. Where both have a note, draft or notification setting, `new` keeps its
+    /// own.
+    fn merge_node(&mut self, old: NodeNum, new: NodeNum) -> Result<()>;
+    /// Remember a waypoint a node shared, replacing an earlier version with the same ID.
+    fn save_waypoint(&mut self, from: NodeNum, waypoint: &Waypoint) -> Result<()>;
+    /// Messages containing every word of `query`, or words starting with them, newest first.
+    fn search_messages(&self, query: &str) -> Result<Vec<(NodeNum, Message)>>;
+    /// Unexpired waypoints whose name or description contains every word of `query`, or words
+    /// starting with them, newest first, with the node that shared each.
+    fn search_waypoints(&self, query: &str) -> Result<Vec<(NodeNum, Waypoint)>>;
+    /// Nodes whose conversation has a message matching `query` as `search_messages` does.
+    fn nodes_with_messages(&self, query: &str) -> Result<HashSet<NodeNum>>;
+    /// Delete data older than the retention policy allows, returning the number of rows removed.
+    fn prune(&mut self, retention: &Retention) -> Result<usize>;
+    /// Wait until every write made so far has reached the database.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+    /// Group the writes until `end_batch` into one transaction.
+    fn begin_batch(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn end_batch(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut conn = Connection::open(path)?;
+        // The retention task writes through its own connection, so wait out its locks.
+        conn.busy_timeout(Duration::from_secs(5))?;
+        // Every message and packet is a write; without WAL each one waits for a full fsync.
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        migrate(&mut conn, Some(path))?;
+        Ok(Self { conn })
+    }
+
+    /// Open a database another process may be writing, for reading only. Nothing is migrated or
+    /// pruned, so the schema must be the one this edda writes; any write fails.
+    pub fn open_read_only(path: &Path) -> Result<Self> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        // A checkpoint by the writer briefly locks out readers.
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.pragma_update(None, "query_only", true)?;
+        let journal_mode: String =
+            conn.pragma_query_value(None, "journal_mode", |row| row.get(0))?;
+        if !journal_mode.eq_ignore_ascii_case("wal") {
+            log::warn!(
+                "{} is in {} mode; reads may wait for its writer",
+                path.display(),
+                journal_mode
+            );
+        }
+        // Without the table, nothing has created the schema yet.
+        let version: i64 = conn
+            .query_row(
+                "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0);
+        if version as usize != MIGRATIONS.len() {
+            bail!(
+                "Database schema version {} is not the one this edda reads ({}); open it once \
+                 with a matching edda to migrate it",
+                version,
+                MIGRATIONS.len()
+            );
+        }
+        Ok(Self { conn })
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        let mut conn = Connection::open_in_memory()?;
+        migrate(&mut conn, None)?;
+        Ok(Self { conn })
+    }
+
+    /// Apply the retention policy now, compacting the database if anything was removed.
+    pub fn enforce_retention(&mut self, retention: &Retention) {
+        if retention.is_unlimited() {
+            return;
+        }
+        match self.prune(retention) {
+            Ok(0) => {}
+            Ok(removed) => {
+                log::info!("Retention removed {} rows", removed);
+                if let Err(e) = self.conn.execute_batch("VACUUM") {
+                    log::warn!("Failed to vacuum store: {}", e);
+                }
+            }
+            Err(e) => log::error!("Failed to enforce retention: {}", e),
+        }
+    }
+
+    /// Messages stored with a time after `since`, in chronological order.
+    pub fn get_messages_since(&self, since: i64) -> Result<Vec<(NodeNum, Message)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT node, outgoing, time, channel, text, snr, packet_id, estimated_time, note,
+                    reply_id
+             FROM messages WHERE time > ?1 ORDER BY time, id",
+        )?;
+        let messages = stmt
+            .query_map([since], message_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(messages)
+    }
+
+    /// When the last scheduled export covered data up to, if one ever ran.
+    pub fn get_last_archive(&self) -> Result<Option<i64>> {
+        Ok(self
+            .conn
+            .query_row("SELECT last FROM archive_state WHERE id = 0", [], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    pub fn record_archive(&mut self, time: i64) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO archive_state (id, last) VALUES (0, ?1)
+             ON CONFLICT(id) DO UPDATE SET last = excluded.last",
+            [time],
+        )?;
+        Ok(())
+    }
+}
+
+/// Periodically enforce the retention policy on a background thread with its own connection.
+/// The first run is an interval after startup, which is expected to have enforced it already.
+pub fn spawn_retention_task(path: PathBuf, retention: Retention) {
+    if retention.is_unlimited() {
+        return;
+    }
+    thread::spawn(move || {
+        let mut store = match SqliteStore::open(&path) {
+            Ok(store) => store,
+            Err(e) => {
+                log::error!("Retention task failed to open store: {}", e);
+                return;
+            }
+        };
+        loop {
+            thread::sleep(RETENTION_INTERVAL);
+            store.enforce_retention(&retention);
+        }
+    });
+}
+
+fn schema_version(conn: &Connection) -> Result<usize> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)",
+        [],
+    )?;
+    let version: i64 = conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(version as usize)
+}
+
+/// Bring the schema up to date, backing up the database file first if it has any history.
+fn migrate(conn: &mut Connection, path: Option<&Path>) -> Result<()> {
+    let current = schema_version(conn)?;
+    if current > MIGRATIONS.len() {
+        bail!(
+            "Database schema version {} is newer than this edda supports ({})",
+            current,
+            MIGRATIONS.len()
+        );
+    }
+    if current == MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    if current > 0
+        && let Some(path) = path
+    {
+        let mut backup = PathBuf::from(path).into_os_string();
+        backup.push(format!(".v{current}.bak"));
+        let backup = PathBuf::from(backup);
+        log::info!("Backing up database to {}", backup.display());
+        conn.execute("VACUUM INTO ?1", [backup.to_string_lossy()])?;
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        let version = index + 1;
+        log::info!("Migrating database to schema version {}", version);
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.execute(
+            "INSERT INTO schema_version (version) VALUES (?1)",
+            [version as i64],
+        )?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// A message selected as
+/// `node, outgoing, time, channel, text, snr, packet_id, estimated_time, note, reply_id`.
+fn message_row(row: &Row) -> rusqlite::Result<(NodeNum, Message)> {
+    let ts = DateTime::from_timestamp(row.get(2)?, 0)
+        .unwrap_or_default()
+        .with_timezone(&Local);
+    Ok((
+        row.get(0)?,
+        Message {
+            outgoing: row.get(1)?,
+            ts,
+            channel: row.get(3)?,
+            text: row.get(4)?,
+            snr: row.get(5)?,
+            packet_id: row.get(6)?,
+            estimated_time: row.get(7)?,
+            note: row.get(8)?,
+            reply_id: row.get(9)?,
+        },
+    ))
+}
+
+/// A full-text query matching every word of `query` as a word or the start of one, quoted so
+/// nothing typed is taken for query syntax. `None` if there are no words.
+fn full_text_query(query: &str) -> Option<String> {
+    let words: Vec<String> = query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+    (!words.is_empty()).then(|| words.join(" "))
+}
+
+fn set_notify(conn: &Connection, kind: &str, id: u32, mode: NotifyMode) -> Result<()> {
+    if mode == NotifyMode::Default {
+        conn.execute(
+            "DELETE FROM notify_overrides WHERE kind = ?1 AND id = ?2",
+            params![kind, id],
+        )?;
+    } else {
+        conn.execute(
+            "INSERT INTO notify_overrides (kind, id, mode) VALUES (?1, ?2, ?3)
+             ON CONFLICT(kind, id) DO UPDATE SET mode = excluded.mode",
+            params![kind, id, mode.label()],
+        )?;
+    }
+    Ok(())
+}
+
+impl Store for SqliteStore {
+    fn upsert_node(&mut self, node: &NodeInfo) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO nodes (num, last_heard, info) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(num) DO UPDATE SET last_heard = excluded.last_heard, info = excluded.info",
+            )?
+            .execute(params![node.num, node.last_heard, node.encode_to_vec()])?;
+        Ok(())
+    }
+
+    fn record_position(&mut self, node: NodeNum, position: &Position) -> Result<()> {
+        let (Some(latitude), Some(longitude)) = (position.latitude_i, position.longitude_i) else {
+            return Ok(());
+        };
+        self.conn
+            .prepare_cached(
+                "INSERT INTO positions (node, time, latitude_i, longitude_i, altitude)
+                 VALUES (?1, unixepoch(), ?2, ?3, ?4)",
+            )?
+            .execute(params![node, latitude, longitude, position.altitude])?;
+        Ok(())
+    }
+
+    fn get_positions(&self, from: i64, to: i64) -> Result<Vec<(NodeNum, i64, Position)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT node, time, latitude_i, longitude_i, altitude FROM positions
+             WHERE time BETWEEN ?1 AND ?2 ORDER BY time, rowid",
+        )?;
+        let positions = stmt
+            .query_map([from, to], |row| {
+                let position = Position {
+                    latitude_i: row.get(2)?,
+                    longitude_i: row.get(3)?,
+                    altitude: row.get(4)?,
+                    ..Position::default()
+                };
+                Ok((row.get(0)?, row.get(1)?, position))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(positions)
+    }
+
+    fn record_battery(&mut self, node: NodeNum, level: u32) -> Result<()> {
+        self.conn
+            .prepare_cached("INSERT INTO battery (node, time, level) VALUES (?1, unixepoch(), ?2)")?
+            .execute(params![node, level])?;
+        self.conn
+            .prepare_cached("DELETE FROM battery WHERE node = ?1 AND time < unixepoch() - ?2")?
+            .execute(params![node, battery::TREND_WINDOW])?;
+        Ok(())
+    }
+
+    fn get_battery(&self, since: i64) -> Result<Vec<(NodeNum, i64, u32)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT node, time, level FROM battery WHERE time >= ?1 ORDER BY time, rowid",
+        )?;
+        let levels = stmt
+            .query_map([since], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(levels)
+    }
+
+    fn append_message(&mut self, node: NodeNum, message: &Message) -> Result<bool> {
+        let inserted = self
+            .conn
+            .prepare_cached(
+                "INSERT OR IGNORE INTO messages
+                 (node, outgoing, time, channel, text, snr, packet_id, estimated_time, note,
+                  reply_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?
+            .execute(params![
+                node,
+                message.outgoing,
+                message.ts.timestamp(),
+                message.channel,
+                message.text,
+                message.snr,
+                message.packet_id,
+                message.estimated_time,
+                message.note,
+                message.reply_id
+            ])?;
+        Ok(inserted > 0)
+    }
+
+    fn delete_message(&mut self, node: NodeNum, message: &Message) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM messages WHERE node = ?1 AND outgoing = ?2 AND time = ?3 AND text = ?4",
+            params![node, message.outgoing, message.ts.timestamp(), message.text],
+        )?;
+        Ok(())
+    }
+
+    fn set_message_note(&mut self, node: NodeNum, message: &Message, note: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET note = ?5
+             WHERE node = ?1 AND outgoing = ?2 AND time = ?3 AND text = ?4",
+            params![
+                node,
+                message.outgoing,
+                message.ts.timestamp(),
+                message.text,
+                (!note.is_empty()).then_some(note)
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn set_message_packet_id(
+        &mut self,
+        node: NodeNum,
+        message: &Message,
+        packet_id: u32,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET packet_id = ?5
+             WHERE node = ?1 AND outgoing = ?2 AND time = ?3 AND text = ?4",
+            params![
+                node,
+                message.outgoing,
+                message.ts.timestamp(),
+                message.text,
+                packet_id
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_messages(&self) -> Result<Vec<(NodeNum, Message)>> {
+        self.get_messages_since(i64::MIN)
+    }
+
+    fn get_messages_page(
+        &self,
+        node: Option<NodeNum>,
+        since: i64,
+        limit: usize,
+    ) -> Result<Vec<(NodeNum, Message)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT node, outgoing, time, channel, text, snr, packet_id, estimated_time, note,
+                    reply_id
+             FROM messages WHERE (?1 IS NULL OR node = ?1) AND time > ?2
+             ORDER BY time, id LIMIT ?3",
+        )?;
+        let messages = stmt
+            .query_map(params![node, since, limit as i64], message_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(messages)
+    }
+
+    fn get_conversation(&self, node: NodeNum, range: Range<i64>) -> Result<Vec<Message>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT node, outgoing, time, channel, text, snr, packet_id, estimated_time, note,
+                    reply_id
+             FROM messages WHERE node = ?1 AND time >= ?2 AND time < ?3 ORDER BY time, id",
+        )?;
+        let messages = stmt
+            .query_map(params![node, range.start, range.end], |row| {
+                message_row(row).map(|(_, message)| message)
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(messages)
+    }
+
+    fn get_conversation_stats(&self, node: NodeNum) -> Result<ConversationStats> {
+        let time = |seconds: Option<i64>| {
+            seconds
+                .and_then(|s| DateTime::from_timestamp(s, 0))
+                .map(|t| t.with_timezone(&Local))
+        };
+        let mut stats = self
+            .conn
+            .prepare_cached(
+                "SELECT SUM(NOT outgoing), SUM(outgoing), MIN(time), MAX(time), AVG(snr)
+                 FROM messages WHERE node = ?1",
+            )?
+            .query_row([node], |row| {
+                Ok(ConversationStats {
+                    received: row.get::<_, Option<i64>>(0)?.unwrap_or(0) as usize,
+                    sent: row.get::<_, Option<i64>>(1)?.unwrap_or(0) as usize,
+                    first_contact: time(row.get(2)?),
+                    last_contact: time(row.get(3)?),
+                    average_snr: row.get(4)?,
+                    response_latency: None,
+                })
+            })?;
+        // A response is an incoming message directly following one of ours.
+        stats.response_latency = self
+            .conn
+            .prepare_cached(
+                "SELECT AVG(time - previous_time) FROM (
+                    SELECT outgoing, time,
+                        LAG(outgoing) OVER history AS previous_outgoing,
+                        LAG(time) OVER history AS previous_time
+                    FROM messages WHERE node = ?1
+                    WINDOW history AS (ORDER BY time, id)
+                 )
+                 WHERE NOT outgoing AND previous_outgoing AND time - previous_time <= ?2",
+            )?
+            .query_row(params![node, RESPONSE_WINDOW_SECONDS], |row| row.get(0))?;
+        Ok(stats)
+    }
+
+    fn record_packet(
+        &mut self,
+        node: NodeNum,
+        to: NodeNum,
+        port: i32,
+        airtime_ms: f64,
+    ) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO packets (node, destination, time, port, airtime_ms)
+                 VALUES (?1, ?2, unixepoch(), ?3, ?4)",
+            )?
+            .execute(params![node, to, port, airtime_ms])?;
+        Ok(())
+    }
+
+    fn get_activity(&self, since: i64) -> Result<Vec<NodeActivity>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT node, COUNT(*), SUM(port = ?2), SUM(airtime_ms)
+             FROM packets WHERE time >= ?1 GROUP BY node",
+        )?;
+        let activity = stmt
+            .query_map(params![since, PortNum::TextMessageApp as i32], |row| {
+                Ok(NodeActivity {
+                    node: row.get(0)?,
+                    packets: row.get::<_, i64>(1)? as usize,
+                    messages: row.get::<_, i64>(2)? as usize,
+                    airtime_ms: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(activity)
+    }
+
+    fn get_flows(&self, since: i64) -> Result<Vec<Flow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT node, destination, COUNT(*), SUM(port = ?2)
+             FROM packets WHERE time >= ?1 AND destination IS NOT NULL
+             GROUP BY node, destination",
+        )?;
+        let flows = stmt
+            .query_map(params![since, PortNum::TextMessageApp as i32], |row| {
+                Ok(Flow {
+                    from: row.get(0)?,
+                    to: row.get(1)?,
+                    packets: row.get::<_, i64>(2)? as usize,
+                    messages: row.get::<_, i64>(3)? as usize,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(flows)
+    }
+
+    fn get_notes(&self) -> Result<HashMap<NodeNum, String>> {
+        let mut stmt = self.conn.prepare("SELECT num, note FROM node_notes")?;
+        let notes = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(notes)
+    }
+
+    fn set_note(&mut self, node: NodeNum, note: &str) -> Result<()> {
+        if note.is_empty() {
+            self.conn
+                .execute("DELETE FROM node_notes WHERE num = ?1", [node])?;
+        } else {
+            self.conn.execute(
+                "INSERT INTO node_notes (num, note) VALUES (?1, ?2)
+                 ON CONFLICT(num) DO UPDATE SET note = excluded.note",
+                params![node, note],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn get_read_state(&self) -> Result<HashMap<NodeNum, i64>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT node, last_read FROM read_state")?;
+        let read_state = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(read_state)
+    }
+
+    fn mark_read(&mut self, node: NodeNum, time: i64) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO read_state (node, last_read) VALUES (?1, ?2)
+                 ON CONFLICT(node) DO UPDATE SET last_read = MAX(last_read, excluded.last_read)",
+            )?
+            .execute(params![node, time])?;
+        Ok(())
+    }
+
+    fn get_draft(&self, node: NodeNum) -> Result<Option<String>> {
+        let draft = self
+            .conn
+            .query_row("SELECT text FROM drafts WHERE node = ?1", [node], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(draft)
+    }
+
+    fn set_draft(&mut self, node: NodeNum, text: &str) -> Result<()> {
+        if text.is_empty() {
+            self.conn
+                .execute("DELETE FROM drafts WHERE node = ?1", [node])?;
+        } else {
+            self.conn.execute(
+                "INSERT INTO drafts (node, text) VALUES (?1, ?2)
+                 ON CONFLICT(node) DO UPDATE SET text = excluded.text",
+                params![node, text],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn get_profiles(&self) -> Result<Vec<Profile>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, long_name, short_name, is_licensed, role FROM profiles ORDER BY name",
+        )?;
+        let profiles = stmt
+            .query_map([], |row| {
+                Ok(Profile {
+                    name: row.get(0)?,
+                    long_name: row.get(1)?,
+                    short_name: row.get(2)?,
+                    is_licensed: row.get(3)?,
+                    role: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(profiles)
+    }
+
+    fn save_profile(&mut self, profile: &Profile) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO profiles (name, long_name, short_name, is_licensed, role)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                profile.name,
+                profile.long_name,
+                profile.short_name,
+                profile.is_licensed,
+                profile.role
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn delete_profile(&mut self, name: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM profiles WHERE name = ?1", [name])?;
+        Ok(())
+    }
+
+    fn get_psk_rotations(&self) -> Result<HashMap<i32, i64>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT channel, rotated FROM psk_rotations")?;
+        let rotations = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(rotations)
+    }
+
+    fn record_psk_rotation(&mut self, channel: i32, time: i64) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO psk_rotations (channel, rotated) VALUES (?1, ?2)",
+            params![channel, time],
+        )?;
+        Ok(())
+    }
+
+    fn get_admin_keypair(&self) -> Result<Option<AdminKeypair>> {
+        let keypair = self
+            .conn
+            .query_row(
+                "SELECT public, private FROM admin_keypair WHERE id = 0",
+                [],
+                |row| {
+                    Ok(AdminKeypair {
+                        public: row.get(0)?,
+                        private: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(keypair)
+    }
+
+    fn save_admin_keypair(&mut self, keypair: &AdminKeypair) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO admin_keypair (id, public, private) VALUES (0, ?1, ?2)",
+            params![keypair.public, keypair.private],
+        )?;
+        Ok(())
+    }
+
+    fn get_outbox(&self) -> Result<Vec<OutboxEntry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, node, text, reply_id, emoji, state, queued, attempts FROM outbox
+             ORDER BY queued, id",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                OutboxEntry {
+                    id: row.get(0)?,
+                    to: row.get(1)?,
+                    text: row.get(2)?,
+                    reply_id: row.get(3)?,
+                    emoji: row.get(4)?,
+                    state: OutboxState::Queued,
+                    queued: DateTime::from_timestamp(row.get(6)?, 0)
+                        .unwrap_or_default()
+                        .with_timezone(&Local),
+                    attempts: row.get(7)?,
+                },
+                row.get::<_, String>(5)?,
+            ))
+        })?;
+        let mut entries = Vec::new();
+        for row in rows {
+            let (mut entry, state) = row?;
+            let Some(state) = OutboxState::parse(&state) else {
+                continue;
+            };
+            entry.state = state;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    fn save_outbox_entry(&mut self, entry: &OutboxEntry) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO outbox
+             (id, node, text, reply_id, emoji, state, queued, attempts)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                entry.id,
+                entry.to,
+                entry.text,
+                entry.reply_id,
+                entry.emoji,
+                entry.state.label(),
+                entry.queued.timestamp(),
+                entry.attempts
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn delete_outbox_entry(&mut self, id: i64) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM outbox WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    fn get_session_state(&self) -> Result<SessionState> {
+        let state = self
+            .conn
+            .query_row(
+                "SELECT contact, scroll, focus FROM session_state WHERE id = 0",
+                [],
+                |row| {
+                    Ok(SessionState {
+                        contact: row.get(0)?,
+                        scroll: row.get::<_, i64>(1)? as usize,
+                        focus: row
+                            .get::<_, Option<String>>(2)?
+                            .as_deref()
+                            .and_then(Focus::from_pane_name),
+                    })
+                },
+            )
+            .optional()?;
+        Ok(state.unwrap_or_default())
+    }
+
+    fn save_session_state(&mut self, state: &SessionState) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO session_state (id, contact, scroll, focus)
+             VALUES (0, ?1, ?2, ?3)",
+            params![
+                state.contact,
+                state.scroll as i64,
+                state.focus.and_then(Focus::pane_name)
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_view_states(&self) -> Result<HashMap<String, ViewState>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT view, selected, scroll, filter FROM view_state")?;
+        let states = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    ViewState {
+                        selected: row.get::<_, Option<i64>>(1)?.map(|i| i as usize),
+                        scroll: row.get::<_, i64>(2)? as usize,
+                        filter: row.get(3)?,
+                    },
+                ))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(states)
+    }
+
+    fn save_view_state(&mut self, view: &str, state: &ViewState) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO view_state (view, selected, scroll, filter)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                view,
+                state.selected.map(|i| i as i64),
+                state.scroll as i64,
+                state.filter
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_notify_overrides(&self) -> Result<NotifyOverrides> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT kind, id, mode FROM notify_overrides")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, u32>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        let mut overrides = NotifyOverrides::default();
+        for row in rows {
+            let (kind, id, mode) = row?;
+            let Some(mode) = NotifyMode::parse(&mode) else {
+                continue;
+            };
+            match kind.as_str() {
+                "node" => overrides.nodes.insert(id, mode),
+                _ => overrides.channels.insert(id, mode),
+            };
+        }
+        Ok(overrides)
+    }
+
+    fn set_node_notify(&mut self, node: NodeNum, mode: NotifyMode) -> Result<()> {
+        set_notify(&self.conn, "node", node, mode)
+    }
+
+    fn set_channel_notify(&mut self, channel: u32, mode: NotifyMode) -> Result<()> {
+        set_notify(&self.conn, "channel", channel, mode)
+    }
+
+    fn record_contact(&mut self, contact: &ContactId, node: NodeNum, time: i64) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO contacts (contact, node, last_seen) VALUES (?1, ?2, ?3)
+             ON CONFLICT(contact, node) DO UPDATE SET last_seen = MAX(last_seen, excluded.last_seen)",
+            params![contact.to_string(), node, time],
+        )?;
+        Ok(())
+    }
+
+    fn get_contacts(&self) -> Result<Vec<Contact>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT contact, node, last_seen FROM contacts ORDER BY last_seen")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, NodeNum>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        })?;
+        let mut contacts: Vec<Contact> = Vec::new();
+        for row in rows {
+            let (id, node, time) = row?;
+            let Some(id) = ContactId::parse(&id) else {
+                log::warn!("Ignoring stored contact with unknown identity {}", id);
+                continue;
+            };
+            match contacts.iter_mut().find(|contact| contact.id == id) {
+                Some(contact) => contact.heard(node, time),
+                None => {
+                    let mut contact = Contact::new(id);
+                    contact.heard(node, time);
+                    contacts.push(contact);
+                }
+            }
+        }
+        Ok(contacts)
+    }
+
+    fn merge_node(&mut self, old: NodeNum, new: NodeNum) -> Result<()> {
+        // A savepoint, as this may run within a batch.
+        let tx = self.conn.savepoint()?;
+        for statement in [
+            // Identical messages in both conversations stay behind and are deleted below.
+            "UPDATE OR IGNORE messages SET node = ?2 WHERE node = ?1",
+            "UPDATE positions SET node = ?2 WHERE node = ?1",
+            "UPDATE battery SET node = ?2 WHERE node = ?1",
+            "UPDATE packets SET node = ?2 WHERE node = ?1",
+            "UPDATE packets SET destination = ?2 WHERE destination = ?1",
+            "UPDATE OR IGNORE node_notes SET num = ?2 WHERE num = ?1",
+            "INSERT INTO read_state (node, last_read)
+             SELECT ?2, last_read FROM read_state WHERE node = ?1
+             ON CONFLICT(node) DO UPDATE SET last_read = MAX(last_read, excluded.last_read)",
+            "UPDATE OR IGNORE drafts SET node = ?2 WHERE node = ?1",
+            "UPDATE OR IGNORE notify_overrides SET id = ?2 WHERE kind = 'node' AND id = ?1",
+            "UPDATE outbox SET node = ?2 WHERE node = ?1",
+        ] {
+            tx.execute(statement, params![old, new])?;
+        }
+        for statement in [
+            "DELETE FROM messages WHERE node = ?1",
+            "DELETE FROM node_notes WHERE num = ?1",
+            "DELETE FROM read_state WHERE node = ?1",
+            "DELETE FROM drafts WHERE node = ?1",
+            "DELETE FROM notify_overrides WHERE kind = 'node' AND id = ?1",
+            "DELETE FROM nodes WHERE num = ?1",
+        ] {
+            tx.execute(statement, [old])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn save_waypoint(&mut self, from: NodeNum, waypoint: &Waypoint) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO waypoints (id, node, time, expire, name, description, info)
+                 VALUES (?1, ?2, unixepoch(), ?3, ?4, ?5, ?6)
+                 ON CONFLICT(id) DO UPDATE SET node = excluded.node, time = excluded.time,
+                     expire = excluded.expire, name = excluded.name,
+                     description = excluded.description, info = excluded.info",
+            )?
+            .execute(params![
+                waypoint.id,
+                from,
+                waypoint.expire,
+                waypoint.name,
+                waypoint.description,
+                waypoint.encode_to_vec()
+            ])?;
+        Ok(())
+    }
+
+    fn search_messages(&self, query: &str) -> Result<Vec<(NodeNum, Message)>> {
+        let Some(query) = full_text_query(query) else {
+            return Ok(Vec::new());
+        };
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT node, outgoing, time, channel, messages.text, snr, packet_id, estimated_time,
+                    note, reply_id
+             FROM messages_text JOIN messages ON messages.id = messages_text.rowid
+             WHERE messages_text MATCH ?1 ORDER BY time DESC, messages.id DESC LIMIT ?2",
+        )?;
+        let messages = stmt
+            .query_map(params![query, SEARCH_LIMIT], message_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(messages)
+    }
+
+    fn nodes_with_messages(&self, query: &str) -> Result<HashSet<NodeNum>> {
+        let Some(query) = full_text_query(query) else {
+            return Ok(HashSet::new());
+        };
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT DISTINCT node
+             FROM messages_text JOIN messages ON messages.id = messages_text.rowid
+             WHERE messages_text MATCH ?1",
+        )?;
+        let nodes = stmt
+            .query_map([query], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(nodes)
+    }
+
+    fn search_waypoints(&self, query: &str) -> Result<Vec<(NodeNum, Waypoint)>> {
+        let Some(query) = full_text_query(query) else {
+            return Ok(Vec::new());
+        };
+        // An expiry of 0 is never; the apps delete a waypoint by expiring it.
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT node, info
+             FROM waypoints_text JOIN waypoints ON waypoints.id = waypoints_text.rowid
+             WHERE waypoints_text MATCH ?1 AND (expire = 0 OR expire > unixepoch())
+             ORDER BY time DESC LIMIT ?2",
+        )?;
+        let waypoints = stmt
+            .query_map(params![query, SEARCH_LIMIT], |row| {
+                Ok((row.get(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?
+            .filter_map(|row| match row {
+                Ok((node, info)) => match Waypoint::decode(info.as_slice()) {
+                    Ok(waypoint) => Some((node, waypoint)),
+                    Err(e) => {
+                        log::warn!("Skipping undecodable waypoint in store: {}", e);
+                        None
+                    }
+                },
+                Err(e) => {
+                    log::warn!("Failed to read waypoint from store: {}", e);
+                    None
+                }
+            })
+            .collect();
+        Ok(waypoints)
+    }
+
+    fn prune(&mut self, retention: &Retention) -> Result<usize> {
+        // The full-text index drops deleted messages through its trigger.
+        let tables = [
+            ("messages", retention.messages_days),
+            ("battery", retention.telemetry_days),
+            ("positions", retention.positions_days),
+            ("packets", retention.packets_days),
+        ];
+        let mut removed = 0;
+        for (table, days) in tables {
+            if let Some(days) = days.filter(|&days| days > 0) {
+                removed += self.conn.execute(
+                    &format!("DELETE FROM {table} WHERE time < unixepoch() - ?1"),
+                    [i64::from(days) * SECONDS_PER_DAY],
+                )?;
+            }
+        }
+        if let Some(days) = retention.nodes_days.filter(|&days| days > 0) {
+            // A last_heard of 0 is unknown, not long ago.
+            removed += self.conn.execute(
+                "DELETE FROM nodes WHERE last_heard > 0 AND last_heard < unixepoch() - ?1",
+                [i64::from(days) * SECONDS_PER_DAY],
+            )?;
+        }
+        Ok(removed)
+    }
+
+    /// Open a transaction for the writes that follow. A write that fails within it only undoes
+    /// its own statement; the rest of the batch stays and is committed by `end_batch`. (If
+    /// SQLite rolls the whole transaction back itself, as it may when the disk is full, the
+    /// writes after that point are committed one by one.)
+    fn begin_batch(&mut self) -> Result<()> {
+        self.conn.execute_batch("BEGIN")?;
+        Ok(())
+    }
+
+    /// Commit the batch. If that fails, the batch is rolled back and its writes are lost, so the
+    /// connection is never left inside a transaction for the next one.
+    fn end_batch(&mut self) -> Result<()> {
+        if let Err(e) = self.conn.execute_batch("COMMIT") {
+            if !self.conn.is_autocommit() {
+                self.conn.execute_batch("ROLLBACK")?;
+            }
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    fn get_nodes(&self) -> Result<Vec<NodeInfo>> {
+        let mut stmt = self.conn.prepare("SELECT info FROM nodes ORDER BY num")?;
+        let nodes = stmt
+            .query_map([], |row| row.get::<_, Vec<u8>>(0))?
+            .filter_map(|blob| match blob.map(|b| NodeInfo::decode(b.as_slice())) {
+                Ok(Ok(node)) => Some(node),
+                Ok(Err(e)) => {
+                    log::warn!("Skipping undecodable node in store: {}", e);
+                    None
+                }
+                Err(e) => {
+                    log::warn!("Failed to read node from store: {}", e);
+                    None
+                }
+            })
+            .collect();
+        Ok(nodes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: NodeNum = 0x1111_1111;
+    const NEW: NodeNum = 0x2222_2222;
+
+    fn message(text: &str) -> Message {
+        Message {
+            outgoing: false,
+            ts: Local::now(),
+            channel: 0,
+            text: text.to_string(),
+            snr: None,
+            packet_id: None,
+            estimated_time: false,
+            note: None,
+            reply_id: None,
+        }
+    }
+
+    fn count(store: &SqliteStore, table: &str) -> i64 {
+        store
+            .conn
+            .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn an_old_schema_is_migrated_with_its_history() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // The schema as of the first release with messages.
+        schema_version(&conn).unwrap();
+        for (index, migration) in MIGRATIONS[..3].iter().enumerate() {
+            conn.execute_batch(migration).unwrap();
+            conn.execute(
+                "INSERT INTO schema_version (version) VALUES (?1)",
+                [index as i64 + 1],
+            )
+            .unwrap();
+        }
+        conn.execute(
+            "INSERT INTO messages (node, outgoing, time, channel, text)
+             VALUES (?1, 0, unixepoch(), 0, 'from before the upgrade')",
+            [OLD],
+        )
+        .unwrap();
+
+        migrate(&mut conn, None).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), MIGRATIONS.len());
+        let store = SqliteStore { conn };
+        let messages = store.get_messages().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, OLD);
+        assert_eq!(messages[0].1.snr, None);
+        // The search index was built from the messages already there.
+        assert_eq!(store.search_messages("upgrade").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn pruning_removes_only_what_retention_expired() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let day = SECONDS_PER_DAY;
+        for age in [0, 5 * day, 40 * day] {
+            store
+                .conn
+                .execute(
+                    "INSERT INTO positions (node, time, latitude_i, longitude_i)
+                     VALUES (?1, unixepoch() - ?2, 0, 0)",
+                    params![OLD, age],
+                )
+                .unwrap();
+            store
+                .conn
+                .execute(
+                    "INSERT INTO packets (node, time, port, airtime_ms)
+                     VALUES (?1, unixepoch() - ?2, 1, 10.0)",
+                    params![OLD, age],
+                )
+                .unwrap();
+        }
+        // Heard long ago, recently, and never known.
+        for (num, last_heard) in [
+            (1, "unixepoch() - 400 * 86400"),
+            (2, "unixepoch()"),
+            (3, "0"),
+        ] {
+            store
+                .conn
+                .execute(
+                    &format!(
+                        "INSERT INTO nodes (num, last_heard, info) VALUES (?1, {last_heard}, x'')"
+                    ),
+                    [num],
+                )
+                .unwrap();
+        }
+
+        let retention = Retention {
+            messages_days: None,
+            telemetry_days: None,
+            positions_days: Some(30),
+            packets_days: Some(1),
+            nodes_days: Some(365),
+            remove_from_device: false,
+        };
+        assert_eq!(store.prune(&retention).unwrap(), 1 + 2 + 1);
+        assert_eq!(count(&store, "positions"), 2);
+        assert_eq!(count(&store, "packets"), 1);
+        assert_eq!(count(&store, "nodes"), 2);
+        // Nothing more is due.
+        assert_eq!(store.prune(&retention).unwrap(), 0);
+    }
+
+    #[test]
+    fn old_messages_and_telemetry_are_pruned_by_default() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let mut old = message("beacon from last season");
+        old.ts -= chrono::Duration::days(91);
+        store.append_message(OLD, &old).unwrap();
+        store
+            .append_message(OLD, &message("beacon from today"))
+            .unwrap();
+        store.record_battery(OLD, 80).unwrap();
+        store
+            .conn
+            .execute(
+                "INSERT INTO battery (node, time, level) VALUES (?1, unixepoch() - 31 * 86400, 90)",
+                [OLD],
+            )
+            .unwrap();
+
+        assert_eq!(store.prune(&Retention::default()).unwrap(), 2);
+        let texts = |messages: Vec<(NodeNum, Message)>| {
+            messages
+                .into_iter()
+                .map(|(_, message)| message.text)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(texts(store.get_messages().unwrap()), ["beacon from today"]);
+        assert_eq!(
+            texts(store.search_messages("beacon").unwrap()),
+            ["beacon from today"]
+        );
+        assert_eq!(count(&store, "battery"), 1);
+    }
+
+    #[test]
+    fn messages_are_paged_by_time() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        for (node, text, days) in [(OLD, "one", 3), (NEW, "two", 2), (OLD, "three", 1)] {
+            let mut message = message(text);
+            message.ts -= chrono::Duration::days(days);
+            store.append_message(node, &message).unwrap();
+        }
+        let texts = |page: Vec<(NodeNum, Message)>| {
+            page.into_iter()
+                .map(|(_, message)| message.text)
+                .collect::<Vec<_>>()
+        };
+
+        let first = store.get_messages_page(None, i64::MIN, 2).unwrap();
+        let since = first[1].1.ts.timestamp();
+        assert_eq!(texts(first), ["one", "two"]);
+        assert_eq!(
+            texts(store.get_messages_page(None, since, 2).unwrap()),
+            ["three"]
+        );
+        assert_eq!(
+            texts(store.get_messages_page(Some(OLD), i64::MIN, 10).unwrap()),
+            ["one", "three"]
+        );
+    }
+
+    #[test]
+    fn a_merge_failing_partway_is_rolled_back_within_its_batch() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        store
+            .append_message(OLD, &message("before the reflash"))
+            .unwrap();
+        store.set_note(OLD, "hilltop").unwrap();
+        store
+            .upsert_node(&NodeInfo {
+                num: OLD,
+                ..NodeInfo::default()
+            })
+            .unwrap();
+        // Removing the old node is the last step of the merge.
+        store
+            .conn
+            .execute_batch(
+                "CREATE TEMP TRIGGER fail_merge BEFORE DELETE ON nodes
+                 BEGIN SELECT RAISE(ABORT, 'disk on fire'); END;",
+            )
+            .unwrap();
+
+        store.begin_batch().unwrap();
+        store
+            .append_message(NEW, &message("after the reflash"))
+            .unwrap();
+        assert!(store.merge_node(OLD, NEW).is_err());
+        store.end_batch().unwrap();
+
+        let nodes: Vec<_> = store
+            .get_messages()
+            .unwrap()
+            .into_iter()
+            .map(|(node, _)| node)
+            .collect();
+        assert_eq!(nodes, [OLD, NEW]);
+        assert_eq!(
+            store.get_notes().unwrap().get(&OLD).map(String::as_str),
+            Some("hilltop")
+        );
+        assert_eq!(store.get_nodes().unwrap()[0].num, OLD);
+    }
+}
@@ -0,0 +1,168 @@
+//! Structured messages between edda clients: checklists and status reports with typed fields,
+//! sent as compact JSON on the private app port.
+//!
+//! Other Meshtastic apps never see the private port, so they get a plain text rendering on the
+//! text port instead. Only nodes we have heard edda traffic from on the private port get the
+//! JSON; broadcasts always go as text. Either way the message is kept in the conversation as
+//! `EDDA-JSON|<json>`, like thumbnails and ICS-213 forms are.
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::PACKET_BYTE_LIMIT;
+
+const TEXT_PREFIX: &str = "EDDA-JSON|";
+/// Marks a structured payload, so it is told apart from thumbnails and compressed text.
+const MAGIC: &[u8] = b"EJ";
+
+/// Field names are kept to a letter to leave room for content in a packet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "k", rename_all = "lowercase")]
+pub enum Structured {
+    Checklist {
+        #[serde(rename = "t")]
+        title: String,
+        /// Items in order, with whether each is done.
+        #[serde(rename = "i")]
+        items: Vec<(String, bool)>,
+    },
+    Status {
+        #[serde(rename = "t")]
+        title: String,
+        /// Named values in order, e.g. `("power", "mains")`.
+        #[serde(rename = "f")]
+        fields: Vec<(String, String)>,
+    },
+}
+
+impl Structured {
+    /// Parse what was typed to compose a message: `Title: item, x done item` for a checklist,
+    /// or `Title: name=value, name=value` for a status report.
+    pub fn parse(line: &str) -> Option<Self> {
+        let (title, rest) = line.split_once(':')?;
+        let title = title.trim().to_string();
+        let entries: Vec<&str> = rest
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .collect();
+        if title.is_empty() || entries.is_empty() {
+            return None;
+        }
+        if entries.iter().all(|entry| entry.contains('=')) {
+            let fields = entries
+                .iter()
+                .filter_map(|entry| entry.split_once('='))
+                .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                .collect();
+            return Some(Self::Status { title, fields });
+        }
+        let items = entries
+            .iter()
+            .map(|entry| match entry.strip_prefix("x ") {
+                Some(item) => (item.trim().to_string(), true),
+                None => (entry.to_string(), false),
+            })
+            .collect();
+        Some(Self::Checklist { title, items })
+    }
+
+    pub fn title(&self) -> &str {
+        match self {
+            Self::Checklist { title, .. } | Self::Status { title, .. } => title,
+        }
+    }
+
+    /// What kind of message this is, as shown above it.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Checklist { .. } => "CHECKLIST",
+            Self::Status { .. } => "STATUS",
+        }
+    }
+
+    /// One line per item or field, for the conversation.
+    pub fn lines(&self) -> Vec<String> {
+        match self {
+            Self::Checklist { items, .. } => items
+                .iter()
+                .map(|(item, done)| format!("[{}] {item}", if *done { 'x' } else { ' ' }))
+                .collect(),
+            Self::Status { fields, .. } => fields
+                .iter()
+                .map(|(name, value)| format!("{name}: {value}"))
+                .collect(),
+        }
+    }
+
+    /// The plain text sent to clients that cannot read the JSON.
+    pub fn fallback(&self) -> String {
+        format!(
+            "{} {}: {}",
+            self.label(),
+            self.title(),
+            self.lines().join("; ")
+        )
+    }
+
+    /// The payload for the private port.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = MAGIC.to_vec();
+        payload.extend(serde_json::to_vec(self).unwrap_or_default());
+        payload
+    }
+
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        serde_json::from_slice(payload.strip_prefix(MAGIC)?).ok()
+    }
+
+    /// Whether both the JSON and the text fallback fit in a packet.
+    pub fn fits(&self) -> bool {
+        self.encode().len() <= PACKET_BYTE_LIMIT && self.fallback().len() <= PACKET_BYTE_LIMIT
+    }
+
+    /// The message as kept in the conversation and the store.
+    pub fn to_text(&self) -> String {
+        format!(
+            "{TEXT_PREFIX}{}",
+            serde_json::to_string(self).unwrap_or_default()
+        )
+    }
+
+    /// Parse a message, returning `None` if it is not a structured one.
+    pub fn from_text(text: &str) -> Option<Self> {
+        serde_json::from_str(text.strip_prefix(TEXT_PREFIX)?).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_lines_become_checklists_or_status_reports() {
+        let checklist = Structured::parse("Go bag: water, x radio, first aid").unwrap();
+        assert_eq!(
+            checklist,
+            Structured::Checklist {
+                title: "Go bag".to_string(),
+                items: vec![
+                    ("water".to_string(), false),
+                    ("radio".to_string(), true),
+                    ("first aid".to_string(), false),
+                ],
+            }
+        );
+        assert_eq!(
+            checklist.fallback(),
+            "CHECKLIST Go bag: [ ] water; [x] radio; [ ] first aid"
+        );
+
+        let status = Structured::parse("Shelter 2: power=mains, beds = 14").unwrap();
+        assert_eq!(status.lines(), ["power: mains", "beds: 14"]);
+        assert_eq!(Structured::decode(&status.encode()), Some(status.clone()));
+        assert_eq!(Structured::from_text(&status.to_text()), Some(status));
+
+        assert_eq!(Structured::parse("no title here"), None);
+        assert_eq!(Structured::parse("Empty: , "), None);
+    }
+}
@@ -0,0 +1,100 @@
+//! Coverage survey mode: record where we were each time a node was heard, and how well.
+//!
+//! Samples are appended to a CSV file that can later be turned into GeoJSON for mapping.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+use meshtastic::protobufs::{MeshPacket, Position};
+use serde_json::json;
+
+use crate::export::DEGREES_PER_UNIT;
+use crate::types::format_node_num;
+
+const HEADER: &str = "time,latitude,longitude,altitude,node,snr,rssi,hops";
+
+pub struct Survey {
+    path: PathBuf,
+}
+
+impl Survey {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Record a sample for a packet heard while at `position`.
+    pub fn record(&self, position: &Position, packet: &MeshPacket) {
+        let (Some(latitude), Some(longitude)) = (position.latitude_i, position.longitude_i) else {
+            return;
+        };
+        if let Err(e) = self.append(latitude, longitude, position.altitude, packet) {
+            log::error!("Failed to write survey sample: {}", e);
+        }
+    }
+
+    fn append(
+        &self,
+        latitude: i32,
+        longitude: i32,
+        altitude: Option<i32>,
+        packet: &MeshPacket,
+    ) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", HEADER)?;
+        }
+        let hops =
+            (packet.hop_start > 0).then(|| packet.hop_start.saturating_sub(packet.hop_limit));
+        writeln!(
+            file,
+            "{},{:.7},{:.7},{},{},{},{},{}",
+            Local::now().to_rfc3339(),
+            f64::from(latitude) * DEGREES_PER_UNIT,
+            f64::from(longitude) * DEGREES_PER_UNIT,
+            altitude.map(|a| a.to_string()).unwrap_or_default(),
+            format_node_num(packet.from),
+            packet.rx_snr,
+            packet.rx_rssi,
+            hops.map(|h| h.to_string()).unwrap_or_default(),
+        )
+    }
+}
+
+/// Convert a survey CSV into a GeoJSON `FeatureCollection`, returning the number of samples.
+pub fn csv_to_geojson(csv: &Path, out: &Path) -> io::Result<usize> {
+    let contents = fs::read_to_string(csv)?;
+    let features: Vec<_> = contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(',').collect();
+            let [time, latitude, longitude, _altitude, node, snr, rssi, hops] = fields[..] else {
+                return None;
+            };
+            let latitude: f64 = latitude.parse().ok()?;
+            let longitude: f64 = longitude.parse().ok()?;
+            Some(json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [longitude, latitude] },
+                "properties": {
+                    "time": time,
+                    "node": node,
+                    "snr": snr.parse::<f32>().ok(),
+                    "rssi": rssi.parse::<i32>().ok(),
+                    "hops": hops.parse::<u32>().ok(),
+                },
+            }))
+        })
+        .collect();
+    let count = features.len();
+    let geojson = json!({ "type": "FeatureCollection", "features": features });
+    fs::write(out, serde_json::to_string_pretty(&geojson)?)?;
+    Ok(count)
+}
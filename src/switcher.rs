@@ -0,0 +1,36 @@
+//! Fuzzy matching for the quick switcher (Ctrl+K), which jumps to a conversation by typing a few
+//! letters of a node or channel name instead of scrolling through the node list.
+
+/// How well `query` matches `candidate`, or `None` unless all its characters appear in order,
+/// ignoring case. Runs of adjacent characters and matches at the start of a word score higher,
+/// and skipped characters lower, so `"base"` prefers "Base Station" to "Big Antenna Site East".
+pub fn score(query: &str, candidate: &str) -> Option<i32> {
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous: Option<usize> = None;
+    for wanted in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = position + candidate[position..].iter().position(|&c| c == wanted)?;
+        score += 1;
+        if previous.is_some_and(|p| p + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || !candidate[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        score -= (found - position).min(5) as i32;
+        previous = Some(found);
+        position = found + 1;
+    }
+    Some(score)
+}
+
+/// The candidates matching `query`, best first; ties keep their order.
+pub fn rank<T>(query: &str, candidates: Vec<(String, T)>) -> Vec<(String, T)> {
+    let mut scored: Vec<(i32, (String, T))> = candidates
+        .into_iter()
+        .filter_map(|(label, target)| Some((score(query, &label)?, (label, target))))
+        .collect();
+    scored.sort_by_key(|(score, _)| -score);
+    scored.into_iter().map(|(_, candidate)| candidate).collect()
+}
@@ -0,0 +1,147 @@
+//! Reply chains in a conversation, for the threaded display: each reply nested under the message
+//! it answers, in the order they arrived, and a collapsed message shown without its replies.
+//!
+//! A reply is threaded under the earlier message with the packet ID it replies to. Replies to
+//! messages not in the conversation, e.g. pruned or never received, start threads of their own.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::types::Message;
+
+/// A message as the threaded display shows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// Index of the message in the conversation.
+    pub index: usize,
+    /// How many replies deep it is.
+    pub depth: usize,
+    /// Replies hidden under it by collapsing it.
+    pub hidden: usize,
+}
+
+/// The messages of a conversation in thread order, leaving out the replies under the messages
+/// whose packet IDs are in `collapsed`.
+pub fn order(messages: &[Message], collapsed: &HashSet<u32>) -> Vec<Entry> {
+    let mut by_packet: HashMap<u32, usize> = HashMap::new();
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); messages.len()];
+    let mut roots = Vec::new();
+    for (index, message) in messages.iter().enumerate() {
+        // Only earlier messages can be replied to, which also rules out cycles.
+        match message.reply_id.and_then(|id| by_packet.get(&id)) {
+            Some(&parent) => children[parent].push(index),
+            None => roots.push(index),
+        }
+        if let Some(packet_id) = message.packet_id {
+            by_packet.entry(packet_id).or_insert(index);
+        }
+    }
+
+    // Replies always come after what they reply to, so sizes add up from the end.
+    let mut sizes = vec![1; messages.len()];
+    for index in (0..messages.len()).rev() {
+        sizes[index] += children[index].iter().map(|&c| sizes[c]).sum::<usize>();
+    }
+
+    let mut entries = Vec::with_capacity(messages.len());
+    let mut stack: Vec<(usize, usize)> = roots.into_iter().rev().map(|root| (root, 0)).collect();
+    while let Some((index, depth)) = stack.pop() {
+        let folded = messages[index]
+            .packet_id
+            .is_some_and(|id| collapsed.contains(&id));
+        entries.push(Entry {
+            index,
+            depth,
+            hidden: if folded { sizes[index] - 1 } else { 0 },
+        });
+        if !folded {
+            stack.extend(
+                children[index]
+                    .iter()
+                    .rev()
+                    .map(|&child| (child, depth + 1)),
+            );
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+
+    use super::*;
+
+    fn message(packet_id: u32, reply_id: Option<u32>) -> Message {
+        Message {
+            outgoing: false,
+            ts: Local::now(),
+            channel: 0,
+            text: packet_id.to_string(),
+            snr: None,
+            packet_id: Some(packet_id),
+            estimated_time: false,
+            note: None,
+            reply_id,
+        }
+    }
+
+    fn shown(entries: &[Entry]) -> Vec<(usize, usize, usize)> {
+        entries
+            .iter()
+            .map(|entry| (entry.index, entry.depth, entry.hidden))
+            .collect()
+    }
+
+    #[test]
+    fn replies_nest_under_what_they_answer() {
+        let messages = [
+            message(1, None),
+            message(2, None),
+            message(3, Some(1)),
+            message(4, Some(3)),
+            message(5, Some(2)),
+            message(6, Some(1)),
+            // Replying to something not in the conversation.
+            message(7, Some(99)),
+        ];
+        assert_eq!(
+            shown(&order(&messages, &HashSet::new())),
+            [
+                (0, 0, 0),
+                (2, 1, 0),
+                (3, 2, 0),
+                (5, 1, 0),
+                (1, 0, 0),
+                (4, 1, 0),
+                (6, 0, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn collapsed_threads_hide_and_count_their_replies() {
+        let messages = [
+            message(1, None),
+            message(2, Some(1)),
+            message(3, Some(2)),
+            message(4, None),
+        ];
+        assert_eq!(
+            shown(&order(&messages, &HashSet::from([1]))),
+            [(0, 0, 2), (3, 0, 0)]
+        );
+        assert_eq!(
+            shown(&order(&messages, &HashSet::from([2]))),
+            [(0, 0, 0), (1, 1, 1), (3, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn replies_to_later_messages_are_not_threaded() {
+        let messages = [message(1, Some(2)), message(2, Some(1))];
+        assert_eq!(
+            shown(&order(&messages, &HashSet::new())),
+            [(0, 0, 0), (1, 1, 0)]
+        );
+    }
+}
@@ -0,0 +1,499 @@
+//! Experimental: tiny dithered black and white thumbnails of images, sent to other edda users
+//! over the private app port and drawn as block art in the conversation.
+//!
+//! An image is scaled to fit 64×48 pixels, dithered to one bit per pixel and split into chunks
+//! of one packet each. The receiver puts the chunks back together and asks the sender again for
+//! any still missing after a while, so a transfer survives lost packets.
+//!
+//! Images are read as binary PGM or PPM (netpbm), which most tools write, e.g.
+//! `magick photo.jpg photo.pgm`. The finished thumbnail is kept in the conversation as a text
+//! message, `EDDA-IMG|<width>|<height>|<base64 bits>`, like ICS-213 forms are.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use color_eyre::eyre::{Result, bail, eyre};
+
+use crate::types::NodeNum;
+
+pub const MAX_WIDTH: usize = 64;
+pub const MAX_HEIGHT: usize = 48;
+const TEXT_PREFIX: &str = "EDDA-IMG";
+const MAGIC: &[u8; 2] = b"ET";
+const CHUNK: u8 = 0;
+const RESEND: u8 = 1;
+/// Image bytes per chunk, leaving room for the header in a packet.
+const CHUNK_BYTES: usize = 160;
+/// Ask for the missing chunks after this long without one arriving.
+const STALL: Duration = Duration::from_secs(20);
+/// Give up on a transfer after asking this many times.
+const MAX_RESENDS: u32 = 3;
+/// Sent thumbnails are kept this long to answer requests for missing chunks.
+const KEEP_SENT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub width: u8,
+    pub height: u8,
+    /// One bit per pixel, row by row, most significant bit first, set for dark pixels.
+    bits: Vec<u8>,
+}
+
+impl Thumbnail {
+    /// Read a PGM or PPM image and make a thumbnail of it.
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        let (width, height, gray) = parse_pnm(&data)?;
+        Ok(Self::from_gray(width, height, &gray))
+    }
+
+    /// Scale an 8-bit grayscale image down to fit the thumbnail size and dither it.
+    pub fn from_gray(width: usize, height: usize, gray: &[u8]) -> Self {
+        let scale = (width as f32 / MAX_WIDTH as f32)
+            .max(height as f32 / MAX_HEIGHT as f32)
+            .max(1.0);
+        let w = ((width as f32 / scale).round() as usize).clamp(1, MAX_WIDTH);
+        let h = ((height as f32 / scale).round() as usize).clamp(1, MAX_HEIGHT);
+
+        // Average the source pixels behind each thumbnail pixel.
+        let mut levels = vec![0.0f32; w * h];
+        for y in 0..h {
+            let (top, bottom) = (
+                y * height / h,
+                ((y + 1) * height / h).max(y * height / h + 1),
+            );
+            for x in 0..w {
+                let (left, right) = (x * width / w, ((x + 1) * width / w).max(x * width / w + 1));
+                let mut sum = 0u32;
+                for row in top..bottom {
+                    for col in left..right {
+                        sum += u32::from(gray[row * width + col]);
+                    }
+                }
+                levels[y * w + x] = sum as f32 / ((bottom - top) * (right - left)) as f32;
+            }
+        }
+
+        // Floyd-Steinberg error diffusion.
+        let mut bits = vec![0u8; (w * h).div_ceil(8)];
+        for y in 0..h {
+            for x in 0..w {
+                let i = y * w + x;
+                let dark = levels[i] < 128.0;
+                if dark {
+                    bits[i / 8] |= 0x80 >> (i % 8);
+                }
+                let error = levels[i] - if dark { 0.0 } else { 255.0 };
+                let mut spread = |dx: isize, dy: usize, weight: f32| {
+                    let nx = x as isize + dx;
+                    if nx >= 0 && (nx as usize) < w && y + dy < h {
+                        levels[(y + dy) * w + nx as usize] += error * weight / 16.0;
+                    }
+                };
+                spread(1, 0, 7.0);
+                spread(-1, 1, 3.0);
+                spread(0, 1, 5.0);
+                spread(1, 1, 1.0);
+            }
+        }
+        Self {
+            width: w as u8,
+            height: h as u8,
+            bits,
+        }
+    }
+
+    fn from_parts(width: u8, height: u8, bits: Vec<u8>) -> Option<Self> {
+        let pixels = usize::from(width) * usize::from(height);
+        (width > 0
+            && height > 0
+            && usize::from(width) <= MAX_WIDTH
+            && usize::from(height) <= MAX_HEIGHT
+            && bits.len() == pixels.div_ceil(8))
+        .then_some(Self {
+            width,
+            height,
+            bits,
+        })
+    }
+
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        if x >= usize::from(self.width) || y >= usize::from(self.height) {
+            return false;
+        }
+        let i = y * usize::from(self.width) + x;
+        self.bits[i / 8] & (0x80 >> (i % 8)) != 0
+    }
+
+    /// Block art of the thumbnail, two by two pixels per character.
+    pub fn render(&self) -> Vec<String> {
+        const QUADRANTS: [char; 16] = [
+            ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+        ];
+        (0..usize::from(self.height))
+            .step_by(2)
+            .map(|y| {
+                (0..usize::from(self.width))
+                    .step_by(2)
+                    .map(|x| {
+                        let quadrant = usize::from(self.is_dark(x, y))
+                            | usize::from(self.is_dark(x + 1, y)) << 1
+                            | usize::from(self.is_dark(x, y + 1)) << 2
+                            | usize::from(self.is_dark(x + 1, y + 1)) << 3;
+                        QUADRANTS[quadrant]
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The thumbnail as a text message, for the conversation and the store.
+    pub fn to_text(&self) -> String {
+        format!(
+            "{TEXT_PREFIX}|{}|{}|{}",
+            self.width,
+            self.height,
+            STANDARD.encode(&self.bits)
+        )
+    }
+
+    /// Parse a message, returning `None` if it is not a thumbnail.
+    pub fn from_text(text: &str) -> Option<Self> {
+        let mut fields = text.splitn(4, '|');
+        if fields.next()? != TEXT_PREFIX {
+            return None;
+        }
+        let width = fields.next()?.parse().ok()?;
+        let height = fields.next()?.parse().ok()?;
+        let bits = STANDARD.decode(fields.next()?).ok()?;
+        Self::from_parts(width, height, bits)
+    }
+
+    /// The packets carrying the thumbnail as transfer `id`.
+    fn chunks(&self, id: u32) -> Vec<Vec<u8>> {
+        let mut data = vec![self.width, self.height];
+        data.extend(&self.bits);
+        let pieces: Vec<&[u8]> = data.chunks(CHUNK_BYTES).collect();
+        pieces
+            .iter()
+            .enumerate()
+            .map(|(index, piece)| {
+                Packet::Chunk {
+                    id,
+                    index: index as u8,
+                    count: pieces.len() as u8,
+                    data: piece.to_vec(),
+                }
+                .encode()
+            })
+            .collect()
+    }
+}
+
+/// The header and pixels of a binary PGM (`P5`) or PPM (`P6`) image, as 8-bit gray levels.
+fn parse_pnm(data: &[u8]) -> Result<(usize, usize, Vec<u8>)> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    // Magic number, width, height and maximum value, separated by whitespace and comments.
+    while fields.len() < 4 {
+        while pos < data.len() && (data[pos].is_ascii_whitespace() || data[pos] == b'#') {
+            if data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+            } else {
+                pos += 1;
+            }
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            bail!("Image ends in its header");
+        }
+        fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+    }
+    // A single whitespace character separates the header from the pixels.
+    pos += 1;
+    let channels = match fields[0].as_str() {
+        "P5" => 1,
+        "P6" => 3,
+        other => bail!("Not a binary PGM or PPM image (starts with {})", other),
+    };
+    let number = |field: &str| -> Result<usize> {
+        field
+            .parse()
+            .map_err(|_| eyre!("Bad number {} in image header", field))
+    };
+    let (width, height, max) = (
+        number(&fields[1])?,
+        number(&fields[2])?,
+        number(&fields[3])?,
+    );
+    if width == 0 || height == 0 || max == 0 || max > 255 {
+        bail!(
+            "Unsupported image: {}x{} with maximum value {}",
+            width,
+            height,
+            max
+        );
+    }
+    let pixels = data
+        .get(pos..pos + width * height * channels)
+        .ok_or_else(|| eyre!("Image is shorter than its header says"))?;
+    let gray = pixels
+        .chunks(channels)
+        .map(|pixel| {
+            let sum: usize = pixel.iter().map(|&v| usize::from(v)).sum();
+            (sum / channels * 255 / max) as u8
+        })
+        .collect();
+    Ok((width, height, gray))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Packet {
+    /// Piece `index` of `count` of transfer `id`.
+    Chunk {
+        id: u32,
+        index: u8,
+        count: u8,
+        data: Vec<u8>,
+    },
+    /// The receiver asking for the pieces of transfer `id` it is missing.
+    Resend { id: u32, missing: Vec<u8> },
+}
+
+impl Packet {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        match self {
+            Packet::Chunk {
+                id,
+                index,
+                count,
+                data,
+            } => {
+                bytes.push(CHUNK);
+                bytes.extend(id.to_le_bytes());
+                bytes.extend([*index, *count]);
+                bytes.extend(data);
+            }
+            Packet::Resend { id, missing } => {
+                bytes.push(RESEND);
+                bytes.extend(id.to_le_bytes());
+                bytes.extend(missing);
+            }
+        }
+        bytes
+    }
+
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let rest = bytes.strip_prefix(MAGIC)?;
+        let (&kind, rest) = rest.split_first()?;
+        let id = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?);
+        let rest = &rest[4..];
+        match kind {
+            CHUNK => {
+                let (&index, rest) = rest.split_first()?;
+                let (&count, data) = rest.split_first()?;
+                (index < count).then(|| Packet::Chunk {
+                    id,
+                    index,
+                    count,
+                    data: data.to_vec(),
+                })
+            }
+            RESEND => Some(Packet::Resend {
+                id,
+                missing: rest.to_vec(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// What a received packet completed or asked for.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Received {
+    /// The last missing piece of a thumbnail arrived.
+    Complete(Thumbnail),
+    /// The receiver of one of our thumbnails is missing these packets.
+    Resend(Vec<Vec<u8>>),
+}
+
+struct Partial {
+    pieces: Vec<Option<Vec<u8>>>,
+    last: Instant,
+    requests: u32,
+}
+
+/// Thumbnails on their way out and in.
+#[derive(Default)]
+pub struct Transfers {
+    /// The packets of thumbnails we sent, by transfer ID, with when and to whom.
+    sent: HashMap<u32, (Instant, NodeNum, Vec<Vec<u8>>)>,
+    /// Thumbnails being received, by sender and transfer ID.
+    receiving: HashMap<(NodeNum, u32), Partial>,
+}
+
+impl Transfers {
+    /// The packets to send a thumbnail to `to` in, keeping them to send again if asked.
+    pub fn send(&mut self, to: NodeNum, thumbnail: &Thumbnail) -> Vec<Vec<u8>> {
+        let id = rand::random();
+        let packets = thumbnail.chunks(id);
+        self.sent
+            .retain(|_, (sent, _, _)| sent.elapsed() < KEEP_SENT);
+        self.sent.insert(id, (Instant::now(), to, packets.clone()));
+        packets
+    }
+
+    /// Handle a packet from `from` on the private port.
+    pub fn receive(&mut self, from: NodeNum, payload: &[u8]) -> Option<Received> {
+        match Packet::parse(payload)? {
+            Packet::Chunk {
+                id,
+                index,
+                count,
+                data,
+            } => {
+                let partial = self.receiving.entry((from, id)).or_insert_with(|| Partial {
+                    pieces: vec![None; usize::from(count)],
+                    last: Instant::now(),
+                    requests: 0,
+                });
+                let piece = partial.pieces.get_mut(usize::from(index))?;
+                *piece = Some(data);
+                partial.last = Instant::now();
+                if partial.pieces.iter().any(Option::is_none) {
+                    return None;
+                }
+                let data: Vec<u8> = self
+                    .receiving
+                    .remove(&(from, id))?
+                    .pieces
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .collect();
+                let (&width, rest) = data.split_first()?;
+                let (&height, bits) = rest.split_first()?;
+                let thumbnail = Thumbnail::from_parts(width, height, bits.to_vec());
+                if thumbnail.is_none() {
+                    log::warn!("Received a malformed thumbnail from {}", from);
+                }
+                thumbnail.map(Received::Complete)
+            }
+            Packet::Resend { id, missing } => {
+                let (_, to, packets) = self.sent.get(&id).filter(|(_, to, _)| *to == from)?;
+                log::debug!(
+                    "Sending {} missing thumbnail chunks to {}",
+                    missing.len(),
+                    to
+                );
+                let packets = missing
+                    .iter()
+                    .filter_map(|&index| packets.get(usize::from(index)).cloned())
+                    .collect();
+                Some(Received::Resend(packets))
+            }
+        }
+    }
+
+    /// Requests for the missing pieces of transfers that stalled, with whom to send them to.
+    /// Transfers still incomplete after a few requests are given up on.
+    pub fn stalled(&mut self) -> Vec<(NodeNum, Vec<u8>)> {
+        let mut requests = Vec::new();
+        self.receiving.retain(|&(from, id), partial| {
+            if partial.last.elapsed() < STALL {
+                return true;
+            }
+            if partial.requests == MAX_RESENDS {
+                log::warn!("Gave up on a thumbnail from {}", from);
+                return false;
+            }
+            partial.requests += 1;
+            partial.last = Instant::now();
+            let missing = (0..partial.pieces.len() as u8)
+                .filter(|&index| partial.pieces[usize::from(index)].is_none())
+                .collect();
+            requests.push((from, Packet::Resend { id, missing }.encode()));
+            true
+        });
+        requests
+    }
+
+    /// Pieces received and expected of each thumbnail on its way in, by sender.
+    pub fn progress(&self) -> Vec<(NodeNum, usize, usize)> {
+        self.receiving
+            .iter()
+            .map(|(&(from, _), partial)| {
+                let received = partial.pieces.iter().filter(|p| p.is_some()).count();
+                (from, received, partial.pieces.len())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: usize, height: usize) -> Vec<u8> {
+        (0..width * height)
+            .map(|i| ((i % width) * 255 / (width - 1)) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn images_are_scaled_to_fit() {
+        let thumbnail = Thumbnail::from_gray(640, 240, &gradient(640, 240));
+        assert_eq!((thumbnail.width, thumbnail.height), (64, 24));
+        // Dark on the left, light on the right.
+        assert!(thumbnail.is_dark(0, 10));
+        assert!(!thumbnail.is_dark(63, 10));
+        assert_eq!(thumbnail.render().len(), 12);
+        assert_eq!(Thumbnail::from_text(&thumbnail.to_text()), Some(thumbnail));
+    }
+
+    #[test]
+    fn pnm_headers_may_have_comments() {
+        let mut data = b"P6\n# made by hand\n2 1\n255\n".to_vec();
+        data.extend([0, 0, 0, 255, 255, 255]);
+        assert_eq!(parse_pnm(&data).unwrap(), (2, 1, vec![0, 255]));
+        assert!(parse_pnm(b"P3\n2 1\n255\n0 0 0 1 1 1").is_err());
+    }
+
+    #[test]
+    fn missing_chunks_are_sent_again() {
+        let thumbnail = Thumbnail::from_gray(64, 48, &gradient(64, 48));
+        let (mut sender, mut receiver) = (Transfers::default(), Transfers::default());
+        let packets = sender.send(2, &thumbnail);
+        assert_eq!(packets.len(), 3);
+        // The second packet is lost.
+        assert_eq!(receiver.receive(1, &packets[0]), None);
+        assert_eq!(receiver.receive(1, &packets[2]), None);
+        assert_eq!(receiver.progress(), vec![(1, 2, 3)]);
+
+        for partial in receiver.receiving.values_mut() {
+            partial.last -= STALL;
+        }
+        let requests = receiver.stalled();
+        assert_eq!(requests.len(), 1);
+        let (to, request) = &requests[0];
+        assert_eq!(*to, 1);
+        let resent = match sender.receive(2, request) {
+            Some(Received::Resend(resent)) => resent,
+            other => panic!("expected packets to resend, got {:?}", other),
+        };
+        assert_eq!(resent, vec![packets[1].clone()]);
+        assert_eq!(
+            receiver.receive(1, &resent[0]),
+            Some(Received::Complete(thumbnail))
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! Net-control traffic log of numbered formal messages and their receipts.
+//!
+//! Every event is appended to the configured `traffic_log` file, `traffic.tsv` in the data
+//! directory by default, so numbering and receipts survive restarts, and the whole log can be
+//! exported as a printable table.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local};
+
+use crate::forms::{Ics213, Precedence};
+use crate::types::NodeNum;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone)]
+pub struct TrafficEntry {
+    pub number: u32,
+    pub direction: Direction,
+    pub peer: NodeNum,
+    pub time: DateTime<Local>,
+    pub precedence: Precedence,
+    pub subject: String,
+    pub receipt: Option<DateTime<Local>>,
+}
+
+pub struct TrafficLog {
+    path: PathBuf,
+    entries: Vec<TrafficEntry>,
+}
+
+impl TrafficLog {
+    /// Load the traffic log from `path`, starting empty if it does not exist yet.
+    pub fn load(path: PathBuf) -> Self {
+        let mut log = Self {
+            path,
+            entries: Vec::new(),
+        };
+        match fs::read_to_string(&log.path) {
+            Ok(contents) => {
+                for line in contents.lines() {
+                    if log.replay(line).is_none() {
+                        log::warn!("Skipping malformed traffic log line: {}", line);
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => log::error!("Failed to read traffic log: {}", e),
+        }
+        log
+    }
+
+    fn replay(&mut self, line: &str) -> Option<()> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let number = fields.get(1)?.parse().ok()?;
+        let time = DateTime::parse_from_rfc3339(fields.get(2)?).ok()?.into();
+        let peer = fields.get(3)?.parse().ok()?;
+        match *fields.first()? {
+            "A" => self.mark_receipt(peer, number, time)?,
+            kind => {
+                let direction = match kind {
+                    "S" => Direction::Sent,
+                    "R" => Direction::Received,
+                    _ => return None,
+                };
+                self.entries.push(TrafficEntry {
+                    number,
+                    direction,
+                    peer,
+                    time,
+                    precedence: Precedence::from_code(fields.get(4)?)?,
+                    subject: fields.get(5)?.to_string(),
+                    receipt: None,
+                });
+            }
+        }
+        Some(())
+    }
+
+    /// The number to assign to the next outgoing form.
+    pub fn next_number(&self) -> u32 {
+        self.entries
+            .iter()
+            .filter(|e| e.direction == Direction::Sent)
+            .map(|e| e.number)
+            .max()
+            .unwrap_or(0)
+            + 1
+    }
+
+    pub fn record_sent(&mut self, peer: NodeNum, form: &Ics213) {
+        self.record(Direction::Sent, peer, form);
+    }
+
+    pub fn record_received(&mut self, peer: NodeNum, form: &Ics213) {
+        self.record(Direction::Received, peer, form);
+    }
+
+    fn record(&mut self, direction: Direction, peer: NodeNum, form: &Ics213) {
+        let entry = TrafficEntry {
+            number: form.number,
+            direction,
+            peer,
+            time: Local::now(),
+            precedence: form.precedence,
+            subject: form.subject.replace('\t', " "),
+            receipt: None,
+        };
+        let kind = if direction == Direction::Sent {
+            "S"
+        } else {
+            "R"
+        };
+        self.append(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            kind,
+            entry.number,
+            entry.time.to_rfc3339(),
+            entry.peer,
+            entry.precedence.code(),
+            entry.subject
+        ));
+        self.entries.push(entry);
+    }
+
+    /// Note that `peer` acknowledged our form `number`.
+    pub fn record_receipt(&mut self, peer: NodeNum, number: u32) {
+        let time = Local::now();
+        if self.mark_receipt(peer, number, time).is_some() {
+            self.append(&format!("A\t{}\t{}\t{}", number, time.to_rfc3339(), peer));
+        } else {
+            log::warn!("Receipt from {} for unknown message #{}", peer, number);
+        }
+    }
+
+    fn mark_receipt(&mut self, peer: NodeNum, number: u32, time: DateTime<Local>) -> Option<()> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|e| e.direction == Direction::Sent && e.number == number && e.peer == peer)?;
+        entry.receipt = Some(time);
+        Some(())
+    }
+
+    fn append(&self, line: &str) {
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut f| writeln!(f, "{}", line));
+        if let Err(e) = result {
+            log::error!("Failed to write traffic log: {}", e);
+        }
+    }
+
+    /// Write a printable copy of the log to a timestamped file in `dir`, returning its path.
+    pub fn export(&self, dir: &Path, name: impl Fn(NodeNum) -> String) -> io::Result<PathBuf> {
+        let now = Local::now();
+        let path = dir.join(format!("traffic_log_{}.txt", now.format("%Y%m%d_%H%M%S")));
+        let mut file = File::create(&path)?;
+        writeln!(
+            file,
+            "TRAFFIC LOG - exported {}",
+            now.format("%Y-%m-%d %H:%M")
+        )?;
+        writeln!(file)?;
+        writeln!(
+            file,
+            "{:>5}  {:<4}  {:<20}  {:<16}  {:<9}  {:<8}  SUBJECT",
+            "NO.", "DIR", "STATION", "TIME", "PREC", "RECEIPT"
+        )?;
+        for entry in &self.entries {
+            writeln!(
+                file,
+                "{:>5}  {:<4}  {:<20}  {:<16}  {:<9}  {:<8}  {}",
+                entry.number,
+                if entry.direction == Direction::Sent {
+                    "OUT"
+                } else {
+                    "IN"
+                },
+                name(entry.peer),
+                entry.time.format("%Y-%m-%d %H:%M"),
+                entry.precedence.label(),
+                entry
+                    .receipt
+                    .map(|t| t.format("%H:%M").to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                entry.subject
+            )?;
+        }
+        Ok(path)
+    }
+}
@@ -1,26 +1,109 @@
 //! The UI code as well as business logic.
 
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
 use chrono::{DateTime, Local};
 use color_eyre::eyre::Result;
-use meshtastic::{protobufs::NodeInfo, types::NodeId};
+use meshtastic::{
+    protobufs::{
+        Channel, DeviceMetrics, MeshPacket, NodeInfo, Position, User,
+        config::{DeviceConfig, LoRaConfig, SecurityConfig},
+        log_record::Level,
+    },
+    types::NodeId,
+};
 use ratatui::{
     DefaultTerminal,
-    crossterm::event::{self, Event, KeyCode},
+    crossterm::event::{self, Event, KeyCode, KeyEvent},
     prelude::*,
     widgets::{
-        Block, List, ListState, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState, Wrap,
+        Block, Cell, Clear, List, ListState, Paragraph, Row, Scrollbar, ScrollbarOrientation,
+        ScrollbarState, Table, TableState, Wrap,
+        canvas::{Canvas, Map, MapResolution},
     },
 };
 use tokio::{
-    sync::mpsc::{Receiver, Sender},
+    sync::mpsc::{Receiver, Sender, error::TrySendError},
     time::Instant,
 };
+use unicode_width::UnicodeWidthStr;
 
-use crate::types::{Focus, MeshEvent, NodeNum, UiEvent};
+use crate::admin_keys::{self, AdminKeypair};
+use crate::announce::Announcer;
+use crate::aprs::AprsGateway;
+use crate::battery::{self, BatteryHistory};
+use crate::bulk_admin::{BulkAdmin, Change, NodeState};
+use crate::busy_channel::BusyChannel;
+use crate::channel_badges::ChannelBadges;
+use crate::channels;
+use crate::command_line::{Command, CommandLine, Export, Theme};
+use crate::compression::{self, COMPRESSED_TEXT_LIMIT};
+use crate::config::{Config, NodeColumn, UiConfig};
+#[cfg(unix)]
+use crate::control::{self, Call, ControlSocket};
+use crate::conversation_view::{self, ConversationView};
+#[cfg(all(feature = "dbus", target_os = "linux"))]
+use crate::dbus::DesktopBus;
+use crate::device_log::{self, DeviceLog};
+use crate::device_time;
+use crate::export;
+use crate::fixed_position::{PositionDraft, PositionField};
+use crate::focus::{FocusManager, Route};
+use crate::forms::{self, FormDraft, FormField, Ics213};
+use crate::glyphs;
+use crate::health::{self, Deliveries, Health, HopTracker};
+use crate::keymap::Keymap;
+use crate::lora::{LoraDraft, LoraField};
+use crate::macros::Macros;
+use crate::mesh::{MeshError, Transport};
+use crate::message_actions::{self, MessageAction};
+use crate::monitor::MonitorLog;
+use crate::notify::{NotifyMode, NotifyOverrides};
+use crate::outbox::{Acknowledgement, OutboxEntry, OutboxState};
+use crate::pager::Pager;
+use crate::palette::Palette;
+use crate::plain;
+use crate::presence::ChannelPresence;
+use crate::probe::Probe;
+use crate::profiles::Profile;
+use crate::push::Pusher;
+use crate::remote_reboot::{RemoteReboot, Stage};
+use crate::replay::Replay;
+use crate::search::{self, SearchResult};
+use crate::session_log::SessionLog;
+use crate::smart_views::SmartView;
+use crate::stats::{self, ConversationStats, Flow, NodeActivity, Ranking, Window};
+use crate::store::Store;
+use crate::structured::Structured;
+use crate::survey;
+use crate::switcher;
+use crate::thumbnail::{Received, Thumbnail, Transfers};
+use crate::traffic::TrafficLog;
+use crate::types::{
+    BROADCAST, ConnectionStatus, Contact, ContactId, Focus, MeshEvent, Message, NodeNum,
+    PACKET_BYTE_LIMIT, SessionState, UiEvent, ViewState, format_node_num,
+};
+use crate::undo::{Undo, UndoStack};
+use crate::view::{Action, Screens, View};
+use crate::watchdog::Watchdog;
 
-const PACKET_BYTE_LIMIT: usize = 200;
+/// How often to pick up read state from other instances sharing the store.
+const READ_SYNC_INTERVAL: Duration = Duration::from_secs(2);
+/// How often a database browsed without a radio is re-read for what its writer added.
+const OFFLINE_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the configuration file is checked for changes.
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// How long a notice stays in the title bar.
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+/// Mesh events handled between checks for key presses, so a flood can't freeze the keyboard.
+const MAX_EVENTS_PER_LOOP: usize = 100;
+/// Name the node list's state is kept under, with the screens' states.
+const NODE_LIST_VIEW: &str = "nodes";
 
 pub struct App {
     pub transmitter: Sender<UiEvent>,
@@ -29,387 +112,4414 @@ pub struct App {
     pub nodes: HashMap<NodeNum, NodeInfo>,
     pub input: String,
     pub search: String,
-    pub focus: Option<Focus>,
+    /// Query typed into the quick switcher.
+    pub switcher: String,
+    pub switcher_state: ListState,
+    /// Query typed into the global search, and what it found across nodes and history.
+    pub global_search: String,
+    pub search_results: Vec<SearchResult>,
+    pub search_results_state: ListState,
+    /// Saved node filters from the configuration, and the one the node list shows, if any.
+    pub smart_views: Vec<SmartView>,
+    pub smart_view: Option<usize>,
+    pub focus: FocusManager,
+    pub command_line: CommandLine,
+    pub macros: Macros,
+    /// Screens open over the main panes, which take the keys while there are any.
+    pub screens: Screens,
     pub node_list_state: ListState,
     pub current_contact: Option<NodeNum>,
-    pub conversations: HashMap<NodeNum, Vec<(bool, DateTime<Local>, String)>>,
+    pub conversations: HashMap<NodeNum, Vec<Message>>,
+    pub conversation_view: ConversationView,
+    /// Lines the open conversation is scrolled down by.
+    pub conversation_scroll: usize,
+    /// Index of the selected message in the open conversation.
+    pub selected_message: Option<usize>,
+    /// Show conversations threaded, replies nested under what they answer.
+    pub threaded: bool,
+    /// Packet IDs of the messages whose replies are hidden when threaded.
+    pub collapsed: HashSet<u32>,
+    pub message_action_state: ListState,
+    pub message_details: bool,
+    /// Show the selected message's packet decoded and in hex, under its details.
+    pub raw_packet_view: bool,
+    /// Packets of the messages received this session, by packet ID.
+    pub raw_packets: HashMap<u32, MeshPacket>,
+    /// Packet ID of the message the input will be sent as a reply to.
+    pub reply_to: Option<u32>,
+    /// Nodes picked in the node list; input is sent to each of them as a direct message.
+    pub team: BTreeSet<NodeNum>,
+    /// Outbox entries of the last message sent to the team, to show its progress.
+    pub team_send: Vec<i64>,
+    /// The latest remote reboot, shown until another is started.
+    pub remote_reboot: Option<RemoteReboot>,
+    /// The latest capability probe of a remote node.
+    pub probe: Option<Probe>,
+    /// The latest change applied to every node in the team.
+    pub bulk_admin: Option<BulkAdmin>,
+    /// The change being set up for the team: a channel index to copy, or else a position
+    /// broadcast interval.
+    pub bulk_channel: bool,
+    pub bulk_value: String,
+    /// Outgoing messages not confirmed yet.
+    pub outbox: Vec<OutboxEntry>,
+    /// Acknowledgements heard this session, by outbox entry, which is also the timestamp of
+    /// the outgoing message in microseconds.
+    pub acks: HashMap<i64, Acknowledgement>,
+    pub outbox_table_state: TableState,
+    pub session_log: Option<SessionLog>,
+    pub announcer: Option<Announcer>,
+    pub push: Option<Pusher>,
+    pub aprs: Option<AprsGateway>,
+    pub form: FormDraft,
+    pub traffic: TrafficLog,
+    pub geojson_feed: Option<PathBuf>,
+    pub survey_file: Option<PathBuf>,
+    /// Directory exports are written to.
+    pub export_dir: PathBuf,
+    pub store: Box<dyn Store>,
+    pub my_node: Option<NodeInfo>,
+    pub notes: HashMap<NodeNum, String>,
+    pub note: String,
+    /// The note being typed for the selected message.
+    pub message_note: String,
+    /// Nodes whose user ID changed while we knew them, with the old and new IDs.
+    pub id_changes: HashMap<NodeNum, (String, String)>,
+    /// Nodes that came back under a new number after a reflash, by their new number, with the
+    /// old number their history can be merged from.
+    pub renumbered: HashMap<NodeNum, NodeNum>,
+    /// Everyone heard with a public key or user ID, and the node numbers they used.
+    pub contacts: HashMap<ContactId, Contact>,
+    /// Per conversation, the time of the newest message read here or by another instance.
+    pub last_read: HashMap<NodeNum, i64>,
+    pub last_read_sync: Instant,
+    /// Recent battery levels of every node, for the trend in the node list.
+    pub battery: BatteryHistory,
+    pub device_config: Option<DeviceConfig>,
+    pub profiles: Vec<Profile>,
+    pub profile_list_state: ListState,
+    /// Name being typed to save the current identity as a new profile.
+    pub profile_name: String,
+    pub lora_config: Option<LoRaConfig>,
+    pub lora: LoraDraft,
+    pub fixed_position: PositionDraft,
+    /// Channels configured on our device, by index.
+    pub channels: BTreeMap<i32, Channel>,
+    pub psk_rotations: HashMap<i32, i64>,
+    pub psk_rotation_days: Option<u32>,
+    /// Send messages too long for one packet compressed, for other edda clients.
+    pub compress_long_messages: bool,
+    pub channel_list_state: ListState,
+    /// Channel whose PSK rotation is waiting for a second ENTER to confirm.
+    pub pending_rotation: Option<i32>,
+    pub security_config: Option<SecurityConfig>,
+    /// This machine's keypair for remote administration.
+    pub admin_keypair: Option<AdminKeypair>,
+    pub admin_key_list_state: ListState,
+    /// A public key being typed or pasted in to add to the device.
+    pub admin_key_input: String,
+    pub notify_overrides: NotifyOverrides,
+    /// Statistics for the current contact, refreshed as the conversation changes.
+    pub contact_stats: ConversationStats,
+    pub leaderboard: Vec<NodeActivity>,
+    pub leaderboard_window: Window,
+    pub leaderboard_ranking: Ranking,
+    /// Who sent packets to whom, for the flow matrix.
+    pub flows: Vec<Flow>,
+    pub flow_window: Window,
+    /// Count text messages in the flow matrix rather than all packets.
+    pub flow_messages: bool,
+    pub deliveries: Deliveries,
+    pub hop_tracker: HopTracker,
+    pub presence: ChannelPresence,
+    /// Holds broadcasts back while the channel is busy.
+    pub busy_channel: BusyChannel,
+    /// Thumbnails on their way out and in, if the experiment is on.
+    pub thumbnails: Option<Transfers>,
+    /// Path of an image being typed in, to send as a thumbnail.
+    pub thumbnail_path: String,
+    /// Checklist or status report being typed in, as `Title: item, item`.
+    pub structured_draft: String,
+    /// Default-channel traffic seen through MQTT, in monitor mode.
+    pub monitor: MonitorLog,
+    pub monitor_enabled: bool,
+    /// Recent hints that something about the mesh changed for the worse.
+    pub anomalies: Vec<(DateTime<Local>, String)>,
+    /// Why the connection to the device ended, if it did.
+    pub(crate) connection_error: Option<MeshError>,
+    /// None until the device has first connected.
+    pub(crate) connection_status: Option<ConnectionStatus>,
+    pub device_log: DeviceLog,
+    pub watchdog: Watchdog,
+    /// Shortest time between two redraws.
+    pub redraw_interval: Duration,
+    /// How often the screen is redrawn at the latest and timeouts are checked.
+    pub tick_rate: Duration,
+    pub palette: Palette,
+    pub keymap: Keymap,
+    /// When the configuration file had last changed as of the last check.
+    config_modified: Option<SystemTime>,
+    /// The configuration file, to reload when it changes.
+    config_file: PathBuf,
+    last_config_check: Instant,
+    /// A short notice shown in the title bar, with when it appeared and its colour.
+    toast: Option<(Instant, String, Color)>,
+    /// What the local actions that lost something removed, to put back with `U`.
+    undo: UndoStack,
+    pub plain: bool,
+    /// Draw line art in ASCII for terminals without good Unicode support.
+    pub ascii: bool,
+    /// Share of the screen width taken by the node list, in percent.
+    pub node_list_width: u16,
+    pub node_columns: Vec<NodeColumn>,
+    pub channel_badges: ChannelBadges,
+    /// Reaction sent to direct messages once read, if read receipts are on.
+    pub read_receipt: Option<String>,
+    /// How the device is reached, shown in the title bar; none for mock scenarios.
+    pub transport: Option<Transport>,
+    /// With several radios, the name of the active one.
+    pub radio: Option<String>,
+    /// Browsing a database without a radio, as `edda view` does: nothing can be sent, and the
+    /// store is re-read now and then for what a daemon writing to it added.
+    pub offline: bool,
+    /// Nothing may be written to the store either.
+    pub read_only: bool,
+    pub last_offline_reload: Instant,
+    /// Socket scripts and other frontends drive edda through, if configured.
+    #[cfg(unix)]
+    pub control: Option<ControlSocket>,
+    #[cfg(all(feature = "dbus", target_os = "linux"))]
+    pub desktop_bus: DesktopBus,
+    #[cfg(all(feature = "dbus", target_os = "linux"))]
+    pub desktop_requests: Receiver<(NodeNum, String)>,
 }
 
 impl App {
-    pub fn new(transmitter: Sender<UiEvent>, receiver: Receiver<MeshEvent>) -> Self {
-        Self {
+    pub fn new(
+        transmitter: Sender<UiEvent>,
+        receiver: Receiver<MeshEvent>,
+        config: &Config,
+        store: Box<dyn Store>,
+    ) -> Self {
+        let nodes: HashMap<NodeNum, NodeInfo> = match store.get_nodes() {
+            Ok(nodes) => nodes.into_iter().map(|n| (n.num, n)).collect(),
+            Err(e) => {
+                log::error!("Failed to load nodes from store: {}", e);
+                HashMap::new()
+            }
+        };
+        let mut contacts: HashMap<ContactId, Contact> = match store.get_contacts() {
+            Ok(contacts) => contacts
+                .into_iter()
+                .map(|contact| (contact.id.clone(), contact))
+                .collect(),
+            Err(e) => {
+                log::error!("Failed to load contacts from store: {}", e);
+                HashMap::new()
+            }
+        };
+        // Nodes stored before contacts were.
+        for node in nodes.values() {
+            for id in node.user.iter().flat_map(ContactId::of) {
+                contacts
+                    .entry(id.clone())
+                    .or_insert_with(|| Contact::new(id))
+                    .heard(node.num, i64::from(node.last_heard));
+            }
+        }
+        let mut conversations: HashMap<NodeNum, Vec<Message>> = HashMap::new();
+        match store.get_messages() {
+            Ok(messages) => {
+                for (num, message) in messages {
+                    conversations.entry(num).or_default().push(message);
+                }
+            }
+            Err(e) => log::error!("Failed to load messages from store: {}", e),
+        }
+        #[cfg(all(feature = "dbus", target_os = "linux"))]
+        let (desktop_bus, desktop_requests) = DesktopBus::spawn();
+        let notes = store.get_notes().unwrap_or_else(|e| {
+            log::error!("Failed to load notes from store: {}", e);
+            HashMap::new()
+        });
+        let last_read = store.get_read_state().unwrap_or_else(|e| {
+            log::error!("Failed to load read state from store: {}", e);
+            HashMap::new()
+        });
+        let battery = store
+            .get_battery(Local::now().timestamp() - battery::TREND_WINDOW)
+            .unwrap_or_else(|e| {
+                log::error!("Failed to load battery levels from store: {}", e);
+                Vec::new()
+            })
+            .into_iter()
+            .collect();
+        let profiles = store.get_profiles().unwrap_or_else(|e| {
+            log::error!("Failed to load profiles from store: {}", e);
+            Vec::new()
+        });
+        let psk_rotations = store.get_psk_rotations().unwrap_or_else(|e| {
+            log::error!("Failed to load PSK rotations from store: {}", e);
+            HashMap::new()
+        });
+        let admin_keypair = store.get_admin_keypair().unwrap_or_else(|e| {
+            log::error!("Failed to load admin keypair from store: {}", e);
+            None
+        });
+        let notify_overrides = store.get_notify_overrides().unwrap_or_else(|e| {
+            log::error!("Failed to load notification overrides from store: {}", e);
+            NotifyOverrides::default()
+        });
+        let outbox = store.get_outbox().unwrap_or_else(|e| {
+            log::error!("Failed to load outbox from store: {}", e);
+            Vec::new()
+        });
+        let mut node_list_state = ListState::default();
+        if !nodes.is_empty() {
+            node_list_state.select(Some(0));
+        }
+
+        let mut app = Self {
             transmitter,
             receiver,
             vertical_scroll_state: ScrollbarState::default(),
-            nodes: HashMap::new(),
+            nodes,
             input: String::with_capacity(PACKET_BYTE_LIMIT),
             search: String::new(),
-            focus: None,
-            node_list_state: ListState::default(),
+            switcher: String::new(),
+            switcher_state: ListState::default(),
+            global_search: String::new(),
+            search_results: Vec::new(),
+            search_results_state: ListState::default(),
+            smart_views: smart_views(&config.ui, store.as_ref()),
+            smart_view: None,
+            focus: FocusManager::default(),
+            command_line: CommandLine::default(),
+            macros: Macros::default(),
+            screens: Screens::default(),
+            node_list_state,
             current_contact: None,
-            conversations: HashMap::new(),
+            conversations,
+            conversation_view: ConversationView::default(),
+            conversation_scroll: 0,
+            selected_message: None,
+            threaded: config.ui.threaded,
+            collapsed: HashSet::new(),
+            message_action_state: ListState::default(),
+            message_details: false,
+            raw_packet_view: false,
+            raw_packets: HashMap::new(),
+            reply_to: None,
+            team: BTreeSet::new(),
+            team_send: Vec::new(),
+            remote_reboot: None,
+            probe: None,
+            bulk_admin: None,
+            bulk_channel: true,
+            bulk_value: String::new(),
+            outbox,
+            acks: HashMap::new(),
+            outbox_table_state: TableState::default(),
+            session_log: config.session_log_dir.clone().map(SessionLog::new),
+            announcer: config.announce_file.clone().map(Announcer::new),
+            push: Pusher::spawn(config.push.clone()),
+            aprs: config.aprs.clone().map(AprsGateway::spawn),
+            form: FormDraft::default(),
+            traffic: TrafficLog::load(config.traffic_log.clone()),
+            geojson_feed: config.geojson_feed.clone(),
+            survey_file: config.survey_file.clone(),
+            export_dir: config.export_dir(),
+            store,
+            my_node: None,
+            notes,
+            note: String::new(),
+            message_note: String::new(),
+            id_changes: HashMap::new(),
+            renumbered: HashMap::new(),
+            contacts,
+            last_read,
+            last_read_sync: Instant::now(),
+            battery,
+            device_config: None,
+            profiles,
+            profile_list_state: ListState::default(),
+            profile_name: String::new(),
+            lora_config: None,
+            lora: LoraDraft::default(),
+            fixed_position: PositionDraft::default(),
+            channels: BTreeMap::new(),
+            psk_rotations,
+            psk_rotation_days: config.psk_rotation_days,
+            compress_long_messages: config.compress_long_messages,
+            channel_list_state: ListState::default(),
+            pending_rotation: None,
+            security_config: None,
+            admin_keypair,
+            admin_key_list_state: ListState::default(),
+            admin_key_input: String::new(),
+            notify_overrides,
+            contact_stats: ConversationStats::default(),
+            leaderboard: Vec::new(),
+            leaderboard_window: Window::default(),
+            leaderboard_ranking: Ranking::default(),
+            flows: Vec::new(),
+            flow_window: Window::default(),
+            flow_messages: false,
+            deliveries: Deliveries::default(),
+            hop_tracker: HopTracker::default(),
+            presence: ChannelPresence::default(),
+            busy_channel: BusyChannel::new(config.defer_broadcasts_above),
+            thumbnails: config.experimental.thumbnails.then(Transfers::default),
+            thumbnail_path: String::new(),
+            structured_draft: String::new(),
+            monitor: MonitorLog::default(),
+            monitor_enabled: config.monitor.is_some(),
+            anomalies: Vec::new(),
+            connection_error: None,
+            connection_status: None,
+            device_log: DeviceLog::default(),
+            watchdog: Watchdog::new(config.watchdog.clone()),
+            redraw_interval: redraw_interval(&config.ui),
+            tick_rate: Duration::from_millis(config.ui.tick_rate_ms.max(1)),
+            palette: Palette::new(&config.ui.colors),
+            keymap: Keymap::new(&config.ui.keys),
+            config_modified: Config::modified(&config.file),
+            config_file: config.file.clone(),
+            last_config_check: Instant::now(),
+            toast: None,
+            undo: UndoStack::default(),
+            plain: config.ui.plain,
+            ascii: glyphs::ascii_only(config.ui.ascii),
+            node_list_width: config.ui.node_list_width.clamp(10, 90),
+            node_columns: config.ui.node_columns.clone(),
+            channel_badges: ChannelBadges::new(&config.ui.channels),
+            read_receipt: read_receipt(&config.ui),
+            transport: None,
+            radio: None,
+            offline: false,
+            read_only: false,
+            last_offline_reload: Instant::now(),
+            #[cfg(unix)]
+            control: config.control_socket.clone().map(ControlSocket::spawn),
+            #[cfg(all(feature = "dbus", target_os = "linux"))]
+            desktop_bus,
+            #[cfg(all(feature = "dbus", target_os = "linux"))]
+            desktop_requests,
+        };
+        app.fold_contacts();
+        app.resume_session();
+        app
+    }
+
+    /// Reopen the conversation, scroll position and pane left open last time.
+    fn resume_session(&mut self) {
+        let state = match self.store.get_session_state() {
+            Ok(state) => state,
+            Err(e) => {
+                log::error!("Failed to load session state: {}", e);
+                return;
+            }
+        };
+        if let Some(id) = state.contact.map(|id| self.current_node(id))
+            && (self.nodes.contains_key(&id) || self.conversations.contains_key(&id))
+        {
+            self.open_conversation(id);
+            self.conversation_scroll = state.scroll;
+        }
+        if let Some(focus) = state.focus {
+            self.focus.set(focus);
+        }
+        match self.store.get_view_states() {
+            Ok(states) => self.screens.states = states,
+            Err(e) => log::error!("Failed to load view states: {}", e),
+        }
+        if let Some(nodes) = self.screens.states.get(NODE_LIST_VIEW) {
+            self.search = nodes.filter.clone();
+            self.node_list_state.select(nodes.selected);
         }
     }
 
-    fn get_sorted_nodes(&self) -> Vec<&NodeInfo> {
-        let mut nodes: Vec<_> = self.nodes.values().collect();
-        nodes.sort_by_key(|n| n.num);
-        nodes
+    fn save_session(&mut self) {
+        if self.read_only {
+            return;
+        }
+        let state = SessionState {
+            contact: self.current_contact,
+            scroll: self.conversation_scroll,
+            focus: self
+                .focus
+                .current()
+                .filter(|focus| focus.pane_name().is_some()),
+        };
+        if let Err(e) = self.store.save_session_state(&state) {
+            log::error!("Failed to store session state: {}", e);
+        }
+
+        let mut screens = std::mem::take(&mut self.screens);
+        screens.keep_open_states(self);
+        screens.states.insert(
+            NODE_LIST_VIEW.to_string(),
+            ViewState {
+                selected: self.node_list_state.selected(),
+                scroll: 0,
+                filter: self.search.clone(),
+            },
+        );
+        for (view, state) in &screens.states {
+            if let Err(e) = self.store.save_view_state(view, state) {
+                log::error!("Failed to store the state of {}: {}", view, e);
+            }
+        }
+        self.screens = screens;
     }
 
-    fn get_visible_nodes(&self) -> Vec<&NodeInfo> {
-        let sorted = self.get_sorted_nodes();
-        sorted
-            .into_iter()
-            .filter(|n| {
-                let Some(user) = n.user.as_ref() else {
-                    return false;
-                };
-                if self.search.is_empty() {
-                    return true;
-                }
-                user.long_name
-                    .to_lowercase()
-                    .contains(&self.search.to_lowercase())
+    /// Short names of a few nodes, then how many more there are.
+    fn short_names(&self, nodes: &[NodeNum]) -> String {
+        const SHOWN: usize = 5;
+        let mut names: Vec<String> = nodes
+            .iter()
+            .take(SHOWN)
+            .map(|num| {
+                self.nodes
+                    .get(num)
+                    .and_then(|n| n.user.as_ref())
+                    .map_or_else(|| format_node_num(*num), |u| u.short_name.clone())
             })
-            .collect()
+            .collect();
+        if nodes.len() > SHOWN {
+            names.push(format!("+{}", nodes.len() - SHOWN));
+        }
+        names.join(", ")
     }
 
-    fn update(&mut self) {
-        match self.receiver.try_recv() {
-            Ok(MeshEvent::NodeAvailable(node_info)) => {
-                let is_empty = self.nodes.is_empty();
-                self.nodes.insert(node_info.num, *node_info);
-                if is_empty {
-                    self.node_list_state.select(Some(0));
-                }
-            }
-            Ok(MeshEvent::Message { node_id, message }) => {
-                self.conversations.entry(node_id.id()).or_default().push((
-                    false,
-                    Local::now(),
-                    message,
-                ));
-            }
-            Err(_) => {}
+    /// A human-readable name for a node, falling back to its hex ID when unknown.
+    pub(crate) fn node_name(&self, num: NodeNum) -> String {
+        self.nodes
+            .get(&num)
+            .and_then(|n| n.user.as_ref())
+            .map(|u| u.long_name.clone())
+            .unwrap_or_else(|| format_node_num(num))
+    }
+
+    /// Which node passed a recipient's acknowledgement back to us. The firmware only reports
+    /// the last byte of its number, so several known nodes may match it.
+    fn describe_route(&self, to: NodeNum, ack: &Acknowledgement) -> Option<String> {
+        let relay_node = ack.relay_node?;
+        if ack.hops == Some(0) || to & 0xff == u32::from(relay_node) {
+            return Some(format!("direct from {}", self.node_name(to)));
         }
+        let my_num = self.my_node.as_ref().map(|n| n.num);
+        let candidates: Vec<String> = self
+            .nodes
+            .keys()
+            .filter(|&&num| num & 0xff == u32::from(relay_node) && Some(num) != my_num)
+            .map(|&num| self.node_name(num))
+            .collect();
+        let relay = if candidates.is_empty() {
+            format!("a node ending in {relay_node:02x}")
+        } else {
+            candidates.join(" or ")
+        };
+        Some(match ack.hops {
+            Some(1) => format!("via {relay}, 1 hop"),
+            Some(hops) => format!("via {relay}, {hops} hops"),
+            None => format!("via {relay}"),
+        })
     }
 
-    pub fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
-        let tick_rate = Duration::from_millis(250);
-        let mut last_tick = Instant::now();
-        loop {
-            terminal.draw(|frame| self.draw(frame))?;
+    fn write_geojson_feed(&self) {
+        if let Some(path) = &self.geojson_feed
+            && let Err(e) = export::write_nodes_geojson(self.nodes.values(), path)
+        {
+            log::error!("Failed to write GeoJSON feed: {}", e);
+        }
+    }
 
-            self.update();
+    /// Run the command on the command line, leaving its error there if it fails. Returns whether
+    /// the line is done with, as it is once the command parses.
+    pub(crate) fn submit_command(&mut self) -> bool {
+        let command = match self.command_line.submit() {
+            Ok(command) => command,
+            Err(e) => {
+                self.command_line.error = Some(e.to_string());
+                return false;
+            }
+        };
+        self.run_command(command);
+        true
+    }
 
-            let timeout = tick_rate.saturating_sub(last_tick.elapsed());
-            if event::poll(timeout)?
-                && let Event::Key(key) = event::read()?
-            {
-                match key.code {
-                    KeyCode::Esc => {
-                        self.focus = None;
-                    }
-                    KeyCode::Tab => {
-                        self.focus = match self.focus {
-                            None => Some(Focus::Search),
-                            Some(Focus::Search) => Some(Focus::Input),
-                            Some(Focus::Input) => Some(Focus::Conversation),
-                            Some(Focus::Conversation) => Some(Focus::NodeList),
-                            Some(Focus::NodeList) => Some(Focus::Search),
-                        };
-                    }
-                    KeyCode::BackTab => {
-                        self.focus = match self.focus {
-                            None => Some(Focus::Search),
-                            Some(Focus::Search) => Some(Focus::NodeList),
-                            Some(Focus::NodeList) => Some(Focus::Conversation),
-                            Some(Focus::Conversation) => Some(Focus::Input),
-                            Some(Focus::Input) => Some(Focus::Search),
-                        };
-                    }
-                    _ => {
-                        if let Some(focus) = self.focus {
-                            match focus {
-                                Focus::NodeList => match key.code {
-                                    KeyCode::Char('j') | KeyCode::Down => {
-                                        self.node_list_state.select_next()
-                                    }
-                                    KeyCode::Char('k') | KeyCode::Up => {
-                                        self.node_list_state.select_previous()
-                                    }
-                                    KeyCode::Enter => {
-                                        if let Some(selected_index) =
-                                            self.node_list_state.selected()
-                                        {
-                                            let nodes = self.get_visible_nodes();
-                                            if let Some(selected_node) = nodes.get(selected_index) {
-                                                let new_node = Some(selected_node.num);
-                                                if new_node != self.current_contact {
-                                                    // TODO(reggens): add db lookup here
-                                                    self.current_contact = new_node;
-                                                }
-                                            }
-                                        }
-                                    }
-                                    _ => {}
-                                },
-                                Focus::Conversation => match key.code {
-                                    KeyCode::Char('j') | KeyCode::Down => {
-                                        self.vertical_scroll_state.next();
-                                    }
-                                    KeyCode::Char('k') | KeyCode::Up => {
-                                        self.vertical_scroll_state.prev();
-                                    }
-                                    _ => {}
-                                },
-                                Focus::Input => match key.code {
-                                    KeyCode::Char(c) => {
-                                        // Arbitrary limit of 237 characters
-                                        if self.input.len() <= PACKET_BYTE_LIMIT {
-                                            self.input.push(c);
-                                        }
-                                    }
-                                    KeyCode::Backspace => {
-                                        self.input.pop();
-                                    }
-                                    KeyCode::Enter => {
-                                        if let Some(id) = self.current_contact {
-                                            self.conversations.entry(id).or_default().push((
-                                                true,
-                                                Local::now(),
-                                                self.input.clone(),
-                                            ));
-
-                                            let node_id = NodeId::new(id);
-                                            let msg = UiEvent::Message {
-                                                node_id,
-                                                message: self.input.clone(),
-                                            };
-
-                                            log::info!("Sending packet to {}", node_id);
-                                            self.input.clear();
-                                            self.transmitter.try_send(msg).unwrap();
-                                        }
-                                    }
-                                    _ => {}
-                                },
-                                Focus::Search => match key.code {
-                                    KeyCode::Char(c) => {
-                                        if c != ' ' {
-                                            self.search.push(c);
-                                        }
-                                    }
-                                    KeyCode::Backspace => {
-                                        self.search.pop();
-                                    }
-                                    KeyCode::Enter => {
-                                        self.search.push('\n');
-                                    }
-                                    _ => {}
-                                },
-                            }
-                        } else if let KeyCode::Char('q') = key.code {
-                            return Ok(());
-                        }
-                    }
+    fn run_command(&mut self, command: Command) {
+        if self.offline && matches!(command, Command::Connect(_) | Command::Radio(_)) {
+            self.refuse_offline("connect");
+            return;
+        }
+        match command {
+            Command::Connect(transport) => {
+                if let Err(e) = self
+                    .transmitter
+                    .try_send(UiEvent::Connect(transport.clone()))
+                {
+                    log::error!("Failed to ask for connecting to {}: {}", transport, e);
+                    return;
                 }
+                self.toast = Some((
+                    Instant::now(),
+                    format!("Connecting to {transport}"),
+                    Color::Green,
+                ));
+                self.transport = Some(transport);
             }
-            if last_tick.elapsed() >= tick_rate {
-                last_tick = Instant::now();
+            Command::Export(Export::Json) => self.export_json(),
+            Command::Export(Export::Map) => self.export_node_map(),
+            Command::Export(Export::Survey) => self.export_survey(),
+            Command::Export(Export::Traffic) => self.export_traffic_log(),
+            Command::Filter(text) => self.search = text,
+            Command::Radio(name) => {
+                if let Err(e) = self
+                    .transmitter
+                    .try_send(UiEvent::SwitchRadio(name.clone()))
+                {
+                    log::error!("Failed to ask for switching to radio {}: {}", name, e);
+                }
+            }
+            Command::Replay { from, to } => self.open_replay(from, to),
+            Command::Theme(theme) => {
+                self.plain = theme == Theme::Plain;
+                self.ascii = theme == Theme::Ascii;
             }
         }
     }
 
-    fn build_constraints(frame: &mut Frame) -> (Rect, Rect, Rect, Rect, Rect) {
-        let area = frame.area();
+    /// The directory exports are written to, created if need be.
+    fn export_dir(&self) -> std::io::Result<&Path> {
+        std::fs::create_dir_all(&self.export_dir)?;
+        Ok(&self.export_dir)
+    }
 
-        let horizontal_chunks =
-            Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)])
-                .split(area);
+    fn export_node_map(&self) {
+        let path = self.export_dir.join(format!(
+            "nodes_{}.geojson",
+            Local::now().format("%Y%m%d_%H%M%S")
+        ));
+        match self
+            .export_dir()
+            .and_then(|_| export::write_nodes_geojson(self.nodes.values(), &path))
+        {
+            Ok(()) => log::info!("Exported node map to {}", path.display()),
+            Err(e) => log::error!("Failed to export node map: {}", e),
+        }
+    }
 
-        let left_side = Layout::vertical([Constraint::Min(4), Constraint::Percentage(100)])
-            .split(horizontal_chunks[0]);
+    fn export_survey(&self) {
+        let Some(csv) = &self.survey_file else {
+            return;
+        };
+        let path = csv.with_extension("geojson");
+        match survey::csv_to_geojson(csv, &path) {
+            Ok(count) => log::info!("Exported {} survey samples to {}", count, path.display()),
+            Err(e) => log::error!("Failed to export survey: {}", e),
+        }
+    }
 
-        let right_side = Layout::vertical([
-            Constraint::Min(1),
-            Constraint::Percentage(10),
-            Constraint::Percentage(90),
-        ])
-        .split(horizontal_chunks[1]);
+    fn export_json(&self) {
+        let path = self.export_dir.join(format!(
+            "edda_export_{}.json",
+            Local::now().format("%Y%m%d_%H%M%S")
+        ));
+        let messages = self
+            .conversations
+            .iter()
+            .flat_map(|(num, msgs)| msgs.iter().map(|m| (*num, m)));
+        let json = export::python_json(self.my_node.as_ref(), self.get_sorted_nodes(), messages);
+        match serde_json::to_string_pretty(&json)
+            .map_err(std::io::Error::from)
+            .and_then(|s| self.export_dir().and_then(|_| std::fs::write(&path, s)))
+        {
+            Ok(()) => log::info!("Exported to {}", path.display()),
+            Err(e) => log::error!("Failed to export JSON: {}", e),
+        }
+    }
 
-        (
-            left_side[0],
-            left_side[1],
-            right_side[0],
-            right_side[1],
-            right_side[2],
-        )
+    /// Apply the change set up in the bulk admin popup to every node in the team.
+    pub(crate) fn start_bulk_admin(&mut self) {
+        if self.team.is_empty() {
+            return;
+        }
+        let value: Option<u32> = self.bulk_value.parse().ok();
+        let change = if self.bulk_channel {
+            value
+                .and_then(|index| self.channels.get(&(index as i32)))
+                .map(|channel| Change::Channel(channel.clone()))
+        } else {
+            value.map(Change::PositionInterval)
+        };
+        let Some(change) = change else {
+            self.toast = Some((
+                Instant::now(),
+                "Type the index of one of this device's channels, or an interval in seconds"
+                    .to_string(),
+                Color::Red,
+            ));
+            return;
+        };
+        log::info!("Applying {} to {} nodes", change.label(), self.team.len());
+        let mut bulk = BulkAdmin::new(change, self.team.iter().copied());
+        let first = bulk.start_next();
+        self.bulk_admin = Some(bulk);
+        self.send_bulk_admin(first);
     }
 
-    fn draw(&mut self, frame: &mut Frame) {
-        let (search_rect, node_list_rect, title_rect, input_rect, conversation_rect) =
-            Self::build_constraints(frame);
+    /// Send the next step of a bulk change. A step the radio cannot take times out like one
+    /// that goes unanswered, and the node is marked failed.
+    fn send_bulk_admin(&mut self, event: Option<UiEvent>) {
+        if let Some(event) = event {
+            self.hand_to_mesh(event, "send the bulk change");
+        }
+    }
 
-        self.draw_title(frame, title_rect);
-        self.draw_conversation(frame, conversation_rect, input_rect);
-        self.draw_node_list(frame, node_list_rect);
-        self.draw_input_box(frame, input_rect);
-        self.draw_search_box(frame, search_rect);
-        self.set_cursor_position(frame, input_rect);
+    /// Probe the current contact, unless it is already being probed.
+    pub(crate) fn start_probe(&mut self) {
+        let Some(node) = self.current_contact.filter(|node| *node != BROADCAST) else {
+            return;
+        };
+        if self
+            .probe
+            .as_ref()
+            .is_some_and(|probe| probe.node == node && probe.is_waiting())
+        {
+            self.open_screen(Box::new(ProbeView));
+            return;
+        }
+        let what = format!("probe {}", self.node_name(node));
+        if self.hand_to_mesh(UiEvent::Probe(node), &what) {
+            self.probe = Some(Probe::new(node));
+            self.open_screen(Box::new(ProbeView));
+        }
     }
 
-    fn draw_title(&self, frame: &mut Frame, rect: Rect) {
-        let title = Block::new()
-            .title_alignment(Alignment::Center)
-            .title("MESHCOM 0.0.1".bold());
-        frame.render_widget(title, rect);
+    fn export_probe(&mut self) {
+        let Some(probe) = &self.probe else {
+            return;
+        };
+        match self
+            .export_dir()
+            .and_then(|dir| probe.export(dir, |num| self.node_name(num)))
+        {
+            Ok(path) => {
+                log::info!("Exported probe report to {}", path.display());
+                self.toast = Some((
+                    Instant::now(),
+                    format!("Saved {}", path.display()),
+                    Color::Green,
+                ));
+            }
+            Err(e) => log::error!("Failed to export probe report: {}", e),
+        }
     }
 
-    fn draw_conversation(
-        &mut self,
-        frame: &mut Frame,
-        conversation_rect: Rect,
-        scrollbar_rect: Rect,
-    ) {
-        let current_num = self.current_contact;
+    fn export_traffic_log(&self) {
+        match self
+            .export_dir()
+            .and_then(|dir| self.traffic.export(dir, |num| self.node_name(num)))
+        {
+            Ok(path) => log::info!("Exported traffic log to {}", path.display()),
+            Err(e) => log::error!("Failed to export traffic log: {}", e),
+        }
+    }
 
-        let content_len = current_num
-            .and_then(|num| self.conversations.get(&num))
-            .map(|v| v.len())
-            .unwrap_or(0);
-        self.vertical_scroll_state = self.vertical_scroll_state.content_length(content_len);
+    fn push_message(&mut self, id: NodeNum, message: Message) {
+        if let Err(e) = self.store.append_message(id, &message) {
+            log::error!("Failed to store message: {}", e);
+        }
+        self.conversations.entry(id).or_default().push(message);
+        self.refresh_smart_view();
+        if self.current_contact == Some(id) {
+            let ts = self.conversations[&id]
+                .last()
+                .map_or(0, |m| m.ts.timestamp());
+            self.mark_read(id, ts);
+            self.refresh_contact_stats(id);
+        }
+    }
 
-        let title = if let Some(num) = current_num {
-            let long_name = self
-                .nodes
-                .get(&num)
-                .and_then(|n| n.user.as_ref())
-                .map(|u| u.long_name.as_str())
-                .unwrap_or("UNKNOWN");
-            format!("CONNECTED: {}", long_name)
-        } else {
-            "NO NODE CONNECTED".to_string()
+    /// Show the next smart view in the node list, `step` views on, going through all nodes.
+    pub(crate) fn cycle_smart_view(&mut self, step: isize) {
+        // Position 0 is all nodes, the views follow.
+        let count = self.smart_views.len() as isize + 1;
+        let position = self.smart_view.map_or(0, |i| i as isize + 1);
+        self.smart_view = match (position + step).rem_euclid(count) {
+            0 => None,
+            position => Some(position as usize - 1),
         };
+        self.refresh_smart_view();
+        self.node_list_state.select(Some(0));
+    }
 
-        let text: Vec<Line> = current_num
-            .and_then(|num| self.conversations.get(&num))
-            .map(|msgs| {
-                msgs.iter()
-                    .map(|x| {
-                        let mut spans = Vec::new();
-                        spans.push(Span::raw(x.1.format("%H:%M:%S").to_string()));
-                        let colour = if x.0 { Color::Yellow } else { Color::Blue };
-                        spans.push(Span::styled("> ", Style::default().fg(colour)));
-                        spans.push(Span::raw(&x.2));
-                        Line::from(spans)
-                    })
-                    .collect()
-            })
-            .unwrap_or_default();
+    /// Match the shown smart view against the messages stored so far.
+    fn refresh_smart_view(&mut self) {
+        let Some(view) = self
+            .smart_view
+            .and_then(|i| self.smart_views.get_mut(i))
+            .filter(|view| view.uses_messages())
+        else {
+            return;
+        };
+        if let Err(e) = view.refresh(self.store.as_ref()) {
+            log::error!("Failed to evaluate smart view {}: {}", view.name, e);
+        }
+    }
 
-        let paragraph = Paragraph::new(text).gray().block(
-            Block::bordered()
-                .gray()
-                .title(title.as_str().bold())
-                .border_style(if self.focus == Some(Focus::Conversation) {
-                    Style::default().fg(Color::Yellow)
-                } else {
-                    Style::default()
-                }),
-        );
-        frame.render_widget(paragraph, conversation_rect);
-        frame.render_stateful_widget(
-            Scrollbar::new(ScrollbarOrientation::VerticalRight)
-                .begin_symbol(Some("#"))
-                .end_symbol(Some("#")),
-            scrollbar_rect,
-            &mut self.vertical_scroll_state,
-        );
+    fn refresh_contact_stats(&mut self, id: NodeNum) {
+        self.contact_stats = self.store.get_conversation_stats(id).unwrap_or_else(|e| {
+            log::error!("Failed to compute conversation statistics: {}", e);
+            ConversationStats::default()
+        });
     }
 
-    fn draw_node_list(&mut self, frame: &mut Frame, rect: Rect) {
-        let nodes_list_block = Block::bordered()
-            .gray()
-            .title("NODE LIST".bold())
-            .border_style(if self.focus == Some(Focus::NodeList) {
-                Style::default().fg(Color::Yellow)
-            } else {
-                Style::default()
-            });
+    /// Record an outgoing message locally and hand it to the Meshtastic thread.
+    pub(crate) fn send_message(&mut self, id: NodeNum, message: String) {
+        self.send_reply(id, message, None, false);
+    }
 
-        let visible_nodes = self.get_visible_nodes();
-        let items: Vec<_> = visible_nodes
-            .iter()
-            .filter_map(|nodeinfo| {
-                let user = nodeinfo.user.as_ref()?;
-                let long_name = user.short_name.clone();
-                let mut line = Line::from(long_name);
-                if nodeinfo.hops_away() == 0 {
-                    line = line.patch_style(Style::default().fg(Color::Green));
-                }
-                if self.current_contact == Some(nodeinfo.num) {
-                    line = line.patch_style(
-                        Style::default()
-                            .add_modifier(Modifier::BOLD)
-                            .fg(Color::Cyan),
-                    );
-                }
-                Some(line)
-            })
+    /// Send a message as separate direct messages to everyone in the team.
+    fn send_to_team(&mut self, message: String) {
+        let team: Vec<NodeNum> = self.team.iter().copied().collect();
+        self.team_send = team
+            .into_iter()
+            .map(|id| self.send_reply(id, message.clone(), None, false))
             .collect();
+    }
 
-        let list = List::new(items)
-            .block(nodes_list_block)
-            .highlight_symbol("> ")
-            .highlight_style(Style::default().bg(Color::DarkGray));
+    /// Progress of the last message sent to the team, once it has been sent.
+    fn team_progress(&self) -> Option<String> {
+        if self.team_send.is_empty() {
+            return None;
+        }
+        let (mut waiting, mut failed) = (0, 0);
+        for id in &self.team_send {
+            match self.outbox.iter().find(|entry| entry.id == *id) {
+                Some(entry) if entry.state == OutboxState::Failed => failed += 1,
+                Some(_) => waiting += 1,
+                None => {}
+            }
+        }
+        let delivered = self.team_send.len() - waiting - failed;
+        Some(format!(
+            "{}/{} delivered, {} failed",
+            delivered,
+            self.team_send.len(),
+            failed
+        ))
+    }
 
-        frame.render_stateful_widget(list, rect, &mut self.node_list_state);
+    /// Send a message that replies to, or with `emoji` reacts to, the packet `reply_id`,
+    /// returning its outbox entry.
+    fn send_reply(
+        &mut self,
+        id: NodeNum,
+        message: String,
+        reply_id: Option<u32>,
+        emoji: bool,
+    ) -> i64 {
+        if let Some(session_log) = &self.session_log {
+            session_log.record("ME", &self.node_name(id), 0, &message);
+        }
+        if let Some(announcer) = &self.announcer {
+            announcer.sent(&self.node_name(id), &message);
+        }
+
+        let mut entry = OutboxEntry::new(id, message, reply_id, emoji);
+        let defer = entry.deferrable() && self.busy_channel.is_busy();
+        if defer {
+            log::info!("Deferring broadcast while the channel is busy");
+            entry.state = OutboxState::Deferred;
+            entry.attempts = 0;
+        }
+        // Sharing the timestamp ties the message to its outbox entry and acknowledgements.
+        self.push_message(
+            id,
+            Message {
+                outgoing: true,
+                ts: entry.queued,
+                channel: 0,
+                text: entry.text.clone(),
+                snr: None,
+                packet_id: None,
+                estimated_time: false,
+                note: None,
+                reply_id: entry.reply_id,
+            },
+        );
+
+        if !defer && !self.dispatch(&entry) {
+            entry.state = OutboxState::Failed;
+        }
+        if let Err(e) = self.store.save_outbox_entry(&entry) {
+            log::error!("Failed to store outbox entry: {}", e);
+        }
+        let outbox = entry.id;
+        self.outbox.push(entry);
+        outbox
     }
 
-    fn draw_input_box(&self, frame: &mut Frame, rect: Rect) {
-        let input_box = Paragraph::new(self.input.as_str())
-            .block(Block::bordered().title("INPUT".bold()).border_style(
-                if self.focus == Some(Focus::Input) {
-                    Style::default().fg(Color::Yellow)
-                } else {
-                    Style::default()
-                },
-            ))
-            .wrap(Wrap { trim: false });
-        frame.render_widget(input_box, rect);
+    /// Hand an outbox entry to the Meshtastic thread, returning `false` if it could not take it.
+    /// The caller marks the entry failed, so it can be retried from the outbox.
+    fn dispatch(&mut self, entry: &OutboxEntry) -> bool {
+        let node_id = NodeId::new(entry.to);
+        log::info!("Sending packet to {}", node_id);
+        self.hand_to_mesh(
+            UiEvent::Message {
+                node_id,
+                message: entry.text.clone(),
+                channel: 0,
+                reply_id: entry.reply_id,
+                emoji: entry.emoji,
+                outbox: Some(entry.id),
+            },
+            &format!("send to {node_id}"),
+        )
     }
 
-    fn draw_search_box(&self, frame: &mut Frame, rect: Rect) {
-        let search_box = Paragraph::new(self.search.as_str())
-            .block(Block::bordered().title("SEARCH".bold()).border_style(
-                if self.focus == Some(Focus::Search) {
-                    Style::default().fg(Color::Yellow)
-                } else {
-                    Style::default()
-                },
-            ))
-            .wrap(Wrap { trim: false });
-        frame.render_widget(search_box, rect);
+    /// Hand an event to the Meshtastic thread. If its queue is full or it has stopped, say that
+    /// `what` failed and return `false`.
+    fn hand_to_mesh(&mut self, event: UiEvent, what: &str) -> bool {
+        let Err(e) = self.transmitter.try_send(event) else {
+            return true;
+        };
+        log::error!("Failed to {}: {}", what, e);
+        let reason = match e {
+            TrySendError::Full(_) => "the radio is busy",
+            TrySendError::Closed(_) => "the radio connection has stopped",
+        };
+        self.toast = Some((
+            Instant::now(),
+            format!("Failed to {what}: {reason}"),
+            Color::Red,
+        ));
+        false
     }
 
-    fn set_cursor_position(&self, frame: &mut Frame, input_rect: Rect) {
-        if self.focus == Some(Focus::Input) {
-            let input_width = input_rect.width.saturating_sub(2); // Subtract 2 for borders
-            let line_count = (self.input.len() as u16 / input_width) + 1;
-            let cursor_x = input_rect.x + (self.input.len() as u16 % input_width) + 1;
-            let cursor_y = input_rect.y + line_count;
-            frame.set_cursor_position((cursor_x, cursor_y));
+    /// Record progress of an outbox entry. Entries that need nothing more leave the outbox.
+    /// Give the message of an outbox entry the packet ID it went out with, so replies to it can
+    /// be threaded under it.
+    fn number_sent_message(&mut self, outbox: i64, packet_id: u32) {
+        let Some(entry) = self.outbox.iter().find(|entry| entry.id == outbox) else {
+            return;
+        };
+        let Some(message) = self.conversations.get_mut(&entry.to).and_then(|messages| {
+            messages
+                .iter_mut()
+                .rev()
+                .find(|m| m.outgoing && m.ts == entry.queued)
+        }) else {
+            return;
+        };
+        message.packet_id = Some(packet_id);
+        if let Err(e) = self
+            .store
+            .set_message_packet_id(entry.to, message, packet_id)
+        {
+            log::error!("Failed to store message packet ID: {}", e);
         }
     }
 
-    fn draw_loading(&self, frame: &mut Frame) {
-        let area = frame.area();
-        let loading_text = "Loading...";
-        let loading_paragraph = Paragraph::new(loading_text)
-            .style(Style::default().fg(Color::White))
-            .alignment(Alignment::Center);
+    fn update_outbox(&mut self, id: i64, state: Option<OutboxState>) {
+        let Some(index) = self.outbox.iter().position(|entry| entry.id == id) else {
+            return;
+        };
+        let result = match state {
+            Some(state) => {
+                self.outbox[index].state = state;
+                self.store.save_outbox_entry(&self.outbox[index])
+            }
+            None => {
+                self.outbox.remove(index);
+                self.store.delete_outbox_entry(id)
+            }
+        };
+        if let Err(e) = result {
+            log::error!("Failed to store outbox entry: {}", e);
+        }
+    }
+
+    fn selected_outbox_entry(&self) -> Option<i64> {
+        let index = self.outbox_table_state.selected()?;
+        Some(self.outbox.get(index)?.id)
+    }
+
+    /// Send the broadcasts held back while the channel was busy, once it no longer is.
+    fn release_deferred(&mut self) {
+        if self.busy_channel.is_busy() {
+            return;
+        }
+        let deferred: Vec<i64> = self
+            .outbox
+            .iter()
+            .filter(|entry| entry.state == OutboxState::Deferred)
+            .map(|entry| entry.id)
+            .collect();
+        for id in deferred {
+            self.retry_outbox_entry(id);
+        }
+    }
+
+    /// Longest message that can be typed: what fits in a packet, or more if it may be compressed.
+    fn input_limit(&self) -> usize {
+        if self.compress_long_messages {
+            COMPRESSED_TEXT_LIMIT
+        } else {
+            PACKET_BYTE_LIMIT
+        }
+    }
+
+    /// Send the checklist or status report typed in to the current contact, returning whether
+    /// it was sent.
+    pub(crate) fn send_structured(&mut self) -> bool {
+        let Some(id) = self.current_contact else {
+            return false;
+        };
+        let problem = match Structured::parse(&self.structured_draft) {
+            Some(structured) if structured.fits() => {
+                self.structured_draft.clear();
+                self.focus.set(Focus::Conversation);
+                self.send_reply(id, structured.to_text(), None, false);
+                return true;
+            }
+            Some(_) => "Too long for one packet",
+            None => "Type Title: item, x done item  or  Title: name=value, name=value",
+        };
+        self.toast = Some((Instant::now(), problem.to_string(), Color::Red));
+        false
+    }
+
+    /// Send the image at the path typed in to the current contact, as a thumbnail, returning
+    /// whether it was sent.
+    pub(crate) fn send_thumbnail(&mut self) -> bool {
+        let (Some(to), Some(transfers)) = (self.current_contact, self.thumbnails.as_mut()) else {
+            return false;
+        };
+        let path = PathBuf::from(self.thumbnail_path.trim());
+        let thumbnail = match Thumbnail::load(&path) {
+            Ok(thumbnail) => thumbnail,
+            Err(e) => {
+                log::error!("Failed to load image {}: {}", path.display(), e);
+                self.toast = Some((
+                    Instant::now(),
+                    format!("Cannot send image: {e}"),
+                    Color::Red,
+                ));
+                return false;
+            }
+        };
+        log::info!("Sending a thumbnail of {} to {}", path.display(), to);
+        for payload in transfers.send(to, &thumbnail) {
+            // Pieces already queued are sent again if the peer asks; try again later.
+            if !self.send_private(to, payload) {
+                return false;
+            }
+        }
+        self.push_message(
+            to,
+            Message {
+                outgoing: true,
+                ts: Local::now(),
+                channel: 0,
+                text: thumbnail.to_text(),
+                snr: None,
+                packet_id: None,
+                estimated_time: false,
+                note: None,
+                reply_id: None,
+            },
+        );
+        self.thumbnail_path.clear();
+        self.focus.set(Focus::Conversation);
+        true
+    }
+
+    fn receive_private(&mut self, from: NodeNum, payload: &[u8]) {
+        match self
+            .thumbnails
+            .as_mut()
+            .and_then(|transfers| transfers.receive(from, payload))
+        {
+            Some(Received::Complete(thumbnail)) => {
+                log::info!("Received a thumbnail from {}", format_node_num(from));
+                self.push_message(
+                    from,
+                    Message {
+                        outgoing: false,
+                        ts: Local::now(),
+                        channel: 0,
+                        text: thumbnail.to_text(),
+                        snr: None,
+                        packet_id: None,
+                        estimated_time: false,
+                        note: None,
+                        reply_id: None,
+                    },
+                );
+            }
+            Some(Received::Resend(packets)) => {
+                for payload in packets {
+                    if !self.send_private(from, payload) {
+                        break;
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Ask again for the missing pieces of thumbnails that stopped arriving.
+    fn request_stalled_thumbnails(&mut self) {
+        let requests = self
+            .thumbnails
+            .as_mut()
+            .map(Transfers::stalled)
+            .unwrap_or_default();
+        for (to, payload) in requests {
+            if !self.send_private(to, payload) {
+                break;
+            }
+        }
+    }
+
+    /// Queue a thumbnail packet, returning `false` if the radio could not take it. Missing
+    /// pieces are asked for again once a transfer stalls.
+    fn send_private(&mut self, to: NodeNum, payload: Vec<u8>) -> bool {
+        self.hand_to_mesh(UiEvent::PrivateData { to, payload }, "send the thumbnail")
+    }
+
+    pub(crate) fn retry_outbox_entry(&mut self, id: i64) {
+        let Some(entry) = self.outbox.iter_mut().find(|entry| entry.id == id) else {
+            return;
+        };
+        entry.state = OutboxState::Queued;
+        entry.queued = Local::now();
+        entry.attempts += 1;
+        let entry = entry.clone();
+        if let Err(e) = self.store.save_outbox_entry(&entry) {
+            log::error!("Failed to store outbox entry: {}", e);
+        }
+        if !self.dispatch(&entry) {
+            self.update_outbox(id, Some(OutboxState::Failed));
+        }
+    }
+
+    fn node_available(&mut self, node_info: NodeInfo) {
+        let is_empty = self.nodes.is_empty();
+        self.check_user_id(&node_info);
+        self.note_contact(&node_info);
+        self.check_renumbered(&node_info);
+        if let Some(aprs) = &mut self.aprs {
+            aprs.node_info(&node_info);
+        }
+        if let Err(e) = self.store.upsert_node(&node_info) {
+            log::error!("Failed to store node {}: {}", node_info.num, e);
+        }
+        self.nodes.insert(node_info.num, node_info);
+        if is_empty {
+            self.node_list_state.select(Some(0));
+        }
+        self.write_geojson_feed();
+    }
+
+    /// Warn if a node number we know is now used by a different user ID, which can mean a node
+    /// was replaced or someone is impersonating it.
+    fn check_user_id(&mut self, node_info: &NodeInfo) {
+        let old_id = self
+            .nodes
+            .get(&node_info.num)
+            .and_then(|n| n.user.as_ref())
+            .map(|u| u.id.clone());
+        let new_id = node_info.user.as_ref().map(|u| u.id.clone());
+        if let (Some(old_id), Some(new_id)) = (old_id, new_id)
+            && !old_id.is_empty()
+            && old_id != new_id
+        {
+            log::warn!(
+                "User ID of node {} changed from {} to {}",
+                format_node_num(node_info.num),
+                old_id,
+                new_id
+            );
+            self.id_changes.insert(node_info.num, (old_id, new_id));
+        }
+    }
+
+    /// Note the identities a node's user can be recognised by, so their conversation follows
+    /// them to a new node number.
+    fn note_contact(&mut self, node_info: &NodeInfo) {
+        let Some(user) = &node_info.user else {
+            return;
+        };
+        let heard = i64::from(node_info.last_heard);
+        for id in ContactId::of(user) {
+            self.contacts
+                .entry(id.clone())
+                .or_insert_with(|| Contact::new(id))
+                .heard(node_info.num, heard);
+        }
+        if let Err(e) = self.store.record_user(node_info.num, user, heard) {
+            log::error!("Failed to store contact {}: {}", node_info.num, e);
+        }
+    }
+
+    /// The node number the person behind `num` uses now, which is `num` unless they were heard
+    /// since under another.
+    pub(crate) fn current_node(&self, num: NodeNum) -> NodeNum {
+        self.contacts
+            .values()
+            .filter(|contact| contact.nodes.iter().any(|&(node, _)| node == num))
+            .filter_map(|contact| contact.nodes.last())
+            .max_by_key(|&&(_, heard)| heard)
+            .map_or(num, |&(node, _)| node)
+    }
+
+    /// Show the conversations with nodes whose people have moved on to a new number under that
+    /// number, as happens when a device is reflashed or replaced.
+    fn fold_contacts(&mut self) {
+        let moved: Vec<(NodeNum, NodeNum)> = self
+            .conversations
+            .keys()
+            .map(|&old| (old, self.current_node(old)))
+            .filter(|(old, new)| old != new)
+            .collect();
+        for (old, new) in moved {
+            self.fold_contact(old, new);
+            if self.nodes.contains_key(&old) {
+                self.renumbered.insert(new, old);
+            }
+        }
+    }
+
+    /// Notice a node whose public key or custom user ID is that of a node we know under another
+    /// number, and show their conversation as one.
+    fn check_renumbered(&mut self, node_info: &NodeInfo) {
+        let num = node_info.num;
+        let Some(old) = node_info
+            .user
+            .iter()
+            .flat_map(ContactId::of)
+            .filter_map(|id| self.contacts.get(&id))
+            .filter(|contact| contact.node() == Some(num))
+            .flat_map(|contact| contact.nodes.iter().rev().skip(1))
+            .map(|&(node, _)| node)
+            .find(|node| self.nodes.contains_key(node))
+        else {
+            return;
+        };
+        self.fold_contact(old, num);
+        if self.renumbered.insert(num, old) != Some(old) {
+            self.report_anomaly(format!(
+                "{} looks like {} under a new number; their conversations are shown as one, \
+                 press u on it to merge their history for good",
+                format_node_num(num),
+                format_node_num(old)
+            ));
+        }
+    }
+
+    /// Show what we have from `old` under `new` in this session, leaving the store as it is.
+    fn fold_contact(&mut self, old: NodeNum, new: NodeNum) {
+        if let Some(mut history) = self.conversations.remove(&old) {
+            let messages = self.conversations.entry(new).or_default();
+            history.append(messages);
+            history.sort_by_key(|message| message.ts);
+            *messages = history;
+            self.conversation_view.invalidate();
+        }
+        if let Some(note) = self.notes.remove(&old) {
+            self.notes.entry(new).or_insert(note);
+        }
+        if let Some(read) = self.last_read.remove(&old) {
+            let last_read = self.last_read.entry(new).or_default();
+            *last_read = (*last_read).max(read);
+        }
+        if self.current_contact == Some(old) {
+            self.open_conversation(new);
+        }
+    }
+
+    /// Merge the history of the node `new` used to be into its conversation.
+    pub(crate) fn merge_renumbered(&mut self, new: NodeNum) {
+        let Some(old) = self.renumbered.remove(&new) else {
+            return;
+        };
+        if let Err(e) = self.store.merge_node(old, new) {
+            log::error!("Failed to merge node {} into {}: {}", old, new, e);
+            return;
+        }
+        log::info!(
+            "Merged the history of {} into {}",
+            format_node_num(old),
+            format_node_num(new)
+        );
+        self.fold_contact(old, new);
+        for entry in self.outbox.iter_mut().filter(|entry| entry.to == old) {
+            entry.to = new;
+        }
+        match self.store.get_notify_overrides() {
+            Ok(overrides) => self.notify_overrides = overrides,
+            Err(e) => log::error!("Failed to load notification overrides: {}", e),
+        }
+        self.nodes.remove(&old);
+        self.id_changes.remove(&old);
+        self.conversation_view.invalidate();
+        self.refresh_contact_stats(new);
+    }
+
+    fn mark_read(&mut self, id: NodeNum, time: i64) {
+        if !self.read_only
+            && let Err(e) = self.store.mark_read(id, time)
+        {
+            log::error!("Failed to store read state: {}", e);
+        }
+        let last_read = self.last_read.entry(id).or_default();
+        let previous = *last_read;
+        *last_read = (*last_read).max(time);
+        if !self.offline {
+            self.send_read_receipts(id, previous, time);
+        }
+    }
+
+    /// React to the direct messages received this session that were just read, between
+    /// `previous` and `time`, if read receipts are on.
+    fn send_read_receipts(&mut self, id: NodeNum, previous: i64, time: i64) {
+        let Some(reaction) = self.read_receipt.clone() else {
+            return;
+        };
+        let read: Vec<u32> = self
+            .conversations
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .filter(|m| !m.outgoing && m.ts.timestamp() > previous && m.ts.timestamp() <= time)
+            .filter_map(|m| m.packet_id)
+            .filter(|packet_id| {
+                self.raw_packets
+                    .get(packet_id)
+                    .is_some_and(wants_read_receipt)
+            })
+            .collect();
+        for packet_id in read {
+            self.send_reply(id, reaction.clone(), Some(packet_id), true);
+        }
+    }
+
+    fn unread_count(&self, id: NodeNum) -> usize {
+        let last_read = self.last_read.get(&id).copied().unwrap_or(0);
+        self.conversations.get(&id).map_or(0, |messages| {
+            messages
+                .iter()
+                .filter(|m| !m.outgoing && m.ts.timestamp() > last_read)
+                .count()
+        })
+    }
+
+    /// Pick up conversations read by other instances sharing the store.
+    fn sync_read_state(&mut self) {
+        if self.last_read_sync.elapsed() < READ_SYNC_INTERVAL {
+            return;
+        }
+        self.last_read_sync = Instant::now();
+        match self.store.get_read_state() {
+            Ok(read_state) => {
+                for (id, time) in read_state {
+                    let last_read = self.last_read.entry(id).or_default();
+                    *last_read = (*last_read).max(time);
+                }
+            }
+            Err(e) => log::error!("Failed to load read state from store: {}", e),
+        }
+    }
+
+    /// Without a radio, pick up the nodes, messages and battery levels another process wrote to
+    /// the store.
+    fn reload_offline(&mut self) {
+        if !self.offline || self.last_offline_reload.elapsed() < OFFLINE_RELOAD_INTERVAL {
+            return;
+        }
+        self.last_offline_reload = Instant::now();
+        match self.store.get_nodes() {
+            Ok(nodes) => self.nodes = nodes.into_iter().map(|n| (n.num, n)).collect(),
+            Err(e) => log::error!("Failed to load nodes from store: {}", e),
+        }
+        match self.store.get_messages() {
+            Ok(messages) => {
+                let mut conversations: HashMap<NodeNum, Vec<Message>> = HashMap::new();
+                for (num, message) in messages {
+                    conversations.entry(num).or_default().push(message);
+                }
+                self.conversations = conversations;
+                self.conversation_view.invalidate();
+            }
+            Err(e) => log::error!("Failed to load messages from store: {}", e),
+        }
+        match self
+            .store
+            .get_battery(Local::now().timestamp() - battery::TREND_WINDOW)
+        {
+            Ok(levels) => self.battery = levels.into_iter().collect(),
+            Err(e) => log::error!("Failed to load battery levels from store: {}", e),
+        }
+    }
+
+    /// Apply changes to the configuration file: the UI settings, watchdog thresholds, PSK
+    /// rotation reminders and broadcast deferral. Everything else takes a restart.
+    fn reload_config(&mut self) {
+        if self.last_config_check.elapsed() < CONFIG_CHECK_INTERVAL {
+            return;
+        }
+        self.last_config_check = Instant::now();
+        let modified = Config::modified(&self.config_file);
+        if modified == self.config_modified {
+            return;
+        }
+        self.config_modified = modified;
+        match Config::load_from(&self.config_file) {
+            Ok(config) => {
+                log::info!("Reloaded configuration");
+                self.redraw_interval = redraw_interval(&config.ui);
+                self.tick_rate = Duration::from_millis(config.ui.tick_rate_ms.max(1));
+                self.palette = Palette::new(&config.ui.colors);
+                self.keymap = Keymap::new(&config.ui.keys);
+                self.plain = config.ui.plain;
+                self.ascii = glyphs::ascii_only(config.ui.ascii);
+                self.node_list_width = config.ui.node_list_width.clamp(10, 90);
+                self.channel_badges = ChannelBadges::new(&config.ui.channels);
+                self.read_receipt = read_receipt(&config.ui);
+                self.threaded = config.ui.threaded;
+                self.smart_views = smart_views(&config.ui, self.store.as_ref());
+                self.smart_view = self.smart_view.filter(|&i| i < self.smart_views.len());
+                self.node_columns = config.ui.node_columns;
+                self.conversation_view.invalidate();
+                self.watchdog.set_config(config.watchdog);
+                self.psk_rotation_days = config.psk_rotation_days;
+                self.compress_long_messages = config.compress_long_messages;
+                self.busy_channel.threshold = config.defer_broadcasts_above;
+                self.toast = Some((
+                    Instant::now(),
+                    "Configuration reloaded".to_string(),
+                    Color::Green,
+                ));
+            }
+            Err(e) => {
+                log::error!("Failed to reload configuration: {:?}", e);
+                // Parse errors go on to quote the offending lines, which the title has no room for.
+                let cause = e.root_cause().to_string();
+                let cause = cause.lines().next().unwrap_or_default();
+                self.toast = Some((Instant::now(), format!("{}: {}", e, cause), Color::Red));
+            }
+        }
+    }
+
+    /// Keep the unsent input of the current conversation in the store.
+    fn save_draft(&mut self) {
+        if !self.read_only
+            && let Some(id) = self.current_contact
+            && let Err(e) = self.store.set_draft(id, &self.input)
+        {
+            log::error!("Failed to store draft: {}", e);
+        }
+    }
+
+    /// Switch conversations, carrying drafts and read state over.
+    pub(crate) fn open_conversation(&mut self, id: NodeNum) {
+        self.save_draft();
+        if self.current_contact != Some(id) {
+            self.conversation_scroll = 0;
+            self.selected_message = None;
+            self.reply_to = None;
+        }
+        self.current_contact = Some(id);
+        self.input = match self.store.get_draft(id) {
+            Ok(draft) => draft.unwrap_or_default(),
+            Err(e) => {
+                log::error!("Failed to load draft: {}", e);
+                String::new()
+            }
+        };
+        let newest = self
+            .conversations
+            .get(&id)
+            .and_then(|messages| messages.last())
+            .map(|m| m.ts.timestamp());
+        if let Some(newest) = newest {
+            self.mark_read(id, newest);
+        }
+        self.refresh_contact_stats(id);
+    }
+
+    /// Move the message selection in the open conversation by `step`.
+    fn select_message(&mut self, step: isize) {
+        let count = self
+            .current_contact
+            .and_then(|id| self.conversations.get(&id))
+            .map_or(0, Vec::len);
+        // Threaded, in the order shown as of the last frame.
+        let order = self.conversation_view.order();
+        if self.threaded && !order.is_empty() {
+            let position = self
+                .selected_message
+                .and_then(|index| order.iter().position(|&shown| shown == index));
+            self.selected_message = Some(match position {
+                Some(position) => order[position.saturating_add_signed(step).min(order.len() - 1)],
+                None => order[0],
+            });
+            return;
+        }
+        self.selected_message = match (self.selected_message, count) {
+            (_, 0) => None,
+            (None, _) => Some(0),
+            (Some(index), _) => Some(index.saturating_add_signed(step).min(count - 1)),
+        };
+    }
+
+    /// Switch between the threaded and the chronological display.
+    fn toggle_threaded(&mut self) {
+        self.threaded = !self.threaded;
+        self.conversation_view.invalidate();
+    }
+
+    /// Hide the replies under the selected message, or show them again.
+    fn toggle_collapsed(&mut self) {
+        let packet_id = self
+            .current_contact
+            .and_then(|id| self.conversations.get(&id))
+            .zip(self.selected_message)
+            .and_then(|(messages, index)| messages.get(index))
+            .and_then(|message| message.packet_id);
+        if let Some(packet_id) = packet_id
+            && self.threaded
+        {
+            if !self.collapsed.remove(&packet_id) {
+                self.collapsed.insert(packet_id);
+            }
+            self.conversation_view.invalidate();
+        }
+    }
+
+    pub(crate) fn run_message_action(&mut self, action: MessageAction) {
+        let (Some(id), Some(index)) = (self.current_contact, self.selected_message) else {
+            return;
+        };
+        let Some(message) = self.conversations.get(&id).and_then(|m| m.get(index)) else {
+            return;
+        };
+        let message = message.clone();
+        self.focus.set(Focus::Conversation);
+        match action {
+            MessageAction::Reply => {
+                self.reply_to = message.packet_id;
+                self.focus.set(Focus::Input);
+            }
+            MessageAction::React => match message.packet_id {
+                Some(packet_id) => {
+                    self.send_reply(
+                        id,
+                        message_actions::REACTION.to_string(),
+                        Some(packet_id),
+                        true,
+                    );
+                }
+                None => log::warn!("Cannot react to a message without a known packet ID"),
+            },
+            MessageAction::Copy => {
+                if let Err(e) = message_actions::copy_to_clipboard(&message.text) {
+                    log::error!("Failed to copy message: {}", e);
+                }
+            }
+            MessageAction::Details => self.message_details = !self.message_details,
+            MessageAction::Note => {
+                self.message_note = message.note.unwrap_or_default();
+                self.open_screen(Box::new(MessageNoteView));
+            }
+            MessageAction::Resend => self.send_message(id, message.text),
+            MessageAction::Delete => {
+                if let Err(e) = self.store.delete_message(id, &message) {
+                    log::error!("Failed to delete message: {}", e);
+                }
+                self.done_undoably(Undo::Message {
+                    node: id,
+                    message: message.clone(),
+                });
+                if let Some(messages) = self.conversations.get_mut(&id) {
+                    messages.remove(index);
+                    self.selected_message = index.checked_sub(1).or(Some(0));
+                    if messages.is_empty() {
+                        self.selected_message = None;
+                    }
+                }
+                self.conversation_view.invalidate();
+                self.refresh_contact_stats(id);
+            }
+        }
+    }
+
+    /// Save the connected device's current identity under the typed name.
+    fn save_profile(&mut self) {
+        let Some(user) = self.my_node.as_ref().and_then(|n| n.user.as_ref()) else {
+            log::warn!("Cannot save a profile before the device has reported its owner");
+            return;
+        };
+        let name = std::mem::take(&mut self.profile_name);
+        let profile = Profile::from_owner(name, user, self.device_config.as_ref());
+        if let Err(e) = self.store.save_profile(&profile) {
+            log::error!("Failed to store profile: {}", e);
+        }
+        self.profiles.retain(|p| p.name != profile.name);
+        self.profiles.push(profile);
+        self.profiles.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    /// Apply the selected profile, returning whether the radio took it.
+    fn apply_profile(&mut self) -> bool {
+        let Some(profile) = self
+            .profile_list_state
+            .selected()
+            .and_then(|i| self.profiles.get(i))
+        else {
+            return false;
+        };
+        log::info!("Applying profile {}", profile.name);
+        let (event, what) = (
+            UiEvent::ApplyProfile(profile.clone()),
+            format!("apply profile {}", profile.name),
+        );
+        self.hand_to_mesh(event, &what)
+    }
+
+    fn delete_profile(&mut self) {
+        let Some(index) = self.profile_list_state.selected() else {
+            return;
+        };
+        if index < self.profiles.len() {
+            let profile = self.profiles.remove(index);
+            if let Err(e) = self.store.delete_profile(&profile.name) {
+                log::error!("Failed to delete profile: {}", e);
+            }
+            self.done_undoably(Undo::Profile(profile));
+        }
+    }
+
+    /// Keep what an action removed for undoing, and say how to.
+    fn done_undoably(&mut self, undo: Undo) {
+        self.toast = Some((
+            Instant::now(),
+            format!("{}. Undo (U)", undo.done()),
+            Color::Yellow,
+        ));
+        self.undo.push(undo);
+    }
+
+    /// Put back what the last action that lost something removed.
+    pub(crate) fn undo(&mut self) {
+        let Some(undo) = self.undo.pop() else {
+            self.toast = Some((Instant::now(), "Nothing to undo".to_string(), Color::Yellow));
+            return;
+        };
+        let what = undo.describe();
+        let stored = match &undo {
+            Undo::Message { node, message } => {
+                self.store.append_message(*node, message).map(|_| ())
+            }
+            Undo::Note { node, note } => self.store.set_note(*node, note),
+            Undo::Profile(profile) => self.store.save_profile(profile),
+        };
+        if let Err(e) = stored {
+            log::error!("Failed to restore the {}: {}", what, e);
+            self.toast = Some((
+                Instant::now(),
+                format!("Failed to restore the {what}"),
+                Color::Red,
+            ));
+            self.undo.push(undo);
+            return;
+        }
+        match undo {
+            Undo::Message { node, message } => {
+                let messages = self.conversations.entry(node).or_default();
+                let index = messages.partition_point(|m| m.ts <= message.ts);
+                messages.insert(index, message);
+                self.conversation_view.invalidate();
+                self.refresh_contact_stats(node);
+            }
+            Undo::Note { node, note } => {
+                self.notes.insert(node, note);
+            }
+            Undo::Profile(profile) => {
+                self.profiles.push(profile);
+                self.profiles.sort_by(|a, b| a.name.cmp(&b.name));
+            }
+        }
+        self.toast = Some((Instant::now(), format!("Restored the {what}"), Color::Green));
+    }
+
+    /// Start rebooting a node, or go ahead once the operator pressed the key a second time.
+    pub(crate) fn reboot_node(&mut self, node: NodeNum) {
+        let Some(reboot) = self.remote_reboot.as_mut().filter(|r| r.node == node) else {
+            self.remote_reboot = Some(RemoteReboot::new(node));
+            return;
+        };
+        if reboot.confirm() {
+            let what = format!("reboot {}", self.node_name(node));
+            if !self.hand_to_mesh(UiEvent::RequestAdminSession(node), &what) {
+                self.reboot_not_sent();
+            }
+        } else if reboot.is_finished() {
+            self.remote_reboot = Some(RemoteReboot::new(node));
+        }
+    }
+
+    /// End a remote reboot whose next request the radio could not take.
+    fn reboot_not_sent(&mut self) {
+        if let Some(reboot) = &mut self.remote_reboot {
+            reboot.not_sent();
+        }
+        self.remote_reboot_finished();
+    }
+
+    /// Report how a remote reboot ended, like other alerts.
+    fn remote_reboot_finished(&mut self) {
+        if let Some(reboot) = &self.remote_reboot {
+            let alert = format!(
+                "Reboot of {}: {}",
+                self.node_name(reboot.node),
+                reboot.status()
+            );
+            self.watchdog_alert(alert);
+        }
+    }
+
+    /// Apply the LoRa settings edited, returning whether the radio took them.
+    fn apply_lora(&mut self) -> bool {
+        let Some(current) = &self.lora_config else {
+            return false;
+        };
+        if let Some(error) = self.lora.error() {
+            log::warn!("Not applying LoRa config: {}", error);
+            return false;
+        }
+        let event = UiEvent::SetLoraConfig(self.lora.apply(current));
+        self.hand_to_mesh(event, "apply the LoRa config")
+    }
+
+    /// Set the fixed position typed in, returning whether the radio took it.
+    fn apply_fixed_position(&mut self) -> bool {
+        let Some(position) = self.fixed_position.position() else {
+            log::warn!("Not setting fixed position: invalid coordinates");
+            return false;
+        };
+        self.hand_to_mesh(
+            UiEvent::SetFixedPosition(position),
+            "set the fixed position",
+        )
+    }
+
+    fn record_psk_rotation(&mut self, index: i32) {
+        let now = Local::now().timestamp();
+        if let Err(e) = self.store.record_psk_rotation(index, now) {
+            log::error!("Failed to store PSK rotation: {}", e);
+        }
+        self.psk_rotations.insert(index, now);
+    }
+
+    fn rotation_due(&self, channel: &Channel) -> bool {
+        let (Some(days), Some(rotated)) = (
+            self.psk_rotation_days,
+            self.psk_rotations.get(&channel.index),
+        ) else {
+            return false;
+        };
+        channels::has_private_psk(channel)
+            && Local::now().timestamp() - rotated > i64::from(days) * 24 * 60 * 60
+    }
+
+    /// Conversations the quick switcher offers for its query, best match first. A channel jumps
+    /// to the node that last wrote on it, as conversations are kept per node.
+    fn switcher_matches(&self) -> Vec<(String, NodeNum)> {
+        let mut candidates: Vec<(String, NodeNum)> = self
+            .get_sorted_nodes()
+            .into_iter()
+            .filter_map(|node| {
+                let user = node.user.as_ref()?;
+                Some((
+                    format!("{} ({})", user.long_name, user.short_name),
+                    node.num,
+                ))
+            })
+            .collect();
+        for channel in self.enabled_channels() {
+            let latest = self
+                .conversations
+                .iter()
+                .filter_map(|(&id, messages)| {
+                    let message = messages
+                        .iter()
+                        .rev()
+                        .find(|m| !m.outgoing && m.channel == channel.index as u32)?;
+                    Some((message.ts, id))
+                })
+                .max();
+            if let Some((_, id)) = latest {
+                candidates.push((
+                    format!(
+                        "#{} (last: {})",
+                        channels::channel_name(channel),
+                        self.node_name(id)
+                    ),
+                    id,
+                ));
+            }
+        }
+        switcher::rank(&self.switcher, candidates)
+    }
+
+    /// Search again for what is typed into the global search.
+    pub(crate) fn refresh_search_results(&mut self) {
+        let nodes = self.nodes.values().map(|node| {
+            let mut description = format_node_num(node.num);
+            if let Some(user) = &node.user {
+                description.push_str(&format!(" {} {}", user.long_name, user.short_name));
+            }
+            if let Some(note) = self.notes.get(&node.num) {
+                description.push_str(&format!(" {note}"));
+            }
+            (node.num, description)
+        });
+        self.search_results = search::search(&self.global_search, nodes, self.store.as_ref())
+            .unwrap_or_else(|e| {
+                log::error!("Failed to search: {}", e);
+                Vec::new()
+            });
+        self.search_results_state
+            .select((!self.search_results.is_empty()).then_some(0));
+    }
+
+    /// Jump to the view showing the selected search result: the node in the node list, the
+    /// message in its conversation, or the conversation with the node that shared a waypoint.
+    /// Returns whether a result was selected.
+    pub(crate) fn open_search_result(&mut self) -> bool {
+        let Some(result) = self
+            .search_results_state
+            .selected()
+            .and_then(|i| self.search_results.get(i))
+        else {
+            return false;
+        };
+        match result {
+            SearchResult::Node(num) => {
+                let num = *num;
+                // The node list filter could be hiding it.
+                self.search.clear();
+                let index = self.get_visible_nodes().iter().position(|n| n.num == num);
+                self.node_list_state.select(index);
+                self.open_conversation(num);
+                self.focus.set(Focus::NodeList);
+            }
+            SearchResult::Message { node, message } => {
+                let (node, message) = (*node, message.clone());
+                self.open_conversation(node);
+                self.selected_message = self.conversations.get(&node).and_then(|messages| {
+                    messages
+                        .iter()
+                        // The store keeps whole seconds.
+                        .position(|m| {
+                            m.ts.timestamp() == message.ts.timestamp() && m.text == message.text
+                        })
+                });
+                self.focus.set(Focus::Conversation);
+            }
+            SearchResult::Waypoint { from, waypoint } => {
+                let from = *from;
+                let text = match (waypoint.latitude_i, waypoint.longitude_i) {
+                    (Some(latitude), Some(longitude)) => format!(
+                        "Waypoint {} at {:.5}, {:.5}",
+                        waypoint.name,
+                        f64::from(latitude) * export::DEGREES_PER_UNIT,
+                        f64::from(longitude) * export::DEGREES_PER_UNIT
+                    ),
+                    _ => format!("Waypoint {}", waypoint.name),
+                };
+                self.toast = Some((Instant::now(), text, Color::Green));
+                self.open_conversation(from);
+                self.focus.set(Focus::Input);
+            }
+        }
+        true
+    }
+
+    fn enabled_channels(&self) -> Vec<&Channel> {
+        self.channels
+            .values()
+            .filter(|c| channels::is_enabled(c))
+            .collect()
+    }
+
+    fn export_channel_url(&self) {
+        let url = channels::channel_url(self.channels.values(), self.lora_config.as_ref());
+        match self
+            .export_dir()
+            .and_then(|dir| channels::export_url(&url, self.ascii, dir))
+        {
+            Ok(path) => log::info!("Exported channel URL and QR code to {}", path.display()),
+            Err(e) => log::error!("Failed to export channel URL: {}", e),
+        }
+    }
+
+    /// Rotate the selected channel's PSK, asking for a second ENTER first.
+    fn rotate_selected_psk(&mut self) {
+        let Some(channel) = self
+            .channel_list_state
+            .selected()
+            .and_then(|i| self.enabled_channels().get(i).copied().cloned())
+        else {
+            return;
+        };
+        if self.pending_rotation != Some(channel.index) {
+            self.pending_rotation = Some(channel.index);
+            return;
+        }
+        self.pending_rotation = None;
+
+        let channel = channels::with_new_psk(&channel);
+        log::info!("Rotating PSK of {}", channels::channel_name(&channel));
+        // Without the device taking it, the old key stays and nothing was rotated.
+        if !self.hand_to_mesh(UiEvent::SetChannel(channel.clone()), "rotate the PSK") {
+            return;
+        }
+        self.record_psk_rotation(channel.index);
+        self.channels.insert(channel.index, channel);
+        // Everyone else needs the new key, so have it ready to share.
+        self.export_channel_url();
+    }
+
+    /// This machine's admin keypair, generating and storing one on first use.
+    fn own_admin_keypair(&mut self) -> AdminKeypair {
+        if let Some(keypair) = &self.admin_keypair {
+            return keypair.clone();
+        }
+        let keypair = AdminKeypair::generate();
+        if let Err(e) = self.store.save_admin_keypair(&keypair) {
+            log::error!("Failed to store admin keypair: {}", e);
+        }
+        log::info!(
+            "Generated admin keypair with public key {}",
+            admin_keys::format_key(&keypair.public)
+        );
+        self.admin_keypair = Some(keypair.clone());
+        keypair
+    }
+
+    fn add_admin_key(&mut self, key: Vec<u8>) {
+        let Some(current) = &self.security_config else {
+            return;
+        };
+        match admin_keys::with_admin_key(current, key) {
+            Some(security_config) => self.set_security_config(security_config),
+            None => log::warn!(
+                "Device already has {} admin keys, remove one first",
+                admin_keys::MAX_ADMIN_KEYS
+            ),
+        }
+    }
+
+    fn remove_selected_admin_key(&mut self) {
+        if let (Some(current), Some(index)) =
+            (&self.security_config, self.admin_key_list_state.selected())
+        {
+            let security_config = admin_keys::without_admin_key(current, index);
+            self.set_security_config(security_config);
+        }
+    }
+
+    /// Send new admin keys to the device, keeping the old ones shown if it cannot take them.
+    fn set_security_config(&mut self, security_config: SecurityConfig) {
+        let event = UiEvent::SetSecurityConfig(security_config.clone());
+        if self.hand_to_mesh(event, "change the admin keys") {
+            self.security_config = Some(security_config);
+        }
+    }
+
+    /// The channel the contact last wrote to us on.
+    fn contact_channel(&self, id: NodeNum) -> Option<u32> {
+        self.conversations
+            .get(&id)?
+            .iter()
+            .rev()
+            .find(|m| !m.outgoing)
+            .map(|m| m.channel)
+    }
+
+    fn cycle_node_notify(&mut self, id: NodeNum) {
+        let mode = self.notify_overrides.node(id).next();
+        if let Err(e) = self.store.set_node_notify(id, mode) {
+            log::error!("Failed to store notification override: {}", e);
+        }
+        match mode {
+            NotifyMode::Default => self.notify_overrides.nodes.remove(&id),
+            mode => self.notify_overrides.nodes.insert(id, mode),
+        };
+    }
+
+    fn cycle_channel_notify(&mut self, channel: u32) {
+        let mode = self.notify_overrides.channel(channel).next();
+        if let Err(e) = self.store.set_channel_notify(channel, mode) {
+            log::error!("Failed to store notification override: {}", e);
+        }
+        match mode {
+            NotifyMode::Default => self.notify_overrides.channels.remove(&channel),
+            mode => self.notify_overrides.channels.insert(channel, mode),
+        };
+    }
+
+    fn refresh_leaderboard(&mut self) {
+        let since = Local::now().timestamp() - self.leaderboard_window.seconds();
+        self.leaderboard = self.store.get_activity(since).unwrap_or_else(|e| {
+            log::error!("Failed to load activity from store: {}", e);
+            Vec::new()
+        });
+        stats::rank(&mut self.leaderboard, self.leaderboard_ranking);
+    }
+
+    fn refresh_flows(&mut self) {
+        let since = Local::now().timestamp() - self.flow_window.seconds();
+        self.flows = self.store.get_flows(since).unwrap_or_else(|e| {
+            log::error!("Failed to load packet flows from store: {}", e);
+            Vec::new()
+        });
+    }
+
+    fn report_anomaly(&mut self, anomaly: String) {
+        log::warn!("{}", anomaly);
+        if self.anomalies.len() >= health::MAX_ANOMALIES {
+            self.anomalies.remove(0);
+        }
+        self.anomalies.push((Local::now(), anomaly));
+    }
+
+    fn watchdog_alert(&mut self, alert: String) {
+        if let Some(push) = &self.push {
+            push.alert(&alert);
+        }
+        if let Some(announcer) = &self.announcer {
+            announcer.alert(&alert);
+        }
+        self.report_anomaly(alert);
+    }
+
+    pub(crate) fn health(&self) -> Health {
+        Health::compute(
+            self.nodes.values(),
+            Local::now().timestamp(),
+            &self.deliveries,
+            self.my_node
+                .as_ref()
+                .and_then(|n| n.device_metrics)
+                .and_then(|m| m.channel_utilization),
+        )
+    }
+
+    fn save_note(&mut self, id: NodeNum) {
+        let note = std::mem::take(&mut self.note);
+        if let Err(e) = self.store.set_note(id, &note) {
+            log::error!("Failed to store note: {}", e);
+        }
+        let previous = if note.is_empty() {
+            self.notes.remove(&id)
+        } else {
+            self.notes.insert(id, note.clone())
+        };
+        if let Some(previous) = previous.filter(|previous| *previous != note) {
+            self.done_undoably(Undo::Note {
+                node: id,
+                note: previous,
+            });
+        }
+    }
+
+    /// Store the typed note on the selected message.
+    fn save_message_note(&mut self) {
+        let note = std::mem::take(&mut self.message_note);
+        let (Some(id), Some(index)) = (self.current_contact, self.selected_message) else {
+            return;
+        };
+        let Some(message) = self
+            .conversations
+            .get_mut(&id)
+            .and_then(|messages| messages.get_mut(index))
+        else {
+            return;
+        };
+        if let Err(e) = self.store.set_message_note(id, message, &note) {
+            log::error!("Failed to store message note: {}", e);
+        }
+        message.note = (!note.is_empty()).then_some(note);
+        self.conversation_view.invalidate();
+    }
+
+    fn get_sorted_nodes(&self) -> Vec<&NodeInfo> {
+        let mut nodes: Vec<_> = self.nodes.values().collect();
+        nodes.sort_by_key(|n| n.num);
+        nodes
+    }
+
+    pub(crate) fn get_visible_nodes(&self) -> Vec<&NodeInfo> {
+        let sorted = self.get_sorted_nodes();
+        let now = Local::now().timestamp();
+        let view = self.smart_view.and_then(|i| self.smart_views.get(i));
+        sorted
+            .into_iter()
+            .filter(|n| view.is_none_or(|view| view.matches(n, now)))
+            .filter(|n| {
+                let Some(user) = n.user.as_ref() else {
+                    return false;
+                };
+                if self.search.is_empty() {
+                    return true;
+                }
+                user.long_name
+                    .to_lowercase()
+                    .contains(&self.search.to_lowercase())
+            })
+            .collect()
+    }
+
+    /// Answer a call made through the control socket.
+    #[cfg(unix)]
+    fn answer_control(&mut self, request: control::Request) {
+        let answer = match &request.call {
+            Call::SendText { to, text } => {
+                let outbox = self.send_reply(*to, text.clone(), None, false);
+                serde_json::json!({ "outbox": outbox })
+            }
+            Call::ListNodes => control::nodes_json(self.nodes.values()),
+            Call::GetConversation(node) => {
+                control::conversation_json(self.conversations.get(node).map_or(&[], Vec::as_slice))
+            }
+        };
+        request.reply(answer);
+    }
+
+    /// Handle the next event from the Meshtastic thread, returning `false` if there was none.
+    pub(crate) fn update(&mut self) -> bool {
+        #[cfg(all(feature = "dbus", target_os = "linux"))]
+        if let Ok((to, text)) = self.desktop_requests.try_recv() {
+            self.send_message(to, text);
+        }
+        #[cfg(unix)]
+        if let Some(request) = self.control.as_mut().and_then(ControlSocket::try_recv) {
+            self.answer_control(request);
+        }
+
+        let event = self.receiver.try_recv();
+        let handled = event.is_ok();
+        #[cfg(unix)]
+        if let (Some(control), Ok(event)) = (&self.control, &event) {
+            control.event(event);
+        }
+        if handled && let Some(alert) = self.watchdog.heard() {
+            self.watchdog_alert(alert);
+        }
+        if let Ok(event) = event {
+            self.mesh_event(event);
+        }
+        handled
+    }
+
+    fn mesh_event(&mut self, event: MeshEvent) {
+        match event {
+            MeshEvent::MyNode(node_info) => {
+                if let Some(aprs) = &mut self.aprs {
+                    aprs.node_info(&node_info);
+                }
+                self.my_node = Some(*node_info);
+            }
+            MeshEvent::DeviceConfig(device_config) => self.device_config = Some(device_config),
+            MeshEvent::LoraConfig(lora_config) => self.lora_config = Some(lora_config),
+            MeshEvent::SecurityConfig(security_config) => {
+                self.security_config = Some(security_config);
+            }
+            MeshEvent::Channel(channel) => self.channel_heard(channel),
+            MeshEvent::NodeAvailable(node_info) => self.node_available(*node_info),
+            MeshEvent::User { from, user } => self.user_heard(from, user),
+            MeshEvent::Position { node_id, position } => self.position_heard(node_id, position),
+            MeshEvent::Waypoint { from, waypoint } => {
+                if let Err(e) = self.store.save_waypoint(from, &waypoint) {
+                    log::error!("Failed to store waypoint from {}: {}", from, e);
+                }
+            }
+            MeshEvent::PacketHeard {
+                from,
+                to,
+                port,
+                hops,
+                airtime_ms,
+                channel,
+            } => self.packet_heard(from, to, port, hops, airtime_ms, channel),
+            MeshEvent::Sent { outbox, packet_id } => self.message_sent(outbox, packet_id),
+            MeshEvent::AdminSession { from, passkey } => self.admin_session(from, passkey),
+            MeshEvent::Relayed { outbox } => {
+                self.acks.entry(outbox).or_default().relayed = true;
+                self.conversation_view.invalidate();
+                self.update_outbox(outbox, Some(OutboxState::Relayed));
+            }
+            MeshEvent::Delivery {
+                to,
+                delivered,
+                outbox,
+                relay_node,
+                hops,
+            } => self.delivery(to, delivered, outbox, relay_node, hops),
+            MeshEvent::Rebooted => {
+                let alert = self.watchdog.rebooted();
+                self.watchdog_alert(alert);
+            }
+            MeshEvent::DeviceLog(record) => self.device_log.push(record),
+            MeshEvent::Monitored(packet) => self.monitor.push(*packet),
+            MeshEvent::RemoteConfig { from, config } => {
+                let next = self
+                    .bulk_admin
+                    .as_mut()
+                    .and_then(|bulk| bulk.config(from, config));
+                self.send_bulk_admin(next);
+            }
+            MeshEvent::Metadata { from, metadata } => {
+                if let Some(probe) = self.probe.as_mut().filter(|p| p.node == from) {
+                    probe.metadata = Some(metadata);
+                }
+            }
+            MeshEvent::Traceroute { from, route } => {
+                if let Some(probe) = self.probe.as_mut().filter(|p| p.node == from) {
+                    probe.route = Some(route);
+                }
+            }
+            MeshEvent::PrivateData { from, payload } => self.receive_private(from, &payload),
+            MeshEvent::Radio { name, transport } => self.radio_changed(name, transport),
+            MeshEvent::ConnectionStatus(status) => {
+                if status == ConnectionStatus::Connected {
+                    self.connection_error = None;
+                }
+                self.connection_status = Some(status);
+            }
+            MeshEvent::Error(error) => {
+                let report = format!("{}. {}", error, error.guidance());
+                if error.is_fatal() {
+                    self.watchdog_alert(report);
+                    self.connection_error = Some(error);
+                } else {
+                    self.report_anomaly(report);
+                }
+            }
+            MeshEvent::DeviceMetrics { from, metrics } => self.metrics_heard(from, metrics),
+            MeshEvent::Message {
+                node_id,
+                to,
+                channel,
+                message,
+                snr,
+                id,
+                packet,
+            } => {
+                let (ts, estimated_time) = device_time::received_at(packet.rx_time);
+                let message = Message {
+                    outgoing: false,
+                    ts,
+                    channel,
+                    text: message,
+                    snr: Some(snr),
+                    packet_id: Some(id),
+                    estimated_time,
+                    note: None,
+                    reply_id: message_actions::reply_id(&packet),
+                };
+                self.raw_packets.insert(id, *packet);
+                self.message_received(node_id.id(), to.id(), message);
+            }
+        }
+    }
+
+    fn channel_heard(&mut self, channel: Channel) {
+        // Start the rotation clock for keys we have never seen changed.
+        if channels::has_private_psk(&channel) && !self.psk_rotations.contains_key(&channel.index) {
+            self.record_psk_rotation(channel.index);
+        }
+        if self.rotation_due(&channel) {
+            log::warn!(
+                "PSK of {} is due for rotation",
+                channels::channel_name(&channel)
+            );
+        }
+        self.channels.insert(channel.index, channel);
+    }
+
+    fn user_heard(&mut self, from: NodeNum, user: User) {
+        let mut node_info = self.nodes.get(&from).cloned().unwrap_or(NodeInfo {
+            num: from,
+            ..NodeInfo::default()
+        });
+        node_info.user = Some(user);
+        node_info.last_heard = Local::now().timestamp() as u32;
+        self.node_available(node_info);
+    }
+
+    fn position_heard(&mut self, node_id: NodeId, position: Position) {
+        if let Some(probe) = self.probe.as_mut().filter(|p| p.node == node_id.id()) {
+            probe.position = Some(position);
+        }
+        if let Some(aprs) = &mut self.aprs {
+            aprs.position(node_id.id(), &position);
+        }
+        if let Err(e) = self.store.record_position(node_id.id(), &position) {
+            log::error!("Failed to store position of {}: {}", node_id, e);
+        }
+        if let Some(node) = self.nodes.get_mut(&node_id.id()) {
+            node.position = Some(position);
+            if let Err(e) = self.store.upsert_node(node) {
+                log::error!("Failed to store node {}: {}", node.num, e);
+            }
+            self.write_geojson_feed();
+        }
+    }
+
+    fn packet_heard(
+        &mut self,
+        from: NodeNum,
+        to: NodeNum,
+        port: i32,
+        hops: Option<u32>,
+        airtime_ms: f64,
+        channel: Option<u32>,
+    ) {
+        if let Some(channel) = channel {
+            self.presence
+                .record(channel, from, Local::now().timestamp());
+        }
+        if self
+            .remote_reboot
+            .as_mut()
+            .is_some_and(|reboot| reboot.node == from && reboot.heard(port))
+        {
+            self.remote_reboot_finished();
+        }
+        if let Some(hops) = hops {
+            if let Some(node) = self.nodes.get_mut(&from) {
+                node.hops_away = Some(hops);
+                node.last_heard = Local::now().timestamp() as u32;
+            }
+            if let Some(anomaly) = self.hop_tracker.record(from, hops) {
+                self.report_anomaly(anomaly);
+            }
+        }
+        if let Err(e) = self.store.record_packet(from, to, port, airtime_ms) {
+            log::error!("Failed to store packet metadata: {}", e);
+        }
+        self.refresh_screens();
+    }
+
+    fn message_sent(&mut self, outbox: i64, packet_id: Option<u32>) {
+        if let Some(packet_id) = packet_id {
+            self.number_sent_message(outbox, packet_id);
+        }
+        let broadcast = self
+            .outbox
+            .iter()
+            .any(|entry| entry.id == outbox && entry.to == BROADCAST);
+        // Broadcasts are never acknowledged, so being sent is all that can happen.
+        let state = (!broadcast).then_some(OutboxState::Sent);
+        self.update_outbox(outbox, state);
+    }
+
+    fn admin_session(&mut self, from: NodeNum, passkey: Vec<u8>) {
+        let next = self
+            .bulk_admin
+            .as_mut()
+            .and_then(|bulk| bulk.session(from, passkey.clone()));
+        self.send_bulk_admin(next);
+        if let Some(reboot) = &mut self.remote_reboot
+            && reboot.node == from
+            && reboot.session_opened()
+        {
+            let what = format!("reboot {}", self.node_name(from));
+            let event = UiEvent::RebootNode {
+                node: from,
+                passkey,
+            };
+            if !self.hand_to_mesh(event, &what) {
+                self.reboot_not_sent();
+            }
+        }
+    }
+
+    fn delivery(
+        &mut self,
+        to: NodeNum,
+        delivered: bool,
+        outbox: Option<i64>,
+        relay_node: Option<u8>,
+        hops: Option<u32>,
+    ) {
+        self.deliveries.record(to, delivered);
+        let Some(outbox) = outbox else {
+            let next = self
+                .bulk_admin
+                .as_mut()
+                .and_then(|bulk| bulk.delivery(to, delivered));
+            self.send_bulk_admin(next);
+            return;
+        };
+        if delivered {
+            let ack = self.acks.entry(outbox).or_default();
+            ack.delivered = true;
+            ack.relay_node = relay_node;
+            ack.hops = hops;
+            self.conversation_view.invalidate();
+        }
+        let state = (!delivered).then_some(OutboxState::Failed);
+        self.update_outbox(outbox, state);
+    }
+
+    fn radio_changed(&mut self, name: String, transport: Transport) {
+        // The new radio tells its own node, configuration and channels next.
+        self.my_node = None;
+        self.device_config = None;
+        self.lora_config = None;
+        self.security_config = None;
+        self.channels.clear();
+        self.connection_status = None;
+        self.connection_error = None;
+        self.radio = Some(name);
+        self.transport = Some(transport);
+    }
+
+    fn metrics_heard(&mut self, from: NodeNum, metrics: DeviceMetrics) {
+        if let Some(probe) = self.probe.as_mut().filter(|p| p.node == from) {
+            probe.metrics = Some(metrics);
+        }
+        if let Some(my_node) = self.my_node.as_mut().filter(|n| n.num == from) {
+            my_node.device_metrics = Some(metrics);
+            self.own_metrics(metrics);
+        }
+        let own = self.my_node.as_ref().is_some_and(|n| n.num == from);
+        let direct = self
+            .nodes
+            .get(&from)
+            .is_some_and(|n| n.hops_away == Some(0));
+        if let Some(utilization) = metrics.channel_utilization
+            && (own || direct)
+        {
+            self.busy_channel.report(utilization);
+            self.release_deferred();
+            self.request_stalled_thumbnails();
+        }
+        if let Some(level) = metrics.battery_level {
+            self.battery.record(from, Local::now().timestamp(), level);
+            if let Err(e) = self.store.record_battery(from, level) {
+                log::error!("Failed to store battery level of {}: {}", from, e);
+            }
+        }
+        if let Some(node) = self.nodes.get_mut(&from) {
+            node.device_metrics = Some(metrics);
+        }
+    }
+
+    /// Alert on our own node's battery running low or its channel getting busy.
+    fn own_metrics(&mut self, metrics: DeviceMetrics) {
+        if let Some(alert) = metrics
+            .battery_level
+            .and_then(|level| self.watchdog.battery(level))
+        {
+            self.watchdog_alert(alert);
+        }
+        if let Some(alert) = metrics
+            .channel_utilization
+            .and_then(|utilization| self.watchdog.channel_utilization(utilization))
+        {
+            if let Some(session_log) = &self.session_log {
+                session_log.annotate(&alert);
+            }
+            self.watchdog_alert(alert);
+        }
+    }
+
+    fn message_received(&mut self, from: NodeNum, to: NodeNum, message: Message) {
+        let (channel, text) = (message.channel, &message.text);
+        if let Some(session_log) = &self.session_log {
+            let to = if to == BROADCAST { "ALL" } else { "ME" };
+            session_log.record(&self.node_name(from), to, channel, text);
+        }
+        if let Some(announcer) = &self.announcer {
+            announcer.received(&self.node_name(from), to == BROADCAST, channel, text);
+        }
+        let notify = self.notify_overrides.mode(from, channel);
+        if let Some(push) = &self.push {
+            push.message(&self.node_name(from), text, notify);
+        }
+        if let Some(form) = Ics213::parse(text) {
+            self.traffic.record_received(from, &form);
+            self.send_message(from, forms::encode_receipt(form.number));
+        } else if let Some(number) = forms::parse_receipt(text) {
+            self.traffic.record_receipt(from, number);
+        }
+        #[cfg(all(feature = "dbus", target_os = "linux"))]
+        self.desktop_bus
+            .message_received(from, self.node_name(from), channel, text, notify);
+        self.push_message(from, message);
+    }
+
+    pub fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        let mut last_tick = Instant::now();
+        let mut last_draw: Option<Instant> = None;
+        let mut dirty = true;
+        loop {
+            // Redraw when something changed, but no more often than configured, so busy channels
+            // don't flood slow terminals. Ratatui only sends the cells that differ from the last
+            // frame, and a tick redraws anyway to keep times current.
+            let draw_due = last_draw.is_none_or(|drawn| {
+                let since = drawn.elapsed();
+                (dirty && since >= self.redraw_interval)
+                    || since >= self.tick_rate.max(self.redraw_interval)
+            });
+            if draw_due {
+                terminal.draw(|frame| self.draw(frame))?;
+                last_draw = Some(Instant::now());
+                dirty = false;
+            }
+
+            for _ in 0..MAX_EVENTS_PER_LOOP {
+                if !self.update() {
+                    break;
+                }
+                dirty = true;
+            }
+            self.sync_read_state();
+            self.reload_offline();
+            self.reload_config();
+            self.release_deferred();
+            if let Some(alert) = self.watchdog.check() {
+                self.watchdog_alert(alert);
+            }
+            if self
+                .remote_reboot
+                .as_mut()
+                .is_some_and(RemoteReboot::check_timeout)
+            {
+                self.remote_reboot_finished();
+            }
+            let next = self.bulk_admin.as_mut().and_then(BulkAdmin::check_timeout);
+            self.send_bulk_admin(next);
+
+            let mut timeout = self.tick_rate.saturating_sub(last_tick.elapsed());
+            if dirty && let Some(drawn) = last_draw {
+                timeout = timeout.min(self.redraw_interval.saturating_sub(drawn.elapsed()));
+            }
+            if event::poll(timeout)?
+                && let Event::Key(key) = event::read()?
+            {
+                dirty = true;
+                if let ControlFlow::Break(result) = self.press(key) {
+                    return result;
+                }
+            }
+            if last_tick.elapsed() >= self.tick_rate {
+                last_tick = Instant::now();
+            }
+        }
+    }
+
+    /// Whether `key` is a command key, pressed with nothing focused and no screen open.
+    fn is_command(&self, key: &KeyEvent) -> bool {
+        self.screens.is_empty() && self.focus.route(key) == Route::Command
+    }
+
+    /// Act on a key pressed, or on the keys of the macro it replays.
+    pub(crate) fn press(&mut self, key: KeyEvent) -> ControlFlow<Result<()>> {
+        let command = self.is_command(&key);
+        for key in self.macros.keys(key, command) {
+            self.handle_key(key)?;
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Act on a key, breaking with the result of the app once it quits.
+    fn handle_key(&mut self, key: KeyEvent) -> ControlFlow<Result<()>> {
+        let route = if self.screens.is_empty() {
+            self.focus.route(&key)
+        } else {
+            Route::Screen
+        };
+        let key = match route {
+            Route::Command => self.keymap.translate(key),
+            _ => key,
+        };
+        if let Some(what) = self.refused(&route, &key) {
+            self.refuse_offline(what);
+            return ControlFlow::Continue(());
+        }
+        let flow = self.route_key(route, key);
+        // There is nothing to type into without a radio to send it.
+        if self.offline && self.focus.current() == Some(Focus::Input) {
+            self.focus.set(Focus::Conversation);
+        }
+        flow
+    }
+
+    /// Browse the store without a radio, and with `read_only` without writing to it either.
+    pub fn browse_offline(&mut self, read_only: bool) {
+        self.offline = true;
+        self.read_only = read_only;
+        if self.focus.current() == Some(Focus::Input) {
+            self.focus.set(Focus::Conversation);
+        }
+    }
+
+    /// What a key would do that browsing without a radio, or read-only, rules out, if anything.
+    fn refused(&self, route: &Route, key: &KeyEvent) -> Option<&'static str> {
+        if !self.offline {
+            return None;
+        }
+        match (route, key.code) {
+            (Route::Command, KeyCode::Char('f' | 'p' | 'R' | 'r' | 'l' | 'c' | 'a' | 'P')) => {
+                Some("change or ask anything of the device")
+            }
+            (Route::Command, KeyCode::Char('B' | 'e' | 'i' | 'o')) => Some("send"),
+            (Route::Command, KeyCode::Char('n' | 'm' | 'M' | 'u' | 'U')) if self.read_only => {
+                Some("change anything")
+            }
+            _ => None,
+        }
+    }
+
+    /// What a message action would do that browsing without a radio, or read-only, rules out.
+    fn refused_message_action(&self, action: MessageAction) -> Option<&'static str> {
+        if !self.offline {
+            return None;
+        }
+        match action {
+            MessageAction::Reply | MessageAction::React | MessageAction::Resend => Some("send"),
+            MessageAction::Note | MessageAction::Delete if self.read_only => {
+                Some("change anything")
+            }
+            _ => None,
+        }
+    }
+
+    fn refuse_offline(&mut self, what: &str) {
+        let why = if self.read_only {
+            "Read-only"
+        } else {
+            "No radio"
+        };
+        self.toast = Some((
+            Instant::now(),
+            format!("{why}: cannot {what} here"),
+            Color::Red,
+        ));
+    }
+
+    fn route_key(&mut self, route: Route, key: KeyEvent) -> ControlFlow<Result<()>> {
+        match route {
+            Route::Screen => self.screen_key(key),
+            Route::Unfocus => self.focus.clear(),
+            Route::Switcher => self.open_switcher(),
+            Route::GlobalSearch => self.open_global_search(),
+            Route::Next => self.focus.next(),
+            Route::Previous => self.focus.previous(),
+            Route::Pane(Focus::NodeList) => self.node_list_key(key),
+            Route::Pane(Focus::Conversation) => self.conversation_key(key),
+            Route::Pane(Focus::Input) => self.input_key(key),
+            Route::Pane(Focus::Note) => self.note_key(key),
+            Route::Pane(Focus::Search) => self.search_key(key),
+            Route::Command => return self.command_key(key),
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn node_list_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => self.node_list_state.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => self.node_list_state.select_previous(),
+            KeyCode::Enter => self.open_selected_node(),
+            KeyCode::Char(']') => self.cycle_smart_view(1),
+            KeyCode::Char('[') => self.cycle_smart_view(-1),
+            KeyCode::Char(' ') => self.toggle_team_member(),
+            _ => {}
+        }
+    }
+
+    fn conversation_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => self.select_message(1),
+            KeyCode::Char('k') | KeyCode::Up => self.select_message(-1),
+            KeyCode::Char('t') => self.toggle_threaded(),
+            KeyCode::Char(' ') => self.toggle_collapsed(),
+            KeyCode::Enter if self.selected_message.is_some() => self.open_message_actions(),
+            _ => {}
+        }
+    }
+
+    fn input_key(&mut self, key: KeyEvent) {
+        match key.code {
+            // Arbitrary limit of 237 characters
+            KeyCode::Char(c) if self.input.len() <= self.input_limit() => {
+                self.input.push(c);
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Enter => self.send_input(),
+            _ => {}
+        }
+    }
+
+    fn note_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Char(c) => self.note.push(c),
+            KeyCode::Backspace => {
+                self.note.pop();
+            }
+            KeyCode::Enter => {
+                if let Some(id) = self.current_contact {
+                    self.save_note(id);
+                }
+                self.focus.clear();
+            }
+            _ => {}
+        }
+    }
+
+    fn search_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Char(c) if c != ' ' => {
+                self.search.push(c);
+            }
+            KeyCode::Backspace => {
+                self.search.pop();
+            }
+            KeyCode::Enter => {
+                self.search.push('\n');
+            }
+            _ => {}
+        }
+    }
+
+    /// Keys that work from any pane, after the keymap translated them.
+    fn command_key(&mut self, key: KeyEvent) -> ControlFlow<Result<()>> {
+        let contact = self.current_contact;
+        match key.code {
+            KeyCode::Char('q') => {
+                self.save_draft();
+                self.save_session();
+                return ControlFlow::Break(self.store.flush());
+            }
+            KeyCode::Char(':') => {
+                self.command_line.open();
+                self.open_screen(Box::new(CommandLineView));
+            }
+            KeyCode::Char('f') if contact.is_some() => self.open_screen(Box::new(FormView)),
+            KeyCode::Char('n') => self.open_note(),
+            KeyCode::Char('p') => self.open_profiles(),
+            KeyCode::Char('R') => {
+                if let Some(id) = contact {
+                    self.reboot_node(id);
+                }
+            }
+            KeyCode::Char('r') => self.open_lora(),
+            KeyCode::Char('l') => self.open_fixed_position(),
+            KeyCode::Char('c') => self.open_channels(),
+            KeyCode::Char('a') => self.open_admin_keys(),
+            KeyCode::Char('m') => {
+                if let Some(id) = contact {
+                    self.cycle_node_notify(id);
+                }
+            }
+            KeyCode::Char('M') => {
+                if let Some(channel) = contact.and_then(|id| self.contact_channel(id)) {
+                    self.cycle_channel_notify(channel);
+                }
+            }
+            KeyCode::Char('w') => self.open_screen(Box::new(HealthView)),
+            KeyCode::Char('d') => self.open_screen(Box::new(DeviceLogView)),
+            KeyCode::Char('x') => self.open_screen(Box::new(MonitorView)),
+            KeyCode::Char('P') => self.start_probe(),
+            KeyCode::Char('B') => self.open_screen(Box::new(BulkAdminView)),
+            KeyCode::Char('e') if contact.is_some() => self.open_screen(Box::new(StructuredView)),
+            KeyCode::Char('i') if self.thumbnails.is_some() && contact.is_some() => {
+                self.open_screen(Box::new(ThumbnailView))
+            }
+            KeyCode::Char('o') => self.open_outbox(),
+            KeyCode::Char('s') => self.open_leaderboard(),
+            KeyCode::Char('u') => {
+                if let Some(id) = contact {
+                    self.merge_renumbered(id);
+                }
+            }
+            KeyCode::Char('U') => self.undo(),
+            KeyCode::Char('T') => {
+                if let Some(id) = contact {
+                    self.open_transcript(id);
+                }
+            }
+            KeyCode::Char('v') => self.open_flows(),
+            KeyCode::Char('g') => self.export_node_map(),
+            KeyCode::Char('h') => self.export_survey(),
+            KeyCode::Char('j') => self.export_json(),
+            KeyCode::Char('t') => self.export_traffic_log(),
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn open_switcher(&mut self) {
+        self.switcher.clear();
+        self.switcher_state.select(Some(0));
+        self.focus.clear();
+        self.open_screen(Box::new(SwitcherView));
+    }
+
+    fn open_global_search(&mut self) {
+        self.global_search.clear();
+        self.refresh_search_results();
+        self.focus.clear();
+        self.open_screen(Box::new(GlobalSearchView));
+    }
+
+    /// Open the conversation with the node selected in the node list.
+    fn open_selected_node(&mut self) {
+        let nodes = self.get_visible_nodes();
+        let selected = self.node_list_state.selected().and_then(|i| nodes.get(i));
+        if let Some(num) = selected.map(|node| node.num)
+            && Some(num) != self.current_contact
+        {
+            self.open_conversation(num);
+        }
+    }
+
+    /// Add the node selected in the node list to the team, or take it off.
+    fn toggle_team_member(&mut self) {
+        let nodes = self.get_visible_nodes();
+        let selected = self.node_list_state.selected().and_then(|i| nodes.get(i));
+        if let Some(num) = selected.map(|node| node.num)
+            && !self.team.remove(&num)
+        {
+            self.team.insert(num);
+        }
+    }
+
+    fn open_message_actions(&mut self) {
+        self.message_action_state.select(Some(0));
+        self.message_details = false;
+        self.raw_packet_view = false;
+        self.open_screen(Box::new(MessageActionsView));
+    }
+
+    /// Send what was typed to the team, or else to the open conversation.
+    fn send_input(&mut self) {
+        if self.reconnecting() {
+            self.toast = Some((
+                Instant::now(),
+                "Not connected to the device; send once reconnected".to_string(),
+                Color::Red,
+            ));
+        } else if !compression::fits(&self.input, self.compress_long_messages) {
+            self.toast = Some((
+                Instant::now(),
+                "Too long to send, even compressed".to_string(),
+                Color::Red,
+            ));
+        } else if !self.team.is_empty() {
+            let message = std::mem::take(&mut self.input);
+            self.save_draft();
+            self.send_to_team(message);
+        } else if let Some(id) = self.current_contact {
+            let message = std::mem::take(&mut self.input);
+            self.save_draft();
+            let reply_to = self.reply_to.take();
+            self.send_reply(id, message, reply_to, false);
+        }
+    }
+
+    fn open_note(&mut self) {
+        if let Some(id) = self.current_contact {
+            self.note = self.notes.get(&id).cloned().unwrap_or_default();
+            self.focus.set(Focus::Note);
+        }
+    }
+
+    fn open_profiles(&mut self) {
+        if self.profile_list_state.selected().is_none() && !self.profiles.is_empty() {
+            self.profile_list_state.select(Some(0));
+        }
+        self.open_screen(Box::new(ProfilesView));
+    }
+
+    fn open_lora(&mut self) {
+        match &self.lora_config {
+            Some(lora_config) => {
+                self.lora = LoraDraft::from_config(lora_config);
+                self.open_screen(Box::new(LoraView));
+            }
+            None => log::warn!("LoRa config not received from device yet"),
+        }
+    }
+
+    fn open_fixed_position(&mut self) {
+        let position = self.my_node.as_ref().and_then(|n| n.position.as_ref());
+        self.fixed_position = PositionDraft::from_position(position);
+        self.open_screen(Box::new(FixedPositionView));
+    }
+
+    fn open_channels(&mut self) {
+        if self.channel_list_state.selected().is_none() {
+            self.channel_list_state.select(Some(0));
+        }
+        self.pending_rotation = None;
+        self.open_screen(Box::new(ChannelsView));
+    }
+
+    fn open_admin_keys(&mut self) {
+        match &self.security_config {
+            Some(_) => {
+                self.admin_key_input.clear();
+                self.open_screen(Box::new(AdminKeysView));
+            }
+            None => log::warn!("Security config not received from device yet"),
+        }
+    }
+
+    fn open_outbox(&mut self) {
+        self.outbox_table_state.select(Some(0));
+        self.open_screen(Box::new(OutboxView));
+    }
+
+    fn open_leaderboard(&mut self) {
+        self.refresh_leaderboard();
+        self.open_screen(Box::new(LeaderboardView));
+    }
+
+    fn open_flows(&mut self) {
+        self.refresh_flows();
+        self.open_screen(Box::new(FlowsView));
+    }
+
+    /// Open the conversation with `id` in the pager.
+    pub(crate) fn open_transcript(&mut self, id: NodeNum) {
+        let name = self.node_name(id);
+        let messages = self.conversations.get(&id).map_or(&[][..], Vec::as_slice);
+        let lines = conversation_view::transcript(&name, messages, &self.channel_badges);
+        self.open_screen(Box::new(Pager::new(name, lines)));
+    }
+
+    /// Play back what was stored from `from` to `to`.
+    fn open_replay(&mut self, from: DateTime<Local>, to: DateTime<Local>) {
+        let positions = match self.store.get_positions(from.timestamp(), to.timestamp()) {
+            Ok(positions) => positions,
+            Err(e) => {
+                log::error!("Failed to load positions for the replay: {}", e);
+                return;
+            }
+        };
+        let messages = self
+            .conversations
+            .iter()
+            .flat_map(|(node, messages)| messages.iter().map(|message| (*node, message.clone())))
+            .collect();
+        self.open_screen(Box::new(Replay::new(from, to, positions, messages)));
+    }
+
+    pub(crate) fn open_screen(&mut self, view: Box<dyn View>) {
+        self.with_screens(|screens, app| screens.open(app, view));
+    }
+
+    fn reconnecting(&self) -> bool {
+        matches!(
+            self.connection_status,
+            Some(ConnectionStatus::Reconnecting { .. })
+        )
+    }
+
+    /// Pass a key to the open screens.
+    pub(crate) fn screen_key(&mut self, key: KeyEvent) {
+        self.with_screens(|screens, app| screens.handle_action(app, key));
+    }
+
+    /// Let the open screens catch up with a packet heard.
+    fn refresh_screens(&mut self) {
+        self.with_screens(Screens::refresh);
+    }
+
+    /// Work on the screens taken out of the app, keeping any a view opens on the app meanwhile.
+    fn with_screens(&mut self, work: impl FnOnce(&mut Screens, &mut App)) {
+        let mut screens = std::mem::take(&mut self.screens);
+        work(&mut screens, self);
+        let opened = std::mem::replace(&mut self.screens, screens);
+        self.screens.extend(opened);
+    }
+
+    fn build_constraints(
+        frame: &mut Frame,
+        node_list_width: u16,
+    ) -> (Rect, Rect, Rect, Rect, Rect, Rect) {
+        let area = frame.area();
+
+        let horizontal_chunks = Layout::horizontal([
+            Constraint::Percentage(node_list_width),
+            Constraint::Percentage(100 - node_list_width),
+        ])
+        .split(area);
+
+        let left_side = Layout::vertical([
+            Constraint::Min(4),
+            Constraint::Percentage(100),
+            Constraint::Length(11),
+        ])
+        .split(horizontal_chunks[0]);
+
+        let right_side = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Percentage(10),
+            Constraint::Percentage(90),
+        ])
+        .split(horizontal_chunks[1]);
+
+        (
+            left_side[0],
+            left_side[1],
+            left_side[2],
+            right_side[0],
+            right_side[1],
+            right_side[2],
+        )
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let (search_rect, node_list_rect, detail_rect, title_rect, input_rect, conversation_rect) =
+            Self::build_constraints(frame, self.node_list_width);
+
+        self.draw_title(frame, title_rect);
+        let conversation_rect = self.draw_congestion_banner(frame, conversation_rect);
+        self.draw_conversation(frame, conversation_rect, input_rect);
+        self.draw_node_list(frame, node_list_rect);
+        self.draw_node_detail(frame, detail_rect);
+        self.draw_input_box(frame, input_rect);
+        self.draw_search_box(frame, search_rect);
+        self.set_cursor_position(frame, input_rect);
+        let mut screens = std::mem::take(&mut self.screens);
+        screens.draw(self, frame, conversation_rect);
+        self.screens = screens;
+        if self.plain {
+            plain::simplify(frame.buffer_mut());
+        } else if self.ascii {
+            glyphs::to_ascii(frame.buffer_mut());
+        }
+    }
+
+    /// The command line, over the bottom line of the screen.
+    fn draw_command_line(&self, frame: &mut Frame) {
+        let area = frame.area();
+        let rect = Rect {
+            y: area.bottom().saturating_sub(1),
+            height: area.height.min(1),
+            ..area
+        };
+        let mut line = Line::from(format!(":{}", self.command_line.text));
+        if let Some(error) = &self.command_line.error {
+            line.push_span(Span::styled(
+                format!("  {error}"),
+                Style::default().fg(Color::Red).bold(),
+            ));
+        }
+        frame.render_widget(Clear, rect);
+        frame.render_widget(Paragraph::new(line), rect);
+        let cursor = rect.x + 1 + self.command_line.text.width() as u16;
+        frame.set_cursor_position((cursor.min(rect.right().saturating_sub(1)), rect.y));
+    }
+
+    fn draw_global_search(&mut self, frame: &mut Frame, rect: Rect) {
+        let mut items = vec![Line::from(format!("> {}", self.global_search)).bold()];
+        for result in &self.search_results {
+            let (kind, color, text) = match result {
+                SearchResult::Node(num) => {
+                    let short = self
+                        .nodes
+                        .get(num)
+                        .and_then(|node| node.user.as_ref())
+                        .map(|user| user.short_name.clone())
+                        .unwrap_or_default();
+                    (
+                        "node",
+                        Color::Cyan,
+                        format!(
+                            "{} ({short}) {}",
+                            self.node_name(*num),
+                            format_node_num(*num)
+                        ),
+                    )
+                }
+                SearchResult::Message { node, message } => {
+                    let from = if message.outgoing {
+                        format!("me to {}", self.node_name(*node))
+                    } else {
+                        self.node_name(*node)
+                    };
+                    (
+                        "msg",
+                        Color::Reset,
+                        format!(
+                            "{} {from}: {}",
+                            message.ts.format("%Y-%m-%d %H:%M"),
+                            message.text.replace('\n', " ")
+                        ),
+                    )
+                }
+                SearchResult::Waypoint { from, waypoint } => (
+                    "wpt",
+                    Color::Green,
+                    format!(
+                        "{}: {} (from {})",
+                        waypoint.name,
+                        waypoint.description,
+                        self.node_name(*from)
+                    ),
+                ),
+            };
+            items.push(Line::from(vec![
+                Span::styled(format!("  {kind:<5}"), Style::default().fg(color)),
+                Span::raw(text),
+            ]));
+        }
+        // The query line stays put; the selection starts on the first result below it.
+        let mut state = self.search_results_state.clone();
+        state.select(self.search_results_state.selected().map(|i| i + 1));
+        let list = List::new(items)
+            .block(
+                Block::bordered()
+                    .title(format!("SEARCH ({} found)", self.search_results.len()).bold())
+                    .title_bottom("ENTER: go to  ESC: close")
+                    .border_style(Style::default().fg(self.palette.accent)),
+            )
+            .highlight_style(Style::default().bg(self.palette.selection));
+        frame.render_widget(Clear, rect);
+        frame.render_stateful_widget(list, rect, &mut state);
+    }
+
+    fn draw_switcher(&mut self, frame: &mut Frame, rect: Rect) {
+        let matches = self.switcher_matches();
+        let mut items = vec![Line::from(format!("> {}", self.switcher)).bold()];
+        items.extend(
+            matches
+                .into_iter()
+                .map(|(label, _)| Line::from(format!("  {label}"))),
+        );
+        let height = (items.len() as u16 + 2).min(rect.height);
+        // The query line stays put; the selection starts on the first match below it.
+        let mut state = self.switcher_state.clone();
+        state.select(self.switcher_state.selected().map(|i| i + 1));
+        let list = List::new(items)
+            .block(
+                Block::bordered()
+                    .title("JUMP TO".bold())
+                    .title_bottom("ENTER: open  ESC: close")
+                    .border_style(Style::default().fg(self.palette.accent)),
+            )
+            .highlight_style(Style::default().bg(self.palette.selection));
+        let area = Rect {
+            width: rect.width.min(50),
+            height,
+            ..rect
+        };
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn draw_structured(&self, frame: &mut Frame, rect: Rect) {
+        let to = self
+            .current_contact
+            .map(|id| self.node_name(id))
+            .unwrap_or_default();
+        let mut lines = vec![Line::from(format!("> {}", self.structured_draft)).bold()];
+        match Structured::parse(&self.structured_draft) {
+            Some(structured) => {
+                lines.push(Line::from(format!(
+                    "{} {}",
+                    structured.label(),
+                    structured.title()
+                )));
+                lines.extend(structured.lines().into_iter().map(Line::from));
+            }
+            None => {
+                lines.push(Line::from("Checklist: Title: item, x done item").gray());
+                lines.push(Line::from("Status report: Title: name=value, name=value").gray());
+            }
+        }
+        let height = lines.len() as u16 + 2;
+        let popup = Paragraph::new(lines).block(
+            Block::bordered()
+                .title(format!("STRUCTURED MESSAGE TO {to}").bold())
+                .title_bottom("ENTER: send  ESC: close")
+                .border_style(Style::default().fg(self.palette.accent)),
+        );
+        let area = Rect {
+            width: rect.width.min(70),
+            height: rect.height.min(height),
+            ..rect
+        };
+        frame.render_widget(Clear, area);
+        frame.render_widget(popup, area);
+    }
+
+    fn draw_thumbnail(&self, frame: &mut Frame, rect: Rect) {
+        let to = self
+            .current_contact
+            .map(|id| self.node_name(id))
+            .unwrap_or_default();
+        let lines = vec![
+            Line::from(format!("> {}", self.thumbnail_path)).bold(),
+            Line::from("A PGM or PPM image, sent as a 64x48 black and white thumbnail").gray(),
+        ];
+        let popup = Paragraph::new(lines).block(
+            Block::bordered()
+                .title(format!("SEND THUMBNAIL TO {to}").bold())
+                .title_bottom("ENTER: send  ESC: close")
+                .border_style(Style::default().fg(self.palette.accent)),
+        );
+        let area = Rect {
+            width: rect.width.min(70),
+            height: rect.height.min(4),
+            ..rect
+        };
+        frame.render_widget(Clear, area);
+        frame.render_widget(popup, area);
+    }
+
+    fn draw_outbox(&mut self, frame: &mut Frame, rect: Rect) {
+        let now = Local::now();
+        let rows: Vec<Row> = self
+            .outbox
+            .iter()
+            .map(|entry| {
+                let state = Cell::from(entry.state.label()).style(match entry.state {
+                    OutboxState::Queued => Style::default(),
+                    OutboxState::Sent => Style::default().fg(Color::Yellow),
+                    OutboxState::Relayed => Style::default().fg(Color::Cyan),
+                    OutboxState::Failed => Style::default().fg(Color::Red),
+                    OutboxState::Deferred => Style::default().fg(Color::Magenta),
+                });
+                Row::new(vec![
+                    state,
+                    Cell::from(self.node_name(entry.to)),
+                    Cell::from(entry.attempts.to_string()),
+                    Cell::from(stats::format_duration(
+                        (now - entry.queued).num_seconds() as f64
+                    )),
+                    Cell::from(entry.text.clone()),
+                ])
+            })
+            .collect();
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(7),
+                Constraint::Length(16),
+                Constraint::Length(5),
+                Constraint::Length(8),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(Row::new(vec!["STATE", "TO", "TRIES", "AGE", "MESSAGE"]).bold())
+        .block(
+            Block::bordered()
+                .title(format!("OUTBOX ({})", self.outbox.len()).bold())
+                .title_bottom("c: cancel  r: retry now")
+                .border_style(Style::default().fg(self.palette.accent)),
+        )
+        .row_highlight_style(Style::default().bg(self.palette.selection));
+        frame.render_widget(Clear, rect);
+        frame.render_stateful_widget(table, rect, &mut self.outbox_table_state);
+    }
+
+    fn draw_message_actions(&mut self, frame: &mut Frame, rect: Rect) {
+        let message = self
+            .current_contact
+            .zip(self.selected_message)
+            .and_then(|(id, index)| self.conversations.get(&id)?.get(index));
+        let details = match (message, self.message_details) {
+            (Some(message), true) => {
+                let contact = self.current_contact.unwrap_or_default();
+                let ack = message
+                    .outgoing
+                    .then(|| self.acks.get(&message.ts.timestamp_micros()))
+                    .flatten();
+                let packet = message
+                    .packet_id
+                    .filter(|_| !message.outgoing)
+                    .and_then(|id| self.raw_packets.get(&id));
+                let mut details = message_actions::details(
+                    message,
+                    &self.node_name(contact),
+                    ack.and_then(|ack| self.describe_route(contact, ack)),
+                );
+                match packet {
+                    Some(packet) => {
+                        details.extend(message_actions::packet_details(packet));
+                        if self.raw_packet_view {
+                            details.push(String::new());
+                            details.extend(message_actions::raw_packet(packet));
+                        }
+                    }
+                    None if self.raw_packet_view => {
+                        details.push(String::new());
+                        details.push("Raw packet: only kept for this session".to_string());
+                    }
+                    None => {}
+                }
+                details
+            }
+            _ => Vec::new(),
+        };
+        let mut items: Vec<Line> = MessageAction::ALL
+            .iter()
+            .map(|action| Line::from(action.label()))
+            .collect();
+        if !details.is_empty() {
+            items.push(Line::default());
+            items.extend(details.into_iter().map(Line::from));
+        }
+        let height = items.len() as u16 + 2;
+        let list = List::new(items)
+            .block(
+                Block::bordered()
+                    .title("MESSAGE".bold())
+                    .title_bottom(if self.message_details {
+                        "ENTER: run  x: raw packet  ESC: close"
+                    } else {
+                        "ENTER: run  ESC: close"
+                    })
+                    .border_style(Style::default().fg(self.palette.accent)),
+            )
+            .highlight_symbol("> ")
+            .highlight_style(Style::default().bg(self.palette.selection));
+        // The decoded packet needs the room.
+        let width = if self.raw_packet_view { 80 } else { 40 };
+        let area = Rect {
+            width: rect.width.min(width),
+            height: height.min(rect.height),
+            ..rect
+        };
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut self.message_action_state);
+    }
+
+    fn draw_message_note(&self, frame: &mut Frame, rect: Rect) {
+        let area = Rect {
+            width: rect.width.min(60),
+            height: rect.height.min(3),
+            ..rect
+        };
+        let input = Paragraph::new(self.message_note.as_str()).block(
+            Block::bordered()
+                .title("MESSAGE NOTE".bold())
+                .title_bottom("ENTER: save (empty clears)  ESC: close")
+                .border_style(Style::default().fg(self.palette.accent)),
+        );
+        frame.render_widget(Clear, area);
+        frame.render_widget(input, area);
+    }
+
+    fn draw_form(&self, frame: &mut Frame, rect: Rect) {
+        let encoded_len = self.form.form.encode().len();
+        let title = format!("ICS-213 {}/{}", encoded_len, PACKET_BYTE_LIMIT);
+        let lines: Vec<Line> = FormField::ALL
+            .iter()
+            .map(|field| {
+                let line = Line::from(vec![
+                    Span::styled(
+                        format!("{:>10}: ", field.label()),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(self.form.value(*field)),
+                ]);
+                if *field == self.form.field {
+                    line.patch_style(Style::default().fg(Color::Yellow))
+                } else {
+                    line
+                }
+            })
+            .collect();
+
+        let popup = Paragraph::new(lines)
+            .block(
+                Block::bordered()
+                    .title(title.bold())
+                    .title_bottom("SPACE: precedence  ENTER: next/send  ESC: close")
+                    .border_style(if encoded_len > PACKET_BYTE_LIMIT {
+                        Style::default().fg(Color::Red)
+                    } else {
+                        Style::default().fg(self.palette.accent)
+                    }),
+            )
+            .wrap(Wrap { trim: false });
+        let area = Rect {
+            height: rect.height.min(FormField::ALL.len() as u16 + 6),
+            ..rect
+        };
+        frame.render_widget(Clear, area);
+        frame.render_widget(popup, area);
+    }
+
+    fn draw_profiles(&mut self, frame: &mut Frame, rect: Rect) {
+        let items: Vec<Line> = self
+            .profiles
+            .iter()
+            .map(|p| {
+                let licensed = if p.is_licensed { " licensed" } else { "" };
+                Line::from(format!(
+                    "{}: {} ({}) {}{}",
+                    p.name,
+                    p.long_name,
+                    p.short_name,
+                    p.role_name(),
+                    licensed
+                ))
+            })
+            .collect();
+        let title = if self.profile_name.is_empty() {
+            "PROFILES".to_string()
+        } else {
+            format!("SAVE CURRENT AS: {}", self.profile_name)
+        };
+        let list = List::new(items)
+            .block(
+                Block::bordered()
+                    .title(title.bold())
+                    .title_bottom("ENTER: apply  type a name + ENTER: save current  DEL: delete")
+                    .border_style(Style::default().fg(self.palette.accent)),
+            )
+            .highlight_symbol("> ")
+            .highlight_style(Style::default().bg(self.palette.selection));
+        let area = Rect {
+            height: (self.profiles.len() as u16 + 2).max(3).min(rect.height),
+            ..rect
+        };
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut self.profile_list_state);
+    }
+
+    fn draw_lora(&self, frame: &mut Frame, rect: Rect) {
+        let mut lines: Vec<Line> = LoraField::ALL
+            .iter()
+            .map(|field| {
+                let line = Line::from(vec![
+                    Span::styled(
+                        format!("{:>7}: ", field.label()),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(self.lora.value(*field)),
+                ]);
+                if *field == self.lora.field {
+                    line.patch_style(Style::default().fg(Color::Yellow))
+                } else {
+                    line
+                }
+            })
+            .collect();
+        lines.push(Line::default());
+        for warning in self.lora.warnings() {
+            lines.push(Line::from(warning));
+        }
+        let error = self.lora.error();
+        if let Some(error) = &error {
+            lines.push(Line::styled(
+                error.as_str(),
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ));
+        }
+
+        let height = lines.len() as u16 + 2;
+        let popup = Paragraph::new(lines)
+            .block(
+                Block::bordered()
+                    .title("LORA SETUP".bold())
+                    .title_bottom("LEFT/RIGHT: change  ENTER: apply (device restarts)  ESC: close")
+                    .border_style(if error.is_some() {
+                        Style::default().fg(Color::Red)
+                    } else {
+                        Style::default().fg(self.palette.accent)
+                    }),
+            )
+            .wrap(Wrap { trim: false });
+        let area = Rect {
+            height: rect.height.min(height),
+            ..rect
+        };
+        frame.render_widget(Clear, area);
+        frame.render_widget(popup, area);
+    }
+
+    fn draw_fixed_position(&self, frame: &mut Frame, rect: Rect) {
+        let lines: Vec<Line> = PositionField::ALL
+            .iter()
+            .map(|field| {
+                let line = Line::from(vec![
+                    Span::styled(
+                        format!("{:>12}: ", field.label()),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(self.fixed_position.value(*field)),
+                ]);
+                if *field == self.fixed_position.field {
+                    line.patch_style(Style::default().fg(Color::Yellow))
+                } else {
+                    line
+                }
+            })
+            .collect();
+        let valid = self.fixed_position.position().is_some();
+
+        frame.render_widget(Clear, rect);
+        let block = Block::bordered()
+            .title("FIXED POSITION".bold())
+            .title_bottom("ENTER: next/apply  ESC: close")
+            .border_style(if valid {
+                Style::default().fg(self.palette.accent)
+            } else {
+                Style::default().fg(Color::Red)
+            });
+        let inner = block.inner(rect);
+        frame.render_widget(block, rect);
+        let [fields_rect, map_rect] =
+            Layout::vertical([Constraint::Length(4), Constraint::Min(0)]).areas(inner);
+        frame.render_widget(Paragraph::new(lines), fields_rect);
+
+        // Show the point on a world map so typos such as a missing minus sign stand out.
+        let point = self.fixed_position.coordinates();
+        let map = Canvas::default()
+            .x_bounds([-180.0, 180.0])
+            .y_bounds([-90.0, 90.0])
+            .paint(|ctx| {
+                ctx.draw(&Map {
+                    color: Color::DarkGray,
+                    resolution: MapResolution::High,
+                });
+                if let Some((latitude, longitude)) = point {
+                    ctx.layer();
+                    ctx.print(longitude, latitude, "X".red().bold());
+                }
+            });
+        frame.render_widget(map, map_rect);
+    }
+
+    fn draw_channels(&mut self, frame: &mut Frame, rect: Rect) {
+        let now = Local::now().timestamp();
+        let enabled = self.enabled_channels();
+        let items: Vec<Line> = enabled
+            .iter()
+            .map(|channel| {
+                let key = if !channels::has_private_psk(channel) {
+                    "default key".to_string()
+                } else {
+                    match self.psk_rotations.get(&channel.index) {
+                        Some(rotated) => {
+                            format!("key changed {} days ago", (now - rotated) / (24 * 60 * 60))
+                        }
+                        None => "private key".to_string(),
+                    }
+                };
+                let mut line = Line::from(format!("{}: {}", channels::channel_name(channel), key));
+                let present = self.presence.present(channel.index as u32, now);
+                if !present.is_empty() {
+                    line.push_span(Span::styled(
+                        format!("  here: {}", self.short_names(&present)),
+                        Style::default().fg(Color::Green),
+                    ));
+                }
+                if self.rotation_due(channel) {
+                    line.patch_style(Style::default().fg(Color::Red))
+                } else {
+                    line
+                }
+            })
+            .collect();
+        let height = items.len() as u16 + 2;
+        let title = match self.pending_rotation.and_then(|i| self.channels.get(&i)) {
+            Some(channel) => format!(
+                "ENTER again to give {} a new key",
+                channels::channel_name(channel)
+            ),
+            None => "CHANNELS".to_string(),
+        };
+
+        let list = List::new(items)
+            .block(
+                Block::bordered()
+                    .title(title.bold())
+                    .title_bottom("ENTER: new random key  e: export URL + QR  ESC: close")
+                    .border_style(Style::default().fg(self.palette.accent)),
+            )
+            .highlight_symbol("> ")
+            .highlight_style(Style::default().bg(self.palette.selection));
+        let area = Rect {
+            height: height.max(3).min(rect.height),
+            ..rect
+        };
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut self.channel_list_state);
+    }
+
+    fn draw_health(&self, frame: &mut Frame, rect: Rect) {
+        let health = self.health();
+        let unknown = || "unknown".to_string();
+        let mut lines = vec![
+            Line::from(format!("Direct neighbors: {}", health.direct_neighbors)),
+            Line::from(format!(
+                "Average hops: {}",
+                health
+                    .average_hops
+                    .map_or_else(unknown, |h| format!("{:.1}", h))
+            )),
+            Line::from(format!(
+                "Loss to favorites: {}",
+                health
+                    .favorite_loss
+                    .map_or_else(unknown, |l| format!("{:.0}%", l * 100.0))
+            )),
+            Line::from(format!(
+                "Channel utilization: {}",
+                health
+                    .channel_utilization
+                    .map_or_else(unknown, |u| format!("{:.1}%", u))
+            )),
+            Line::default(),
+        ];
+        if let Some(error) = &self.connection_error {
+            lines.push(Line::styled(
+                error.to_string(),
+                Style::default().fg(Color::Red).bold(),
+            ));
+            lines.push(Line::from(error.guidance()));
+            if let Some(ConnectionStatus::Reconnecting { attempt, after }) = self.connection_status
+            {
+                lines.push(Line::from(format!(
+                    "Reconnecting, attempt {} after {} s",
+                    attempt,
+                    after.as_secs()
+                )));
+            }
+            lines.push(Line::default());
+        }
+        lines.extend(
+            health
+                .warnings()
+                .into_iter()
+                .map(|w| Line::styled(w, Style::default().fg(Color::Red))),
+        );
+        lines.extend(self.anomalies.iter().rev().map(|(time, anomaly)| {
+            Line::styled(
+                format!("{} {}", time.format("%H:%M"), anomaly),
+                Style::default().fg(Color::Yellow),
+            )
+        }));
+
+        let health_box = Paragraph::new(lines)
+            .block(
+                Block::bordered()
+                    .title("MESH HEALTH".bold())
+                    .title_bottom("ESC: close")
+                    .border_style(Style::default().fg(self.palette.accent)),
+            )
+            .wrap(Wrap { trim: false });
+        frame.render_widget(Clear, rect);
+        frame.render_widget(health_box, rect);
+    }
+
+    fn draw_device_log(&self, frame: &mut Frame, rect: Rect) {
+        let records = self.device_log.visible();
+        let height = rect.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = records[records.len().saturating_sub(height)..]
+            .iter()
+            .map(|record| {
+                let level = device_log::level(record);
+                let color = match level {
+                    Level::Critical | Level::Error => Color::Red,
+                    Level::Warning => Color::Yellow,
+                    Level::Debug | Level::Trace => Color::DarkGray,
+                    Level::Info | Level::Unset => Color::Reset,
+                };
+                let time = DateTime::from_timestamp(i64::from(record.time), 0)
+                    .filter(|_| record.time != 0)
+                    .map_or_else(
+                        || "--:--:--".to_string(),
+                        |time| time.with_timezone(&Local).format("%H:%M:%S").to_string(),
+                    );
+                let source = if record.source.is_empty() {
+                    String::new()
+                } else {
+                    format!("[{}] ", record.source)
+                };
+                Line::styled(
+                    format!(
+                        "{} {:<5} {}{}",
+                        time,
+                        level.as_str_name(),
+                        source,
+                        record.message.trim_end()
+                    ),
+                    Style::default().fg(color),
+                )
+            })
+            .collect();
+
+        let follow = if self.device_log.follow {
+            "following"
+        } else {
+            "paused"
+        };
+        let log_box = Paragraph::new(lines).block(
+            Block::bordered()
+                .title("DEVICE LOG".bold())
+                .title(format!(
+                    " {} and up, {} ",
+                    self.device_log.min_level.as_str_name(),
+                    follow
+                ))
+                .title_bottom("l: level  f: follow  UP/DOWN/PGUP/PGDN: scroll  ESC: close")
+                .border_style(Style::default().fg(Color::Cyan)),
+        );
+        frame.render_widget(Clear, rect);
+        frame.render_widget(log_box, rect);
+    }
+
+    fn draw_bulk_admin(&self, frame: &mut Frame, rect: Rect) {
+        let mut lines = Vec::new();
+        if let Some(bulk) = &self.bulk_admin {
+            lines.push(Line::from(format!("Applying {}", bulk.change.label())).bold());
+            for (node, state) in &bulk.nodes {
+                let color = match state {
+                    NodeState::Done => Color::Green,
+                    NodeState::Failed(_) => Color::Red,
+                    _ => Color::Reset,
+                };
+                lines.push(Line::styled(
+                    format!("{:<24} {}", self.node_name(*node), state.label()),
+                    Style::default().fg(color),
+                ));
+            }
+            lines.push(Line::from(""));
+        }
+        if self.bulk_admin.as_ref().is_none_or(BulkAdmin::is_finished) {
+            let what = if self.bulk_channel {
+                "Copy this device's channel number"
+            } else {
+                "Broadcast position every (seconds)"
+            };
+            lines.push(Line::from(format!("{what}: {}", self.bulk_value)).bold());
+            lines.push(
+                Line::from(format!(
+                    "to the {} nodes selected with SPACE in the node list",
+                    self.team.len()
+                ))
+                .gray(),
+            );
+        }
+        let height = lines.len() as u16 + 2;
+        let popup = Paragraph::new(lines).block(
+            Block::bordered()
+                .title("BULK ADMIN".bold())
+                .title_bottom("SPACE: channel/position interval  ENTER: apply  ESC: close")
+                .border_style(Style::default().fg(self.palette.accent)),
+        );
+        let area = Rect {
+            height: rect.height.min(height),
+            ..rect
+        };
+        frame.render_widget(Clear, area);
+        frame.render_widget(popup, area);
+    }
+
+    fn draw_probe(&self, frame: &mut Frame, rect: Rect) {
+        let Some(probe) = &self.probe else {
+            return;
+        };
+        let lines: Vec<Line> = probe
+            .report(|num| self.node_name(num))
+            .into_iter()
+            .map(Line::from)
+            .collect();
+        let status = if probe.is_waiting() {
+            " waiting for answers "
+        } else {
+            " done "
+        };
+        let height = lines.len() as u16 + 2;
+        let report = Paragraph::new(lines)
+            .block(
+                Block::bordered()
+                    .title("PROBE".bold())
+                    .title(status)
+                    .title_bottom("e: export as text  ESC: close")
+                    .border_style(Style::default().fg(Color::Cyan)),
+            )
+            .wrap(Wrap { trim: false });
+        let area = Rect {
+            height: rect.height.min(height),
+            ..rect
+        };
+        frame.render_widget(Clear, area);
+        frame.render_widget(report, area);
+    }
+
+    fn draw_monitor(&self, frame: &mut Frame, rect: Rect) {
+        let height = rect.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = if !self.monitor_enabled {
+            vec![Line::from("Add a [monitor] section to the configuration to watch default channels through MQTT").gray()]
+        } else {
+            self.monitor
+                .packets()
+                .rev()
+                .take(height)
+                .map(|packet| {
+                    let to = if packet.to == BROADCAST {
+                        "all".to_string()
+                    } else {
+                        self.monitor.name(packet.to)
+                    };
+                    Line::from(vec![
+                        Span::raw(format!(
+                            "{} {} > {}: {} ",
+                            packet.heard.format("%H:%M:%S"),
+                            self.monitor.name(packet.from),
+                            to,
+                            packet.summary
+                        )),
+                        Span::styled(
+                            format!("[{}]", packet.provenance()),
+                            Style::default().fg(Color::Magenta),
+                        ),
+                    ])
+                })
+                .collect()
+        };
+        let monitor_box = Paragraph::new(lines).block(
+            Block::bordered()
+                .title("MONITOR: DEFAULT CHANNELS VIA MQTT".bold())
+                .title(format!(" {} packets, newest first ", self.monitor.len()))
+                .title_bottom("Not heard by your radio  ESC: close")
+                .border_style(Style::default().fg(Color::Magenta)),
+        );
+        frame.render_widget(Clear, rect);
+        frame.render_widget(monitor_box, rect);
+    }
+
+    fn draw_leaderboard(&self, frame: &mut Frame, rect: Rect) {
+        let header = Row::new(
+            std::iter::once(Cell::from("NODE")).chain(Ranking::ALL.iter().map(|ranking| {
+                let cell = Cell::from(ranking.label());
+                if *ranking == self.leaderboard_ranking {
+                    cell.style(
+                        Style::default()
+                            .fg(Color::Yellow)
+                            .add_modifier(Modifier::BOLD),
+                    )
+                } else {
+                    cell
+                }
+            })),
+        );
+        let rows = self.leaderboard.iter().map(|activity| {
+            Row::new([
+                self.node_name(activity.node),
+                activity.packets.to_string(),
+                activity.messages.to_string(),
+                stats::format_duration(activity.airtime_ms / 1000.0),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(1),
+                Constraint::Length(8),
+                Constraint::Length(8),
+                Constraint::Length(8),
+            ],
+        )
+        .header(header)
+        .block(
+            Block::bordered()
+                .title(
+                    format!(
+                        "MOST ACTIVE NODES, LAST {}",
+                        self.leaderboard_window.label()
+                    )
+                    .bold(),
+                )
+                .title_bottom("LEFT/RIGHT: window  o: rank by  ESC: close")
+                .border_style(Style::default().fg(self.palette.accent)),
+        );
+        frame.render_widget(Clear, rect);
+        frame.render_widget(table, rect);
+    }
+
+    /// Who sent packets to whom: senders down the side, destinations across the top, with
+    /// broadcasts in the last column.
+    fn draw_flows(&self, frame: &mut Frame, rect: Rect) {
+        const NAME_WIDTH: u16 = 10;
+        const CELL_WIDTH: u16 = 6;
+        let limit = (rect.width.saturating_sub(NAME_WIDTH + 2) / CELL_WIDTH).saturating_sub(1);
+        let nodes = stats::busiest_nodes(&self.flows, limit as usize);
+        let count = |from: NodeNum, to: NodeNum| {
+            self.flows
+                .iter()
+                .find(|flow| flow.from == from && flow.to == to)
+                .map_or(0, |flow| {
+                    if self.flow_messages {
+                        flow.messages
+                    } else {
+                        flow.packets
+                    }
+                })
+        };
+        let short_name = |num: NodeNum| {
+            self.nodes
+                .get(&num)
+                .and_then(|node| node.user.as_ref())
+                .map_or_else(|| format_node_num(num), |user| user.short_name.clone())
+        };
+        let destinations: Vec<NodeNum> = nodes.iter().copied().chain([BROADCAST]).collect();
+
+        let header = Row::new(std::iter::once(Cell::from("FROM \\ TO")).chain(
+            destinations.iter().map(|&to| {
+                if to == BROADCAST {
+                    Cell::from("ALL")
+                } else {
+                    Cell::from(short_name(to))
+                }
+            }),
+        ))
+        .style(Style::default().add_modifier(Modifier::BOLD));
+        let rows =
+            nodes.iter().map(|&from| {
+                Row::new(std::iter::once(Cell::from(short_name(from))).chain(
+                    destinations.iter().map(|&to| match count(from, to) {
+                        0 => Cell::from(".").style(Style::default().fg(Color::DarkGray)),
+                        n => Cell::from(n.to_string()),
+                    }),
+                ))
+            });
+        let widths = std::iter::once(Constraint::Length(NAME_WIDTH))
+            .chain(destinations.iter().map(|_| Constraint::Length(CELL_WIDTH)));
+        let measure = if self.flow_messages {
+            "TEXT MESSAGES"
+        } else {
+            "PACKETS"
+        };
+        let table = Table::new(rows, widths).header(header).block(
+            Block::bordered()
+                .title(format!("{} SENT, LAST {}", measure, self.flow_window.label()).bold())
+                .title_bottom("LEFT/RIGHT: window  o: packets/messages  ESC: close")
+                .border_style(Style::default().fg(self.palette.accent)),
+        );
+        frame.render_widget(Clear, rect);
+        frame.render_widget(table, rect);
+    }
+
+    fn draw_admin_keys(&mut self, frame: &mut Frame, rect: Rect) {
+        let own_key = self.admin_keypair.as_ref().map(|k| k.public.clone());
+        let items: Vec<Line> = self
+            .security_config
+            .iter()
+            .flat_map(|config| config.admin_key.iter())
+            .map(|key| {
+                let mut line = Line::from(admin_keys::format_key(key));
+                if own_key.as_ref() == Some(key) {
+                    line.push_span(Span::styled(
+                        " (this machine)",
+                        Style::default().fg(Color::Green),
+                    ));
+                }
+                line
+            })
+            .collect();
+        let height = items.len() as u16 + 4;
+        let own = match &own_key {
+            Some(key) => format!("This machine: {}", admin_keys::format_key(key)),
+            None => "This machine has no admin key yet".to_string(),
+        };
+
+        let area = Rect {
+            height: height.min(rect.height),
+            ..rect
+        };
+        frame.render_widget(Clear, area);
+        let block = Block::bordered()
+            .title(
+                format!(
+                    "ADMIN KEYS ({}/{})",
+                    items.len(),
+                    admin_keys::MAX_ADMIN_KEYS
+                )
+                .bold(),
+            )
+            .title_bottom("ENTER: add typed key, or this machine's if empty  DEL: remove")
+            .border_style(Style::default().fg(self.palette.accent));
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+        let [list_rect, own_rect, input_rect] = Layout::vertical([
+            Constraint::Min(0),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(inner);
+        let list = List::new(items)
+            .highlight_symbol("> ")
+            .highlight_style(Style::default().bg(self.palette.selection));
+        frame.render_stateful_widget(list, list_rect, &mut self.admin_key_list_state);
+        frame.render_widget(Paragraph::new(own).gray(), own_rect);
+        frame.render_widget(
+            Paragraph::new(format!("Add: {}", self.admin_key_input)),
+            input_rect,
+        );
+    }
+
+    fn draw_title(&self, frame: &mut Frame, rect: Rect) {
+        let mut title = Block::new()
+            .title_alignment(Alignment::Center)
+            .title("MESHCOM 0.0.1".bold());
+        match (&self.radio, &self.transport) {
+            (Some(radio), Some(transport)) => {
+                title = title.title(format!(" {radio}: {transport} "));
+            }
+            (None, Some(transport)) => title = title.title(format!(" {transport} ")),
+            _ => {}
+        }
+        if self.read_only {
+            title = title.title(" READ-ONLY ".yellow().bold());
+        } else if self.offline {
+            title = title.title(" NO RADIO ".yellow().bold());
+        }
+        if let Some(register) = self.macros.recording() {
+            title = title.title(format!(" RECORDING @{register} ").yellow().bold());
+        }
+        if self.channels.values().any(|c| self.rotation_due(c)) {
+            title = title.title(" PSK ROTATION DUE (c) ".red().bold());
+        }
+        if self.reconnecting() {
+            title = title.title(" RECONNECTING... (w) ".red().bold());
+        } else if self.connection_error.is_some() {
+            title = title.title(" DISCONNECTED (w) ".red().bold());
+        }
+        if let Some((shown, text, colour)) = &self.toast
+            && shown.elapsed() < TOAST_DURATION
+        {
+            title = title.title(format!(" {} ", text).fg(*colour).bold());
+        }
+        let warnings = self.health().warnings().len();
+        if warnings > 0 {
+            title = title.title(format!(" {} HEALTH WARNINGS (w) ", warnings).red().bold());
+        }
+        frame.render_widget(title, rect);
+    }
+
+    /// While the channel has been busy for a while, a warning across the top of `rect`. Returns
+    /// what is left of it.
+    fn draw_congestion_banner(&self, frame: &mut Frame, rect: Rect) -> Rect {
+        if !self.watchdog.congested() {
+            return rect;
+        }
+        let utilization = self
+            .my_node
+            .as_ref()
+            .and_then(|n| n.device_metrics)
+            .and_then(|m| m.channel_utilization)
+            .unwrap_or_default();
+        let [banner, rest] =
+            Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]).areas(rect);
+        let text = format!(
+            " CHANNEL {utilization:.0}% BUSY: send only what matters, and slow position and \
+             telemetry updates "
+        );
+        frame.render_widget(
+            Paragraph::new(text).style(Style::default().fg(Color::Black).bg(Color::Red).bold()),
+            banner,
+        );
+        rest
+    }
+
+    fn draw_conversation(
+        &mut self,
+        frame: &mut Frame,
+        conversation_rect: Rect,
+        scrollbar_rect: Rect,
+    ) {
+        let current_num = self.current_contact;
+
+        let title = if let Some(num) = current_num {
+            let long_name = self
+                .nodes
+                .get(&num)
+                .and_then(|n| n.user.as_ref())
+                .map(|u| u.long_name.as_str())
+                .unwrap_or("UNKNOWN");
+            let mut title = format!("CONNECTED: {}", long_name);
+            let receiving = self.thumbnails.iter().flat_map(Transfers::progress);
+            for (_, received, expected) in receiving.filter(|(from, _, _)| *from == num) {
+                title.push_str(&format!(" (THUMBNAIL {received}/{expected})"));
+            }
+            title
+        } else {
+            "NO NODE CONNECTED".to_string()
+        };
+
+        // Only the visible lines are copied into the paragraph, not the whole history.
+        let inner = conversation_rect.inner(Margin::new(1, 1));
+        let messages = current_num
+            .and_then(|num| self.conversations.get(&num))
+            .map_or(&[][..], Vec::as_slice);
+        self.conversation_view.layout(
+            current_num,
+            messages,
+            &self.acks,
+            &self.channel_badges,
+            inner.width,
+            self.threaded.then_some(&self.collapsed),
+        );
+        let lines = self.conversation_view.lines();
+        let height = usize::from(inner.height);
+        // Keep the selected message in view and highlight it.
+        let selected = self
+            .selected_message
+            .and_then(|index| self.conversation_view.message_range(index));
+        if let Some(selected) = &selected {
+            if selected.start < self.conversation_scroll {
+                self.conversation_scroll = selected.start;
+            } else if selected.end > self.conversation_scroll + height {
+                self.conversation_scroll = (selected.end - height).min(selected.start);
+            }
+        }
+        self.conversation_scroll = self.conversation_scroll.min(lines.len().saturating_sub(1));
+        self.vertical_scroll_state =
+            ScrollbarState::new(lines.len()).position(self.conversation_scroll);
+        let highlight = self.focus.is(Focus::Conversation);
+        let text: Vec<Line> = lines
+            .iter()
+            .enumerate()
+            .skip(self.conversation_scroll)
+            .take(height)
+            .map(|(index, line)| match &selected {
+                Some(selected) if highlight && selected.contains(&index) => {
+                    line.clone().bg(self.palette.selection)
+                }
+                _ => line.clone(),
+            })
+            .collect();
+
+        let paragraph = Paragraph::new(text).gray().block(
+            Block::bordered()
+                .gray()
+                .title(title.as_str().bold())
+                .border_style(if self.focus.is(Focus::Conversation) {
+                    Style::default().fg(self.palette.accent)
+                } else {
+                    Style::default()
+                }),
+        );
+        frame.render_widget(paragraph, conversation_rect);
+        frame.render_stateful_widget(
+            Scrollbar::new(ScrollbarOrientation::VerticalRight)
+                .begin_symbol(Some("#"))
+                .end_symbol(Some("#")),
+            scrollbar_rect,
+            &mut self.vertical_scroll_state,
+        );
+    }
+
+    fn draw_node_list(&mut self, frame: &mut Frame, rect: Rect) {
+        let mut nodes_list_block = Block::bordered()
+            .gray()
+            .title("NODE LIST".bold())
+            .border_style(if self.focus.is(Focus::NodeList) {
+                Style::default().fg(self.palette.accent)
+            } else {
+                Style::default()
+            });
+
+        if !self.smart_views.is_empty() {
+            let names =
+                std::iter::once("All").chain(self.smart_views.iter().map(|v| v.name.as_str()));
+            let selected = self.smart_view.map_or(0, |i| i + 1);
+            let mut spans = vec![Span::raw("[ ")];
+            for (i, name) in names.enumerate() {
+                if i > 0 {
+                    spans.push(Span::raw(" | "));
+                }
+                spans.push(if i == selected {
+                    Span::styled(name.to_string(), Style::default().fg(Color::Yellow).bold())
+                } else {
+                    Span::raw(name.to_string())
+                });
+            }
+            spans.push(Span::raw(" ]"));
+            nodes_list_block = nodes_list_block.title_bottom(Line::from(spans));
+        }
+
+        let now = Local::now().timestamp();
+        let visible_nodes = self.get_visible_nodes();
+        let items: Vec<_> = visible_nodes
+            .iter()
+            .filter_map(|nodeinfo| {
+                let user = nodeinfo.user.as_ref()?;
+                let cells: Vec<String> = self
+                    .node_columns
+                    .iter()
+                    .map(|column| match column {
+                        NodeColumn::Name => {
+                            let name = format!("{:<4}", user.short_name);
+                            if self.team.is_empty() {
+                                name
+                            } else if self.team.contains(&nodeinfo.num) {
+                                format!("[x] {name}")
+                            } else {
+                                format!("[ ] {name}")
+                            }
+                        }
+                        NodeColumn::Snr => format!("{:>5.1}", nodeinfo.snr),
+                        NodeColumn::Hops => match nodeinfo.hops_away {
+                            Some(hops) => format!("{hops:>2}h"),
+                            None => " ?h".to_string(),
+                        },
+                        NodeColumn::LastHeard if nodeinfo.last_heard == 0 => format!("{:>5}", "-"),
+                        NodeColumn::LastHeard => format!(
+                            "{:>5}",
+                            stats::format_duration(
+                                (now - i64::from(nodeinfo.last_heard)).max(0) as f64
+                            )
+                        ),
+                        NodeColumn::Battery => {
+                            match nodeinfo.device_metrics.and_then(|m| m.battery_level) {
+                                // Levels above 100 mean the node runs on external power.
+                                Some(level) if level > 100 => format!("{:>10}", "PWR"),
+                                Some(level) => format!(
+                                    "{} {level:>3}%{}",
+                                    battery::gauge(level),
+                                    self.battery
+                                        .trend(nodeinfo.num)
+                                        .map_or(' ', |trend| trend.arrow())
+                                ),
+                                None => format!("{:>10}", "-"),
+                            }
+                        }
+                    })
+                    .collect();
+                let mut line = Line::from(cells.join(" ").trim_end().to_string());
+                if let Some(badge) = self
+                    .contact_channel(nodeinfo.num)
+                    .and_then(|channel| self.channel_badges.span(channel))
+                {
+                    line.spans.insert(0, badge);
+                }
+                let unread = self.unread_count(nodeinfo.num);
+                if unread > 0 {
+                    line.push_span(Span::styled(
+                        format!(" ({unread})"),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ));
+                }
+                if nodeinfo.hops_away() == 0 {
+                    line = line.patch_style(Style::default().fg(Color::Green));
+                }
+                if self.current_contact == Some(nodeinfo.num) {
+                    line = line.patch_style(
+                        Style::default()
+                            .add_modifier(Modifier::BOLD)
+                            .fg(Color::Cyan),
+                    );
+                }
+                Some(line)
+            })
+            .collect();
+
+        let list = List::new(items)
+            .block(nodes_list_block)
+            .highlight_symbol("> ")
+            .highlight_style(Style::default().bg(self.palette.selection));
+
+        frame.render_stateful_widget(list, rect, &mut self.node_list_state);
+    }
+
+    fn draw_node_detail(&self, frame: &mut Frame, rect: Rect) {
+        let editing = self.focus.is(Focus::Note);
+        let mut lines = Vec::new();
+        if let Some(num) = self.current_contact {
+            let user = self.nodes.get(&num).and_then(|n| n.user.as_ref());
+            lines.push(Line::from(format_node_num(num)));
+            if let Some(user) = user {
+                lines.push(Line::from(format!(
+                    "{} ({})",
+                    user.long_name, user.short_name
+                )));
+            }
+            if let Some((old_id, new_id)) = self.id_changes.get(&num) {
+                lines.push(Line::styled(
+                    format!("USER ID CHANGED: {} -> {}", old_id, new_id),
+                    Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                ));
+            }
+            if let Some(old) = self.renumbered.get(&num) {
+                lines.push(Line::styled(
+                    format!("WAS {} (u: merge history)", format_node_num(*old)),
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::BOLD),
+                ));
+            }
+            let channel = self.contact_channel(num);
+            let mut notify = format!("Notify: {}", self.notify_overrides.node(num).label());
+            let mut mode = self.notify_overrides.node(num);
+            if let Some(channel) = channel {
+                let channel_mode = self.notify_overrides.channel(channel);
+                notify.push_str(&format!("  Channel {}: {}", channel, channel_mode.label()));
+                if mode == NotifyMode::Default {
+                    mode = channel_mode;
+                }
+            }
+            let notify_style = match mode {
+                NotifyMode::Default => Style::default(),
+                NotifyMode::Muted => Style::default().fg(Color::DarkGray),
+                NotifyMode::Always => Style::default().fg(Color::Green),
+            };
+            lines.push(Line::styled(notify, notify_style));
+            if let Some(reboot) = self.remote_reboot.as_ref().filter(|r| r.node == num) {
+                let style = match reboot.stage {
+                    Stage::Back { .. } => Style::default().fg(Color::Green),
+                    Stage::Failed(_) => Style::default().fg(Color::Red),
+                    _ => Style::default().fg(Color::Yellow),
+                };
+                lines.push(Line::styled(format!("Reboot: {}", reboot.status()), style));
+            }
+            let stats = &self.contact_stats;
+            let mut activity = format!("{} received, {} sent", stats.received, stats.sent);
+            if let Some(snr) = stats.average_snr {
+                activity.push_str(&format!(", avg SNR {:.1} dB", snr));
+            }
+            lines.push(Line::from(activity));
+            if let (Some(first), Some(last)) = (stats.first_contact, stats.last_contact) {
+                lines.push(Line::from(format!(
+                    "First {}  Last {}",
+                    first.format("%Y-%m-%d"),
+                    last.format("%Y-%m-%d %H:%M")
+                )));
+            }
+            if let Some(latency) = stats.response_latency {
+                lines.push(Line::from(format!(
+                    "Replies in {} on average",
+                    stats::format_duration(latency)
+                )));
+            }
+            let note = if editing {
+                Some(self.note.as_str())
+            } else {
+                self.notes.get(&num).map(String::as_str)
+            };
+            if let Some(note) = note {
+                lines.push(Line::styled(note, Style::default().fg(Color::Cyan)));
+            }
+        }
+
+        let mut block = Block::bordered().gray().title("DETAIL".bold());
+        if editing {
+            block = block
+                .title_bottom("ENTER: save note")
+                .border_style(Style::default().fg(self.palette.accent));
+        }
+        let detail = Paragraph::new(lines)
+            .block(block)
+            .wrap(Wrap { trim: false });
+        frame.render_widget(detail, rect);
+    }
+
+    fn draw_input_box(&self, frame: &mut Frame, rect: Rect) {
+        let mut title = if !self.team.is_empty() {
+            let mut title = format!("INPUT (TO {} SELECTED NODES)", self.team.len());
+            if let Some(progress) = self.team_progress() {
+                title.push_str(&format!(" LAST: {progress}"));
+            }
+            title
+        } else if self.reply_to.is_some() {
+            "INPUT (REPLY)".to_string()
+        } else {
+            "INPUT".to_string()
+        };
+        if self.input.len() > PACKET_BYTE_LIMIT {
+            title.push_str(" (COMPRESSED, EDDA ONLY)");
+        }
+        let deferred = self
+            .outbox
+            .iter()
+            .filter(|entry| entry.state == OutboxState::Deferred)
+            .count();
+        if deferred > 0 {
+            title.push_str(&format!(" {deferred} BROADCASTS HELD"));
+            if let Some(utilization) = self.busy_channel.utilization() {
+                title.push_str(&format!(", CHANNEL {utilization:.0}% BUSY"));
+            }
+        }
+        // Nothing can be sent until the device is back.
+        let reconnecting = self.reconnecting();
+        if reconnecting {
+            title.push_str(" (NOT CONNECTED)");
+        }
+        let input_box = Paragraph::new(self.input.as_str())
+            .block(
+                Block::bordered()
+                    .title(title.bold())
+                    .border_style(if reconnecting {
+                        Style::default().fg(Color::DarkGray)
+                    } else if self.focus.is(Focus::Input) {
+                        Style::default().fg(self.palette.accent)
+                    } else {
+                        Style::default()
+                    }),
+            )
+            .style(if reconnecting {
+                Style::default().fg(Color::DarkGray)
+            } else {
+                Style::default()
+            })
+            .wrap(Wrap { trim: false });
+        frame.render_widget(input_box, rect);
+    }
+
+    fn draw_search_box(&self, frame: &mut Frame, rect: Rect) {
+        let search_box = Paragraph::new(self.search.as_str())
+            .block(Block::bordered().title("SEARCH".bold()).border_style(
+                if self.focus.is(Focus::Search) {
+                    Style::default().fg(self.palette.accent)
+                } else {
+                    Style::default()
+                },
+            ))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(search_box, rect);
+    }
+
+    fn set_cursor_position(&self, frame: &mut Frame, input_rect: Rect) {
+        if self.focus.is(Focus::Input) {
+            let input_width = input_rect.width.saturating_sub(2); // Subtract 2 for borders
+            let line_count = (self.input.len() as u16 / input_width) + 1;
+            let cursor_x = input_rect.x + (self.input.len() as u16 % input_width) + 1;
+            let cursor_y = input_rect.y + line_count;
+            frame.set_cursor_position((cursor_x, cursor_y));
+        }
+    }
+
+    fn draw_loading(&self, frame: &mut Frame) {
+        let area = frame.area();
+        let loading_text = "Loading...";
+        let loading_paragraph = Paragraph::new(loading_text)
+            .style(Style::default().fg(Color::White))
+            .alignment(Alignment::Center);
 
         let vertical_chunks = Layout::vertical([
             Constraint::Percentage(50),
@@ -428,3 +4538,624 @@ impl App {
         frame.render_widget(loading_paragraph, horizontal_chunks[1]);
     }
 }
+
+/// The radio's own log (d), scrolled with the arrow and page keys.
+pub(crate) struct DeviceLogView;
+
+impl View for DeviceLogView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_device_log(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Up => app.device_log.scroll_up(1),
+            KeyCode::Down => app.device_log.scroll_down(1),
+            KeyCode::PageUp => app.device_log.scroll_up(20),
+            KeyCode::PageDown => app.device_log.scroll_down(20),
+            KeyCode::Char('f') => app.device_log.toggle_follow(),
+            KeyCode::Char('l') => app.device_log.cycle_level(),
+            _ => {}
+        }
+        Action::Handled
+    }
+
+    fn name(&self) -> Option<&'static str> {
+        Some("device log")
+    }
+
+    fn state(&self, app: &App) -> ViewState {
+        ViewState {
+            selected: None,
+            scroll: app.device_log.offset(),
+            filter: app.device_log.min_level.as_str_name().to_string(),
+        }
+    }
+
+    fn restore(&mut self, app: &mut App, state: &ViewState) {
+        if let Some(level) = Level::from_str_name(&state.filter) {
+            app.device_log.min_level = level;
+        }
+        app.device_log.scroll_to(state.scroll);
+    }
+}
+
+/// The mesh health summary (w), with the connection error and any anomalies spotted.
+pub(crate) struct HealthView;
+
+impl View for HealthView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_health(frame, rect);
+    }
+
+    fn handle_action(&mut self, _app: &mut App, _key: KeyEvent) -> Action {
+        Action::Handled
+    }
+}
+
+/// The message form (f), filled in field by field and sent to the current contact.
+pub(crate) struct FormView;
+
+impl View for FormView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_form(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Tab | KeyCode::Down => app.form.next_field(),
+            KeyCode::BackTab | KeyCode::Up => app.form.previous_field(),
+            KeyCode::Char(c) => app.form.push(c),
+            KeyCode::Backspace => app.form.pop(),
+            KeyCode::Enter if app.form.field != FormField::Body => app.form.next_field(),
+            KeyCode::Enter => {
+                app.form.form.number = app.traffic.next_number();
+                let message = app.form.form.encode();
+                if let Some(id) = app.current_contact
+                    && compression::fits(&message, app.compress_long_messages)
+                {
+                    app.traffic.record_sent(id, &app.form.form);
+                    app.form = FormDraft::default();
+                    app.send_message(id, message);
+                    return Action::Close;
+                }
+            }
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// Saved owner profiles (p): type a name to save the current one, or pick one to apply.
+pub(crate) struct ProfilesView;
+
+impl View for ProfilesView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_profiles(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Down => app.profile_list_state.select_next(),
+            KeyCode::Up => app.profile_list_state.select_previous(),
+            KeyCode::Delete => app.delete_profile(),
+            KeyCode::Char(c) => app.profile_name.push(c),
+            KeyCode::Backspace => {
+                app.profile_name.pop();
+            }
+            KeyCode::Enter if app.profile_name.is_empty() => {
+                return Action::close_when(app.apply_profile());
+            }
+            KeyCode::Enter => app.save_profile(),
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// The LoRa settings (r), stepped through with the arrow keys.
+pub(crate) struct LoraView;
+
+impl View for LoraView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_lora(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Down => app.lora.next_field(),
+            KeyCode::Up => app.lora.previous_field(),
+            KeyCode::Right => app.lora.step(true),
+            KeyCode::Left => app.lora.step(false),
+            KeyCode::Enter => return Action::close_when(app.apply_lora()),
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// The device's fixed position (l).
+pub(crate) struct FixedPositionView;
+
+impl View for FixedPositionView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_fixed_position(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Char(c) => app.fixed_position.push(c),
+            KeyCode::Backspace => app.fixed_position.pop(),
+            KeyCode::Down => app.fixed_position.next_field(),
+            KeyCode::Up => app.fixed_position.previous_field(),
+            KeyCode::Enter if app.fixed_position.field != PositionField::Altitude => {
+                app.fixed_position.next_field()
+            }
+            KeyCode::Enter => return Action::close_when(app.apply_fixed_position()),
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// The device's channels (c), with PSK rotation and sharing.
+pub(crate) struct ChannelsView;
+
+impl View for ChannelsView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_channels(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Down => {
+                app.pending_rotation = None;
+                app.channel_list_state.select_next();
+            }
+            KeyCode::Up => {
+                app.pending_rotation = None;
+                app.channel_list_state.select_previous();
+            }
+            KeyCode::Enter => app.rotate_selected_psk(),
+            KeyCode::Char('e') => app.export_channel_url(),
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// The keys allowed to administer the device remotely (a).
+pub(crate) struct AdminKeysView;
+
+impl View for AdminKeysView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_admin_keys(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Down => app.admin_key_list_state.select_next(),
+            KeyCode::Up => app.admin_key_list_state.select_previous(),
+            KeyCode::Delete => app.remove_selected_admin_key(),
+            KeyCode::Char(c) => app.admin_key_input.push(c),
+            KeyCode::Backspace => {
+                app.admin_key_input.pop();
+            }
+            // An empty input adds this machine's own key.
+            KeyCode::Enter if app.admin_key_input.is_empty() => {
+                let keypair = app.own_admin_keypair();
+                app.add_admin_key(keypair.public);
+            }
+            KeyCode::Enter => match admin_keys::parse_key(&app.admin_key_input) {
+                Some(key) => {
+                    app.admin_key_input.clear();
+                    app.add_admin_key(key);
+                }
+                None => log::warn!(
+                    "Not a base64 Curve25519 public key: {}",
+                    app.admin_key_input
+                ),
+            },
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// The most active nodes (s), over a window picked with the arrow keys.
+pub(crate) struct LeaderboardView;
+
+impl View for LeaderboardView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_leaderboard(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Right => {
+                app.leaderboard_window = app.leaderboard_window.next();
+                app.refresh_leaderboard();
+            }
+            KeyCode::Left => {
+                app.leaderboard_window = app.leaderboard_window.previous();
+                app.refresh_leaderboard();
+            }
+            KeyCode::Char('o') => {
+                app.leaderboard_ranking = app.leaderboard_ranking.next();
+                stats::rank(&mut app.leaderboard, app.leaderboard_ranking);
+            }
+            _ => {}
+        }
+        Action::Handled
+    }
+
+    fn refresh(&mut self, app: &mut App) {
+        app.refresh_leaderboard();
+    }
+}
+
+/// Who sends packets to whom (v), over a window picked with the arrow keys.
+pub(crate) struct FlowsView;
+
+impl View for FlowsView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_flows(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Right => {
+                app.flow_window = app.flow_window.next();
+                app.refresh_flows();
+            }
+            KeyCode::Left => {
+                app.flow_window = app.flow_window.previous();
+                app.refresh_flows();
+            }
+            KeyCode::Char('o') => app.flow_messages = !app.flow_messages,
+            _ => {}
+        }
+        Action::Handled
+    }
+
+    fn refresh(&mut self, app: &mut App) {
+        app.refresh_flows();
+    }
+}
+
+/// What can be done with the selected message (Enter in the conversation).
+pub(crate) struct MessageActionsView;
+
+impl View for MessageActionsView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_message_actions(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Down => {
+                let next = app
+                    .message_action_state
+                    .selected()
+                    .map_or(0, |i| i + 1)
+                    .min(MessageAction::ALL.len() - 1);
+                app.message_action_state.select(Some(next));
+            }
+            KeyCode::Up => app.message_action_state.select_previous(),
+            KeyCode::Char('x') if app.message_details => {
+                app.raw_packet_view = !app.raw_packet_view;
+            }
+            KeyCode::Enter => {
+                let Some(&action) = app
+                    .message_action_state
+                    .selected()
+                    .and_then(|i| MessageAction::ALL.get(i))
+                else {
+                    return Action::Handled;
+                };
+                if let Some(what) = app.refused_message_action(action) {
+                    app.refuse_offline(what);
+                    return Action::Handled;
+                }
+                app.run_message_action(action);
+                // The details show in this popup; every other action is done with it.
+                if action != MessageAction::Details {
+                    return Action::Close;
+                }
+            }
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// The local note on the selected message.
+pub(crate) struct MessageNoteView;
+
+impl View for MessageNoteView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_message_note(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Char(c) => app.message_note.push(c),
+            KeyCode::Backspace => {
+                app.message_note.pop();
+            }
+            KeyCode::Enter => {
+                app.save_message_note();
+                app.focus.set(Focus::Conversation);
+                return Action::Close;
+            }
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// Messages not confirmed yet (o), to cancel or retry.
+pub(crate) struct OutboxView;
+
+impl View for OutboxView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_outbox(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Down => app.outbox_table_state.select_next(),
+            KeyCode::Up => app.outbox_table_state.select_previous(),
+            KeyCode::Char('c') => {
+                if let Some(id) = app.selected_outbox_entry() {
+                    app.update_outbox(id, None);
+                }
+            }
+            KeyCode::Char('r') => {
+                if let Some(id) = app.selected_outbox_entry() {
+                    app.retry_outbox_entry(id);
+                }
+            }
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// The quick switcher (Ctrl+K), jumping to a conversation by name.
+pub(crate) struct SwitcherView;
+
+impl View for SwitcherView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_switcher(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Char(c) => {
+                app.switcher.push(c);
+                app.switcher_state.select(Some(0));
+            }
+            KeyCode::Backspace => {
+                app.switcher.pop();
+                app.switcher_state.select(Some(0));
+            }
+            KeyCode::Down => {
+                let next = app
+                    .switcher_state
+                    .selected()
+                    .map_or(0, |i| i + 1)
+                    .min(app.switcher_matches().len().saturating_sub(1));
+                app.switcher_state.select(Some(next));
+            }
+            KeyCode::Up => app.switcher_state.select_previous(),
+            KeyCode::Enter => {
+                let matches = app.switcher_matches();
+                if let Some((_, id)) = app.switcher_state.selected().and_then(|i| matches.get(i)) {
+                    app.open_conversation(*id);
+                    app.focus.set(Focus::Input);
+                    return Action::Close;
+                }
+            }
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// The global search (Ctrl+F) through nodes, messages and waypoints.
+pub(crate) struct GlobalSearchView;
+
+impl View for GlobalSearchView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_global_search(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Char(c) => {
+                app.global_search.push(c);
+                app.refresh_search_results();
+            }
+            KeyCode::Backspace => {
+                app.global_search.pop();
+                app.refresh_search_results();
+            }
+            KeyCode::Down => {
+                let next = app
+                    .search_results_state
+                    .selected()
+                    .map_or(0, |i| i + 1)
+                    .min(app.search_results.len().saturating_sub(1));
+                app.search_results_state.select(Some(next));
+            }
+            KeyCode::Up => app.search_results_state.select_previous(),
+            KeyCode::Enter => return Action::close_when(app.open_search_result()),
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// The command line (:), over the bottom line of the screen.
+pub(crate) struct CommandLineView;
+
+impl View for CommandLineView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, _rect: Rect) {
+        app.draw_command_line(frame);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Char(c) => app.command_line.push(c),
+            KeyCode::Backspace if app.command_line.text.is_empty() => return Action::Close,
+            KeyCode::Backspace => app.command_line.pop(),
+            KeyCode::Up => app.command_line.previous(),
+            KeyCode::Down => app.command_line.next(),
+            KeyCode::Tab => app.command_line.complete(),
+            KeyCode::Enter => return Action::close_when(app.submit_command()),
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// The path of an image to send to the current contact as a thumbnail (i).
+pub(crate) struct ThumbnailView;
+
+impl View for ThumbnailView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_thumbnail(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Char(c) => app.thumbnail_path.push(c),
+            KeyCode::Backspace => {
+                app.thumbnail_path.pop();
+            }
+            KeyCode::Enter => return Action::close_when(app.send_thumbnail()),
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// A checklist or status report for the current contact (e), previewed as it is typed.
+pub(crate) struct StructuredView;
+
+impl View for StructuredView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_structured(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Char(c) => app.structured_draft.push(c),
+            KeyCode::Backspace => {
+                app.structured_draft.pop();
+            }
+            KeyCode::Enter => return Action::close_when(app.send_structured()),
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// Packets on default channels uploaded to MQTT (x).
+pub(crate) struct MonitorView;
+
+impl View for MonitorView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_monitor(frame, rect);
+    }
+
+    fn handle_action(&mut self, _app: &mut App, _key: KeyEvent) -> Action {
+        Action::Handled
+    }
+}
+
+/// The route and signal to the current contact (P), exported with e.
+pub(crate) struct ProbeView;
+
+impl View for ProbeView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_probe(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        if key.code == KeyCode::Char('e') {
+            app.export_probe();
+        }
+        Action::Handled
+    }
+}
+
+/// A setting changed on many nodes at once (B); typing is ignored while a change runs.
+pub(crate) struct BulkAdminView;
+
+impl View for BulkAdminView {
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        app.draw_bulk_admin(frame, rect);
+    }
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action {
+        if app
+            .bulk_admin
+            .as_ref()
+            .is_some_and(|bulk| !bulk.is_finished())
+        {
+            return Action::Handled;
+        }
+        match key.code {
+            KeyCode::Char(' ') => app.bulk_channel = !app.bulk_channel,
+            KeyCode::Char(c) if c.is_ascii_digit() => app.bulk_value.push(c),
+            KeyCode::Backspace => {
+                app.bulk_value.pop();
+            }
+            KeyCode::Enter => app.start_bulk_admin(),
+            _ => {}
+        }
+        Action::Handled
+    }
+}
+
+/// The reaction to send as a read receipt, if they are on.
+fn read_receipt(ui: &UiConfig) -> Option<String> {
+    ui.read_receipts.then(|| ui.read_receipt_emoji.clone())
+}
+
+/// Whether a packet is a direct message worth a read receipt: not a broadcast, and not itself a
+/// reaction, so two clients sending receipts don't answer each other forever.
+fn wants_read_receipt(packet: &MeshPacket) -> bool {
+    packet.to != BROADCAST && !message_actions::is_reaction(packet)
+}
+
+/// The smart views configured, leaving out those that do not parse.
+fn smart_views(config: &UiConfig, store: &dyn Store) -> Vec<SmartView> {
+    config
+        .smart_views
+        .iter()
+        .filter_map(|view| match SmartView::parse(view) {
+            Ok(mut view) => {
+                if let Err(e) = view.refresh(store) {
+                    log::error!("Failed to evaluate smart view {}: {}", view.name, e);
+                }
+                Some(view)
+            }
+            Err(e) => {
+                log::error!("Failed to parse {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Time between redraws allowed by the UI settings.
+fn redraw_interval(ui: &UiConfig) -> Duration {
+    let mut redraws_per_second = ui.max_redraws_per_second;
+    if ui.plain {
+        redraws_per_second = redraws_per_second.min(plain::MAX_REDRAWS_PER_SECOND);
+    }
+    Duration::from_secs(1) / redraws_per_second.max(1)
+}
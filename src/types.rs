@@ -1,34 +1,362 @@
-use std::time::SystemTime;
+use std::fmt;
+use std::time::Duration;
 
-use meshtastic::protobufs::NodeInfo;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::{DateTime, Local};
+use meshtastic::protobufs::{
+    Channel, Config, DeviceMetadata, DeviceMetrics, LogRecord, MeshPacket, NodeInfo, Position,
+    RouteDiscovery, User, Waypoint, admin_message,
+    config::{DeviceConfig, LoRaConfig, SecurityConfig},
+};
 use meshtastic::types::NodeId;
 
+use crate::mesh::{MeshError, Transport};
+use crate::monitor::MonitoredPacket;
+use crate::profiles::Profile;
+
 /// Events originating from the user interface and going to the Meshtastic thread.
 #[derive(Debug)]
 pub enum UiEvent {
-    Message { node_id: NodeId, message: String },
+    Message {
+        node_id: NodeId,
+        message: String,
+        /// Channel index to send on.
+        channel: u32,
+        /// Packet ID of the message this replies or reacts to.
+        reply_id: Option<u32>,
+        /// The message is an emoji reaction to `reply_id`.
+        emoji: bool,
+        /// Outbox entry to report progress for.
+        outbox: Option<i64>,
+    },
+    /// Apply a saved owner profile to our own device.
+    ApplyProfile(Profile),
+    /// Replace the LoRa configuration of our own device.
+    SetLoraConfig(LoRaConfig),
+    /// Replace the security config, including admin keys, of our own device.
+    SetSecurityConfig(SecurityConfig),
+    /// Replace one of our own device's channels.
+    SetChannel(Channel),
+    /// Give our own device a fixed position, for nodes without GPS.
+    SetFixedPosition(Position),
+    /// Ask a remote node for an admin session passkey.
+    RequestAdminSession(NodeNum),
+    /// Reboot a remote node, with the passkey of its admin session.
+    RebootNode { node: NodeNum, passkey: Vec<u8> },
+    /// Send a packet on the private app port, which edda uses for thumbnails.
+    PrivateData { to: NodeNum, payload: Vec<u8> },
+    /// Ask a remote node for its metadata, position and telemetry, and trace the route to it.
+    Probe(NodeNum),
+    /// Send an admin message to a remote node, with the passkey of its admin session if the
+    /// message changes anything.
+    RemoteAdmin {
+        node: NodeNum,
+        payload: Box<admin_message::PayloadVariant>,
+        passkey: Vec<u8>,
+    },
+    /// Drop the connection to the device and connect to this one instead.
+    Connect(Transport),
+    /// With several radios, make the one with this name the active one.
+    SwitchRadio(String),
+}
+
+/// The state of the connection to the device, as it changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    /// Connected, and the device has sent its configuration.
+    Connected,
+    /// The connection was lost or could not be made, and is tried again after a while.
+    Reconnecting { attempt: u32, after: Duration },
 }
 
 /// Events originating from the Meshtastic thread going to the user interface.
 pub enum MeshEvent {
+    /// Information about the node we are connected to.
+    MyNode(Box<NodeInfo>),
     NodeAvailable(Box<NodeInfo>),
-    Message { node_id: NodeId, message: String },
+    /// The device configuration of the node we are connected to.
+    DeviceConfig(DeviceConfig),
+    /// The LoRa configuration of the node we are connected to.
+    LoraConfig(LoRaConfig),
+    /// The security config, including admin keys, of the node we are connected to.
+    SecurityConfig(SecurityConfig),
+    /// One of the channels configured on the node we are connected to.
+    Channel(Channel),
+    Message {
+        node_id: NodeId,
+        to: NodeId,
+        channel: u32,
+        message: String,
+        /// Signal-to-noise ratio the packet was received at, in dB.
+        snr: f32,
+        /// Packet ID, for replying and reacting to the message.
+        id: u32,
+        /// The whole packet, for inspecting it.
+        packet: Box<MeshPacket>,
+    },
+    Position {
+        node_id: NodeId,
+        position: Position,
+    },
+    /// Any packet from another node, for activity statistics.
+    PacketHeard {
+        from: NodeNum,
+        /// Destination, or `BROADCAST`.
+        to: NodeNum,
+        /// A `PortNum`, or -1 if we could not decrypt the packet.
+        port: i32,
+        /// How many times the packet was relayed before reaching us, if known.
+        hops: Option<u32>,
+        /// Estimated time the packet occupied the channel.
+        airtime_ms: f64,
+        /// Channel index the packet was sent on, if we could decrypt it.
+        channel: Option<u32>,
+    },
+    /// The radio took a message we sent.
+    Sent {
+        outbox: i64,
+        /// The ID the packet went out with, if the radio echoed it.
+        packet_id: Option<u32>,
+    },
+    /// Our own node heard a direct message we sent being rebroadcast, before its destination
+    /// acknowledged it.
+    Relayed {
+        outbox: i64,
+    },
+    /// A remote node answered an admin request, opening a session with this passkey.
+    AdminSession {
+        from: NodeNum,
+        passkey: Vec<u8>,
+    },
+    /// Whether a direct message we sent reached its destination.
+    Delivery {
+        to: NodeNum,
+        delivered: bool,
+        outbox: Option<i64>,
+        /// Last byte of the node number that passed the acknowledgement on to us, if the
+        /// firmware reported it.
+        relay_node: Option<u8>,
+        /// How many times the acknowledgement was relayed on its way back, if known.
+        hops: Option<u32>,
+    },
+    /// The device we are connected to restarted.
+    Rebooted,
+    /// A line of the device's own log.
+    DeviceLog(LogRecord),
+    /// A packet on a default channel, uploaded to MQTT by some gateway, in monitor mode.
+    Monitored(Box<MonitoredPacket>),
+    /// A packet on the private app port, which edda uses for thumbnails.
+    PrivateData {
+        from: NodeNum,
+        payload: Vec<u8>,
+    },
+    /// A node announced who it is, as nodes do every few hours and when they start.
+    User {
+        from: NodeNum,
+        user: User,
+    },
+    /// Something went wrong talking to the device. A fatal error ends the connection, which is
+    /// then tried again, as `ConnectionStatus` tells.
+    Error(MeshError),
+    ConnectionStatus(ConnectionStatus),
+    /// With several radios, events about our own node now come from this one, which starts over
+    /// telling its node, configuration and channels.
+    Radio {
+        name: String,
+        transport: Transport,
+    },
+    /// Battery, voltage and channel utilization reported by a node, including our own.
+    DeviceMetrics {
+        from: NodeNum,
+        metrics: DeviceMetrics,
+    },
+    /// A remote node answered a request for its metadata.
+    Metadata {
+        from: NodeNum,
+        metadata: DeviceMetadata,
+    },
+    /// A remote node answered a request for one of its config sections.
+    RemoteConfig {
+        from: NodeNum,
+        config: Config,
+    },
+    /// A node answered our traceroute, with the route there and back.
+    Traceroute {
+        from: NodeNum,
+        route: RouteDiscovery,
+    },
+    /// A node shared a waypoint, or a new version of one.
+    Waypoint {
+        from: NodeNum,
+        waypoint: Waypoint,
+    },
 }
 
 pub type NodeNum = u32;
 
-#[derive(Debug)]
+/// Longest text payload we will send in one packet.
+pub const PACKET_BYTE_LIMIT: usize = 200;
+
+/// Destination address meaning "every node on the channel".
+pub const BROADCAST: NodeNum = 0xFFFFFFFF;
+
+/// Format a node number the way Meshtastic clients display it, e.g. `!a1b2c3d4`.
+pub fn format_node_num(num: NodeNum) -> String {
+    format!("!{num:08x}")
+}
+
+/// Parse a node number written either as `!a1b2c3d4` or in decimal.
+pub fn parse_node_num(s: &str) -> Option<NodeNum> {
+    match s.strip_prefix('!') {
+        Some(hex) => NodeNum::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Who is behind a node. Unlike the node number, which changes when a device is reflashed or
+/// replaced, this stays with the person: the public key of their user, or their user ID.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ContactId {
+    PublicKey(Vec<u8>),
+    UserId(String),
+}
+
+impl ContactId {
+    /// What a user can be recognised by, the public key first.
+    pub fn of(user: &User) -> Vec<ContactId> {
+        let mut ids = Vec::new();
+        if !user.public_key.is_empty() {
+            ids.push(ContactId::PublicKey(user.public_key.clone()));
+        }
+        if !user.id.is_empty() {
+            ids.push(ContactId::UserId(user.id.clone()));
+        }
+        ids
+    }
+
+    /// Parse an identity as written by `Display`, e.g. `key:<base64>` or `id:!a1b2c3d4`.
+    pub fn parse(s: &str) -> Option<Self> {
+        if let Some(key) = s.strip_prefix("key:") {
+            STANDARD.decode(key).ok().map(ContactId::PublicKey)
+        } else {
+            s.strip_prefix("id:")
+                .map(|id| ContactId::UserId(id.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for ContactId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContactId::PublicKey(key) => write!(f, "key:{}", STANDARD.encode(key)),
+            ContactId::UserId(id) => write!(f, "id:{id}"),
+        }
+    }
+}
+
+/// A person on the mesh and the node numbers they have been heard under.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contact {
+    pub id: ContactId,
+    /// Each node number with when it was last heard (seconds since the epoch), oldest first; the
+    /// last is the number the person uses now.
+    pub nodes: Vec<(NodeNum, i64)>,
+}
+
+impl Contact {
+    pub fn new(id: ContactId) -> Self {
+        Self {
+            id,
+            nodes: Vec::new(),
+        }
+    }
+
+    /// The node number the person uses now.
+    pub fn node(&self) -> Option<NodeNum> {
+        self.nodes.last().map(|&(node, _)| node)
+    }
+
+    /// Note that the person was heard under `node` at `time`.
+    pub fn heard(&mut self, node: NodeNum, time: i64) {
+        match self.nodes.iter_mut().find(|(num, _)| *num == node) {
+            Some((_, last)) => *last = (*last).max(time),
+            None => self.nodes.push((node, time)),
+        }
+        self.nodes.sort_by_key(|&(_, time)| time);
+    }
+}
+
+/// A text message in the conversation with a node.
+#[derive(Debug, Clone)]
 pub struct Message {
-    to: NodeId,
-    name: String,
-    ts: SystemTime,
+    pub outgoing: bool,
+    pub ts: DateTime<Local>,
+    pub channel: u32,
+    pub text: String,
+    /// Signal-to-noise ratio an incoming message was received at, if known.
+    pub snr: Option<f32>,
+    /// Packet ID of the message, for replying and reacting to it; an outgoing message has one
+    /// once the radio took it.
+    pub packet_id: Option<u32>,
+    /// The device's clock was not set, so `ts` is when we got the message rather than when the
+    /// device received it.
+    pub estimated_time: bool,
+    /// A local note about the message, e.g. how it was confirmed. Never sent.
+    pub note: Option<String>,
+    /// Packet ID of the message this replies or reacts to.
+    pub reply_id: Option<u32>,
 }
 
-/// The specific element of the UI that is currently focused.
-#[derive(PartialEq, Copy, Clone)]
+/// The pane of the UI that is currently focused. Popups are views opened over the panes; see
+/// `view::Screens`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Focus {
     NodeList,
     Conversation,
     Input,
     Search,
+    /// Editing the note in the node detail pane.
+    Note,
+}
+
+impl Focus {
+    /// Name of a main pane, for remembering it across restarts. The note editor has none.
+    pub fn pane_name(self) -> Option<&'static str> {
+        match self {
+            Focus::NodeList => Some("nodes"),
+            Focus::Conversation => Some("conversation"),
+            Focus::Input => Some("input"),
+            Focus::Search => Some("search"),
+            Focus::Note => None,
+        }
+    }
+
+    pub fn from_pane_name(name: &str) -> Option<Self> {
+        match name {
+            "nodes" => Some(Focus::NodeList),
+            "conversation" => Some(Focus::Conversation),
+            "input" => Some(Focus::Input),
+            "search" => Some(Focus::Search),
+            _ => None,
+        }
+    }
+}
+
+/// Where the TUI was left, restored on the next launch.
+#[derive(Default, Clone)]
+pub struct SessionState {
+    pub contact: Option<NodeNum>,
+    /// Lines the conversation was scrolled down by.
+    pub scroll: usize,
+    pub focus: Option<Focus>,
+}
+
+/// What a pane or screen was showing, kept while it is closed and across restarts.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ViewState {
+    pub selected: Option<usize>,
+    /// Lines scrolled by.
+    pub scroll: usize,
+    /// Filter text, or the name of the level or mode filtered by.
+    pub filter: String,
 }
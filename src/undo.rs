@@ -0,0 +1,87 @@
+//! Undoing local-only actions that lose something: deleting a message, clearing or replacing a
+//! node's note, deleting a profile. Nothing sent to the device or the mesh can be undone.
+//!
+//! Each such action leaves what it removed on a stack, and `U` puts back the last one. Only the
+//! last few are kept, and only until edda quits.
+
+use crate::profiles::Profile;
+use crate::types::{Message, NodeNum};
+
+/// How many actions can be undone.
+const UNDO_LIMIT: usize = 20;
+
+#[derive(Debug, Clone)]
+pub enum Undo {
+    /// A message deleted from the conversation with a node; it goes back in by its time.
+    Message {
+        node: NodeNum,
+        message: Message,
+    },
+    /// The note a node had before it was cleared or replaced.
+    Note {
+        node: NodeNum,
+        note: String,
+    },
+    Profile(Profile),
+}
+
+impl Undo {
+    /// What was done, for the toast offering to undo it.
+    pub fn done(&self) -> &'static str {
+        match self {
+            Undo::Message { .. } => "Message deleted",
+            Undo::Note { .. } => "Note changed",
+            Undo::Profile(_) => "Profile deleted",
+        }
+    }
+
+    /// What undoing puts back, for the toast.
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Undo::Message { .. } => "message",
+            Undo::Note { .. } => "note",
+            Undo::Profile(_) => "profile",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct UndoStack {
+    actions: Vec<Undo>,
+}
+
+impl UndoStack {
+    pub fn push(&mut self, undo: Undo) {
+        if self.actions.len() == UNDO_LIMIT {
+            self.actions.remove(0);
+        }
+        self.actions.push(undo);
+    }
+
+    pub fn pop(&mut self) -> Option<Undo> {
+        self.actions.pop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_last_actions_are_kept() {
+        let mut stack = UndoStack::default();
+        for node in 0..25 {
+            stack.push(Undo::Note {
+                node,
+                note: String::new(),
+            });
+        }
+        let mut left = Vec::new();
+        while let Some(Undo::Note { node, .. }) = stack.pop() {
+            left.push(node);
+        }
+        assert_eq!(left.len(), UNDO_LIMIT);
+        assert_eq!(left.first(), Some(&24));
+        assert_eq!(left.last(), Some(&5));
+    }
+}
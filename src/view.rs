@@ -0,0 +1,149 @@
+//! Screens and popups opened over the main panes, kept as a stack: the top one is drawn last and
+//! gets the keys, and Esc closes it, uncovering the one below.
+//!
+//! Each is a `View`, drawing itself from the app and acting on keys, so adding one (a map,
+//! statistics, settings) means writing the view rather than growing `App::draw` and the key
+//! handling in `App::run`.
+//!
+//! Views with a name keep their state (selection, scrolling, filter) when closed, and have it back
+//! on opening again, including after a restart: the app stores the states on quitting.
+
+use std::any::Any;
+use std::collections::HashMap;
+
+use ratatui::{
+    Frame,
+    crossterm::event::{KeyCode, KeyEvent},
+    layout::Rect,
+};
+
+use crate::tui::App;
+use crate::types::ViewState;
+
+/// What a view did with a key.
+pub enum Action {
+    /// Used the key, or had no use for it; the view stays open.
+    Handled,
+    /// Close this view.
+    Close,
+    /// Open another view on top of this one.
+    Open(Box<dyn View>),
+}
+
+impl Action {
+    /// Close the view once what it is for is done, or else leave it open, e.g. to try again.
+    pub fn close_when(done: bool) -> Self {
+        if done { Action::Close } else { Action::Handled }
+    }
+}
+
+pub trait View: Any {
+    /// Draw over `rect`, the area of the conversation pane. The app is mutable for the list and
+    /// table states it keeps, which scroll as they are drawn.
+    fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect);
+
+    fn handle_action(&mut self, app: &mut App, key: KeyEvent) -> Action;
+
+    /// Catch up with a packet heard while the view is open.
+    fn refresh(&mut self, _app: &mut App) {}
+
+    /// Name to keep the view's state under; views without one start afresh each time.
+    fn name(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// What to keep of the view when it closes.
+    fn state(&self, _app: &App) -> ViewState {
+        ViewState::default()
+    }
+
+    /// Go back to the state the view was left in.
+    fn restore(&mut self, _app: &mut App, _state: &ViewState) {}
+}
+
+#[derive(Default)]
+pub struct Screens {
+    stack: Vec<Box<dyn View>>,
+    /// States of the named views, as last left.
+    pub states: HashMap<String, ViewState>,
+}
+
+impl Screens {
+    pub fn open(&mut self, app: &mut App, mut view: Box<dyn View>) {
+        if let Some(state) = view.name().and_then(|name| self.states.get(name)) {
+            view.restore(app, state);
+        }
+        self.stack.push(view);
+    }
+
+    pub fn close(&mut self, app: &App) {
+        if let Some(view) = self.stack.pop() {
+            self.keep_state(app, view.as_ref());
+        }
+    }
+
+    /// Note the state of every open view, e.g. before quitting.
+    pub fn keep_open_states(&mut self, app: &App) {
+        for view in std::mem::take(&mut self.stack) {
+            self.keep_state(app, view.as_ref());
+            self.stack.push(view);
+        }
+    }
+
+    fn keep_state(&mut self, app: &App, view: &dyn View) {
+        if let Some(name) = view.name() {
+            self.states.insert(name.to_string(), view.state(app));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Whether the view on top is a `V`.
+    pub fn on_top<V: View>(&self) -> bool {
+        self.stack.last().is_some_and(|view| {
+            let view: &dyn Any = view.as_ref();
+            view.is::<V>()
+        })
+    }
+
+    /// Draw every open view, bottom first, so the top one is in front.
+    pub fn draw(&mut self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        for view in &mut self.stack {
+            view.draw(app, frame, rect);
+        }
+    }
+
+    pub fn refresh(&mut self, app: &mut App) {
+        for view in &mut self.stack {
+            view.refresh(app);
+        }
+    }
+
+    /// Views opened, and states kept, while this stack was taken out of the app.
+    pub fn extend(&mut self, opened: Screens) {
+        self.stack.extend(opened.stack);
+        self.states.extend(opened.states);
+    }
+
+    /// Give `key` to the top view, doing what it asks. Esc closes it.
+    pub fn handle_action(&mut self, app: &mut App, key: KeyEvent) {
+        if key.code == KeyCode::Esc {
+            self.close(app);
+            return;
+        }
+        let Some(view) = self.stack.last_mut() else {
+            return;
+        };
+        match view.handle_action(app, key) {
+            Action::Handled => {}
+            Action::Close => self.close(app),
+            Action::Open(view) => self.open(app, view),
+        }
+    }
+}
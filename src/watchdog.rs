@@ -0,0 +1,156 @@
+//! Keeps an eye on the device we are connected to: reboots, sudden battery drops and silence.
+//!
+//! Most useful for unattended base stations, where nobody would otherwise notice.
+
+use std::time::{Duration, Instant};
+
+use crate::config::WatchdogConfig;
+
+/// Battery drops are measured over this window.
+const BATTERY_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// Battery levels above 100 mean the device is externally powered.
+const MAX_BATTERY_LEVEL: u32 = 100;
+
+pub struct Watchdog {
+    config: WatchdogConfig,
+    last_heard: Instant,
+    silent: bool,
+    /// Highest battery level within the window, and when it was seen.
+    battery_peak: Option<(u32, Instant)>,
+    /// When channel utilization last went above the threshold, while it stays there.
+    busy_since: Option<Instant>,
+    /// Whether it has stayed there long enough to warn about.
+    congested: bool,
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        Self {
+            config,
+            last_heard: Instant::now(),
+            silent: false,
+            battery_peak: None,
+            busy_since: None,
+            congested: false,
+        }
+    }
+
+    /// Use new thresholds, keeping what was observed so far.
+    pub fn set_config(&mut self, config: WatchdogConfig) {
+        self.config = config;
+    }
+
+    /// Note that the device said something. Returns an alert if it had gone silent.
+    pub fn heard(&mut self) -> Option<String> {
+        self.last_heard = Instant::now();
+        if !self.silent {
+            return None;
+        }
+        self.silent = false;
+        Some("Device is talking again".to_string())
+    }
+
+    pub fn rebooted(&mut self) -> String {
+        self.battery_peak = None;
+        "Device rebooted".to_string()
+    }
+
+    /// Note our own battery level. Returns an alert if it fell sharply.
+    pub fn battery(&mut self, level: u32) -> Option<String> {
+        if level > MAX_BATTERY_LEVEL {
+            self.battery_peak = None;
+            return None;
+        }
+        let now = Instant::now();
+        let peak = match self.battery_peak {
+            Some((peak, seen)) if peak >= level && now - seen < BATTERY_WINDOW => peak,
+            _ => {
+                self.battery_peak = Some((level, now));
+                return None;
+            }
+        };
+        if peak - level < self.config.battery_drop_percent {
+            return None;
+        }
+        // Start over from here so the same drop is only reported once.
+        self.battery_peak = Some((level, now));
+        Some(format!(
+            "Device battery dropped from {}% to {}% within an hour",
+            peak, level
+        ))
+    }
+
+    /// Note the channel utilization our device reports, in percent. Returns an alert once it has
+    /// stayed above the threshold for long enough, and again when it falls back below.
+    pub fn channel_utilization(&mut self, utilization: f32) -> Option<String> {
+        if utilization <= self.config.channel_utilization_percent {
+            self.busy_since = None;
+            if !self.congested {
+                return None;
+            }
+            self.congested = false;
+            return Some(format!(
+                "Channel utilization back down to {:.0}%",
+                utilization
+            ));
+        }
+        let since = *self.busy_since.get_or_insert_with(Instant::now);
+        let limit = Duration::from_secs(u64::from(self.config.channel_utilization_minutes) * 60);
+        if self.congested || since.elapsed() < limit {
+            return None;
+        }
+        self.congested = true;
+        Some(format!(
+            "Channel utilization above {:.0}% for {} minutes, at {:.0}%: reduce traffic",
+            self.config.channel_utilization_percent,
+            self.config.channel_utilization_minutes,
+            utilization
+        ))
+    }
+
+    /// Whether the channel has been busy for long enough to warn about, and still is.
+    pub fn congested(&self) -> bool {
+        self.congested
+    }
+
+    /// Call periodically. Returns an alert once the device has been silent too long.
+    pub fn check(&mut self) -> Option<String> {
+        let limit = Duration::from_secs(u64::from(self.config.silence_minutes) * 60);
+        if self.silent || self.last_heard.elapsed() < limit {
+            return None;
+        }
+        self.silent = true;
+        Some(format!(
+            "Nothing heard from the device for {} minutes",
+            self.config.silence_minutes
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sustained_channel_utilization_is_reported_once_each_way() {
+        let mut watchdog = Watchdog::new(WatchdogConfig {
+            channel_utilization_minutes: 0,
+            ..WatchdogConfig::default()
+        });
+        assert_eq!(watchdog.channel_utilization(30.0), None);
+        assert!(watchdog.channel_utilization(55.0).is_some());
+        assert!(watchdog.congested());
+        assert_eq!(watchdog.channel_utilization(60.0), None);
+        assert!(watchdog.channel_utilization(20.0).is_some());
+        assert!(!watchdog.congested());
+        assert_eq!(watchdog.channel_utilization(20.0), None);
+    }
+
+    #[test]
+    fn brief_channel_utilization_spikes_are_ignored() {
+        let mut watchdog = Watchdog::new(WatchdogConfig::default());
+        assert_eq!(watchdog.channel_utilization(80.0), None);
+        assert!(!watchdog.congested());
+        assert_eq!(watchdog.channel_utilization(10.0), None);
+    }
+}
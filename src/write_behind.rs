@@ -0,0 +1,486 @@
+//! A store that does its SQLite work on a dedicated thread, so bursts of packets never stall the
+//! UI loop.
+//!
+//! Writes are queued and return immediately; a failed write is logged by the writer thread.
+//! Writes queued together, such as the node database arriving on connect, run as one batch, so
+//! a burst costs one commit instead of one per row. Reads are queued behind the pending writes
+//! and wait for their answer, so they always see everything written before them. `flush` (also
+//! run on drop) waits until the queue is empty.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+use color_eyre::eyre::{Result, eyre};
+use meshtastic::protobufs::{NodeInfo, Position, Waypoint};
+
+use crate::admin_keys::AdminKeypair;
+use crate::config::Retention;
+use crate::notify::{NotifyMode, NotifyOverrides};
+use crate::outbox::OutboxEntry;
+use crate::profiles::Profile;
+use crate::stats::{ConversationStats, Flow, NodeActivity};
+use crate::store::Store;
+use crate::types::{Contact, ContactId, Message, NodeNum, SessionState, ViewState};
+
+/// Writes run in one batch at most, so a long burst still commits now and then.
+const BATCH_LIMIT: usize = 256;
+
+type Task<S> = Box<dyn FnOnce(&mut S) + Send>;
+
+enum Job<S> {
+    Write(Task<S>),
+    /// Ends the batch of writes before it, so it sees them committed.
+    Read(Task<S>),
+}
+
+pub struct WriteBehind<S> {
+    jobs: Option<Sender<Job<S>>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl<S: Store + Send + 'static> WriteBehind<S> {
+    pub fn new(mut store: S) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job<S>>();
+        let writer = thread::spawn(move || {
+            let mut next = None;
+            while let Some(job) = next.take().or_else(|| queue.recv().ok()) {
+                let write = match job {
+                    Job::Read(read) => {
+                        read(&mut store);
+                        continue;
+                    }
+                    Job::Write(write) => write,
+                };
+                let mut writes = vec![write];
+                while writes.len() < BATCH_LIMIT
+                    && let Ok(job) = queue.try_recv()
+                {
+                    match job {
+                        Job::Write(write) => writes.push(write),
+                        read => {
+                            next = Some(read);
+                            break;
+                        }
+                    }
+                }
+                run_batch(&mut store, writes);
+            }
+        });
+        Self {
+            jobs: Some(jobs),
+            writer: Some(writer),
+        }
+    }
+
+    fn submit(&self, job: Job<S>) -> Result<()> {
+        self.jobs
+            .as_ref()
+            .and_then(|jobs| jobs.send(job).ok())
+            .ok_or_else(|| eyre!("Store writer thread has stopped"))
+    }
+
+    /// Queue a write. `what` names it in the log if it fails.
+    fn write(
+        &self,
+        what: &'static str,
+        write: impl FnOnce(&mut S) -> Result<()> + Send + 'static,
+    ) -> Result<()> {
+        self.submit(Job::Write(Box::new(move |store| {
+            if let Err(e) = write(store) {
+                log::error!("Failed to {}: {}", what, e);
+            }
+        })))
+    }
+
+    /// Run a read after all queued writes and wait for its result.
+    fn read<T: Send + 'static>(
+        &self,
+        read: impl FnOnce(&mut S) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let (answer, result) = mpsc::channel();
+        self.submit(Job::Read(Box::new(move |store| {
+            let _ = answer.send(read(store));
+        })))?;
+        result
+            .recv()
+            .map_err(|_| eyre!("Store writer thread has stopped"))?
+    }
+}
+
+/// Run queued writes in one transaction. A write that fails is logged and the rest still run.
+fn run_batch<S: Store>(store: &mut S, writes: Vec<Task<S>>) {
+    let batched = match store.begin_batch() {
+        Ok(()) => true,
+        Err(e) => {
+            log::error!("Failed to start a batch of writes: {}", e);
+            false
+        }
+    };
+    for write in writes {
+        write(store);
+    }
+    if batched && let Err(e) = store.end_batch() {
+        log::error!("Failed to commit a batch of writes: {}", e);
+    }
+}
+
+impl<S> Drop for WriteBehind<S> {
+    fn drop(&mut self) {
+        // Closing the queue lets the writer finish what is pending and exit.
+        drop(self.jobs.take());
+        if let Some(writer) = self.writer.take()
+            && writer.join().is_err()
+        {
+            log::error!("Store writer thread panicked; queued writes may be lost");
+        }
+    }
+}
+
+impl<S: Store + Send + 'static> Store for WriteBehind<S> {
+    fn upsert_node(&mut self, node: &NodeInfo) -> Result<()> {
+        let node = node.clone();
+        self.write("store node", move |store| store.upsert_node(&node))
+    }
+
+    fn get_nodes(&self) -> Result<Vec<NodeInfo>> {
+        self.read(|store| store.get_nodes())
+    }
+
+    fn record_position(&mut self, node: NodeNum, position: &Position) -> Result<()> {
+        let position = *position;
+        self.write("store position", move |store| {
+            store.record_position(node, &position)
+        })
+    }
+
+    fn get_positions(&self, from: i64, to: i64) -> Result<Vec<(NodeNum, i64, Position)>> {
+        self.read(move |store| store.get_positions(from, to))
+    }
+
+    fn record_battery(&mut self, node: NodeNum, level: u32) -> Result<()> {
+        self.write("store battery level", move |store| {
+            store.record_battery(node, level)
+        })
+    }
+
+    fn get_battery(&self, since: i64) -> Result<Vec<(NodeNum, i64, u32)>> {
+        self.read(move |store| store.get_battery(since))
+    }
+
+    /// Queued like any other write, so duplicates cannot be reported and this is always `true`.
+    fn append_message(&mut self, node: NodeNum, message: &Message) -> Result<bool> {
+        let message = message.clone();
+        self.write("store message", move |store| {
+            store.append_message(node, &message).map(|_| ())
+        })?;
+        Ok(true)
+    }
+
+    fn delete_message(&mut self, node: NodeNum, message: &Message) -> Result<()> {
+        let message = message.clone();
+        self.write("delete message", move |store| {
+            store.delete_message(node, &message)
+        })
+    }
+
+    fn set_message_note(&mut self, node: NodeNum, message: &Message, note: &str) -> Result<()> {
+        let message = message.clone();
+        let note = note.to_string();
+        self.write("store message note", move |store| {
+            store.set_message_note(node, &message, &note)
+        })
+    }
+
+    fn set_message_packet_id(
+        &mut self,
+        node: NodeNum,
+        message: &Message,
+        packet_id: u32,
+    ) -> Result<()> {
+        let message = message.clone();
+        self.write("store message packet ID", move |store| {
+            store.set_message_packet_id(node, &message, packet_id)
+        })
+    }
+
+    fn get_messages(&self) -> Result<Vec<(NodeNum, Message)>> {
+        self.read(|store| store.get_messages())
+    }
+
+    fn get_messages_page(
+        &self,
+        node: Option<NodeNum>,
+        since: i64,
+        limit: usize,
+    ) -> Result<Vec<(NodeNum, Message)>> {
+        self.read(move |store| store.get_messages_page(node, since, limit))
+    }
+
+    fn get_conversation(&self, node: NodeNum, range: Range<i64>) -> Result<Vec<Message>> {
+        self.read(move |store| store.get_conversation(node, range))
+    }
+
+    fn get_conversation_stats(&self, node: NodeNum) -> Result<ConversationStats> {
+        self.read(move |store| store.get_conversation_stats(node))
+    }
+
+    fn record_packet(
+        &mut self,
+        node: NodeNum,
+        to: NodeNum,
+        port: i32,
+        airtime_ms: f64,
+    ) -> Result<()> {
+        self.write("store packet metadata", move |store| {
+            store.record_packet(node, to, port, airtime_ms)
+        })
+    }
+
+    fn get_activity(&self, since: i64) -> Result<Vec<NodeActivity>> {
+        self.read(move |store| store.get_activity(since))
+    }
+
+    fn get_flows(&self, since: i64) -> Result<Vec<Flow>> {
+        self.read(move |store| store.get_flows(since))
+    }
+
+    fn get_notes(&self) -> Result<HashMap<NodeNum, String>> {
+        self.read(|store| store.get_notes())
+    }
+
+    fn set_note(&mut self, node: NodeNum, note: &str) -> Result<()> {
+        let note = note.to_string();
+        self.write("store note", move |store| store.set_note(node, &note))
+    }
+
+    fn get_read_state(&self) -> Result<HashMap<NodeNum, i64>> {
+        self.read(|store| store.get_read_state())
+    }
+
+    fn mark_read(&mut self, node: NodeNum, time: i64) -> Result<()> {
+        self.write("store read state", move |store| store.mark_read(node, time))
+    }
+
+    fn get_draft(&self, node: NodeNum) -> Result<Option<String>> {
+        self.read(move |store| store.get_draft(node))
+    }
+
+    fn set_draft(&mut self, node: NodeNum, text: &str) -> Result<()> {
+        let text = text.to_string();
+        self.write("store draft", move |store| store.set_draft(node, &text))
+    }
+
+    fn get_profiles(&self) -> Result<Vec<Profile>> {
+        self.read(|store| store.get_profiles())
+    }
+
+    fn save_profile(&mut self, profile: &Profile) -> Result<()> {
+        let profile = profile.clone();
+        self.write("store profile", move |store| store.save_profile(&profile))
+    }
+
+    fn delete_profile(&mut self, name: &str) -> Result<()> {
+        let name = name.to_string();
+        self.write("delete profile", move |store| store.delete_profile(&name))
+    }
+
+    fn get_psk_rotations(&self) -> Result<HashMap<i32, i64>> {
+        self.read(|store| store.get_psk_rotations())
+    }
+
+    fn record_psk_rotation(&mut self, channel: i32, time: i64) -> Result<()> {
+        self.write("store PSK rotation", move |store| {
+            store.record_psk_rotation(channel, time)
+        })
+    }
+
+    fn get_admin_keypair(&self) -> Result<Option<AdminKeypair>> {
+        self.read(|store| store.get_admin_keypair())
+    }
+
+    fn save_admin_keypair(&mut self, keypair: &AdminKeypair) -> Result<()> {
+        let keypair = keypair.clone();
+        self.write("store admin keypair", move |store| {
+            store.save_admin_keypair(&keypair)
+        })
+    }
+
+    fn get_notify_overrides(&self) -> Result<NotifyOverrides> {
+        self.read(|store| store.get_notify_overrides())
+    }
+
+    fn set_node_notify(&mut self, node: NodeNum, mode: NotifyMode) -> Result<()> {
+        self.write("store node notifications", move |store| {
+            store.set_node_notify(node, mode)
+        })
+    }
+
+    fn set_channel_notify(&mut self, channel: u32, mode: NotifyMode) -> Result<()> {
+        self.write("store channel notifications", move |store| {
+            store.set_channel_notify(channel, mode)
+        })
+    }
+
+    fn get_outbox(&self) -> Result<Vec<OutboxEntry>> {
+        self.read(|store| store.get_outbox())
+    }
+
+    fn save_outbox_entry(&mut self, entry: &OutboxEntry) -> Result<()> {
+        let entry = entry.clone();
+        self.write("store outbox entry", move |store| {
+            store.save_outbox_entry(&entry)
+        })
+    }
+
+    fn delete_outbox_entry(&mut self, id: i64) -> Result<()> {
+        self.write("delete outbox entry", move |store| {
+            store.delete_outbox_entry(id)
+        })
+    }
+
+    fn get_session_state(&self) -> Result<SessionState> {
+        self.read(|store| store.get_session_state())
+    }
+
+    fn save_session_state(&mut self, state: &SessionState) -> Result<()> {
+        let state = state.clone();
+        self.write("store session state", move |store| {
+            store.save_session_state(&state)
+        })
+    }
+
+    fn get_view_states(&self) -> Result<HashMap<String, ViewState>> {
+        self.read(|store| store.get_view_states())
+    }
+
+    fn save_view_state(&mut self, view: &str, state: &ViewState) -> Result<()> {
+        let view = view.to_string();
+        let state = state.clone();
+        self.write("store view state", move |store| {
+            store.save_view_state(&view, &state)
+        })
+    }
+
+    fn record_contact(&mut self, contact: &ContactId, node: NodeNum, time: i64) -> Result<()> {
+        let contact = contact.clone();
+        self.write("store contact", move |store| {
+            store.record_contact(&contact, node, time)
+        })
+    }
+
+    fn get_contacts(&self) -> Result<Vec<Contact>> {
+        self.read(|store| store.get_contacts())
+    }
+
+    fn merge_node(&mut self, old: NodeNum, new: NodeNum) -> Result<()> {
+        self.write("merge node", move |store| store.merge_node(old, new))
+    }
+
+    fn save_waypoint(&mut self, from: NodeNum, waypoint: &Waypoint) -> Result<()> {
+        let waypoint = waypoint.clone();
+        self.write("store waypoint", move |store| {
+            store.save_waypoint(from, &waypoint)
+        })
+    }
+
+    fn search_messages(&self, query: &str) -> Result<Vec<(NodeNum, Message)>> {
+        let query = query.to_string();
+        self.read(move |store| store.search_messages(&query))
+    }
+
+    fn search_waypoints(&self, query: &str) -> Result<Vec<(NodeNum, Waypoint)>> {
+        let query = query.to_string();
+        self.read(move |store| store.search_waypoints(&query))
+    }
+
+    fn nodes_with_messages(&self, query: &str) -> Result<HashSet<NodeNum>> {
+        let query = query.to_string();
+        self.read(move |store| store.nodes_with_messages(&query))
+    }
+
+    fn prune(&mut self, retention: &Retention) -> Result<usize> {
+        let retention = retention.clone();
+        self.read(move |store| store.prune(&retention))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.read(|store| store.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::store::SqliteStore;
+
+    #[test]
+    fn reads_see_every_write_queued_before_them() {
+        let mut store = WriteBehind::new(SqliteStore::open_in_memory().unwrap());
+        for num in 1..=BATCH_LIMIT as u32 * 2 {
+            store
+                .upsert_node(&NodeInfo {
+                    num,
+                    ..NodeInfo::default()
+                })
+                .unwrap();
+            if num == 10 {
+                assert_eq!(store.get_nodes().unwrap().len(), 10);
+            }
+        }
+        store.flush().unwrap();
+        assert_eq!(store.get_nodes().unwrap().len(), BATCH_LIMIT * 2);
+    }
+
+    #[test]
+    fn a_burst_of_writes_commits_once_and_survives_a_failed_write() {
+        let path = env::temp_dir().join(format!("edda-write-behind-{}.db", process::id()));
+        let mut store = WriteBehind::new(SqliteStore::open(&path).unwrap());
+        let reader = SqliteStore::open_read_only(&path).unwrap();
+        // Hold the writer in a read, so the whole burst is queued when it gets to it.
+        let (release, held) = mpsc::channel::<()>();
+        store
+            .submit(Job::Read(Box::new(move |_| {
+                let _ = held.recv();
+            })))
+            .unwrap();
+        let upsert = |store: &WriteBehind<SqliteStore>, num| {
+            let node = NodeInfo {
+                num,
+                ..NodeInfo::default()
+            };
+            store
+                .write("store node", move |store| store.upsert_node(&node))
+                .unwrap();
+        };
+        for num in 1..=5 {
+            upsert(&store, num);
+        }
+        store
+            .write("fail on purpose", |_| Err(eyre!("disk on fire")))
+            .unwrap();
+        // Another connection sees none of the batch until it is committed as a whole.
+        let (seen, visible) = mpsc::channel();
+        store
+            .write("look from another connection", move |_| {
+                let _ = seen.send(reader.get_nodes().map(|nodes| nodes.len()));
+                Ok(())
+            })
+            .unwrap();
+        for num in 6..=10 {
+            upsert(&store, num);
+        }
+        release.send(()).unwrap();
+        store.flush().unwrap();
+
+        assert_eq!(visible.recv().unwrap().unwrap(), 0);
+        assert_eq!(store.get_nodes().unwrap().len(), 10);
+        drop(store);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+}